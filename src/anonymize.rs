@@ -0,0 +1,120 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Scrambling names, messages, and contents, like `git fast-export
+//! --anonymize`, so that a repository can be shared without what is in it.
+//!
+//! Everything is replaced with a token derived from a hash of what it
+//! replaces, salted with a random seed. Thus, the same name always becomes the
+//! same token and the shape of the history is kept, but the tokens can't be
+//! worked out by hashing guesses at what they stand for. The seed is kept in
+//! the object map, so tokens only stay the same across runs that use the same
+//! map.
+
+use sha2::{Digest, Sha256};
+
+/// A random value that is mixed into every token.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Seed([u8; 20]);
+
+impl From<git2::Oid> for Seed {
+    fn from(oid: git2::Oid) -> Seed {
+        let mut seed = [0; 20];
+        seed.copy_from_slice(oid.as_bytes());
+        Seed(seed)
+    }
+}
+
+/// Returns a short token standing in for some data of the given kind.
+fn token(seed: Seed, kind: &str, data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(seed.0);
+    hasher.update(kind.as_bytes());
+    hasher.update(b"\0");
+    hasher.update(data);
+
+    hasher.finalize()[..6]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Returns the scrambled author or committer. The time is kept.
+pub fn signature(
+    seed: Seed,
+    signature: &git2::Signature<'_>,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    let name = format!("User {}", token(seed, "name", signature.name_bytes()));
+    let email = format!(
+        "user-{}@example.com",
+        token(seed, "email", &signature.email_bytes().to_ascii_lowercase())
+    );
+
+    git2::Signature::new(&name, &email, &signature.when())
+}
+
+/// Returns the scrambled commit message.
+pub fn message(seed: Seed, message: &[u8]) -> Vec<u8> {
+    format!("Commit {}\n", token(seed, "message", message)).into_bytes()
+}
+
+/// Returns the scrambled name of a file or directory.
+pub fn path_name(seed: Seed, name: &[u8]) -> String {
+    format!("path-{}", token(seed, "path", name))
+}
+
+/// Returns the scrambled contents of a blob.
+pub fn contents(seed: Seed, blob: git2::Oid) -> Vec<u8> {
+    format!("Blob {}\n", token(seed, "blob", blob.as_bytes())).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let seed = Seed::default();
+        assert_eq!(path_name(seed, b"src"), path_name(seed, b"src"));
+        assert_ne!(path_name(seed, b"src"), path_name(seed, b"lib"));
+        assert_ne!(token(seed, "path", b"x"), token(seed, "name", b"x"));
+        assert_eq!(token(seed, "path", b"x").len(), 12);
+
+        let other = Seed::from(git2::Oid::from_bytes(&[1; 20]).unwrap());
+        assert_ne!(path_name(seed, b"src"), path_name(other, b"src"));
+    }
+
+    #[test]
+    fn test_signature() {
+        let when = git2::Time::new(1500000000, 60);
+        let a =
+            git2::Signature::new("Jane", "Jane@Example.com", &when).unwrap();
+        let b =
+            git2::Signature::new("Jane", "jane@example.com", &when).unwrap();
+
+        let a = signature(Seed::default(), &a).unwrap();
+        let b = signature(Seed::default(), &b).unwrap();
+
+        assert_eq!(a.name(), b.name());
+        assert_eq!(a.email(), b.email());
+        assert!(a.name().unwrap().starts_with("User "));
+        assert_eq!(a.when(), when);
+    }
+}
@@ -21,7 +21,150 @@ use std::path::PathBuf;
 
 use structopt::StructOpt;
 
+use crate::commit::Prune;
+use crate::eol::Eol;
+use crate::filter::Submodules;
+use crate::map::MapBackend;
+use crate::mapfile::MapFormat;
+use crate::refs::ReplaceRefs;
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix, such as
+/// `5M`.
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k')) | Some((i, 'K')) => (&s[..i], 1 << 10),
+        Some((i, 'm')) | Some((i, 'M')) => (&s[..i], 1 << 20),
+        Some((i, 'g')) | Some((i, 'G')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size '{}'", s))
+}
+
+/// Parses a time as in the headers of a commit, which is the number of seconds
+/// since the Unix epoch with an optional time zone offset, such as
+/// `1500000000 +0100`.
+fn parse_time(s: &str) -> Result<git2::Time, String> {
+    let invalid = || format!("Invalid time '{}'", s);
+
+    let mut parts = s.split_whitespace();
+
+    let seconds = parts
+        .next()
+        .and_then(|seconds| seconds.trim_start_matches('@').parse::<i64>().ok())
+        .ok_or_else(invalid)?;
+
+    let offset = match parts.next() {
+        Some(offset) if offset.len() == 5 && offset.is_ascii() => {
+            let sign = match &offset[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => return Err(invalid()),
+            };
+
+            let hours = offset[1..3].parse::<i32>().map_err(|_| invalid())?;
+            let minutes = offset[3..].parse::<i32>().map_err(|_| invalid())?;
+
+            sign * (hours * 60 + minutes)
+        }
+        Some(_) => return Err(invalid()),
+        None => 0,
+    };
+
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(git2::Time::new(seconds, offset))
+}
+
+/// Parses a date of the form `YYYY-MM-DD` (at midnight UTC) or a time of the
+/// form `@seconds`, returning the seconds since the Unix epoch. Returns `None`
+/// if it is neither.
+pub fn parse_date(s: &str) -> Option<i64> {
+    if let Some(seconds) = s.strip_prefix('@') {
+        return seconds.parse().ok();
+    }
+
+    let mut parts = s.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Some(year)), Some(Some(month)), Some(Some(day)))
+            if s.len() == 10
+                && (1..=12).contains(&month)
+                && (1..=31).contains(&day) =>
+        {
+            (year, month, day)
+        }
+        _ => return None,
+    };
+
+    // Days since the epoch in the proleptic Gregorian calendar, counting
+    // years from March so that leap days come last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    Some((era * 146097 + day_of_era - 719468) * 86400)
+}
+
+/// Parses a date as `parse_date` does, failing if it is invalid.
+fn parse_date_arg(s: &str) -> Result<i64, String> {
+    parse_date(s).ok_or_else(|| {
+        format!("Expected 'YYYY-MM-DD' or '@seconds' but got '{}'", s)
+    })
+}
+
+/// Parses an identity of the form `Name <email>`.
+fn parse_ident(s: &str) -> Result<(String, String), String> {
+    let invalid = || format!("Expected 'Name <email>' but got '{}'", s);
+
+    let (name, email) = s
+        .trim()
+        .strip_suffix('>')
+        .and_then(|s| s.split_once('<'))
+        .ok_or_else(invalid)?;
+
+    if email.contains(['<', '>']) {
+        return Err(invalid());
+    }
+
+    Ok((name.trim().to_string(), email.to_string()))
+}
+
+/// Parses a trailer of the form `Key: Value`, such as `Signed-off-by: Jane Doe
+/// <jane@example.com>`.
+fn parse_trailer(s: &str) -> Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((key, value))
+            if !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            Ok((key.to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("Expected 'Key: Value' but got '{}'", s)),
+    }
+}
+
 #[derive(StructOpt)]
+#[structopt(
+    after_help = "Use `git-subset clone <url> <dir> [options] [revspec]...` to \
+                  clone a repository and create the subset in the clone, or \
+                  `git-subset mirror [options] <revspec>` to keep a filtered \
+                  mirror of an upstream branch up to date, or `git-subset \
+                  serve [options] [revspec]...` to serve the subsets over \
+                  HTTP, or `git-subset map <command>` to work with the saved \
+                  object maps."
+)]
 pub struct Args {
     /// Don't print as much progress.
     #[structopt(long = "quiet", short = "q")]
@@ -35,23 +178,713 @@ pub struct Args {
     #[structopt(long = "nomap")]
     pub nomap: bool,
 
+    /// Where to keep the saved map: `file`, which is read into memory, or
+    /// `sqlite`, a database that is only read as needed, for repositories too
+    /// large for the map to fit in memory. `sqlite` needs the `sqlite`
+    /// feature.
+    #[structopt(long = "map-backend", default_value = "file")]
+    pub map_backend: MapBackend,
+
+    /// Keeps the saved map in a commit on this reference instead of under
+    /// `.git/subset/`, such as `refs/subset/state/main`, so that it can be
+    /// pushed and fetched to carry on from another clone. A map that isn't on
+    /// it yet is read from `.git/subset/` the first time.
+    #[structopt(long = "state-branch", conflicts_with = "nomap")]
+    pub state_branch: Option<String>,
+
+    /// Saves the map after rewriting this many commits, and again after as
+    /// many more, so that a run that is cut short can carry on from there.
+    #[structopt(long = "checkpoint-commits")]
+    pub checkpoint_commits: Option<usize>,
+
+    /// Saves the map every this many seconds while rewriting commits. Zero
+    /// turns it off.
+    #[structopt(long = "checkpoint-interval", default_value = "600")]
+    pub checkpoint_interval: u64,
+
+    /// Leaves out all symbolic links.
+    #[structopt(long = "strip-symlinks")]
+    pub strip_symlinks: bool,
+
+    /// Leaves out all submodules.
+    #[structopt(long = "strip-gitlinks")]
+    pub strip_gitlinks: bool,
+
+    /// What to do with submodules: `keep` treats them like any other file,
+    /// `drop` leaves all of them out, and `error` fails if any of them would
+    /// be kept, listing where they are.
+    #[structopt(long = "submodules", default_value = "keep")]
+    pub submodules: Submodules,
+
+    /// Removes lines from `.gitignore` and `.gitattributes` files that only
+    /// refer to paths that were left out.
+    #[structopt(long = "prune-patterns")]
+    pub prune_patterns: bool,
+
+    /// Leaves out all blobs bigger than this size. Accepts a `K`, `M`, or `G`
+    /// suffix.
+    #[structopt(long = "strip-blobs-bigger-than", parse(try_from_str = parse_size))]
+    pub strip_blobs_bigger_than: Option<usize>,
+
+    /// Leaves out all blobs whose IDs are listed in this file, one per line.
+    /// Anything after the ID on a line is ignored.
+    #[structopt(long = "strip-blobs-with-ids")]
+    pub strip_blobs_with_ids: Option<PathBuf>,
+
+    /// Writes a report of everything that was left out to this file.
+    #[structopt(long = "report")]
+    pub report: Option<PathBuf>,
+
+    /// Follows renamed files and directories back through history. Their
+    /// former paths are kept too.
+    #[structopt(long = "follow-renames", alias = "follow")]
+    pub follow_renames: bool,
+
+    /// Keeps everything that isn't matched by the filter instead of everything
+    /// that is.
+    #[structopt(long = "invert")]
+    pub invert: bool,
+
+    /// Ignore case when matching paths.
+    #[structopt(long = "icase")]
+    pub icase: bool,
+
+    /// Path to a file in `.mailmap` format used to canonicalize the names and
+    /// email addresses of authors and committers.
+    #[structopt(long = "mailmap")]
+    pub mailmap: Option<PathBuf>,
+
+    /// Canonicalizes authors and committers with the `.mailmap` file at the
+    /// tip of the history being rewritten.
+    #[structopt(long = "use-mailmap")]
+    pub use_mailmap: bool,
+
+    /// Path to a file of replacements to make in commit messages, one per
+    /// line, such as `PROJ-123==>` or `regex:https?://jira\S*==>`. Matches
+    /// are replaced with `***REMOVED***` if no replacement is given.
+    #[structopt(long = "replace-message")]
+    pub replace_message: Option<PathBuf>,
+
+    /// Path to a file of replacements to make in the contents of every file,
+    /// in the same format as `--replace-message`. Binary files are left
+    /// alone.
+    #[structopt(long = "replace-text")]
+    pub replace_text: Option<PathBuf>,
+
+    /// Removes the UTF-8 byte order mark from the start of every text file.
+    #[structopt(long = "strip-bom")]
+    pub strip_bom: bool,
+
+    /// Converts the line endings of every text file to `lf` or `crlf`. Files
+    /// with the `-text` attribute are left alone, as are those that look
+    /// binary unless they have the `text` attribute.
+    #[structopt(long = "normalize-eol")]
+    pub normalize_eol: Option<Eol>,
+
+    /// Replaces common kinds of secrets in the contents of every file, such
+    /// as AWS keys, private keys, and tokens, with `***REMOVED***`. Where
+    /// they were found is written to the report.
+    #[structopt(long = "redact-secrets")]
+    pub redact_secrets: bool,
+
+    /// Scrambles the names of all files and directories, and every author,
+    /// committer, and commit message, keeping the shape of the history.
+    /// Useful for sharing a repository that reproduces a bug without sharing
+    /// what is in it.
+    #[structopt(long = "anonymize")]
+    pub anonymize: bool,
+
+    /// Scrambles the contents of all files too. Requires `--anonymize`.
+    #[structopt(long = "anonymize-contents", requires = "anonymize")]
+    pub anonymize_contents: bool,
+
+    /// Leaves out every file whose contents match this regular expression
+    /// (e.g., `PROPRIETARY - DO NOT DISTRIBUTE`). Can be specified multiple
+    /// times.
+    #[structopt(long = "drop-files-matching-content")]
+    pub drop_files_matching_content: Vec<String>,
+
+    /// Runs this shell command for every file, passing it the contents on
+    /// stdin. What it prints becomes the new contents. The output is cached
+    /// by the contents and the command.
+    #[structopt(long = "blob-callback")]
+    pub blob_callback: Option<String>,
+
+    /// Converts the files matching this pattern in `.gitattributes` syntax
+    /// (e.g., `*.psd`) to Git LFS pointers. Their contents are written to
+    /// `lfs/objects` in the Git directory. Can be specified multiple times.
+    #[structopt(long = "lfs-convert")]
+    pub lfs_convert: Vec<String>,
+
+    /// Rewrites commit hashes in commit messages, such as in "This reverts
+    /// commit abc1234.", to refer to the rewritten commits. Hashes of commits
+    /// that were left out are annotated as such.
+    #[structopt(long = "rewrite-hashes")]
+    pub rewrite_hashes: bool,
+
+    /// Signs every rewritten commit with GPG or SSH, depending on `gpg.format`.
+    /// The key defaults to `user.signingKey`.
+    #[structopt(
+        long = "sign",
+        value_name = "key",
+        min_values = 0,
+        max_values = 1,
+        require_equals = true
+    )]
+    pub sign: Option<Option<String>>,
+
+    /// Which empty commits to leave out: `always` leaves out all of them,
+    /// `auto` only those that became empty due to filtering, and `never` keeps
+    /// all of them.
+    #[structopt(long = "prune-empty", default_value = "always")]
+    pub prune_empty: Prune,
+
+    /// Which degenerate merges to collapse. A merge is degenerate if some of
+    /// its parents are the same or are ancestors of its other parents.
+    /// `always` collapses all of them, `auto` only those that became
+    /// degenerate due to filtering, and `never` keeps all of them.
+    #[structopt(long = "prune-degenerate", default_value = "auto")]
+    pub prune_degenerate: Prune,
+
+    /// Never leaves out or collapses merge commits, even if they are empty or
+    /// degenerate. This keeps the merge topology of the original history.
+    #[structopt(long = "no-prune-merges")]
+    pub no_prune_merges: bool,
+
+    /// Only follows the first parent of merge commits, leaving out the
+    /// branches that were merged. Merges become regular commits, giving a
+    /// linear history that is much smaller.
+    #[structopt(long = "first-parent", conflicts_with = "no-prune-merges")]
+    pub first_parent: bool,
+
+    /// Rewrites the commits into a single chain in topological order, so that
+    /// there are no merges at all. Every commit becomes a child of the commit
+    /// before it.
+    #[structopt(
+        long = "linearize",
+        conflicts_with_all = &["no-prune-merges", "first-parent"]
+    )]
+    pub linearize: bool,
+
+    /// Replaces the author of every rewritten commit, given as `Name
+    /// <email>`. The dates are kept.
+    #[structopt(long = "author", parse(try_from_str = parse_ident))]
+    pub author: Option<(String, String)>,
+
+    /// Replaces the committer of every rewritten commit, given as `Name
+    /// <email>`. The dates are kept.
+    #[structopt(long = "committer", parse(try_from_str = parse_ident))]
+    pub committer: Option<(String, String)>,
+
+    /// Sets the committer date of every rewritten commit to its author date.
+    #[structopt(long = "committer-date-is-author-date")]
+    pub committer_date_is_author_date: bool,
+
+    /// Sets the author and committer dates of every rewritten commit to this
+    /// time, given as seconds since the Unix epoch with an optional time zone
+    /// offset (e.g., `1500000000 +0100`). Useful for reproducible exports.
+    #[structopt(long = "reset-dates", parse(try_from_str = parse_time))]
+    pub reset_dates: Option<git2::Time>,
+
+    /// Squashes all history older than this into a single root commit. This
+    /// is either a revision, which is squashed along with its ancestors, or a
+    /// date (`YYYY-MM-DD` or `@seconds`), before which commits are squashed.
+    #[structopt(long = "squash-before")]
+    pub squash_before: Option<String>,
+
+    /// Grafts the rewritten commits onto this commit where their parents
+    /// weren't rewritten, instead of making them root commits. For example,
+    /// `--graft-onto published base..main` continues the previously published
+    /// history with the rewritten commits of `base..main`.
+    #[structopt(long = "graft-onto")]
+    pub graft_onto: Option<String>,
+
+    /// Only rewrites commits made on or after this date (`YYYY-MM-DD` or
+    /// `@seconds`). Older commits are left out as if they were excluded by a
+    /// range, so they can be squashed or grafted onto something else.
+    #[structopt(long = "since", parse(try_from_str = parse_date_arg))]
+    pub since: Option<i64>,
+
+    /// Only rewrites commits made at or before this date (`YYYY-MM-DD` or
+    /// `@seconds`). Newer commits are left out, and references to them point
+    /// to the newest commit before them that is rewritten.
+    #[structopt(long = "until", parse(try_from_str = parse_date_arg))]
+    pub until: Option<i64>,
+
+    /// Drops the commits whose author or committer matches this regular
+    /// expression (e.g., `contractor@example\.com`), which is matched against
+    /// `Name <email>`. Their changes are folded into the commits that follow
+    /// them. Merges aren't dropped. Can be specified multiple times.
+    #[structopt(long = "drop-commits-by")]
+    pub drop_commits_by: Vec<String>,
+
+    /// Drops the commits whose message matches this regular expression (e.g.,
+    /// `^fixup!`), as with `--drop-commits-by`. Can be specified multiple
+    /// times.
+    #[structopt(long = "drop-message-regex")]
+    pub drop_message_regexes: Vec<String>,
+
+    /// Leaves out merge commits, as with `git log --no-merges`. Their changes
+    /// are folded into the commits that follow them.
+    #[structopt(
+        long = "no-merges",
+        conflicts_with_all = &["no-prune-merges", "merges-only"]
+    )]
+    pub no_merges: bool,
+
+    /// Leaves out all commits that aren't merges, as with `git log
+    /// --merges`. Their changes are folded into the merges that follow them.
+    #[structopt(long = "merges-only")]
+    pub merges_only: bool,
+
+    /// Keeps this commit even if it ends up empty, such as one that is
+    /// referred to by its hash elsewhere. Can be specified multiple times.
+    /// Commits with notes are always kept.
+    #[structopt(long = "keep-commit", number_of_values = 1)]
+    pub keep_commits: Vec<String>,
+
+    /// Keeps the commits listed in this file, one ID per line, as with
+    /// `--keep-commit`. Anything after the ID on a line is ignored.
+    #[structopt(long = "keep-commits-from")]
+    pub keep_commits_from: Option<PathBuf>,
+
+    /// Shell command to run for every rewritten commit. It is given a JSON
+    /// description of the commit on stdin and may print a JSON object to
+    /// change its message, author, or committer, or to skip it. See the
+    /// README for the details.
+    #[structopt(long = "commit-callback")]
+    pub commit_callback: Option<String>,
+
+    /// WebAssembly plugin that can keep, drop, or move each file and rewrite
+    /// commit messages. Only available if built with the `wasm` feature. See
+    /// the README for the interface it must implement.
+    #[structopt(long = "plugin")]
+    pub plugin: Option<PathBuf>,
+
+    /// Lua script defining `on_commit(commit)` to edit or skip commits and
+    /// `on_blob(path, contents)` to edit or drop files. Only available if
+    /// built with the `lua` feature. See the README for the details.
+    #[structopt(long = "lua")]
+    pub lua: Option<PathBuf>,
+
+    /// Appends a trailer to every rewritten commit message, given as `Key:
+    /// Value` (e.g., `Signed-off-by: Jane Doe <jane@example.com>`). Messages
+    /// that already have it are left alone. Can be specified multiple times.
+    #[structopt(long = "add-trailer", parse(try_from_str = parse_trailer))]
+    pub add_trailers: Vec<(String, String)>,
+
+    /// Appends a trailer with the hash of the original commit to every
+    /// rewritten commit message.
+    #[structopt(long = "original-oid")]
+    pub original_oid: bool,
+
+    /// The key of the trailer added by `--original-oid`.
+    #[structopt(long = "original-oid-key", default_value = "original-oid")]
+    pub original_oid_key: String,
+
     /// Path to the repository. Defaults to the current directory.
     #[structopt(long = "repo", short = "r", default_value = ".")]
     pub repo: PathBuf,
 
+    /// Writes the rewritten objects and the new branch into this repository
+    /// instead, initializing a bare one if nothing is there yet. Objects are
+    /// read from the source repository, which is left untouched, and only
+    /// those reachable from the target's references are copied over.
+    #[structopt(
+        long = "target",
+        conflicts_with_all = &["in-place", "notes", "replace-refs"]
+    )]
+    pub target: Option<PathBuf>,
+
     /// Name of the branch to create on the rewritten commits.
-    #[structopt(long = "branch", short = "b")]
-    pub branch: String,
+    #[structopt(long = "branch", short = "b", alias = "branch-included")]
+    pub branch: Option<String>,
+
+    /// Points `HEAD` at the branch once it is created, checking it out if
+    /// there is a working tree.
+    #[structopt(long = "set-head", requires = "branch")]
+    pub set_head: bool,
+
+    /// Creates a linked worktree at this path with the branch checked out,
+    /// as with `git worktree add <path> <branch>`. The path must not exist
+    /// yet.
+    #[structopt(
+        long = "worktree",
+        requires = "branch",
+        conflicts_with = "set-head"
+    )]
+    pub worktree: Option<PathBuf>,
+
+    /// The reflog message for creating the branch, tag, or reference. By
+    /// default, it names the revision that was filtered and the hash of the
+    /// filter, which is also the name of the object map.
+    #[structopt(long = "reflog-message")]
+    pub reflog_message: Option<String>,
+
+    /// Sets the upstream of the branch to `<remote>/<branch>`, as with `git
+    /// branch --set-upstream-to`, so that it can be pushed and pulled right
+    /// away. The remote branch doesn't need to exist yet.
+    #[structopt(long = "track", requires = "branch")]
+    pub track: Option<String>,
+
+    /// Copies the upstream of the branch given as the first revision to the
+    /// new branch.
+    #[structopt(
+        long = "copy-upstream",
+        requires = "branch",
+        conflicts_with = "track"
+    )]
+    pub copy_upstream: bool,
+
+    /// Pushes the created branches, tags, or references to this remote once
+    /// they are created, given as `<remote>[:<refspec>]`. Without a refspec,
+    /// they are pushed to the same names. A refspec without a `:` says where
+    /// to push the first one. Authenticates with the SSH agent or the
+    /// configured credential helpers.
+    #[structopt(long = "push")]
+    pub push: Option<String>,
+
+    /// Name of a tag to create on the rewritten commits instead of a branch.
+    #[structopt(long = "tag", conflicts_with = "branch")]
+    pub tag: Option<String>,
+
+    /// Fully qualified name of a reference to create on the rewritten commits
+    /// instead of a branch, such as `refs/subset/foo`.
+    #[structopt(long = "ref", conflicts_with_all = &["branch", "tag"])]
+    pub reference: Option<String>,
+
+    /// Creates an annotated tag with `--tag` whose message describes how the
+    /// subset was created.
+    #[structopt(long = "annotate", requires = "tag")]
+    pub annotate: bool,
+
+    /// Rewrites all local branches and tags too. The rewritten references are
+    /// created under `<ref-prefix><branch>/`.
+    #[structopt(long = "all")]
+    pub all: bool,
+
+    /// Glob pattern for references to rewrite too, such as `refs/heads/*`.
+    /// Can be specified multiple times.
+    #[structopt(long = "refs")]
+    pub refs: Vec<String>,
+
+    /// Glob pattern for references not to rewrite, such as
+    /// `refs/heads/wip/*`. Can be specified multiple times.
+    #[structopt(long = "exclude-refs")]
+    pub exclude_refs: Vec<String>,
+
+    /// Renames rewritten tags starting with `<old>` to start with `<new>`
+    /// instead, given as `<old>:<new>` (e.g., `v:subset-v`). Can be specified
+    /// multiple times. The first rule that applies to a tag wins.
+    #[structopt(long = "tag-rename")]
+    pub tag_renames: Vec<String>,
+
+    /// Renames rewritten tags with a regular expression, given as
+    /// `<regex>:<replacement>` (e.g., `^(.*)$:$1-subset`). Can be specified
+    /// multiple times. Rules given with `--tag-rename` are tried first.
+    #[structopt(long = "tag-rename-regex")]
+    pub tag_rename_regexes: Vec<String>,
+
+    /// Copies the notes in `refs/notes/commits` (or `core.notesRef`) onto the
+    /// rewritten commits. The notes are created under
+    /// `<ref-prefix><branch>/notes/`, or rewritten in place with `--in-place`.
+    #[structopt(long = "notes")]
+    pub notes: bool,
+
+    /// What to do with replace references (`refs/replace/*`) afterwards:
+    /// `delete-no-add`, `delete-and-add`, `update-no-add`, `update-or-add`,
+    /// or `update-and-add`. Adding them replaces each original commit with
+    /// its rewritten counterpart, so that old hashes keep working.
+    #[structopt(long = "replace-refs")]
+    pub replace_refs: Option<ReplaceRefs>,
+
+    /// Prefix for the names of rewritten references. The name of the branch
+    /// is appended to it.
+    #[structopt(long = "ref-prefix", default_value = "refs/subset/")]
+    pub ref_prefix: String,
+
+    /// Rewrites the references given as revisions (and with `--all` or
+    /// `--refs`) in place instead of creating a new branch. The original
+    /// references are saved under `refs/original/`.
+    #[structopt(long = "in-place")]
+    pub in_place: bool,
+
+    /// Doesn't ask for confirmation before rewriting references in place.
+    #[structopt(long = "yes", short = "y")]
+    pub yes: bool,
+
+    /// Path to a manifest with a filter for each of several branches to
+    /// create in a single pass. Other options apply to every filter.
+    #[structopt(long = "manifest")]
+    pub manifest: Option<PathBuf>,
+
+    /// Name of a second branch to create, with everything the filter leaves
+    /// out instead. Both branches are created in a single pass.
+    #[structopt(long = "branch-excluded")]
+    pub branch_excluded: Option<String>,
 
     /// Path to the file containing paths to keep.
     #[structopt(long = "filter-file")]
     pub filter_file: Option<PathBuf>,
 
-    /// Path to include. Can be specified multiple times.
+    /// Path to include. Can be specified multiple times. A path starting with
+    /// `:` is a git pathspec with magic, such as `:(exclude)src/gen`.
     #[structopt(long = "path", short = "p")]
     pub paths: Vec<PathBuf>,
 
-    /// The ref to filter from.
-    #[structopt(default_value = "HEAD")]
-    pub revspec: String,
+    /// Regular expression matched against the full path of each file and
+    /// directory to include. Can be specified multiple times.
+    #[structopt(long = "path-regex")]
+    pub path_regexes: Vec<String>,
+
+    /// Glob pattern matched against the name of each file and directory to
+    /// include, no matter how deep it is. Can be specified multiple times.
+    #[structopt(long = "path-glob")]
+    pub path_globs: Vec<String>,
+
+    /// Attribute from `.gitattributes` that files to include must have, such
+    /// as `export-subset` or `filter=lfs`. Can be specified multiple times.
+    #[structopt(long = "path-attr")]
+    pub path_attrs: Vec<String>,
+
+    /// Only keeps the contents of this directory and makes it the new root of
+    /// the repository, like `git filter-branch --subdirectory-filter`.
+    #[structopt(long = "subdirectory-filter")]
+    pub subdirectory_filter: Option<String>,
+
+    /// Removes this many leading components from every path, like `tar
+    /// --strip-components`. Files with fewer components are left out.
+    #[structopt(long = "strip-components")]
+    pub strip_components: Option<usize>,
+
+    /// Moves a file or directory somewhere else in the rewritten history, given
+    /// as `<old>:<new>` (e.g., `libfoo/src/:src/`). Can be specified multiple
+    /// times.
+    #[structopt(long = "path-rename")]
+    pub path_renames: Vec<String>,
+
+    /// Moves everything into this directory, such as `imported/foo/`. Useful
+    /// for merging the subset into another repository later.
+    #[structopt(long = "to-subdirectory-filter")]
+    pub to_subdirectory_filter: Option<String>,
+
+    /// Path to a file in `.gitignore` syntax containing paths to leave out.
+    /// Can be specified multiple times.
+    #[structopt(long = "exclude-file")]
+    pub exclude_files: Vec<PathBuf>,
+
+    /// Path to leave out, in `.gitignore` syntax. Can be specified multiple
+    /// times.
+    #[structopt(long = "exclude", short = "x")]
+    pub excludes: Vec<String>,
+
+    /// Commits to exclude along with their ancestors. Can be specified
+    /// multiple times.
+    #[structopt(long = "not", number_of_values = 1)]
+    pub not: Vec<String>,
+
+    /// Reads the history to filter from a stream for `git fast-import` in this
+    /// file (or `-` for standard input), such as one written by `git
+    /// fast-export`. The objects are written to the repository, but the
+    /// references in the stream are not. Revisions can name them, and all of
+    /// them are filtered by default.
+    #[structopt(long = "import-stream", conflicts_with_all = &["in-place", "fetch"])]
+    pub import_stream: Option<PathBuf>,
+
+    /// Writes the rewritten history to this file (or `-` for standard output)
+    /// as a stream for `git fast-import`, instead of creating anything. New
+    /// objects are only kept in memory, so nothing is written to the
+    /// repository.
+    #[structopt(
+        long = "export-stream",
+        conflicts_with_all = &[
+            "in-place", "target", "push", "worktree", "set-head", "track",
+            "copy-upstream", "notes", "replace-refs",
+        ]
+    )]
+    pub export_stream: Option<PathBuf>,
+
+    /// Writes each rewritten commit to this directory as a patch in an email,
+    /// as `git format-patch` does, instead of creating anything. Merge commits
+    /// are left out. Like `--export-stream`, nothing is written to the
+    /// repository.
+    #[structopt(
+        long = "export-patches",
+        conflicts_with_all = &[
+            "in-place", "target", "push", "worktree", "set-head", "track",
+            "copy-upstream", "notes", "replace-refs", "export-stream",
+        ]
+    )]
+    pub export_patches: Option<PathBuf>,
+
+    /// Reads marks from a file written by `--export-marks`, `git fast-import`,
+    /// or `git fast-export`, so that a stream can carry on from an earlier one.
+    /// These are marks for the stream written by `--export-stream`, leaving out
+    /// anything that already has one, or else for the one read by
+    /// `--import-stream`, which can then refer to them.
+    #[structopt(long = "import-marks")]
+    pub import_marks: Option<PathBuf>,
+
+    /// Writes the marks of the stream to this file afterwards, for the next run
+    /// to read with `--import-marks`.
+    #[structopt(long = "export-marks")]
+    pub export_marks: Option<PathBuf>,
+
+    /// Fetches this remote before resolving the revisions, so that the subset
+    /// is made from its latest state, such as with `--fetch origin
+    /// origin/main`. Authenticates like `--push`.
+    #[structopt(long = "fetch")]
+    pub fetch: Option<String>,
+
+    /// Walks history as the commits themselves say, ignoring replace
+    /// references (`refs/replace/*`) and grafts (`info/grafts`).
+    #[structopt(long = "no-replace-objects")]
+    pub no_replace_objects: bool,
+
+    /// The revisions to filter, such as `main develop ^old-base` or
+    /// `v1.0..main`. The branch is created on the first one and other
+    /// references are rewritten as with `--refs`. Defaults to `HEAD`.
+    #[structopt(name = "revspec")]
+    pub revspecs: Vec<String>,
+}
+
+/// Clones a repository and creates the subset in the clone, repacking it
+/// afterwards.
+#[derive(StructOpt)]
+#[structopt(name = "git-subset clone")]
+pub struct CloneArgs {
+    /// The repository to clone.
+    pub url: String,
+
+    /// Where to clone the repository to. It must not exist yet or be empty.
+    pub dir: PathBuf,
+
+    /// Makes a bare clone.
+    #[structopt(long = "bare")]
+    pub bare: bool,
+
+    /// Doesn't repack the clone once the subset is created.
+    #[structopt(long = "no-repack")]
+    pub no_repack: bool,
+
+    #[structopt(flatten)]
+    pub args: Args,
+}
+
+/// Serves the subsets over Git's smart HTTP protocol, so that they can be
+/// cloned and fetched without creating them ahead of time. Each subset is
+/// served at `/<branch>` and is brought up to date whenever it is fetched.
+#[derive(StructOpt)]
+#[structopt(name = "git-subset serve")]
+pub struct ServeArgs {
+    /// The address to listen on.
+    #[structopt(long = "listen", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    #[structopt(flatten)]
+    pub args: Args,
+}
+
+/// Keeps a filtered mirror of an upstream branch up to date by fetching it,
+/// rewriting the new commits, and pushing the result. Needs `--fetch` and
+/// `--branch`, and usually `--push`.
+#[derive(StructOpt)]
+#[structopt(name = "git-subset mirror")]
+pub struct MirrorArgs {
+    /// Runs a cycle every this many seconds instead of just once.
+    #[structopt(long = "interval")]
+    pub interval: Option<u64>,
+
+    #[structopt(flatten)]
+    pub args: Args,
+}
+
+/// Works with the object maps saved in a repository. Each map is named after
+/// the hash of the filter it was written for.
+#[derive(StructOpt)]
+#[structopt(name = "git-subset map")]
+pub struct MapArgs {
+    /// Path to the repository the maps are saved in.
+    #[structopt(long = "repo", short = "r", default_value = ".")]
+    pub repo: PathBuf,
+
+    /// The map to use. Can be left out if the repository has just one.
+    #[structopt(long = "map", short = "m")]
+    pub map: Option<String>,
+
+    /// How the map is stored. Either `file` or `sqlite`.
+    #[structopt(long = "map-backend", default_value = "file")]
+    pub map_backend: MapBackend,
+
+    #[structopt(subcommand)]
+    pub command: MapCommand,
+}
+
+#[derive(StructOpt)]
+pub enum MapCommand {
+    /// Writes the commits in the map along with the commits they became, or
+    /// `null` for those that were left out.
+    Export {
+        /// The format to write. Either `json` or `commit-map`, the format of
+        /// the `commit-map` file written by `git filter-repo`.
+        #[structopt(long = "format", default_value = "json")]
+        format: MapFormat,
+
+        /// Where to write the map. Defaults to standard output.
+        #[structopt(long = "output", short = "o")]
+        output: Option<PathBuf>,
+    },
+
+    /// Adds the commits in a file written by `export`, or by `git
+    /// filter-repo`, to the map. Entries that are already in the map are
+    /// replaced.
+    Import {
+        /// The format to read. Either `json` or `commit-map`.
+        #[structopt(long = "format", default_value = "json")]
+        format: MapFormat,
+
+        /// The file to read, or `-` for standard input.
+        input: PathBuf,
+    },
+
+    /// Checks that every object the map refers to is still in the repository,
+    /// such as after `git gc`, and that every entry resolves.
+    Verify,
+
+    /// Lists every saved map along with its size, when it was last used, and
+    /// the filter files it was last used with.
+    List,
+
+    /// Deletes saved maps that are no longer needed. Needs `--older-than`,
+    /// `--missing-filters`, or both.
+    Prune {
+        /// Deletes maps that haven't been used in this many days.
+        #[structopt(long = "older-than")]
+        older_than: Option<u64>,
+
+        /// Deletes maps whose filter files or manifests no longer exist.
+        #[structopt(long = "missing-filters")]
+        missing_filters: bool,
+
+        /// Only lists the maps that would be deleted.
+        #[structopt(long = "dry-run", short = "n")]
+        dry_run: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("1970-01-01"), Some(0));
+        assert_eq!(parse_date("2000-03-01"), Some(951868800));
+        assert_eq!(parse_date("2020-02-29"), Some(1582934400));
+        assert_eq!(parse_date("@1500000000"), Some(1500000000));
+        assert_eq!(parse_date("2020-13-01"), None);
+        assert_eq!(parse_date("2020-1-1"), None);
+        assert_eq!(parse_date("HEAD~2"), None);
+        assert_eq!(parse_date("deadbeef"), None);
+    }
 }
@@ -0,0 +1,300 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Git attributes read from `.gitattributes` files.
+
+use crate::error::Error;
+use crate::glob::glob_match_path;
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// The state of an attribute for a path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AttrState {
+    /// The attribute is set, such as `text`.
+    Set,
+
+    /// The attribute is unset, such as `-text`.
+    Unset,
+
+    /// The attribute is set to a value, such as `filter=lfs`.
+    Value(String),
+
+    /// No rule says anything about the attribute, or it was reset with
+    /// `!text`.
+    Unspecified,
+}
+
+/// Parses a single attribute in `.gitattributes` syntax into its name and
+/// state.
+fn parse_attr(attr: &str) -> (&str, AttrState) {
+    if let Some(name) = attr.strip_prefix('-') {
+        (name, AttrState::Unset)
+    } else if let Some(name) = attr.strip_prefix('!') {
+        (name, AttrState::Unspecified)
+    } else if let Some((name, value)) = attr.split_once('=') {
+        (name, AttrState::Value(value.to_string()))
+    } else {
+        (attr, AttrState::Set)
+    }
+}
+
+/// A condition on an attribute, such as `export-subset` or `filter=lfs`. It
+/// uses the same syntax as a single attribute in a `.gitattributes` file.
+#[derive(Clone, Debug, Hash)]
+pub struct AttrMatch {
+    name: String,
+    state: AttrState,
+}
+
+impl AttrMatch {
+    pub fn new(attr: &str) -> Result<AttrMatch, Error> {
+        let (name, state) = parse_attr(attr);
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(Error::invalid(
+                format!("attribute '{}'", attr),
+                "expected a name without whitespace",
+            ));
+        }
+
+        Ok(AttrMatch {
+            name: name.to_string(),
+            state,
+        })
+    }
+
+    /// Returns `true` if the attribute has the expected state for the path.
+    pub fn matches(
+        &self,
+        stack: &[Rc<AttrFile>],
+        path: &str,
+        icase: bool,
+    ) -> bool {
+        lookup(stack, path, &self.name, icase) == self.state
+    }
+}
+
+/// A line of a `.gitattributes` file.
+#[derive(Debug)]
+struct AttrRule {
+    /// The components of the pattern.
+    pattern: Vec<String>,
+
+    /// If `true`, the pattern does not contain a `/` and is matched against
+    /// the name of a file at any depth.
+    basename: bool,
+
+    /// The attributes in the order they were given.
+    attrs: Vec<(String, AttrState)>,
+}
+
+impl AttrRule {
+    fn parse(line: &str) -> Option<AttrRule> {
+        let mut words = line.split_whitespace();
+        let pattern = words.next()?;
+
+        // Negative patterns are not allowed and macros can only be defined
+        // with `[attr]`, which isn't supported.
+        if pattern.starts_with('!') || pattern.starts_with("[attr]") {
+            return None;
+        }
+
+        let mut attrs = Vec::new();
+
+        for attr in words {
+            let (name, state) = parse_attr(attr);
+
+            if name == "binary" && state == AttrState::Set {
+                // The only built-in macro.
+                for name in &["diff", "merge", "text"] {
+                    attrs.push((name.to_string(), AttrState::Unset));
+                }
+            }
+
+            attrs.push((name.to_string(), state));
+        }
+
+        let basename = !pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        Some(AttrRule {
+            pattern: pattern.split('/').map(String::from).collect(),
+            basename,
+            attrs,
+        })
+    }
+
+    fn matches(&self, path: &[&str], icase: bool) -> bool {
+        if self.basename {
+            path.last().is_some_and(|name| {
+                glob_match_path(&self.pattern, &[name], icase)
+            })
+        } else {
+            glob_match_path(&self.pattern, path, icase)
+        }
+    }
+
+    /// Returns the state the rule gives the attribute, if any. If the
+    /// attribute is given more than once, the last one wins.
+    fn get(&self, name: &str) -> Option<&AttrState> {
+        self.attrs
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, state)| state)
+    }
+}
+
+/// A parsed `.gitattributes` file found in a tree.
+pub struct AttrFile {
+    /// Path of the directory containing the file. This is either empty or
+    /// ends with a `/`.
+    base: String,
+
+    /// ID of the blob the rules were read from. Two files with the same path
+    /// and blob always have the same rules.
+    id: git2::Oid,
+
+    rules: Vec<AttrRule>,
+}
+
+impl fmt::Debug for AttrFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AttrFile")
+            .field(&self.base)
+            .field(&self.id)
+            .finish()
+    }
+}
+
+impl Hash for AttrFile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.base.hash(state);
+        self.id.hash(state);
+    }
+}
+
+impl AttrFile {
+    /// Parses the contents of a `.gitattributes` file found in the directory
+    /// `base`.
+    pub fn parse(base: &str, id: git2::Oid, contents: &[u8]) -> AttrFile {
+        let rules = String::from_utf8_lossy(contents)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(AttrRule::parse)
+            .collect();
+
+        AttrFile {
+            base: base.to_string(),
+            id,
+            rules,
+        }
+    }
+}
+
+/// Looks up the state of an attribute for a file. The stack holds the
+/// `.gitattributes` files of the directories leading up to the file, starting
+/// at the root. As with git, files deeper in the tree take precedence and,
+/// within a file, later lines take precedence.
+pub fn lookup(
+    stack: &[Rc<AttrFile>],
+    path: &str,
+    name: &str,
+    icase: bool,
+) -> AttrState {
+    for file in stack.iter().rev() {
+        let path = match path.strip_prefix(file.base.as_str()) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let components: Vec<&str> = path.split('/').collect();
+
+        for rule in file.rules.iter().rev() {
+            if let Some(state) = rule.get(name) {
+                if rule.matches(&components, icase) {
+                    return state.clone();
+                }
+            }
+        }
+    }
+
+    AttrState::Unspecified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let root = AttrFile::parse(
+            "",
+            git2::Oid::zero(),
+            b"# Comment\n*.png filter=lfs -text\n/docs/** export-subset\n\
+              *.bin binary\ndocs/old.md !export-subset\n",
+        );
+        let sub =
+            AttrFile::parse("src/", git2::Oid::zero(), b"*.png -filter\n");
+        let stack = [Rc::new(root), Rc::new(sub)];
+
+        let attr = |path, name| lookup(&stack, path, name, false);
+
+        assert_eq!(attr("a/b.png", "filter"), AttrState::Value("lfs".into()));
+        assert_eq!(attr("a/b.png", "text"), AttrState::Unset);
+        assert_eq!(attr("src/b.png", "filter"), AttrState::Unset);
+        assert_eq!(attr("src/b.png", "text"), AttrState::Unset);
+        assert_eq!(attr("docs/a/b.md", "export-subset"), AttrState::Set);
+        assert_eq!(
+            attr("docs/old.md", "export-subset"),
+            AttrState::Unspecified
+        );
+        assert_eq!(
+            attr("a/docs/b.md", "export-subset"),
+            AttrState::Unspecified
+        );
+        assert_eq!(attr("x.bin", "diff"), AttrState::Unset);
+        assert_eq!(attr("x.bin", "binary"), AttrState::Set);
+    }
+
+    #[test]
+    fn test_attr_match() {
+        let stack = [Rc::new(AttrFile::parse(
+            "",
+            git2::Oid::zero(),
+            b"*.c text\n",
+        ))];
+
+        assert!(AttrMatch::new("text")
+            .unwrap()
+            .matches(&stack, "a.c", false));
+        assert!(!AttrMatch::new("-text")
+            .unwrap()
+            .matches(&stack, "a.c", false));
+        assert!(AttrMatch::new("!text")
+            .unwrap()
+            .matches(&stack, "a.h", false));
+        assert!(AttrMatch::new("=lfs").is_err());
+    }
+}
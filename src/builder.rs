@@ -0,0 +1,225 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Creating a subset of a repository from Rust code.
+//!
+//! ```no_run
+//! use git_subset::builder::SubsetBuilder;
+//!
+//! let repo = git2::Repository::open(".")?;
+//!
+//! let tip = SubsetBuilder::new()
+//!     .path("src")
+//!     .revision("main")
+//!     .branch("src-only")
+//!     .map_message(|message| message.to_ascii_uppercase())
+//!     .filter_entry(|path, _| !path.ends_with(".key"))
+//!     .on_progress(|done, total| eprintln!("{}/{}", done, total))
+//!     .run(&repo)?;
+//! # Ok::<(), git_subset::Error>(())
+//! ```
+
+use std::path::Path;
+
+use crate::commit::CommitRewrite;
+use crate::error::Error;
+use crate::filter::Filter;
+use crate::map::OidMap;
+use crate::report::Report;
+use crate::subset::{map_name, repo_subset, Revisions, Subset, Target};
+use crate::transform::BlobTransform;
+
+/// Gathers everything needed to create a subset and then creates it.
+///
+/// Unlike the command line, nothing is printed and the object map is only
+/// kept across runs if asked for with `persist_map`.
+#[derive(Default)]
+pub struct SubsetBuilder {
+    filter: Filter,
+    rewrite: CommitRewrite,
+    revisions: Vec<String>,
+    target: Option<Target>,
+    force: bool,
+    persist_map: bool,
+    progress: Option<Box<dyn FnMut(usize, usize)>>,
+}
+
+impl SubsetBuilder {
+    pub fn new() -> SubsetBuilder {
+        SubsetBuilder::default()
+    }
+
+    /// Starts from an existing filter instead of an empty one.
+    pub fn filter(mut self, filter: Filter) -> SubsetBuilder {
+        self.filter = filter;
+        self
+    }
+
+    /// Keeps the given file or directory.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> SubsetBuilder {
+        self.filter.insert(path.as_ref());
+        self
+    }
+
+    /// Starts from an existing set of changes to make to commits instead of
+    /// leaving them as they are.
+    pub fn rewrite(mut self, rewrite: CommitRewrite) -> SubsetBuilder {
+        self.rewrite = rewrite;
+        self
+    }
+
+    /// Adds a revision to rewrite, such as `main` or `v1.0..v2.0`. The branch
+    /// is created on the first one. If none are given, `HEAD` is rewritten.
+    pub fn revision(mut self, spec: &str) -> SubsetBuilder {
+        self.revisions.push(spec.to_string());
+        self
+    }
+
+    /// Creates a branch on the rewritten counterpart of the first revision.
+    pub fn branch(mut self, name: &str) -> SubsetBuilder {
+        self.target = Some(Target::Branch(name.to_string()));
+        self
+    }
+
+    /// Overwrites the branch if it already exists.
+    pub fn force(mut self) -> SubsetBuilder {
+        self.force = true;
+        self
+    }
+
+    /// Reads and writes the object map inside the repository, so that running
+    /// it again only rewrites new commits. This is ignored if any closures are
+    /// given, since they can't be told apart from one another.
+    pub fn persist_map(mut self) -> SubsetBuilder {
+        self.persist_map = true;
+        self
+    }
+
+    /// Rewrites every commit message after all other changes to it are made.
+    pub fn map_message<F>(mut self, f: F) -> SubsetBuilder
+    where
+        F: Fn(&[u8]) -> Vec<u8> + 'static,
+    {
+        self.rewrite.set_message_map(f);
+        self
+    }
+
+    /// Decides whether to keep each file after the filter is applied, given
+    /// its path and tree entry.
+    pub fn filter_entry<F>(mut self, f: F) -> SubsetBuilder
+    where
+        F: Fn(&str, &git2::TreeEntry<'_>) -> bool + 'static,
+    {
+        self.filter.set_entry_filter(f);
+        self
+    }
+
+    /// Runs the contents of every file through a transform after the built-in
+    /// ones.
+    pub fn transform<T>(mut self, transform: T) -> SubsetBuilder
+    where
+        T: BlobTransform + 'static,
+    {
+        self.filter.insert_transform(transform);
+        self
+    }
+
+    /// Calls the closure with the number of commits rewritten so far and the
+    /// total number of commits every now and then.
+    pub fn on_progress<F>(mut self, f: F) -> SubsetBuilder
+    where
+        F: FnMut(usize, usize) + 'static,
+    {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Creates the subset. Returns the new tip commit, or `None` if filtering
+    /// only produced empty commits, in which case no branch is created.
+    pub fn run(
+        mut self,
+        repo: &git2::Repository,
+    ) -> Result<Option<git2::Oid>, Error> {
+        if self.revisions.is_empty() {
+            self.revisions.push("HEAD".to_string());
+        }
+
+        let mut revisions = Revisions::parse(repo, &self.revisions, &[])?;
+
+        // Replace references written by `--replace-refs` point at commits that
+        // were already rewritten.
+        revisions
+            .grafts
+            .ignore_saved(repo)
+            .map_err(|err| Error::failed("load object map", err))?;
+
+        let name = map_name(&self.filter, &self.rewrite, &revisions.grafts);
+        let persist = self.persist_map
+            && !self.filter.has_closures()
+            && !self.rewrite.has_closures();
+
+        let map = if persist {
+            OidMap::from_repo(repo, &name)
+                .map_err(|err| Error::failed("load object map", err))?
+        } else {
+            OidMap::new()
+        };
+
+        let mut subsets = [Subset {
+            target: self.target,
+            filter: self.filter,
+            rewrite: self.rewrite,
+            map,
+            tip: None,
+            ref_prefix: None,
+            notes: None,
+            replace_refs: None,
+            reflog_message: None,
+            checkpoint: None,
+        }];
+
+        let mut progress = self.progress;
+
+        repo_subset(
+            repo,
+            &mut subsets,
+            &mut Report::new(),
+            &revisions,
+            &[],
+            self.force,
+            &mut |_, done, total| {
+                if let Some(progress) = &mut progress {
+                    progress(done, total);
+                }
+            },
+        )?;
+
+        let [subset] = subsets;
+
+        if persist {
+            subset
+                .map
+                .write_repo(repo, &name)
+                .map_err(|err| Error::failed("write object map", err))?;
+        }
+
+        Ok(subset.tip)
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Letting an external program edit commits and files, like `git filter-repo
+//! --commit-callback` but without linking a scripting language.
+//!
+//! For every rewritten commit, the program is run with a JSON object describing
+//! the commit on stdin:
+//!
+//! ```json
+//! {
+//!   "id": "<original commit>",
+//!   "tree": "<rewritten tree>",
+//!   "parents": ["<rewritten parent>", ...],
+//!   "original_parents": ["<original parent>", ...],
+//!   "author": {"name": "...", "email": "...", "time": 0, "offset": 60},
+//!   "committer": {"name": "...", "email": "...", "time": 0, "offset": 60},
+//!   "message": "...",
+//!   "changed_paths": ["src/main.rs", ...]
+//! }
+//! ```
+//!
+//! The author, committer, and message are those that would be written. The
+//! changed paths are relative to the first rewritten parent. Times are in
+//! seconds since the Unix epoch and offsets in minutes. The program prints a
+//! JSON object with the changes to make, all of which are optional:
+//!
+//! ```json
+//! {
+//!   "message": "...",
+//!   "author": {"name": "...", "email": "...", "time": 0, "offset": 0},
+//!   "committer": {"name": "...", "email": "..."},
+//!   "skip": true
+//! }
+//! ```
+//!
+//! Printing nothing leaves the commit as it is.
+//!
+//! A blob callback is given the contents of a file on stdin and prints the new
+//! contents, like a `clean` filter. Since it only sees the contents, its output
+//! is cached by the blob and the command.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use serde_json::{json, Map, Value};
+
+use crate::commit::Metadata;
+
+/// Runs a shell command for every commit to edit it.
+#[derive(Clone, Debug, Hash)]
+pub struct CommitCallback {
+    command: String,
+}
+
+fn signature_json(signature: &git2::Signature<'_>) -> Value {
+    json!({
+        "name": String::from_utf8_lossy(signature.name_bytes()),
+        "email": String::from_utf8_lossy(signature.email_bytes()),
+        "time": signature.when().seconds(),
+        "offset": signature.when().offset_minutes(),
+    })
+}
+
+/// Returns the signature edited by the JSON object. Missing fields are kept.
+fn edit_signature(
+    signature: &git2::Signature<'_>,
+    edit: &Map<String, Value>,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    let string = |key: &str, default: &[u8]| match edit.get(key) {
+        Some(Value::String(s)) => Ok(s.clone()),
+        None => Ok(String::from_utf8_lossy(default).into_owned()),
+        Some(_) => Err(invalid(&format!("'{}' must be a string", key))),
+    };
+
+    let number = |key: &str, default: i64| match edit.get(key) {
+        Some(value) => value
+            .as_i64()
+            .ok_or_else(|| invalid(&format!("'{}' must be an integer", key))),
+        None => Ok(default),
+    };
+
+    let when = signature.when();
+    let time = number("time", when.seconds())?;
+    let offset = number("offset", when.offset_minutes() as i64)?;
+
+    git2::Signature::new(
+        &string("name", signature.name_bytes())?,
+        &string("email", signature.email_bytes())?,
+        &git2::Time::new(time, offset as i32),
+    )
+}
+
+fn invalid(reason: &str) -> git2::Error {
+    git2::Error::from_str(&format!(
+        "Invalid commit callback output: {}",
+        reason
+    ))
+}
+
+impl CommitCallback {
+    pub fn new(command: &str) -> CommitCallback {
+        CommitCallback {
+            command: command.to_string(),
+        }
+    }
+
+    /// Runs the command for the commit `original`, which is rewritten with the
+    /// given tree, parents, and metadata. The metadata is edited as the command
+    /// says. Returns `false` if the commit is to be skipped.
+    pub fn call(
+        &self,
+        original: &git2::Commit<'_>,
+        tree: git2::Oid,
+        parents: &[git2::Oid],
+        changed_paths: &[String],
+        metadata: &mut Metadata,
+    ) -> Result<bool, git2::Error> {
+        let parents: Vec<_> = parents.iter().map(|p| p.to_string()).collect();
+        let original_parents: Vec<_> =
+            original.parent_ids().map(|p| p.to_string()).collect();
+
+        let input = json!({
+            "id": original.id().to_string(),
+            "tree": tree.to_string(),
+            "parents": parents,
+            "original_parents": original_parents,
+            "author": signature_json(&metadata.author),
+            "committer": signature_json(&metadata.committer),
+            "message": String::from_utf8_lossy(&metadata.message),
+            "changed_paths": changed_paths,
+        });
+
+        let output = run(&self.command, input.to_string().as_bytes()).map_err(
+            |err| {
+                git2::Error::from_str(&format!(
+                    "Commit callback failed for {}: {}",
+                    original.id(),
+                    err.message()
+                ))
+            },
+        )?;
+
+        if output.iter().all(u8::is_ascii_whitespace) {
+            return Ok(true);
+        }
+
+        let output: Value = serde_json::from_slice(&output)
+            .map_err(|err| invalid(&err.to_string()))?;
+
+        let output = output
+            .as_object()
+            .ok_or_else(|| invalid("expected an object"))?;
+
+        if let Some(skip) = output.get("skip") {
+            if skip
+                .as_bool()
+                .ok_or_else(|| invalid("'skip' must be a bool"))?
+            {
+                return Ok(false);
+            }
+        }
+
+        match output.get("message") {
+            Some(Value::String(message)) => {
+                metadata.message = message.clone().into_bytes();
+            }
+            Some(_) => return Err(invalid("'message' must be a string")),
+            None => {}
+        }
+
+        for (key, signature) in [
+            ("author", &mut metadata.author),
+            ("committer", &mut metadata.committer),
+        ] {
+            match output.get(key) {
+                Some(Value::Object(edit)) => {
+                    *signature = edit_signature(signature, edit)?;
+                }
+                Some(_) => {
+                    return Err(invalid(&format!(
+                        "'{}' must be an object",
+                        key
+                    )))
+                }
+                None => {}
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Runs a shell command for every file to rewrite its contents.
+#[derive(Clone, Debug, Hash)]
+pub struct BlobCallback {
+    command: String,
+}
+
+impl BlobCallback {
+    pub fn new(command: &str) -> BlobCallback {
+        BlobCallback {
+            command: command.to_string(),
+        }
+    }
+
+    /// Returns the command. The output for a blob only depends on this.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Runs the command for the blob `id` with the given contents and returns
+    /// the new contents.
+    pub fn call(
+        &self,
+        id: git2::Oid,
+        contents: &[u8],
+    ) -> Result<Vec<u8>, git2::Error> {
+        run(&self.command, contents).map_err(|err| {
+            git2::Error::from_str(&format!(
+                "Blob callback failed for {}: {}",
+                id,
+                err.message()
+            ))
+        })
+    }
+}
+
+/// Runs the command with the shell, passing it the input on stdin, and returns
+/// what it prints.
+fn run(command_line: &str, input: &[u8]) -> Result<Vec<u8>, git2::Error> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+
+    let error = |err: std::io::Error| {
+        git2::Error::from_str(&format!(
+            "Failed to run '{}': {}",
+            command_line, err
+        ))
+    };
+
+    let mut child = command
+        .arg(command_line)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(error)?;
+
+    // Write the input on another thread so that a command that prints as it
+    // reads can't fill up its stdout and block forever. It may not read all of
+    // its input, which is fine.
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(&input);
+    });
+
+    let output = child.wait_with_output().map_err(error)?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return Err(git2::Error::from_str(&format!(
+            "'{}' exited with {}",
+            command_line, output.status
+        )));
+    }
+
+    Ok(output.stdout)
+}
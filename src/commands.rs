@@ -0,0 +1,363 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The subcommands of `git-subset` other than creating subsets, most of which
+//! create subsets along the way.
+
+use std::fs;
+use std::io::{self, BufRead};
+use std::net::TcpListener;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crate::args::{CloneArgs, MapArgs, MapCommand, MirrorArgs, ServeArgs};
+use crate::interrupt;
+use crate::manifest::read_manifest;
+use crate::map::{saved_maps, OidMap, SavedMap};
+use crate::mapfile;
+use crate::pack::repack;
+use crate::remote;
+use crate::run::run;
+use crate::serve::Server;
+use crate::Error;
+
+/// Clones a repository and creates the subsets asked for in the clone.
+pub fn clone(args: &mut CloneArgs) -> Result<(), Error> {
+    if !args.args.quiet {
+        println!("Cloning into '{}'...", args.dir.display());
+    }
+
+    let config = git2::Config::open_default()
+        .map_err(|err| Error::failed("open config", err))?;
+
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(remote::callbacks(config));
+
+    let repo = git2::build::RepoBuilder::new()
+        .bare(args.bare)
+        .fetch_options(options)
+        .clone(&args.url, &args.dir)
+        .map_err(|err| Error::failed(format!("clone '{}'", args.url), err))?;
+
+    args.args.repo = args.dir.clone();
+
+    run(&args.args)?;
+
+    if !args.no_repack {
+        let count =
+            repack(&repo).map_err(|err| Error::failed("repack", err))?;
+
+        if !args.args.quiet {
+            println!("Packed {} objects.", count);
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps a filtered mirror of an upstream branch up to date, running a cycle
+/// just once or every so often. Each cycle fetches upstream, rewrites what is
+/// new, and pushes the result. The tips of the last cycle are recorded in the
+/// object map, so only the commits since then are walked, and a map for
+/// another filter starts over from scratch.
+pub fn mirror(args: &mut MirrorArgs) -> Result<(), Error> {
+    if args.args.fetch.is_none() {
+        return Err(Error::Other(
+            "`git-subset mirror` needs `--fetch <remote>`.".into(),
+        ));
+    }
+
+    if args.args.branch.is_none() {
+        return Err(Error::Other(
+            "`git-subset mirror` needs `--branch <name>`.".into(),
+        ));
+    }
+
+    // The branch moves along with upstream, even if it is rewritten.
+    args.args.force = true;
+
+    loop {
+        let result = run(&args.args);
+
+        match args.interval {
+            None => return result,
+            Some(interval) => {
+                // Failures, such as the network being down, are retried in
+                // the next cycle.
+                match result {
+                    Err(Error::Interrupted) => return result,
+                    Err(err) => println!("Error: {}", err),
+                    Ok(()) => {}
+                }
+
+                // Sleeps a second at a time so that an interrupt isn't left
+                // waiting.
+                for _ in 0..interval {
+                    if interrupt::requested() {
+                        return Err(Error::Interrupted);
+                    }
+
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+}
+
+/// Serves the subsets over Git's smart HTTP protocol, creating or updating a
+/// subset whenever its references are listed.
+pub fn serve(args: &mut ServeArgs) -> Result<(), Error> {
+    let mut names: Vec<String> = args
+        .args
+        .branch
+        .iter()
+        .chain(&args.args.branch_excluded)
+        .cloned()
+        .collect();
+
+    if let Some(path) = &args.args.manifest {
+        let manifest = read_manifest(path).map_err(|err| {
+            Error::failed(format!("load manifest '{}'", path.display()), err)
+        })?;
+
+        names.extend(manifest.into_iter().map(|(branch, _)| branch));
+    }
+
+    if names.is_empty() {
+        return Err(Error::Other(
+            "`git-subset serve` needs `--branch` or `--manifest`.".into(),
+        ));
+    }
+
+    // The subsets move along with the revisions they are made from.
+    args.args.force = true;
+    args.args.quiet = true;
+
+    let repo = git2::Repository::open(&args.args.repo)
+        .map_err(|err| Error::failed("open repository", err))?;
+
+    let listener = TcpListener::bind(&args.listen).map_err(|err| {
+        Error::failed(format!("listen on '{}'", args.listen), err)
+    })?;
+
+    let addr = listener.local_addr()?;
+
+    for name in &names {
+        println!("Serving '{}' at http://{}/{}", name, addr, name);
+    }
+
+    let args = &args.args;
+
+    let mut server = Server::new(&repo, |name: &str| {
+        if !names.iter().any(|served| served == name) {
+            return Ok(None);
+        }
+
+        run(args)?;
+
+        Ok(Some(repo.refname_to_id(&format!("refs/heads/{}", name))?))
+    });
+
+    server
+        .serve(&listener)
+        .map_err(|err| Error::failed("serve", err))
+}
+
+/// Runs one of the `git-subset map` commands on a saved object map.
+pub fn map(args: &MapArgs) -> Result<(), Error> {
+    let repo = git2::Repository::open(&args.repo)
+        .map_err(|err| Error::failed("open repository", err))?;
+
+    match &args.command {
+        MapCommand::List => return list_maps(&repo),
+        MapCommand::Prune {
+            older_than,
+            missing_filters,
+            dry_run,
+        } => return prune_maps(&repo, *older_than, *missing_filters, *dry_run),
+        _ => {}
+    }
+
+    let name = match &args.map {
+        Some(name) => name.clone(),
+        None => {
+            let names: Vec<_> = saved_maps(&repo)
+                .map_err(|err| Error::failed("list object maps", err))?
+                .into_iter()
+                .map(|map| map.name)
+                .collect();
+
+            match names.as_slice() {
+                [name] => name.clone(),
+                [] => {
+                    return Err(Error::Other(
+                        "The repository has no saved object maps.".into(),
+                    ))
+                }
+                _ => {
+                    return Err(Error::Other(format!(
+                        "The repository has several object maps, pick one \
+                         with `--map`: {}",
+                        names.join(", ")
+                    )))
+                }
+            }
+        }
+    };
+
+    let mut oidmap =
+        OidMap::open(&repo, &name, args.map_backend).map_err(|err| {
+            Error::failed(format!("open object map '{}'", name), err)
+        })?;
+
+    match &args.command {
+        MapCommand::Export { format, output } => match output {
+            Some(path) => {
+                let f = fs::File::create(path).map_err(|err| {
+                    Error::failed(format!("create '{}'", path.display()), err)
+                })?;
+                mapfile::export(
+                    &repo,
+                    &oidmap,
+                    *format,
+                    io::BufWriter::new(f),
+                )?;
+            }
+            None => {
+                mapfile::export(&repo, &oidmap, *format, io::stdout().lock())?;
+            }
+        },
+        MapCommand::Import { format, input } => {
+            let reader: Box<dyn BufRead> = if input == Path::new("-") {
+                Box::new(io::stdin().lock())
+            } else {
+                let f = fs::File::open(input).map_err(|err| {
+                    Error::failed(format!("open '{}'", input.display()), err)
+                })?;
+                Box::new(io::BufReader::new(f))
+            };
+
+            let count = mapfile::import(&mut oidmap, *format, reader)?;
+
+            oidmap.write_repo(&repo, &name).map_err(|err| {
+                Error::failed(format!("write object map '{}'", name), err)
+            })?;
+
+            println!("Imported {} commits into object map '{}'.", count, name);
+        }
+        MapCommand::Verify => {
+            let problems = oidmap.verify(&repo.odb()?);
+
+            for problem in &problems {
+                println!("{}", problem);
+            }
+
+            if !problems.is_empty() {
+                return Err(Error::Other(format!(
+                    "The object map '{}' has {} problem(s). Remove it or use \
+                     `--nomap` to start over.",
+                    name,
+                    problems.len()
+                )));
+            }
+
+            println!("Object map '{}' is fine.", name);
+        }
+        MapCommand::List | MapCommand::Prune { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Returns how many whole days ago a map was last used.
+fn days_unused(map: &SavedMap) -> u64 {
+    map.modified
+        .elapsed()
+        .map_or(0, |elapsed| elapsed.as_secs() / (24 * 60 * 60))
+}
+
+/// Prints a line about a saved map.
+fn print_map(map: &SavedMap) {
+    print!(
+        "{}  {:>10}  {:>4} days ago",
+        map.name,
+        map.size,
+        days_unused(map)
+    );
+
+    for source in &map.sources {
+        print!("  {}", source.display());
+    }
+
+    println!();
+}
+
+/// Lists the maps saved in a repository.
+fn list_maps(repo: &git2::Repository) -> Result<(), Error> {
+    let maps = saved_maps(repo)
+        .map_err(|err| Error::failed("list object maps", err))?;
+
+    for map in &maps {
+        print_map(map);
+    }
+
+    Ok(())
+}
+
+/// Deletes the maps saved in a repository that haven't been used recently or
+/// whose filter files are gone.
+fn prune_maps(
+    repo: &git2::Repository,
+    older_than: Option<u64>,
+    missing_filters: bool,
+    dry_run: bool,
+) -> Result<(), Error> {
+    if older_than.is_none() && !missing_filters {
+        return Err(Error::Other(
+            "`git-subset map prune` needs `--older-than <days>` or \
+             `--missing-filters`."
+                .into(),
+        ));
+    }
+
+    let maps = saved_maps(repo)
+        .map_err(|err| Error::failed("list object maps", err))?;
+
+    for map in &maps {
+        let old = older_than.is_some_and(|days| days_unused(map) >= days);
+        let orphaned =
+            missing_filters && map.sources.iter().any(|path| !path.exists());
+
+        if !old && !orphaned {
+            continue;
+        }
+
+        print_map(map);
+
+        if !dry_run {
+            map.remove().map_err(|err| {
+                Error::failed(format!("delete object map '{}'", map.name), err)
+            })?;
+        }
+    }
+
+    Ok(())
+}
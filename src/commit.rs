@@ -0,0 +1,1170 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting the metadata of commits, such as their authors and messages.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::anonymize::{self, Seed};
+use crate::callback::CommitCallback;
+use crate::lua::LuaScript;
+use crate::plugin::Plugin;
+use crate::replace::Replacements;
+use crate::sign::Signer;
+
+use regex::bytes::{Captures, Regex};
+
+/// Which commits to prune, such as commits that are empty, meaning that their
+/// trees are the same as those of their parents.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq)]
+pub enum Prune {
+    /// Prunes all of them.
+    #[default]
+    Always,
+
+    /// Only prunes those that became so due to filtering. Commits that already
+    /// were are kept.
+    Auto,
+
+    /// Keeps all of them.
+    Never,
+}
+
+impl FromStr for Prune {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Prune, String> {
+        match s {
+            "always" => Ok(Prune::Always),
+            "auto" => Ok(Prune::Auto),
+            "never" => Ok(Prune::Never),
+            _ => Err(format!(
+                "Expected 'always', 'auto', or 'never' but got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Where history is cut off to be squashed into a single root commit.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+pub enum Cutoff {
+    /// This commit and all of its ancestors.
+    Commit(git2::Oid),
+
+    /// Commits made before this time, in seconds since the Unix epoch.
+    Time(i64),
+}
+
+/// The author, committer, and message of a rewritten commit.
+pub struct Metadata {
+    pub author: git2::Signature<'static>,
+    pub committer: git2::Signature<'static>,
+    pub message: Vec<u8>,
+}
+
+/// Changes to make to each commit besides rewriting its tree.
+///
+/// Since these change the rewritten commits, they are hashed along with the
+/// filter to name the map. Nothing is hashed for changes that aren't made so
+/// that existing maps stay valid.
+#[derive(Default)]
+pub struct CommitRewrite {
+    /// The contents of the mailmap along with the parsed mailmap.
+    mailmap: Option<(String, git2::Mailmap)>,
+
+    /// Replacements to make in commit messages.
+    messages: Replacements,
+
+    /// Trailers to add to commit messages, such as `Signed-off-by`.
+    trailers: Vec<(String, String)>,
+
+    /// Key of a trailer recording the original commit, if any.
+    original_oid: Option<String>,
+
+    /// Matches what looks like commit hashes in commit messages, if they are
+    /// to be rewritten.
+    hashes: Option<Regex>,
+
+    signer: Option<Signer>,
+
+    /// The name and email address to give every author, if any.
+    author: Option<(String, String)>,
+
+    /// The name and email address to give every committer, if any.
+    committer: Option<(String, String)>,
+
+    /// Makes the committer date the same as the author date.
+    committer_date_is_author_date: bool,
+
+    /// Sets both dates to this time.
+    reset_dates: Option<git2::Time>,
+
+    prune_empty: Prune,
+
+    /// Which merges to collapse for being degenerate.
+    prune_degenerate: Prune,
+
+    /// Keeps every merge, no matter if it is empty or degenerate.
+    keep_merges: bool,
+
+    /// Only keeps the first parent of every commit.
+    first_parent: bool,
+
+    /// Makes every commit a child of the one rewritten before it.
+    linearize: bool,
+
+    /// History to squash into a single root commit, if any.
+    squash_before: Option<Cutoff>,
+
+    /// Commit to graft the rewritten commits onto, if any.
+    graft_onto: Option<git2::Oid>,
+
+    /// Commits whose author or committer matches any of these are dropped.
+    drop_by: Vec<Regex>,
+
+    /// Commits whose message matches any of these are dropped.
+    drop_messages: Vec<Regex>,
+
+    /// Commits that are kept even if they end up empty.
+    keep_commits: BTreeSet<git2::Oid>,
+
+    /// Only commits made at or after this time are rewritten, if any.
+    since: Option<i64>,
+
+    /// Only commits made at or before this time are rewritten, if any.
+    until: Option<i64>,
+
+    /// Leaves out merge commits.
+    no_merges: bool,
+
+    /// Leaves out commits that aren't merges.
+    merges_only: bool,
+
+    /// Command that edits each commit, if any.
+    callback: Option<CommitCallback>,
+
+    /// Plugin that rewrites commit messages, if any.
+    plugin: Option<Rc<Plugin>>,
+
+    /// Lua script that edits each commit, if any.
+    lua: Option<Rc<LuaScript>>,
+
+    /// Closure that rewrites each commit message, if any.
+    message_map: Option<MessageMap>,
+
+    /// Scrambles every author, committer, and message after all other changes
+    /// to them are made.
+    anonymize: bool,
+
+    /// Salts the tokens that scrambled names and messages are replaced with.
+    anonymize_seed: Seed,
+}
+
+/// A closure that is given a commit message and returns the new one.
+#[derive(Clone)]
+pub struct MessageMap(Rc<MessageFn>);
+
+type MessageFn = dyn Fn(&[u8]) -> Vec<u8>;
+
+impl fmt::Debug for MessageMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessageMap")
+    }
+}
+
+impl Clone for CommitRewrite {
+    fn clone(&self) -> CommitRewrite {
+        // `git2::Mailmap` can't be cloned, but parsing it again can't fail
+        // either.
+        CommitRewrite {
+            mailmap: self.mailmap.as_ref().map(|(contents, _)| {
+                (
+                    contents.clone(),
+                    git2::Mailmap::from_buffer(contents).unwrap(),
+                )
+            }),
+            messages: self.messages.clone(),
+            trailers: self.trailers.clone(),
+            original_oid: self.original_oid.clone(),
+            hashes: self.hashes.clone(),
+            signer: self.signer.clone(),
+            author: self.author.clone(),
+            committer: self.committer.clone(),
+            committer_date_is_author_date: self.committer_date_is_author_date,
+            reset_dates: self.reset_dates,
+            prune_empty: self.prune_empty,
+            prune_degenerate: self.prune_degenerate,
+            keep_merges: self.keep_merges,
+            first_parent: self.first_parent,
+            linearize: self.linearize,
+            squash_before: self.squash_before,
+            graft_onto: self.graft_onto,
+            drop_by: self.drop_by.clone(),
+            drop_messages: self.drop_messages.clone(),
+            keep_commits: self.keep_commits.clone(),
+            since: self.since,
+            until: self.until,
+            no_merges: self.no_merges,
+            merges_only: self.merges_only,
+            callback: self.callback.clone(),
+            plugin: self.plugin.clone(),
+            lua: self.lua.clone(),
+            message_map: self.message_map.clone(),
+            anonymize: self.anonymize,
+            anonymize_seed: self.anonymize_seed,
+        }
+    }
+}
+
+impl Hash for CommitRewrite {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Some((contents, _)) = &self.mailmap {
+            contents.hash(state);
+        }
+
+        if !self.messages.is_empty() {
+            self.messages.hash(state);
+        }
+
+        if !self.trailers.is_empty() {
+            self.trailers.hash(state);
+        }
+
+        if let Some(key) = &self.original_oid {
+            key.hash(state);
+        }
+
+        if self.hashes.is_some() {
+            "rewrite-hashes".hash(state);
+        }
+
+        if let Some(signer) = &self.signer {
+            signer.hash(state);
+        }
+
+        if let Some(author) = &self.author {
+            ("author", author).hash(state);
+        }
+
+        if let Some(committer) = &self.committer {
+            ("committer", committer).hash(state);
+        }
+
+        if self.committer_date_is_author_date {
+            "committer-date-is-author-date".hash(state);
+        }
+
+        if let Some(time) = &self.reset_dates {
+            time.seconds().hash(state);
+            time.offset_minutes().hash(state);
+        }
+
+        if self.prune_empty != Prune::default() {
+            self.prune_empty.hash(state);
+        }
+
+        // Degenerate merges used to be kept.
+        if self.prune_degenerate != Prune::Never {
+            ("prune-degenerate", self.prune_degenerate).hash(state);
+        }
+
+        if self.keep_merges {
+            "keep-merges".hash(state);
+        }
+
+        if self.first_parent {
+            "first-parent".hash(state);
+        }
+
+        if self.linearize {
+            "linearize".hash(state);
+        }
+
+        if let Some(cutoff) = &self.squash_before {
+            cutoff.hash(state);
+        }
+
+        if let Some(graft) = &self.graft_onto {
+            ("graft-onto", graft).hash(state);
+        }
+
+        for regex in &self.drop_by {
+            ("drop-commits-by", regex.as_str()).hash(state);
+        }
+
+        for regex in &self.drop_messages {
+            ("drop-message-regex", regex.as_str()).hash(state);
+        }
+
+        if !self.keep_commits.is_empty() {
+            ("keep-commits", &self.keep_commits).hash(state);
+        }
+
+        if let Some(since) = self.since {
+            ("since", since).hash(state);
+        }
+
+        if let Some(until) = self.until {
+            ("until", until).hash(state);
+        }
+
+        if self.no_merges {
+            "no-merges".hash(state);
+        }
+
+        if self.merges_only {
+            "merges-only".hash(state);
+        }
+
+        if let Some(callback) = &self.callback {
+            callback.hash(state);
+        }
+
+        if let Some(plugin) = &self.plugin {
+            plugin.hash(state);
+        }
+
+        if let Some(lua) = &self.lua {
+            lua.hash(state);
+        }
+
+        // Closures can't be hashed. Maps of rewrites with them must not be
+        // reused.
+        if self.message_map.is_some() {
+            "message-map".hash(state);
+        }
+
+        if self.anonymize {
+            "anonymize".hash(state);
+        }
+    }
+}
+
+impl CommitRewrite {
+    pub fn new() -> CommitRewrite {
+        CommitRewrite {
+            prune_degenerate: Prune::Auto,
+            ..CommitRewrite::default()
+        }
+    }
+
+    /// Canonicalizes the names and email addresses of authors and committers
+    /// with the given mailmap, in the same format as `.mailmap` files.
+    pub fn set_mailmap(&mut self, contents: &str) -> Result<(), git2::Error> {
+        let mailmap = git2::Mailmap::from_buffer(contents)?;
+        self.mailmap = Some((contents.to_string(), mailmap));
+        Ok(())
+    }
+
+    /// Replaces text in commit messages.
+    pub fn set_message_replacements(&mut self, replacements: Replacements) {
+        self.messages = replacements;
+    }
+
+    /// Appends a trailer with the given key and the original commit hash to
+    /// every commit message, such as `original-oid: <hash>`.
+    pub fn set_original_oid_trailer(&mut self, key: &str) {
+        self.original_oid = Some(key.to_string());
+    }
+
+    /// Appends a trailer to every commit message, unless the message already
+    /// has it.
+    pub fn add_trailer(&mut self, key: &str, value: &str) {
+        self.trailers.push((key.to_string(), value.to_string()));
+    }
+
+    /// Rewrites commit hashes found in commit messages, such as in "This
+    /// reverts commit abc1234.", to refer to the rewritten commits instead.
+    pub fn set_rewrite_hashes(&mut self) {
+        self.hashes = Some(Regex::new(r"\b[0-9a-f]{7,40}\b").unwrap());
+    }
+
+    /// Returns the rewritten message of the commit `id`. If commit hashes are
+    /// to be rewritten, `lookup` is called with each one to get its
+    /// replacement. It returns `None` if it isn't actually a commit hash.
+    pub fn message<'a, F>(
+        &self,
+        id: git2::Oid,
+        message: &'a [u8],
+        mut lookup: F,
+    ) -> Cow<'a, [u8]>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut message = self.messages.apply(message);
+
+        if let Some(hashes) = &self.hashes {
+            let replaced =
+                hashes.replace_all(&message, |caps: &Captures<'_>| {
+                    // The regex only matches ASCII.
+                    let hash = std::str::from_utf8(&caps[0]).unwrap();
+                    lookup(hash).unwrap_or_else(|| hash.to_string())
+                });
+
+            if let Cow::Owned(replaced) = replaced {
+                message = Cow::Owned(replaced);
+            }
+        }
+
+        for (key, value) in &self.trailers {
+            if !has_trailer(&message, key, value) {
+                message = Cow::Owned(append_trailer(&message, key, value));
+            }
+        }
+
+        if let Some(key) = &self.original_oid {
+            message =
+                Cow::Owned(append_trailer(&message, key, &id.to_string()));
+        }
+
+        message
+    }
+
+    /// Returns `true` if the authors, committers, or messages of commits may
+    /// change. Otherwise, a commit with the same tree and parents is the same
+    /// commit.
+    pub fn changes_metadata(&self) -> bool {
+        self.mailmap.is_some()
+            || !self.messages.is_empty()
+            || !self.trailers.is_empty()
+            || self.original_oid.is_some()
+            || self.hashes.is_some()
+            || self.author.is_some()
+            || self.committer.is_some()
+            || self.committer_date_is_author_date
+            || self.reset_dates.is_some()
+            || self.callback.is_some()
+            || self.plugin.is_some()
+            || self.lua.is_some()
+            || self.message_map.is_some()
+            || self.anonymize
+    }
+
+    /// Command that edits each commit, if any.
+    pub fn callback(&self) -> Option<&CommitCallback> {
+        self.callback.as_ref()
+    }
+
+    pub fn set_callback(&mut self, callback: CommitCallback) {
+        self.callback = Some(callback);
+    }
+
+    /// Lets a plugin rewrite every commit message after all other changes to
+    /// it are made. Plugins that don't rewrite messages are ignored.
+    pub fn set_plugin(&mut self, plugin: Rc<Plugin>) {
+        if plugin.rewrites_messages() {
+            self.plugin = Some(plugin);
+        }
+    }
+
+    /// Lua script that edits each commit, if any.
+    pub fn lua(&self) -> Option<&LuaScript> {
+        self.lua.as_deref()
+    }
+
+    /// Lets a Lua script edit every commit. Scripts without `on_commit` are
+    /// ignored.
+    pub fn set_lua(&mut self, lua: Rc<LuaScript>) {
+        if lua.has_on_commit() {
+            self.lua = Some(lua);
+        }
+    }
+
+    /// Lets a closure rewrite every commit message after all other changes to
+    /// it are made, including those of a plugin.
+    pub fn set_message_map<F>(&mut self, f: F)
+    where
+        F: Fn(&[u8]) -> Vec<u8> + 'static,
+    {
+        self.message_map = Some(MessageMap(Rc::new(f)));
+    }
+
+    /// Returns `true` if a closure has been given, in which case the rewrite
+    /// can't be told apart from others by its hash.
+    pub fn has_closures(&self) -> bool {
+        self.message_map.is_some()
+    }
+
+    /// Scrambles every author, committer, and message, like `git fast-export
+    /// --anonymize`. Dates are kept.
+    pub fn anonymize(&self) -> bool {
+        self.anonymize
+    }
+
+    pub fn set_anonymize(&mut self) {
+        self.anonymize = true;
+    }
+
+    /// Sets the random seed that scrambled names and messages are derived
+    /// from, such as the one kept in the object map. It isn't part of the
+    /// hash of the rewrite.
+    pub fn set_anonymize_seed(&mut self, seed: Seed) {
+        self.anonymize_seed = seed;
+    }
+
+    /// Replaces the author of every commit, such as to hide individual
+    /// identities behind a bot account.
+    pub fn set_author(&mut self, name: &str, email: &str) {
+        self.author = Some((name.to_string(), email.to_string()));
+    }
+
+    /// Replaces the committer of every commit.
+    pub fn set_committer(&mut self, name: &str, email: &str) {
+        self.committer = Some((name.to_string(), email.to_string()));
+    }
+
+    /// Which empty commits to leave out.
+    pub fn prune_empty(&self) -> Prune {
+        self.prune_empty
+    }
+
+    pub fn set_prune_empty(&mut self, prune_empty: Prune) {
+        self.prune_empty = prune_empty;
+    }
+
+    /// Which degenerate merges to collapse. A merge is degenerate if some of
+    /// its parents are the same or are ancestors of its other parents.
+    pub fn prune_degenerate(&self) -> Prune {
+        self.prune_degenerate
+    }
+
+    pub fn set_prune_degenerate(&mut self, prune_degenerate: Prune) {
+        self.prune_degenerate = prune_degenerate;
+    }
+
+    /// Whether to keep every merge with all of its parents, even if it is
+    /// empty or degenerate. This takes precedence over `prune_empty` and
+    /// `prune_degenerate`.
+    pub fn keep_merges(&self) -> bool {
+        self.keep_merges
+    }
+
+    pub fn set_keep_merges(&mut self) {
+        self.keep_merges = true;
+    }
+
+    /// Whether to only keep the first parent of every commit, turning merges
+    /// into regular commits.
+    pub fn first_parent(&self) -> bool {
+        self.first_parent
+    }
+
+    pub fn set_first_parent(&mut self) {
+        self.first_parent = true;
+    }
+
+    /// Whether to rewrite the commits into a single chain in the order they
+    /// are rewritten, giving every commit (besides the first) one parent.
+    pub fn linearize(&self) -> bool {
+        self.linearize
+    }
+
+    pub fn set_linearize(&mut self) {
+        self.linearize = true;
+    }
+
+    /// History to squash into a single root commit, if any.
+    pub fn squash_before(&self) -> Option<Cutoff> {
+        self.squash_before
+    }
+
+    pub fn set_squash_before(&mut self, cutoff: Cutoff) {
+        self.squash_before = Some(cutoff);
+    }
+
+    /// Commit to use as the parent in place of parents that weren't rewritten,
+    /// such as those outside of a range, if any.
+    pub fn graft_onto(&self) -> Option<git2::Oid> {
+        self.graft_onto
+    }
+
+    pub fn set_graft_onto(&mut self, graft: git2::Oid) {
+        self.graft_onto = Some(graft);
+    }
+
+    /// Only rewrites commits made at or after this time, in seconds since the
+    /// Unix epoch. Older commits are left out as if they were outside of a
+    /// range.
+    pub fn since(&self) -> Option<i64> {
+        self.since
+    }
+
+    pub fn set_since(&mut self, time: i64) {
+        self.since = Some(time);
+    }
+
+    /// Only rewrites commits made at or before this time, in seconds since the
+    /// Unix epoch. Newer commits are rewritten to the newest commit before
+    /// them that is in the window.
+    pub fn until(&self) -> Option<i64> {
+        self.until
+    }
+
+    pub fn set_until(&mut self, time: i64) {
+        self.until = Some(time);
+    }
+
+    /// Whether to leave out merge commits. Their changes are folded into the
+    /// commits that follow them, as with dropped commits, and the branches
+    /// they merged are no longer reachable through them.
+    pub fn no_merges(&self) -> bool {
+        self.no_merges
+    }
+
+    pub fn set_no_merges(&mut self) {
+        self.no_merges = true;
+    }
+
+    /// Drops all commits that aren't merges, keeping only the merges.
+    pub fn set_merges_only(&mut self) {
+        self.merges_only = true;
+    }
+
+    /// Drops commits whose author or committer, formatted as `Name <email>`,
+    /// matches the regular expression.
+    pub fn add_drop_commits_by(&mut self, regex: Regex) {
+        self.drop_by.push(regex);
+    }
+
+    /// Drops commits whose message matches the regular expression.
+    pub fn add_drop_message_regex(&mut self, regex: Regex) {
+        self.drop_messages.push(regex);
+    }
+
+    /// Keeps the commit even if it ends up empty.
+    pub fn add_keep_commit(&mut self, commit: git2::Oid) {
+        self.keep_commits.insert(commit);
+    }
+
+    /// Returns `true` if the commit is kept even if it ends up empty.
+    pub fn keeps(&self, commit: git2::Oid) -> bool {
+        self.keep_commits.contains(&commit)
+    }
+
+    /// Returns `true` if the commit is to be dropped from the rewritten
+    /// history. Its changes are kept, since the commits that follow it still
+    /// have them.
+    pub fn drops(&self, commit: &git2::Commit<'_>) -> bool {
+        let ident = |signature: git2::Signature<'_>| {
+            let mut ident = signature.name_bytes().to_vec();
+            ident.extend_from_slice(b" <");
+            ident.extend_from_slice(signature.email_bytes());
+            ident.push(b'>');
+            ident
+        };
+
+        let author = ident(commit.author());
+        let committer = ident(commit.committer());
+
+        let message = commit.message_raw_bytes();
+
+        (self.merges_only && commit.parent_count() < 2)
+            || self.drop_by.iter().any(|regex| {
+                regex.is_match(&author) || regex.is_match(&committer)
+            })
+            || self
+                .drop_messages
+                .iter()
+                .any(|regex| regex.is_match(message))
+    }
+
+    /// Returns `true` if the commit is squashed, meaning that it is older than
+    /// the cutoff.
+    pub fn squashes(
+        &self,
+        repo: &git2::Repository,
+        commit: &git2::Commit<'_>,
+    ) -> Result<bool, git2::Error> {
+        Ok(match self.squash_before {
+            Some(Cutoff::Commit(cutoff)) => {
+                commit.id() == cutoff
+                    || repo.graph_descendant_of(cutoff, commit.id())?
+            }
+            Some(Cutoff::Time(time)) => {
+                commit.committer().when().seconds() < time
+            }
+            None => false,
+        })
+    }
+
+    /// Makes the committer date of every commit the same as its author date.
+    pub fn set_committer_date_is_author_date(&mut self) {
+        self.committer_date_is_author_date = true;
+    }
+
+    /// Sets the author and committer dates of every commit to the given time,
+    /// such as for reproducible exports.
+    pub fn set_reset_dates(&mut self, time: git2::Time) {
+        self.reset_dates = Some(time);
+    }
+
+    /// Signs every commit that changed.
+    pub fn set_signer(&mut self, signer: Signer) {
+        self.signer = Some(signer);
+    }
+
+    /// Writes the rewritten counterpart of the commit `original` with the given
+    /// tree, parents, and metadata (see `metadata`).
+    ///
+    /// Unlike `Repository::commit`, the message is written byte for byte, even
+    /// if it isn't valid UTF-8, and other headers are kept (see
+    /// `commit_header`). If nothing changed, this writes the original commit
+    /// again.
+    pub fn write(
+        &self,
+        repo: &git2::Repository,
+        original: &git2::Commit<'_>,
+        tree: git2::Oid,
+        parents: &[git2::Oid],
+        metadata: &Metadata,
+    ) -> Result<git2::Oid, git2::Error> {
+        let Metadata {
+            author,
+            committer,
+            message,
+        } = metadata;
+
+        let (header, changed) =
+            commit_header(original, tree, parents, author, committer, message);
+
+        let mut data = header.clone();
+        data.push(b'\n');
+        data.extend_from_slice(message);
+
+        if let (true, Some(signer)) = (changed, &self.signer) {
+            // The signature covers the commit without the signature header,
+            // which is added at the end of the header.
+            let signature = signer.sign(&data)?;
+            let signature = String::from_utf8_lossy(&signature);
+
+            data = header;
+            data.extend_from_slice(b"gpgsig");
+
+            for line in signature.trim_end().split('\n') {
+                data.push(b' ');
+                data.extend_from_slice(line.as_bytes());
+                data.push(b'\n');
+            }
+
+            data.push(b'\n');
+            data.extend_from_slice(message);
+        }
+
+        repo.odb()?.write(git2::ObjectType::Commit, &data)
+    }
+
+    /// Returns the author, committer, and message of the rewritten counterpart
+    /// of the commit `original`. See `message` for what `lookup` is for.
+    pub fn metadata<F>(
+        &self,
+        original: &git2::Commit<'_>,
+        lookup: F,
+    ) -> Result<Metadata, git2::Error>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let (author, committer) = self.signatures(original)?;
+        let mut message = self
+            .message(original.id(), original.message_raw_bytes(), lookup)
+            .into_owned();
+
+        if let Some(plugin) = &self.plugin {
+            if let Some(rewritten) = plugin.rewrite_message(&message)? {
+                message = rewritten;
+            }
+        }
+
+        if let Some(MessageMap(f)) = &self.message_map {
+            message = f(&message);
+        }
+
+        if self.anonymize {
+            message = anonymize::message(self.anonymize_seed, &message);
+        }
+
+        Ok(Metadata {
+            author,
+            committer,
+            message,
+        })
+    }
+
+    /// Writes the root commit that takes the place of `original` and all of
+    /// its ancestors when squashing history. It has the given tree, which is
+    /// the rewritten tree of `original`, and a message saying what it replaces.
+    pub fn write_squashed(
+        &self,
+        repo: &git2::Repository,
+        original: &git2::Commit<'_>,
+        tree: git2::Oid,
+    ) -> Result<git2::Oid, git2::Error> {
+        let id = original.id().to_string();
+
+        let mut message = format!(
+            "Squash history up to {}\n\n\
+             This commit replaces {}\n\
+             and all of its ancestors. The last of them was:\n\n    ",
+            &id[..7],
+            id
+        )
+        .into_bytes();
+
+        message.extend_from_slice(original.summary_bytes().unwrap_or(b""));
+        message.push(b'\n');
+
+        if self.anonymize {
+            message = anonymize::message(self.anonymize_seed, &message);
+        }
+
+        let (author, committer) = self.signatures(original)?;
+
+        let metadata = Metadata {
+            author,
+            committer,
+            message,
+        };
+
+        self.write(repo, original, tree, &[], &metadata)
+    }
+
+    /// Returns the rewritten author and committer of a commit.
+    fn signatures(
+        &self,
+        original: &git2::Commit<'_>,
+    ) -> Result<(git2::Signature<'static>, git2::Signature<'static>), git2::Error>
+    {
+        let mut author = match &self.author {
+            Some((name, email)) => {
+                git2::Signature::new(name, email, &original.author().when())?
+            }
+            None => self.signature(&original.author())?,
+        };
+
+        let mut committer = match &self.committer {
+            Some((name, email)) => {
+                git2::Signature::new(name, email, &original.committer().when())?
+            }
+            None => self.signature(&original.committer())?,
+        };
+
+        if let Some(time) = &self.reset_dates {
+            author = with_time(&author, time)?;
+            committer = with_time(&committer, time)?;
+        } else if self.committer_date_is_author_date {
+            committer = with_time(&committer, &author.when())?;
+        }
+
+        if self.anonymize {
+            author = anonymize::signature(self.anonymize_seed, &author)?;
+            committer = anonymize::signature(self.anonymize_seed, &committer)?;
+        }
+
+        Ok((author, committer))
+    }
+
+    /// Returns the rewritten author or committer.
+    pub fn signature(
+        &self,
+        signature: &git2::Signature<'_>,
+    ) -> Result<git2::Signature<'static>, git2::Error> {
+        match &self.mailmap {
+            Some((_, mailmap)) => mailmap.resolve_signature(signature),
+            None => Ok(signature.to_owned()),
+        }
+    }
+}
+
+/// Formats a signature as in the `author` and `committer` headers of a commit,
+/// such as `Jane Doe <jane@example.com> 1500000000 +0100`.
+pub fn format_signature(signature: &git2::Signature<'_>) -> Vec<u8> {
+    let when = signature.when();
+    let offset = when.offset_minutes().abs();
+
+    let mut formatted = signature.name_bytes().to_vec();
+    formatted.extend_from_slice(b" <");
+    formatted.extend_from_slice(signature.email_bytes());
+    formatted.extend_from_slice(
+        format!(
+            "> {} {}{:02}{:02}",
+            when.seconds(),
+            when.sign(),
+            offset / 60,
+            offset % 60
+        )
+        .as_bytes(),
+    );
+
+    formatted
+}
+
+/// Returns a copy of the signature with a different time.
+fn with_time(
+    signature: &git2::Signature<'_>,
+    time: &git2::Time,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    git2::Signature::new(
+        &String::from_utf8_lossy(signature.name_bytes()),
+        &String::from_utf8_lossy(signature.email_bytes()),
+        time,
+    )
+}
+
+/// Returns `true` if both signatures have the same name, email address, and
+/// time.
+fn same_signature(a: &git2::Signature<'_>, b: &git2::Signature<'_>) -> bool {
+    a.name_bytes() == b.name_bytes()
+        && a.email_bytes() == b.email_bytes()
+        && a.when() == b.when()
+}
+
+/// Returns the header of a copy of the commit `original` with the given tree,
+/// parents, author, committer, and message, along with whether anything
+/// changed. The blank line separating the header from the message isn't
+/// included.
+///
+/// Unlike `Repository::commit`, other headers (such as `encoding` and
+/// `mergetag`) are kept. Unchanged authors and committers are copied as they
+/// are. The signature of the commit (i.e., `gpgsig`) is only kept if nothing
+/// changed, in which case the copy is the original commit.
+fn commit_header(
+    original: &git2::Commit<'_>,
+    tree: git2::Oid,
+    parents: &[git2::Oid],
+    author: &git2::Signature<'_>,
+    committer: &git2::Signature<'_>,
+    message: &[u8],
+) -> (Vec<u8>, bool) {
+    let same_author = same_signature(author, &original.author());
+    let same_committer = same_signature(committer, &original.committer());
+
+    let changed = tree != original.tree_id()
+        || !parents.iter().cloned().eq(original.parent_ids())
+        || !same_author
+        || !same_committer
+        || message != original.message_raw_bytes();
+
+    let mut data = format!("tree {}\n", tree).into_bytes();
+
+    for parent in parents {
+        data.extend_from_slice(format!("parent {}\n", parent).as_bytes());
+    }
+
+    // Header fields are separated by newlines. Lines starting with a space
+    // continue the previous field.
+    let mut fields: Vec<&[u8]> = Vec::new();
+    let header = original.raw_header_bytes();
+    let mut start = 0;
+
+    for (i, &b) in header.iter().enumerate() {
+        if b == b'\n' && header.get(i + 1) != Some(&b' ') {
+            fields.push(&header[start..=i]);
+            start = i + 1;
+        }
+    }
+
+    if start < header.len() {
+        fields.push(&header[start..]);
+    }
+
+    for field in fields {
+        let key = field.split(|&b| b == b' ').next().unwrap_or(field);
+
+        match key {
+            b"tree" | b"parent" => {}
+            b"author" if !same_author => {
+                data.extend_from_slice(b"author ");
+                data.extend_from_slice(&format_signature(author));
+                data.push(b'\n');
+            }
+            b"committer" if !same_committer => {
+                data.extend_from_slice(b"committer ");
+                data.extend_from_slice(&format_signature(committer));
+                data.push(b'\n');
+            }
+            b"gpgsig" | b"gpgsig-sha256" if changed => {}
+            _ => {
+                data.extend_from_slice(field);
+
+                if !field.ends_with(b"\n") {
+                    data.push(b'\n');
+                }
+            }
+        }
+    }
+
+    (data, changed)
+}
+
+/// Returns `true` if the line looks like a trailer, such as
+/// `Signed-off-by: Jane Doe <jane@example.com>`.
+fn is_trailer(line: &[u8]) -> bool {
+    match line.iter().position(|&b| b == b':') {
+        Some(i) => {
+            i > 0
+                && line[..i]
+                    .iter()
+                    .all(|&b| b.is_ascii_alphanumeric() || b == b'-')
+        }
+        None => false,
+    }
+}
+
+/// Returns the message without trailing whitespace.
+fn trim_end(message: &[u8]) -> &[u8] {
+    let end = message
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    &message[..end]
+}
+
+/// Returns the trailers at the end of a commit message, if any. These are the
+/// lines of the last paragraph if all of them look like trailers. The first
+/// paragraph is the subject, so it never counts as trailers.
+fn trailers(message: &[u8]) -> Option<&[u8]> {
+    let message = trim_end(message);
+
+    message
+        .windows(2)
+        .rposition(|w| w == b"\n\n")
+        .map(|i| &message[i + 2..])
+        .filter(|paragraph| paragraph.split(|&b| b == b'\n').all(is_trailer))
+}
+
+/// Returns `true` if the commit message already has the given trailer.
+fn has_trailer(message: &[u8], key: &str, value: &str) -> bool {
+    let trailer = format!("{}: {}", key, value);
+
+    trailers(message).is_some_and(|trailers| {
+        trailers
+            .split(|&b| b == b'\n')
+            .any(|line| line == trailer.as_bytes())
+    })
+}
+
+/// Appends a trailer to a commit message. As with `git interpret-trailers`,
+/// the trailer is added to the existing trailers, if any. Otherwise, it starts
+/// a new paragraph.
+pub fn append_trailer(message: &[u8], key: &str, value: &str) -> Vec<u8> {
+    let has_trailers = trailers(message).is_some();
+    let message = trim_end(message);
+
+    let mut result = message.to_vec();
+
+    if has_trailers {
+        result.push(b'\n');
+    } else if !message.is_empty() {
+        result.extend_from_slice(b"\n\n");
+    }
+
+    result.extend_from_slice(format!("{}: {}\n", key, value).as_bytes());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mailmap() {
+        let mut rewrite = CommitRewrite::new();
+        rewrite
+            .set_mailmap(
+                "Jane Doe <jane@example.com> <jdoe@old.example.com>\n\
+                 <bob@example.com> Bob <bob@localhost>\n",
+            )
+            .unwrap();
+
+        let time = git2::Time::new(0, 0);
+
+        let jane = git2::Signature::new("jdoe", "jdoe@old.example.com", &time)
+            .unwrap();
+        let jane = rewrite.signature(&jane).unwrap();
+        assert_eq!(jane.name(), Some("Jane Doe"));
+        assert_eq!(jane.email(), Some("jane@example.com"));
+
+        let bob = git2::Signature::new("Bob", "bob@localhost", &time).unwrap();
+        let bob = rewrite.signature(&bob).unwrap();
+        assert_eq!(bob.name(), Some("Bob"));
+        assert_eq!(bob.email(), Some("bob@example.com"));
+
+        let other =
+            git2::Signature::new("Other", "other@example.com", &time).unwrap();
+        let other = rewrite.signature(&other).unwrap();
+        assert_eq!(other.name(), Some("Other"));
+        assert_eq!(other.when(), time);
+    }
+
+    #[test]
+    fn test_rewrite_hashes() {
+        let mut rewrite = CommitRewrite::new();
+        rewrite.set_rewrite_hashes();
+
+        let message = rewrite.message(
+            git2::Oid::zero(),
+            b"Revert abc1234 and 0123456789abcdef, not deadbeefs or abc12",
+            |hash| match hash {
+                "abc1234" => Some("fff0000".to_string()),
+                _ => None,
+            },
+        );
+
+        assert_eq!(
+            message,
+            &b"Revert fff0000 and 0123456789abcdef, not deadbeefs or abc12"[..]
+        );
+    }
+
+    #[test]
+    fn test_append_trailer() {
+        assert_eq!(
+            append_trailer(b"Subject\n", "original-oid", "abc"),
+            b"Subject\n\noriginal-oid: abc\n"
+        );
+        assert_eq!(
+            append_trailer(b"Subject: with colon", "k", "v"),
+            b"Subject: with colon\n\nk: v\n"
+        );
+        assert_eq!(
+            append_trailer(b"Subject\n\nBody text.\n\n", "k", "v"),
+            b"Subject\n\nBody text.\n\nk: v\n"
+        );
+        assert_eq!(
+            append_trailer(
+                b"Subject\n\nSigned-off-by: A <a@example.com>\n",
+                "k",
+                "v"
+            ),
+            b"Subject\n\nSigned-off-by: A <a@example.com>\nk: v\n"
+        );
+        assert_eq!(append_trailer(b"", "k", "v"), b"k: v\n");
+
+        let message = b"Subject\n\nSigned-off-by: A <a@example.com>\n";
+        assert!(has_trailer(message, "Signed-off-by", "A <a@example.com>"));
+        assert!(!has_trailer(message, "Signed-off-by", "B <b@example.com>"));
+        assert!(!has_trailer(b"Signed-off-by: A", "Signed-off-by", "A"));
+    }
+}
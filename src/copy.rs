@@ -0,0 +1,115 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Copying objects between repositories.
+
+use std::collections::HashSet;
+
+/// Returns what the references in a repository point to. Symbolic references
+/// are skipped since they point to another reference.
+pub fn reference_tips(
+    repo: &git2::Repository,
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let mut tips = Vec::new();
+
+    for reference in repo.references()? {
+        if let Some(id) = reference?.target() {
+            tips.push(id);
+        }
+    }
+
+    Ok(tips)
+}
+
+/// Returns every object reachable from `tips` along with its type. Submodule
+/// commits are not followed since they live in another repository.
+pub fn reachable<I>(
+    repo: &git2::Repository,
+    tips: I,
+) -> Result<Vec<(git2::Oid, git2::ObjectType)>, git2::Error>
+where
+    I: IntoIterator<Item = git2::Oid>,
+{
+    let odb = repo.odb()?;
+
+    let mut stack: Vec<git2::Oid> = tips.into_iter().collect();
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+
+        let (_, kind) = odb.read_header(id)?;
+
+        match kind {
+            git2::ObjectType::Commit => {
+                let commit = repo.find_commit(id)?;
+                stack.push(commit.tree_id());
+                stack.extend(commit.parent_ids());
+            }
+            git2::ObjectType::Tree => {
+                let tree = repo.find_tree(id)?;
+                stack.extend(
+                    tree.iter()
+                        .filter(|entry| {
+                            entry.kind() != Some(git2::ObjectType::Commit)
+                        })
+                        .map(|entry| entry.id()),
+                );
+            }
+            git2::ObjectType::Tag => {
+                stack.push(repo.find_tag(id)?.target_id());
+            }
+            _ => {}
+        }
+
+        objects.push((id, kind));
+    }
+
+    Ok(objects)
+}
+
+/// Copies every object reachable from `tips` from one repository into another,
+/// returning the number of objects copied. Objects the destination already has
+/// aren't written again, but what they point to is still checked since new
+/// objects can refer to old ones.
+pub fn copy_reachable<I>(
+    from: &git2::Repository,
+    to: &git2::Odb,
+    tips: I,
+) -> Result<usize, git2::Error>
+where
+    I: IntoIterator<Item = git2::Oid>,
+{
+    let odb = from.odb()?;
+
+    let mut copied = 0;
+
+    for (id, kind) in reachable(from, tips)? {
+        if !to.exists(id) {
+            to.write(kind, odb.read(id)?.data())?;
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Normalizing the line endings of text files.
+
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// The line ending to convert text files to.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum Eol {
+    /// Unix line endings, `\n`.
+    Lf,
+
+    /// Windows line endings, `\r\n`.
+    Crlf,
+}
+
+impl FromStr for Eol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Eol, String> {
+        match s {
+            "lf" => Ok(Eol::Lf),
+            "crlf" => Ok(Eol::Crlf),
+            _ => Err(format!("Expected 'lf' or 'crlf' but got '{}'", s)),
+        }
+    }
+}
+
+impl Eol {
+    /// Converts every line ending in the contents to this one. Lone `\r`s are
+    /// left alone.
+    pub fn normalize<'a>(&self, contents: &'a [u8]) -> Cow<'a, [u8]> {
+        let needs_change = match self {
+            Eol::Lf => contents.windows(2).any(|w| w == b"\r\n"),
+            Eol::Crlf => contents.iter().enumerate().any(|(i, &b)| {
+                b == b'\n' && (i == 0 || contents[i - 1] != b'\r')
+            }),
+        };
+
+        if !needs_change {
+            return Cow::Borrowed(contents);
+        }
+
+        let mut out = Vec::with_capacity(contents.len());
+
+        for (i, &b) in contents.iter().enumerate() {
+            match (self, b) {
+                (Eol::Lf, b'\r') if contents.get(i + 1) == Some(&b'\n') => {}
+                (Eol::Crlf, b'\n') if i == 0 || contents[i - 1] != b'\r' => {
+                    out.extend_from_slice(b"\r\n");
+                }
+                _ => out.push(b),
+            }
+        }
+
+        Cow::Owned(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(Eol::Lf.normalize(b"a\r\nb\rc\n"), &b"a\nb\rc\n"[..]);
+        assert_eq!(
+            Eol::Crlf.normalize(b"a\r\nb\n\nc"),
+            &b"a\r\nb\r\n\r\nc"[..]
+        );
+        assert!(matches!(Eol::Lf.normalize(b"a\nb\n"), Cow::Borrowed(_)));
+        assert!(matches!(Eol::Crlf.normalize(b"a\r\n"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! The errors that can happen while creating a subset.
+
+use std::io;
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// A rule, pattern, or other argument is invalid.
+    #[error("Invalid {what}: {reason}")]
+    Invalid { what: String, reason: String },
+
+    /// Something failed, such as loading a file.
+    #[error("Failed to {action}: {source}")]
+    Failed { action: String, source: Box<Error> },
+
+    /// Filtering only produced empty commits for the given branches, tags, or
+    /// references, so they weren't created.
+    #[error(
+        "Filtering only produced empty commits. No {} created.",
+        .0.join(", ")
+    )]
+    EmptySubset(Vec<String>),
+
+    /// The user decided not to go ahead.
+    #[error("Aborted.")]
+    Aborted,
+
+    /// The run was interrupted, such as with Ctrl-C, and stopped between
+    /// commits.
+    #[error("Interrupted.")]
+    Interrupted,
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Other(message)
+    }
+}
+
+impl Error {
+    /// Returns an error saying that `what`, such as "regex 'foo('", is
+    /// invalid.
+    pub fn invalid<W, R>(what: W, reason: R) -> Error
+    where
+        W: Into<String>,
+        R: ToString,
+    {
+        Error::Invalid {
+            what: what.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Returns an error saying that `action`, such as "load mailmap 'foo'",
+    /// failed. Being interrupted isn't a failure of the action, so it is
+    /// passed along as it is.
+    pub fn failed<A, E>(action: A, source: E) -> Error
+    where
+        A: Into<String>,
+        E: Into<Error>,
+    {
+        match source.into() {
+            Error::Interrupted => Error::Interrupted,
+            source => Error::Failed {
+                action: action.into(),
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error::failed(
+            "load mailmap 'foo'",
+            io::Error::new(io::ErrorKind::NotFound, "not found"),
+        );
+        assert_eq!(err.to_string(), "Failed to load mailmap 'foo': not found");
+
+        let err = Error::failed("create repository subset", Error::Interrupted);
+        assert_eq!(err.to_string(), "Interrupted.");
+
+        let err = Error::invalid("regex '('", "unclosed group");
+        assert_eq!(err.to_string(), "Invalid regex '(': unclosed group");
+
+        let err = Error::EmptySubset(vec![
+            "branch 'a'".to_string(),
+            "tag 'b'".to_string(),
+        ]);
+        assert_eq!(
+            err.to_string(),
+            "Filtering only produced empty commits. No branch 'a', tag 'b' \
+             created."
+        );
+    }
+}
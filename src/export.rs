@@ -0,0 +1,258 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writing history as a stream for `git fast-import`.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::Error;
+
+/// Quotes a path if `git fast-import` would misread it otherwise.
+fn quote(path: &[u8]) -> Vec<u8> {
+    if !path.contains(&b'\n') && !path.starts_with(b"\"") {
+        return path.to_vec();
+    }
+
+    let mut quoted = vec![b'"'];
+
+    for &c in path {
+        match c {
+            b'"' | b'\\' => quoted.extend([b'\\', c]),
+            b'\n' => quoted.extend(b"\\n"),
+            _ => quoted.push(c),
+        }
+    }
+
+    quoted.push(b'"');
+    quoted
+}
+
+/// Writes an identity line, such as `author Jane <jane@example.com> 0 +0000`.
+fn write_ident<W: Write>(
+    out: &mut W,
+    kind: &str,
+    signature: &git2::Signature,
+) -> io::Result<()> {
+    let time = signature.when();
+    let offset = time.offset_minutes();
+
+    write!(out, "{} ", kind)?;
+    out.write_all(signature.name_bytes())?;
+    out.write_all(b" <")?;
+    out.write_all(signature.email_bytes())?;
+    writeln!(
+        out,
+        "> {} {}{:02}{:02}",
+        time.seconds(),
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Writes `data` followed by the contents.
+fn write_data<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    writeln!(out, "data {}", data.len())?;
+    out.write_all(data)?;
+    writeln!(out)
+}
+
+/// Writes commits in the format read by `git fast-import`, along with the blobs
+/// they need. Every blob and commit is given a mark, so that each is only
+/// written once, even when exporting several branches that share history.
+pub struct Exporter<'r, W> {
+    repo: &'r git2::Repository,
+    out: W,
+    marks: HashMap<git2::Oid, u64>,
+
+    /// The mark to give the next new object.
+    next: u64,
+}
+
+impl<'r, W: Write> Exporter<'r, W> {
+    /// Starts a stream. It must be ended with `finish` for `git fast-import`
+    /// to accept it.
+    pub fn new(repo: &'r git2::Repository, mut out: W) -> io::Result<Self> {
+        writeln!(out, "feature done")?;
+
+        Ok(Exporter {
+            repo,
+            out,
+            marks: HashMap::new(),
+            next: 1,
+        })
+    }
+
+    /// Adds marks from an earlier stream, such as those read from a marks file.
+    /// Objects that already have a mark are taken to have been written by then,
+    /// so only what is new since is written.
+    pub fn import_marks(&mut self, marks: &[(u64, git2::Oid)]) {
+        for &(mark, id) in marks {
+            self.marks.insert(id, mark);
+            self.next = self.next.max(mark + 1);
+        }
+    }
+
+    /// Returns the mark of every object, including those from earlier streams.
+    pub fn marks(&self) -> Vec<(u64, git2::Oid)> {
+        self.marks.iter().map(|(&id, &mark)| (mark, id)).collect()
+    }
+
+    /// Returns the mark of an object, giving it a new one if it doesn't have
+    /// one yet.
+    fn mark(&mut self, id: git2::Oid) -> (u64, bool) {
+        if let Some(&mark) = self.marks.get(&id) {
+            return (mark, false);
+        }
+
+        let mark = self.next;
+        self.marks.insert(id, mark);
+        self.next += 1;
+        (mark, true)
+    }
+
+    /// Writes the history of `tip` and points the reference at it. Commits
+    /// that were already written are left out.
+    pub fn export(&mut self, name: &str, tip: git2::Oid) -> Result<(), Error> {
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+        walk.push(tip)?;
+
+        for id in walk {
+            let id = id?;
+
+            if !self.marks.contains_key(&id) {
+                self.export_commit(name, id)?;
+            }
+        }
+
+        writeln!(self.out, "reset {}", name)?;
+        writeln!(self.out, "from :{}", self.marks[&tip])?;
+        writeln!(self.out)?;
+
+        Ok(())
+    }
+
+    /// Writes a commit, with its changes relative to its first parent.
+    fn export_commit(
+        &mut self,
+        name: &str,
+        id: git2::Oid,
+    ) -> Result<(), Error> {
+        let repo = self.repo;
+        let commit = repo.find_commit(id)?;
+
+        let parent = match commit.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+
+        let mut options = git2::DiffOptions::new();
+        options.include_typechange(true);
+
+        let diff = repo.diff_tree_to_tree(
+            parent.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut options),
+        )?;
+
+        let mut changes = Vec::new();
+
+        for delta in diff.deltas() {
+            if delta.status() == git2::Delta::Deleted {
+                let path = delta.old_file().path_bytes().unwrap_or_default();
+                changes.extend(b"D ");
+                changes.extend(quote(path));
+                changes.push(b'\n');
+                continue;
+            }
+
+            let file = delta.new_file();
+            let mode = u32::from(file.mode());
+
+            // Submodules refer to commits in other repositories.
+            let dataref = if file.mode() == git2::FileMode::Commit {
+                file.id().to_string()
+            } else {
+                let (mark, new) = self.mark(file.id());
+
+                if new {
+                    writeln!(self.out, "blob\nmark :{}", mark)?;
+                    write_data(
+                        &mut self.out,
+                        repo.find_blob(file.id())?.content(),
+                    )?;
+                }
+
+                format!(":{}", mark)
+            };
+
+            changes.extend(format!("M {:o} {} ", mode, dataref).as_bytes());
+            changes.extend(quote(file.path_bytes().unwrap_or_default()));
+            changes.push(b'\n');
+        }
+
+        // Without a parent, the commit would otherwise continue the branch.
+        if commit.parent_count() == 0 {
+            writeln!(self.out, "reset {}", name)?;
+        }
+
+        let (mark, _) = self.mark(id);
+        writeln!(self.out, "commit {}\nmark :{}", name, mark)?;
+        write_ident(&mut self.out, "author", &commit.author())?;
+        write_ident(&mut self.out, "committer", &commit.committer())?;
+
+        if let Some(encoding) = commit.message_encoding() {
+            writeln!(self.out, "encoding {}", encoding)?;
+        }
+
+        write_data(&mut self.out, commit.message_raw_bytes())?;
+
+        for (i, parent) in commit.parent_ids().enumerate() {
+            let kind = if i == 0 { "from" } else { "merge" };
+            writeln!(self.out, "{} :{}", kind, self.marks[&parent])?;
+        }
+
+        self.out.write_all(&changes)?;
+        writeln!(self.out)?;
+
+        Ok(())
+    }
+
+    /// Ends the stream, returning the output.
+    pub fn finish(mut self) -> io::Result<W> {
+        writeln!(self.out, "done")?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote(b"a b/c"), b"a b/c");
+        assert_eq!(quote(b"a\nb"), b"\"a\\nb\"");
+        assert_eq!(quote(b"\"a\\b\""), b"\"\\\"a\\\\b\\\"\"");
+    }
+}
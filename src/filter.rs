@@ -18,23 +18,229 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use crate::anonymize::{self, Seed};
+use crate::attributes::{lookup, AttrFile, AttrMatch, AttrState};
+use crate::callback::BlobCallback;
+use crate::eol::Eol;
+use crate::error::Error;
+use crate::gitmodules;
+use crate::glob::{glob_match, glob_match_icase, glob_match_path};
+use crate::lfs::LfsConvert;
+use crate::lua::{BlobAction, LuaScript};
 use crate::map::OidMap;
-use git2;
+use crate::pathspec::Pathspec;
+use crate::patterns;
+use crate::plugin::{PathAction, Plugin};
+use crate::rename::{
+    prefix_tree, put, rename_path, rename_tree, split_path, strip_components,
+    subtree, take, Entry, Rename,
+};
+use crate::replace::Replacements;
+use crate::report::Report;
+use crate::secrets::Secrets;
+use crate::transform::{BlobTransform, Pipeline, StripBom};
 
-use std::collections::BTreeMap;
+use regex::{Regex, RegexBuilder};
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::path::{Component, Path};
+use std::rc::Rc;
+use std::str::FromStr;
 
-#[derive(Debug, Hash)]
+/// The set of rules that decide which paths to keep.
+#[derive(Clone, Debug, Default)]
 pub struct Filter {
-    filter: BTreeMap<String, Filter>,
+    /// Paths to keep, split up into their components.
+    paths: Node,
+
+    /// Regular expressions matched against the full path of each entry.
+    regexes: Vec<Regex>,
+
+    /// Glob patterns matched against the name of each entry at any depth.
+    names: Vec<Pattern>,
+
+    /// Git pathspecs matched against the full path of each entry. Those with
+    /// the `exclude` magic leave paths out instead.
+    pathspecs: Vec<Pathspec>,
+
+    /// Attributes that files to keep must have.
+    attrs: Vec<AttrMatch>,
+
+    /// Attributes of files to leave out.
+    excluded_attrs: Vec<AttrMatch>,
+
+    /// Rules in `.gitignore` syntax for paths to leave out, in the order they
+    /// were given.
+    excludes: Vec<Exclude>,
+
+    /// Types of entries to leave out wherever they are.
+    stripped: Vec<EntryType>,
+
+    /// What to do with submodules that are kept.
+    submodules: Submodules,
+
+    /// Blobs bigger than this many bytes are left out.
+    max_blob_size: Option<usize>,
+
+    /// Blobs to leave out wherever they are.
+    stripped_blobs: BTreeSet<git2::Oid>,
+
+    /// If `true`, lines in `.gitignore` and `.gitattributes` files that only
+    /// refer to paths that were left out are removed.
+    prune_patterns: bool,
+
+    /// If `true`, all rules ignore case.
+    icase: bool,
+
+    /// If `true`, everything that would be kept is left out and everything
+    /// that would be left out is kept.
+    invert: bool,
+
+    /// Directory that becomes the new root after the tree has been filtered.
+    subdirectory: Option<Vec<String>>,
+
+    /// Number of leading path components to remove after the tree has been
+    /// filtered.
+    strip_components: usize,
+
+    /// Rules for moving entries after the tree has been filtered.
+    renames: Vec<Rename>,
+
+    /// Directory to move everything into at the very end.
+    prefix: Vec<String>,
+
+    /// Plugin that decides what to do with each file that is left.
+    plugin: Option<Rc<Plugin>>,
+
+    /// Lua script that may edit or drop each file that is left.
+    lua: Option<Rc<LuaScript>>,
+
+    /// Closure that decides whether to keep each file that is left.
+    entry_filter: Option<EntryFilter>,
+
+    /// Regular expressions for the contents of files to leave out.
+    drop_contents: Vec<regex::bytes::Regex>,
+
+    /// Transforms to run the contents of each file that is left through.
+    transforms: Pipeline,
+
+    /// Finds secrets to redact from each file that is left.
+    secrets: Option<Secrets>,
+
+    /// Files to convert to LFS pointers after everything else.
+    lfs: LfsConvert,
+
+    /// Scrambles the name of every file and directory that is left.
+    anonymize_paths: bool,
+
+    /// Scrambles the contents of every file that is left.
+    anonymize_contents: bool,
+
+    /// Salts the tokens that scrambled names and contents are replaced with.
+    anonymize_seed: Seed,
+}
+
+/// A closure that is given the path and entry of a file and returns `false` to
+/// leave it out.
+#[derive(Clone)]
+pub struct EntryFilter(Rc<EntryFn>);
+
+type EntryFn = dyn Fn(&str, &git2::TreeEntry<'_>) -> bool;
+
+impl fmt::Debug for EntryFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EntryFilter")
+    }
+}
+
+impl Hash for Filter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.paths.hash(state);
+
+        for regex in &self.regexes {
+            regex.as_str().hash(state);
+        }
+
+        self.names.hash(state);
+
+        for spec in &self.pathspecs {
+            spec.as_str().hash(state);
+        }
+
+        self.attrs.hash(state);
+        self.excluded_attrs.hash(state);
+        self.excludes.hash(state);
+        self.stripped.hash(state);
+
+        if self.submodules != Submodules::Keep {
+            self.submodules.hash(state);
+        }
+
+        self.max_blob_size.hash(state);
+        self.stripped_blobs.hash(state);
+        self.prune_patterns.hash(state);
+        self.icase.hash(state);
+        self.invert.hash(state);
+        self.subdirectory.hash(state);
+        self.strip_components.hash(state);
+        self.renames.hash(state);
+        self.prefix.hash(state);
+        self.plugin.hash(state);
+        self.lua.hash(state);
+
+        // Closures can't be hashed. Maps of filters with them must not be
+        // reused.
+        self.entry_filter.is_some().hash(state);
+
+        for regex in &self.drop_contents {
+            regex.as_str().hash(state);
+        }
+
+        self.transforms.hash(state);
+        self.secrets.is_some().hash(state);
+        self.lfs.hash(state);
+        self.anonymize_paths.hash(state);
+        self.anonymize_contents.hash(state);
+    }
 }
 
 impl Filter {
     pub fn new() -> Filter {
         Filter {
-            filter: BTreeMap::new(),
+            paths: Node::default(),
+            regexes: Vec::new(),
+            names: Vec::new(),
+            pathspecs: Vec::new(),
+            attrs: Vec::new(),
+            excluded_attrs: Vec::new(),
+            excludes: Vec::new(),
+            stripped: Vec::new(),
+            submodules: Submodules::Keep,
+            max_blob_size: None,
+            stripped_blobs: BTreeSet::new(),
+            prune_patterns: false,
+            icase: false,
+            invert: false,
+            subdirectory: None,
+            strip_components: 0,
+            renames: Vec::new(),
+            prefix: Vec::new(),
+            plugin: None,
+            lua: None,
+            entry_filter: None,
+            drop_contents: Vec::new(),
+            transforms: Pipeline::new(),
+            secrets: None,
+            lfs: LfsConvert::new(),
+            anonymize_paths: false,
+            anonymize_contents: false,
+            anonymize_seed: Seed::default(),
         }
     }
 
@@ -43,8 +249,9 @@ impl Filter {
         Self::from_reader(io::BufReader::new(fs::File::open(path)?))
     }
 
-    /// Load from a reader. The file shall consist of lines containing paths.
-    /// Blank lines and lines starting with a "#" are ignored.
+    /// Load from a reader. The file shall consist of lines containing rules
+    /// (see `insert_rule`). Blank lines and lines starting with a "#" are
+    /// ignored.
     pub fn from_reader<R: io::BufRead>(reader: R) -> io::Result<Filter> {
         let mut filter = Self::new();
 
@@ -52,132 +259,1999 @@ impl Filter {
             let line = line?;
             let line = line.trim();
 
-            if line.is_empty() || line.starts_with("#") {
+            if line.is_empty() || line.starts_with('#') {
                 // Ignore blank lines and comments
                 continue;
             }
 
-            filter.insert(Path::new(line));
+            filter.insert_rule(line).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            })?;
         }
 
         Ok(filter)
     }
 
+    /// Inserts a rule into the filter. A rule is a path, unless it starts with
+    /// one of these prefixes:
+    ///
+    ///  * "regex:" for a regular expression matched against the full path.
+    ///  * "glob:" for a glob pattern matched against the name of files and
+    ///    directories at any depth.
+    ///  * ":" for a git pathspec with magic, such as `:(exclude)src/gen`.
+    ///  * "attr:" for an attribute that files must have in `.gitattributes`,
+    ///    such as `attr:export-subset`.
+    ///  * "transform:" for a transform to run the contents of every file
+    ///    through, such as `transform:normalize-eol=lf`.
+    ///
+    /// Any rule can additionally be prefixed with "icase:" to ignore case.
+    pub fn insert_rule(&mut self, rule: &str) -> Result<(), Error> {
+        if let Some(spec) = rule.strip_prefix("transform:") {
+            return self.transforms.insert_spec(spec);
+        }
+
+        let (icase, rule) = match rule.strip_prefix("icase:") {
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+
+        if let Some(regex) = rule.strip_prefix("regex:") {
+            self.add_regex(regex, icase).map_err(|err| {
+                Error::invalid(format!("path regex '{}'", regex), err)
+            })
+        } else if let Some(glob) = rule.strip_prefix("glob:") {
+            self.add_glob(glob, icase)
+        } else if rule.starts_with(':') {
+            self.add_pathspec(rule, icase)
+        } else if let Some(attr) = rule.strip_prefix("attr:") {
+            self.insert_attr(attr)
+        } else {
+            self.paths.insert(Path::new(rule), icase);
+            Ok(())
+        }
+    }
+
     /// Inserts a path into the filter. The path is split up and inserted into
     /// the tree.
     pub fn insert(&mut self, path: &Path) {
-        let mut components = path.components();
+        self.paths.insert(path, false);
+    }
+
+    /// Inserts a regular expression into the filter. Any file or directory
+    /// whose full path (e.g., `src/main.rs`) matches the regular expression
+    /// is kept. The regular expression must match the entire path.
+    pub fn insert_regex(&mut self, regex: &str) -> Result<(), regex::Error> {
+        self.add_regex(regex, false)
+    }
+
+    fn add_regex(
+        &mut self,
+        regex: &str,
+        icase: bool,
+    ) -> Result<(), regex::Error> {
+        let regex = RegexBuilder::new(&format!("^(?:{})$", regex))
+            .case_insensitive(icase || self.icase)
+            .build()?;
+
+        self.regexes.push(regex);
+        Ok(())
+    }
 
-        match components.next() {
-            Some(Component::Normal(c)) => {
-                let filter = self
-                    .filter
-                    .entry(String::from(c.to_str().unwrap()))
-                    .or_insert_with(|| Filter::new());
+    /// Inserts a glob pattern into the filter. Any file or directory whose
+    /// name matches the pattern is kept, no matter where it is in the tree.
+    /// For example, `*.proto` keeps all `.proto` files and `Cargo.toml` keeps
+    /// every `Cargo.toml`. The pattern can't contain a `/`.
+    pub fn insert_glob(&mut self, glob: &str) -> Result<(), Error> {
+        self.add_glob(glob, false)
+    }
+
+    fn add_glob(&mut self, glob: &str, icase: bool) -> Result<(), Error> {
+        if glob.is_empty() || glob.contains('/') {
+            return Err(Error::invalid(
+                format!("path glob '{}'", glob),
+                "expected a file name pattern without '/'",
+            ));
+        }
+
+        self.names.push(Pattern::new(glob, icase));
+        Ok(())
+    }
+
+    /// Inserts a git pathspec into the filter. Any file or directory whose full
+    /// path matches the pathspec is kept. If the pathspec has the `exclude`
+    /// magic, matching paths are left out instead.
+    pub fn insert_pathspec(&mut self, spec: &str) -> Result<(), Error> {
+        self.add_pathspec(spec, false)
+    }
+
+    fn add_pathspec(&mut self, spec: &str, icase: bool) -> Result<(), Error> {
+        let spec = Pathspec::new(spec)?;
+
+        self.pathspecs
+            .push(if icase { spec.ignore_case() } else { spec });
+
+        Ok(())
+    }
+
+    /// Keeps all files with the given attribute, such as `export-subset`,
+    /// `-text`, or `filter=lfs`. Attributes are read from the `.gitattributes`
+    /// files in each tree being rewritten.
+    pub fn insert_attr(&mut self, attr: &str) -> Result<(), Error> {
+        self.attrs.push(AttrMatch::new(attr)?);
+        Ok(())
+    }
+
+    /// Makes all rules ignore case, including ones that were already inserted.
+    /// Names that only differ in case are all kept as they are. If a tree
+    /// contains both `Docs` and `docs`, for example, then `docs` keeps both.
+    pub fn set_icase(&mut self) {
+        self.icase = true;
+
+        for regex in &mut self.regexes {
+            *regex = RegexBuilder::new(regex.as_str())
+                .case_insensitive(true)
+                .build()
+                .unwrap();
+        }
+    }
+
+    /// Inverts the filter such that it keeps exactly the paths it would
+    /// otherwise leave out. This makes it possible to split a repository in
+    /// two with the same set of rules. Moving paths around with renames still
+    /// happens afterwards.
+    pub fn set_invert(&mut self) {
+        self.invert = true;
+    }
+
+    /// Returns a copy of the filter that keeps exactly the paths this one
+    /// leaves out.
+    pub fn inverted(&self) -> Filter {
+        Filter {
+            invert: !self.invert,
+            ..self.clone()
+        }
+    }
+
+    /// Loads exclude rules from a file in `.gitignore` syntax.
+    pub fn read_exclude_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> io::Result<()> {
+        self.read_excludes(io::BufReader::new(fs::File::open(path)?))
+    }
+
+    /// Reads exclude rules in `.gitignore` syntax from a reader.
+    pub fn read_excludes<R: io::BufRead>(
+        &mut self,
+        reader: R,
+    ) -> io::Result<()> {
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
 
-                // Insert the rest of the components recursively.
-                filter.insert(components.as_path());
+            if line.is_empty() || line.starts_with('#') {
+                // Ignore blank lines and comments
+                continue;
             }
-            _ => {}
+
+            self.insert_exclude(line).map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts an exclude rule in `.gitignore` syntax. Paths matching the rule
+    /// are left out even if they are matched by another part of the filter.
+    ///
+    /// As with `.gitignore` files, the last matching rule wins, a rule
+    /// starting with `!` includes a path again, a rule ending with `/` only
+    /// matches directories, and a rule without a `/` in the middle matches
+    /// entries at any depth. Note that a path cannot be included again if one
+    /// of its parent directories is excluded.
+    ///
+    /// A rule of the form `type:<type>` leaves out all entries of that type
+    /// instead (see `EntryType`) and a rule of the form `attr:<attr>` leaves
+    /// out all files with that attribute (see `insert_attr`).
+    pub fn insert_exclude(&mut self, rule: &str) -> Result<(), Error> {
+        if let Some(ty) = rule.strip_prefix("type:") {
+            self.strip(ty.parse().map_err(|err| {
+                Error::invalid(format!("exclude rule '{}'", rule), err)
+            })?);
+        } else if let Some(attr) = rule.strip_prefix("attr:") {
+            self.excluded_attrs.push(AttrMatch::new(attr)?);
+        } else {
+            self.excludes.push(Exclude::parse(rule));
+        }
+
+        Ok(())
+    }
+
+    /// Leaves out all entries of the given type, no matter where they are or
+    /// what else matches them.
+    pub fn strip(&mut self, ty: EntryType) {
+        if !self.stripped.contains(&ty) {
+            self.stripped.push(ty);
+        }
+    }
+
+    /// Decides what to do with submodules.
+    pub fn set_submodules(&mut self, submodules: Submodules) {
+        self.submodules = submodules;
+    }
+
+    /// Removes lines from `.gitignore` and `.gitattributes` files that only
+    /// refer to paths that were left out.
+    pub fn set_prune_patterns(&mut self) {
+        self.prune_patterns = true;
+    }
+
+    /// Leaves out all blobs bigger than the given number of bytes.
+    pub fn set_max_blob_size(&mut self, size: usize) {
+        self.max_blob_size = Some(size);
+    }
+
+    /// Leaves out the blob with the given ID, no matter where it is.
+    pub fn strip_blob(&mut self, oid: git2::Oid) {
+        self.stripped_blobs.insert(oid);
+    }
+
+    /// Moves a file or directory somewhere else in the rewritten trees. The
+    /// rule has the form `<old>:<new>`, such as `libfoo/src/:src/`. Missing
+    /// directories leading up to the new path are created.
+    pub fn insert_rename(&mut self, rule: &str) -> Result<(), Error> {
+        self.renames.push(Rename::parse(rule)?);
+        Ok(())
+    }
+
+    /// Makes the given directory the new root of the rewritten trees. Only the
+    /// contents of the directory are kept. Other rules still apply to the
+    /// paths as they were before the directory was moved.
+    pub fn set_subdirectory(&mut self, path: &str) -> Result<(), Error> {
+        let components = split_path(path, path)?;
+
+        if components.is_empty() {
+            return Err(Error::invalid(
+                format!("subdirectory '{}'", path),
+                "it must not be the root",
+            ));
+        }
+
+        self.subdirectory = Some(components);
+        Ok(())
+    }
+
+    /// Removes the given number of leading components from every path, such
+    /// that `third_party/vendor/foo/bar.c` becomes `foo/bar.c` with 2. This
+    /// happens after moving a subdirectory to the root, but before renames.
+    pub fn set_strip_components(&mut self, n: usize) {
+        self.strip_components = n;
+    }
+
+    /// Moves everything in the rewritten trees into the given directory, such
+    /// as `imported/foo/`. This happens after all other rules are applied.
+    pub fn set_prefix(&mut self, path: &str) -> Result<(), Error> {
+        self.prefix = split_path(path, path)?;
+        Ok(())
+    }
+
+    /// Lets a plugin keep, drop, or move each file after all other rules are
+    /// applied.
+    pub fn set_plugin(&mut self, plugin: Rc<Plugin>) {
+        if plugin.filters_paths() {
+            self.plugin = Some(plugin);
         }
     }
 
+    /// Lets a Lua script edit or drop each file after all other rules are
+    /// applied, including those of a plugin.
+    pub fn set_lua(&mut self, lua: Rc<LuaScript>) {
+        if lua.has_on_blob() {
+            self.lua = Some(lua);
+        }
+    }
+
+    /// Lets a closure decide whether to keep each file after all other rules
+    /// are applied. It runs before any plugin or Lua script.
+    pub fn set_entry_filter<F>(&mut self, f: F)
+    where
+        F: Fn(&str, &git2::TreeEntry<'_>) -> bool + 'static,
+    {
+        self.entry_filter = Some(EntryFilter(Rc::new(f)));
+    }
+
+    /// Leaves out every file whose contents match the regular expression,
+    /// such as `PROPRIETARY - DO NOT DISTRIBUTE`. The contents are looked at
+    /// before they are transformed.
+    pub fn insert_drop_content(
+        &mut self,
+        regex: &str,
+    ) -> Result<(), regex::Error> {
+        self.drop_contents.push(regex::bytes::Regex::new(regex)?);
+        Ok(())
+    }
+
+    /// Adds a transform to run the contents of every file that is left
+    /// through. Transforms that only apply to text leave out the files that
+    /// aren't. As with git, a file is text if it has the `text` attribute or
+    /// if it doesn't have `-text` and doesn't look binary.
+    pub fn insert_transform<T>(&mut self, transform: T)
+    where
+        T: BlobTransform + 'static,
+    {
+        self.transforms.insert(Rc::new(transform));
+    }
+
+    /// Removes the UTF-8 byte order mark from the start of every text file
+    /// that is left.
+    pub fn set_strip_bom(&mut self) {
+        self.insert_transform(StripBom);
+    }
+
+    /// Converts the line endings of every text file that is left.
+    pub fn set_normalize_eol(&mut self, eol: Eol) {
+        self.insert_transform(eol);
+    }
+
+    /// Replaces text in the contents of every text file that is left, such as
+    /// to remove leaked passwords.
+    pub fn set_replace_text(&mut self, replacements: Replacements) {
+        if !replacements.is_empty() {
+            self.insert_transform(replacements);
+        }
+    }
+
+    /// Redacts common kinds of secrets, such as access keys and private keys,
+    /// from every file that is left. Binary files are left alone.
+    pub fn set_redact_secrets(&mut self) {
+        self.secrets = Some(Secrets::new());
+    }
+
+    /// Runs a command for every file that is left to rewrite its contents. It
+    /// runs after the built-in transforms.
+    pub fn set_blob_callback(&mut self, callback: BlobCallback) {
+        self.insert_transform(callback);
+    }
+
+    /// Whether the names of files and directories are scrambled.
+    pub fn anonymize(&self) -> bool {
+        self.anonymize_paths
+    }
+
+    /// Scrambles the name of every file and directory, and their contents too
+    /// if `contents` is `true`, like `git fast-export --anonymize`. The shape
+    /// of the trees is kept. This happens after all other rules and hooks are
+    /// applied, except for converting files to LFS pointers.
+    pub fn set_anonymize(&mut self, contents: bool) {
+        self.anonymize_paths = true;
+        self.anonymize_contents = contents;
+    }
+
+    /// Sets the random seed that scrambled names and contents are derived
+    /// from, such as the one kept in the object map. It isn't part of the
+    /// hash of the filter.
+    pub fn set_anonymize_seed(&mut self, seed: Seed) {
+        self.anonymize_seed = seed;
+    }
+
+    /// Converts the files matching a pattern in `.gitattributes` syntax to
+    /// LFS pointers, such as `*.psd`. This happens last, after all other
+    /// rules and hooks are applied. The pattern is added to the
+    /// `.gitattributes` file at the root of every tree that isn't empty.
+    pub fn insert_lfs(&mut self, pattern: &str) -> Result<(), Error> {
+        self.lfs.insert(pattern)
+    }
+
+    /// Returns `true` if a closure has been given, in which case the filter
+    /// can't be told apart from others by its hash.
+    pub fn has_closures(&self) -> bool {
+        self.entry_filter.is_some()
+    }
+
+    /// Returns the paths to keep that don't contain any wildcards and don't
+    /// ignore case.
+    pub fn literal_paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        self.paths.literal_paths("", &mut paths);
+        paths
+    }
+
+    /// Returns `true` if no paths have been specified at all.
     pub fn is_empty(&self) -> bool {
-        self.filter.is_empty()
+        !self.has_includes()
+            && !self.has_excludes()
+            && self.subdirectory.is_none()
+            && self.strip_components == 0
+            && self.renames.is_empty()
+            && self.prefix.is_empty()
+            && !self.has_hooks()
     }
 
+    /// Returns `true` if anything looks at the files that are left after the
+    /// tree has been filtered and moved around.
+    fn has_hooks(&self) -> bool {
+        self.plugin.is_some()
+            || self.lua.is_some()
+            || self.entry_filter.is_some()
+            || !self.drop_contents.is_empty()
+            || !self.transforms.is_empty()
+            || self.secrets.is_some()
+            || !self.lfs.is_empty()
+            || self.anonymize_paths
+    }
+
+    /// Returns where the entry at the given path ends up once everything has
+    /// been moved around, or `None` if it is moved out of the tree.
+    fn moved_path(&self, path: &str) -> Option<String> {
+        let mut components = split_path(path, path).ok()?;
+
+        if let Some(subdirectory) = &self.subdirectory {
+            components = components.strip_prefix(&subdirectory[..])?.to_vec();
+        }
+
+        if components.len() <= self.strip_components {
+            return None;
+        }
+
+        components.drain(..self.strip_components);
+
+        let mut path = self.prefix.clone();
+        path.extend(rename_path(&self.renames, &components));
+
+        Some(path.join("/"))
+    }
+
+    /// Returns the name to give an entry in the rewritten tree.
+    fn entry_name<'a>(&self, name: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.anonymize_paths {
+            Cow::Owned(
+                anonymize::path_name(self.anonymize_seed, name).into_bytes(),
+            )
+        } else {
+            Cow::Borrowed(name)
+        }
+    }
+
+    /// Returns `true` if there are any rules for paths to include. If not,
+    /// everything that isn't excluded is kept.
+    fn has_includes(&self) -> bool {
+        !self.paths.is_empty() || self.matches_anywhere()
+    }
+
+    /// Returns `true` if there are any rules for paths to leave out.
+    fn has_excludes(&self) -> bool {
+        !self.excludes.is_empty()
+            || !self.stripped.is_empty()
+            || self.submodules != Submodules::Keep
+            || self.max_blob_size.is_some()
+            || !self.stripped_blobs.is_empty()
+            || !self.excluded_attrs.is_empty()
+            || self.pathspecs.iter().any(|spec| spec.is_exclude())
+    }
+
+    /// Returns `true` if there are rules that can match anywhere in the tree.
+    /// In that case, every tree needs to be looked at.
+    fn matches_anywhere(&self) -> bool {
+        !self.regexes.is_empty()
+            || !self.names.is_empty()
+            || !self.attrs.is_empty()
+            || self.pathspecs.iter().any(|spec| !spec.is_exclude())
+    }
+
+    /// Matches a single path component against a pattern. The pattern may
+    /// contain the glob wildcards `*`, `?`, and character classes such as
+    /// `[a-z]` or `[!0-9]`. Wildcards never match across path components.
     pub fn match_name(pattern: &str, name: &str) -> bool {
-        // TODO: Do proper pattern matching. This will complicate the
-        // implementation a bit.
-        pattern == "" || pattern == "**" || pattern == name
+        pattern.is_empty() || glob_match(pattern, name)
     }
 
-    /// Attempts to match a `TreeEntry` for each of the filters. If one matches,
-    /// returns a reference to that filter.
-    ///
-    /// FIXME: When glob pattern matching is implemented, there may be multiple
-    /// filters that can match. It would be better to return an iterator of the
-    /// matching filters.
-    pub fn match_entry(&self, entry: &git2::TreeEntry<'_>) -> Option<&Filter> {
-        for (pattern, filter) in &self.filter {
-            if Self::match_name(pattern.as_str(), entry.name().unwrap()) {
-                return Some(filter);
-            }
+    /// Returns `true` if the full path of an entry matches any of the regular
+    /// expressions or pathspecs, or if its name matches any of the name
+    /// patterns.
+    fn match_anywhere(&self, path: &str, name: &str) -> bool {
+        self.regexes.iter().any(|regex| regex.is_match(path))
+            || self.names.iter().any(|glob| glob.matches(name, self.icase))
+            || self.pathspecs.iter().any(|spec| {
+                !spec.is_exclude() && spec.matches(path, self.icase)
+            })
+    }
+
+    /// Returns `true` if the entry at the given path is excluded.
+    fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        // As with git, pathspecs with the `exclude` magic always win.
+        if self
+            .pathspecs
+            .iter()
+            .any(|spec| spec.is_exclude() && spec.matches(path, self.icase))
+        {
+            return true;
         }
 
-        None
+        if self.excludes.is_empty() {
+            return false;
+        }
+
+        let components: Vec<&str> = path.split('/').collect();
+
+        self.excludes
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(&components, is_dir, self.icase))
+            .is_some_and(|rule| !rule.negated)
+    }
+
+    /// Returns `true` if the `.gitattributes` files need to be read.
+    fn uses_attributes(&self) -> bool {
+        !self.attrs.is_empty() || !self.excluded_attrs.is_empty()
+    }
+
+    /// Returns `true` if the file at the given path has any of the attributes
+    /// to keep.
+    fn has_attr(&self, stack: &[Rc<AttrFile>], path: &str) -> bool {
+        self.attrs
+            .iter()
+            .any(|attr| attr.matches(stack, path, self.icase))
+    }
+
+    /// Returns `true` if the file at the given path has any of the attributes
+    /// to leave out.
+    fn has_excluded_attr(&self, stack: &[Rc<AttrFile>], path: &str) -> bool {
+        self.excluded_attrs
+            .iter()
+            .any(|attr| attr.matches(stack, path, self.icase))
+    }
+
+    /// Returns `true` if entries with the given file mode are left out.
+    fn is_stripped(&self, mode: i32) -> bool {
+        EntryType::from_mode(mode).is_some_and(|ty| self.stripped.contains(&ty))
     }
 }
 
-/// Rewrites a tree such that it only contains the entries specified by the tree
-/// filter. This function calls itself recursively to rewrite a tree.
-pub fn filter_tree(
-    repo: &git2::Repository,
-    map: &mut OidMap,
-    filter: &Filter,
-    tree: &git2::Tree<'_>,
-) -> Result<git2::Oid, git2::Error> {
-    match filter_tree_impl(repo, map, filter, tree)? {
-        Some(oid) => Ok(oid),
+/// A type of tree entry that can be left out with a `type:` rule.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryType {
+    /// A regular, non-executable file.
+    File,
 
-        // The tree is entirely empty. Building this tree will always yield the
-        // empty tree hash "4b825dc642cb6eb9a060e54bf8d69288fbee4904". Since we
-        // should only create an empty tree for the root tree (not subtrees), we
-        // don't do this in the recursive impl.
-        None => repo.treebuilder(None)?.write(),
+    /// An executable file.
+    Executable,
+
+    /// A symbolic link.
+    Symlink,
+
+    /// A submodule commit.
+    Gitlink,
+}
+
+impl EntryType {
+    fn from_mode(mode: i32) -> Option<EntryType> {
+        if mode == i32::from(git2::FileMode::Blob) {
+            Some(EntryType::File)
+        } else if mode == i32::from(git2::FileMode::BlobExecutable) {
+            Some(EntryType::Executable)
+        } else if mode == i32::from(git2::FileMode::Link) {
+            Some(EntryType::Symlink)
+        } else if mode == i32::from(git2::FileMode::Commit) {
+            Some(EntryType::Gitlink)
+        } else {
+            None
+        }
     }
 }
 
-fn filter_tree_impl(
-    repo: &git2::Repository,
-    map: &mut OidMap,
-    filter: &Filter,
-    tree: &git2::Tree<'_>,
-) -> Result<Option<git2::Oid>, git2::Error> {
-    if let Some(oid) = map.get(&tree.id()) {
-        // The work has already been done. Skip it.
-        return Ok(*oid);
+impl FromStr for EntryType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EntryType, String> {
+        match s {
+            "file" => Ok(EntryType::File),
+            "executable" => Ok(EntryType::Executable),
+            "symlink" => Ok(EntryType::Symlink),
+            "gitlink" | "submodule" => Ok(EntryType::Gitlink),
+            _ => Err(format!(
+                "Unknown entry type '{}'. Expected one of 'file', \
+                 'executable', 'symlink', or 'gitlink'",
+                s
+            )),
+        }
     }
+}
 
-    let mut builder = repo.treebuilder(None)?;
+/// What to do with the submodules in the trees being filtered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Submodules {
+    /// Treats them like any other file.
+    #[default]
+    Keep,
 
-    for entry in tree {
-        if let Some(filter) = filter.match_entry(&entry) {
-            if filter.is_empty() {
-                // There are no sub-filters. Match this tree entirely.
-                builder.insert(
-                    entry.name_bytes(),
-                    entry.id(),
-                    entry.filemode(),
-                )?;
-            } else if entry.kind() == Some(git2::ObjectType::Tree) {
-                // There are sub-filters and this is a tree object. Recurse into
-                // the tree with the sub-filter for further matching.
-                let obj = entry.to_object(repo)?;
-                let tree = obj.as_tree().unwrap();
-
-                if let Some(newtree) =
-                    filter_tree_impl(repo, map, filter, &tree)?
-                {
-                    builder.insert(
-                        entry.name_bytes(),
-                        newtree,
-                        entry.filemode(),
-                    )?;
-                }
-            }
+    /// Leaves all of them out.
+    Drop,
+
+    /// Fails if any of them would be kept.
+    Error,
+}
+
+impl FromStr for Submodules {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Submodules, String> {
+        match s {
+            "keep" => Ok(Submodules::Keep),
+            "drop" => Ok(Submodules::Drop),
+            "error" => Ok(Submodules::Error),
+            _ => Err(format!(
+                "Expected 'keep', 'drop', or 'error' but got '{}'",
+                s
+            )),
         }
     }
+}
 
-    if builder.len() == 0 {
-        // There are no entries in this tree. Don't write it out.
-        Ok(None)
-    } else {
-        let oid = builder.write()?;
+/// A rule in `.gitignore` syntax for paths to leave out.
+#[derive(Clone, Debug, Hash)]
+struct Exclude {
+    /// The components of the pattern.
+    pattern: Vec<String>,
 
-        // Cache it.
-        map.insert(tree.id(), Some(oid));
+    /// If `true`, the pattern does not contain a `/` and is matched against
+    /// the name of an entry at any depth.
+    basename: bool,
 
-        Ok(Some(oid))
+    /// If `true`, the rule started with a `!` and includes paths excluded by
+    /// a previous rule again.
+    negated: bool,
+
+    /// If `true`, the rule ended with a `/` and only matches directories.
+    dir_only: bool,
+}
+
+impl Exclude {
+    fn parse(rule: &str) -> Exclude {
+        let (negated, rule) = match rule.strip_prefix('!') {
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+
+        // A leading `!` or `#` can be escaped with a backslash.
+        let rule = if rule.starts_with("\\!") || rule.starts_with("\\#") {
+            &rule[1..]
+        } else {
+            rule
+        };
+
+        let (dir_only, rule) = match rule.strip_suffix('/') {
+            Some(rule) => (true, rule),
+            None => (false, rule),
+        };
+
+        let basename = !rule.contains('/');
+        let rule = rule.strip_prefix('/').unwrap_or(rule);
+
+        Exclude {
+            pattern: rule.split('/').map(String::from).collect(),
+            basename,
+            negated,
+            dir_only,
+        }
+    }
+
+    fn matches(&self, path: &[&str], is_dir: bool, icase: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.basename {
+            path.last().is_some_and(|name| {
+                glob_match_path(&self.pattern, &[name], icase)
+            })
+        } else {
+            glob_match_path(&self.pattern, path, icase)
+        }
+    }
+}
+
+/// A glob pattern for a single path component.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Pattern {
+    glob: String,
+
+    /// If `true`, the pattern ignores case. The glob is then stored in lower
+    /// case so that rules that only differ in case share the same node.
+    icase: bool,
+}
+
+impl Pattern {
+    fn new(glob: &str, icase: bool) -> Pattern {
+        Pattern {
+            glob: if icase {
+                glob.to_lowercase()
+            } else {
+                glob.to_string()
+            },
+            icase,
+        }
+    }
+
+    /// Returns `true` if the pattern only matches a name equal to it.
+    fn is_literal(&self) -> bool {
+        !self.icase && !self.glob.contains(['*', '?', '[', '\\'])
+    }
+
+    fn is_double_star(&self) -> bool {
+        self.glob == "**"
+    }
+
+    fn matches(&self, name: &str, icase: bool) -> bool {
+        if self.icase || icase {
+            glob_match_icase(&self.glob, name)
+        } else {
+            Filter::match_name(&self.glob, name)
+        }
+    }
+}
+
+/// A tree of path components. Each path inserted into the filter becomes a
+/// chain of nodes. A node without any children matches everything below it.
+#[derive(Clone, Debug, Default, Hash)]
+struct Node {
+    children: BTreeMap<Pattern, Node>,
+}
+
+impl Node {
+    fn insert(&mut self, path: &Path, icase: bool) {
+        let mut components = path.components();
+
+        if let Some(Component::Normal(c)) = components.next() {
+            let node = self
+                .children
+                .entry(Pattern::new(c.to_str().unwrap(), icase))
+                .or_default();
+
+            // Insert the rest of the components recursively.
+            node.insert(components.as_path(), icase);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Adds the paths below this node without any wildcards to the list.
+    fn literal_paths(&self, prefix: &str, paths: &mut Vec<String>) {
+        for (pattern, node) in &self.children {
+            if !pattern.is_literal() {
+                continue;
+            }
+
+            let path = format!("{}{}", prefix, pattern.glob);
+
+            if node.is_empty() {
+                paths.push(path);
+            } else {
+                node.literal_paths(&(path + "/"), paths);
+            }
+        }
+    }
+
+    /// Returns all of the child nodes whose pattern matches the given name.
+    /// With wildcards, more than one pattern can match the same entry. For
+    /// example, both `*.rs` and `main.*` match `main.rs`.
+    ///
+    /// `**` patterns are not included here. They can match any number of
+    /// directories and are handled by `Active` instead.
+    fn match_entry<'a, 'b>(
+        &'a self,
+        name: &'b str,
+        icase: bool,
+    ) -> impl Iterator<Item = &'a Node> + 'b
+    where
+        'a: 'b,
+    {
+        self.children.iter().filter_map(move |(pattern, node)| {
+            if !pattern.is_double_star() && pattern.matches(name, icase) {
+                Some(node)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// A node of the path tree that applies to the tree currently being
+/// rewritten.
+#[derive(Clone, Copy, Debug, Hash)]
+struct Active<'a> {
+    node: &'a Node,
+
+    /// If `true`, the node was reached through a `**` component. It then keeps
+    /// applying to every directory below the one where it was reached.
+    floating: bool,
+}
+
+impl<'a> Active<'a> {
+    fn new(node: &'a Node, floating: bool) -> Active<'a> {
+        Active { node, floating }
+    }
+
+    fn same(&self, other: &Active<'_>) -> bool {
+        std::ptr::eq(self.node, other.node) && self.floating == other.floating
+    }
+
+    /// Adds a node to the set unless it is already in there. Several paths
+    /// through the tree can lead to the same `**` node.
+    fn push(set: &mut Vec<Active<'a>>, active: Active<'a>) {
+        if !set.iter().any(|a| a.same(&active)) {
+            set.push(active);
+        }
+    }
+
+    /// Adds the nodes reached through `**` components to the set. Since a `**`
+    /// also matches zero directories, its children apply at the current level
+    /// too.
+    fn expand(set: &mut Vec<Active<'a>>) {
+        let mut i = 0;
+
+        while i < set.len() {
+            let node = set[i].node;
+
+            for (pattern, node) in &node.children {
+                if pattern.is_double_star() {
+                    Self::push(set, Active::new(node, true));
+                }
+            }
+
+            i += 1;
+        }
+    }
+
+    /// Returns the set of nodes that apply to the contents of the entry with
+    /// the given name. The set is empty if the entry doesn't match at all.
+    ///
+    /// The set given here must already be expanded. The returned set is not,
+    /// since a `**` only applies inside of a directory.
+    fn match_entry(
+        set: &[Active<'a>],
+        name: &str,
+        icase: bool,
+    ) -> Vec<Active<'a>> {
+        let mut matches = Vec::new();
+
+        for active in set {
+            if active.floating {
+                // The `**` swallows this entry.
+                Self::push(&mut matches, *active);
+            }
+
+            for node in active.node.match_entry(name, icase) {
+                Self::push(&mut matches, Active::new(node, false));
+            }
+        }
+
+        matches
+    }
+}
+
+/// The location of a tree being rewritten and the rules that apply to it.
+#[derive(Clone, Debug, Default, Hash)]
+struct Scope<'a> {
+    /// Path of the tree. This is either empty or ends with a `/`.
+    path: String,
+
+    /// Nodes of the path tree that apply to the tree.
+    active: Vec<Active<'a>>,
+
+    /// If `true`, a parent directory was matched entirely and everything in
+    /// the tree is kept unless it is excluded.
+    included: bool,
+
+    /// The `.gitattributes` files of the tree and its parent directories,
+    /// starting at the root.
+    attributes: Vec<Rc<AttrFile>>,
+}
+
+/// Returns the key used to cache the result of filtering a tree. The same tree
+/// can appear in several places where a different set of rules applies, so the
+/// key must account for both the tree and its scope.
+fn cache_key(
+    tree: git2::Oid,
+    scope: &Scope<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    let mut hasher = DefaultHasher::new();
+    scope.hash(&mut hasher);
+
+    let mut data = tree.as_bytes().to_vec();
+    data.extend_from_slice(&hasher.finish().to_le_bytes());
+
+    git2::Oid::hash_object(git2::ObjectType::Blob, &data)
+}
+
+/// Rewrites a tree such that it only contains the entries specified by the tree
+/// filter. This function calls itself recursively to rewrite a tree.
+pub fn filter_tree(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    report: &mut Report,
+    tree: &git2::Tree<'_>,
+) -> Result<git2::Oid, git2::Error> {
+    let scope = Scope {
+        active: vec![Active::new(&filter.paths, false)],
+        included: !filter.has_includes(),
+        ..Scope::default()
+    };
+
+    let oid = filter_tree_impl(repo, map, filter, report, scope, tree)?;
+
+    let submodules = report.take_submodules();
+
+    if !submodules.is_empty() {
+        return Err(git2::Error::from_str(&format!(
+            "Submodules aren't allowed, but found {}",
+            submodules.join(", ")
+        )));
+    }
+
+    let oid = match oid {
+        Some(oid) => oid,
+
+        // The tree is entirely empty. Building this tree will always yield the
+        // empty tree hash "4b825dc642cb6eb9a060e54bf8d69288fbee4904". Since we
+        // should only create an empty tree for the root tree (not subtrees), we
+        // don't do this in the recursive impl.
+        None => repo.treebuilder(None)?.write()?,
+    };
+
+    let oid = if filter.prune_patterns {
+        prune_patterns(repo, map, tree, oid)?
+    } else {
+        oid
+    };
+
+    let oid = prune_gitmodules(repo, map, tree, oid)?;
+
+    let moved = move_entries(repo, map, filter, oid)?;
+    let mut oid = move_gitmodules(repo, map, filter, oid, moved)?;
+
+    if filter.has_hooks() {
+        oid = hook_tree(repo, map, filter, report, oid)?;
+    }
+
+    Ok(oid)
+}
+
+/// Removes the submodules that were left out from the `.gitmodules` file at
+/// the root of a filtered tree, so that `git submodule init` still works.
+/// Only submodules that were in the original tree but aren't in the filtered
+/// one are removed. Nothing has been moved yet, so both trees have the same
+/// layout. The result is cached by both trees.
+fn prune_gitmodules(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    original: &git2::Tree<'_>,
+    tree: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    if tree == original.id() {
+        return Ok(tree);
+    }
+
+    let mut data = b"prune-gitmodules ".to_vec();
+    data.extend_from_slice(original.id().as_bytes());
+    data.extend_from_slice(tree.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let filtered = repo.find_tree(tree)?;
+
+    let entry = match filtered.get_name(".gitmodules") {
+        Some(entry) if is_file(entry.filemode()) => entry,
+        _ => return Ok(tree),
+    };
+
+    let is_gitlink = |tree: &git2::Tree<'_>, path: &str| {
+        tree.get_path(Path::new(path)).is_ok_and(|entry| {
+            entry.filemode() == i32::from(git2::FileMode::Commit)
+        })
+    };
+
+    let blob = repo.find_blob(entry.id())?;
+    let contents = gitmodules::retain(blob.content(), |path| {
+        !is_gitlink(original, path) || is_gitlink(&filtered, path)
+    });
+
+    let oid = match contents {
+        Some(contents) => {
+            let mut builder = repo.treebuilder(Some(&filtered))?;
+
+            if contents.iter().all(u8::is_ascii_whitespace) {
+                builder.remove(".gitmodules")?;
+            } else {
+                builder.insert(
+                    ".gitmodules",
+                    repo.blob(&contents)?,
+                    entry.filemode(),
+                )?;
+            }
+
+            builder.write()?
+        }
+        None => tree,
+    };
+
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Puts the `.gitmodules` file at the root of a filtered tree back at the root
+/// once everything has been moved around, with the path of each submodule
+/// moved along with it. Submodules that are moved out of the tree are left
+/// out. The result is cached by the trees from before and after the move.
+fn move_gitmodules(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    tree: git2::Oid,
+    moved: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    if tree == moved {
+        return Ok(moved);
+    }
+
+    let mut data = b"move-gitmodules ".to_vec();
+    data.extend_from_slice(tree.as_bytes());
+    data.extend_from_slice(moved.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let entry = match repo.find_tree(tree)?.get_name(".gitmodules") {
+        Some(entry) if is_file(entry.filemode()) => {
+            (entry.id(), entry.filemode())
+        }
+        _ => return Ok(moved),
+    };
+
+    let blob = repo.find_blob(entry.0)?;
+    let contents =
+        gitmodules::move_paths(blob.content(), |path| filter.moved_path(path))
+            .unwrap_or_else(|| blob.content().to_vec());
+
+    // Wherever the file itself was moved to, it doesn't belong there.
+    let mut oid = moved;
+
+    if let Some(path) = filter.moved_path(".gitmodules") {
+        let path = split_path(&path, &path)
+            .map_err(|err| git2::Error::from_str(&err.to_string()))?;
+
+        if let Some((taken, rest)) = take(repo, oid, &path)? {
+            if taken == entry {
+                oid = rest;
+            }
+        }
+    }
+
+    let tree = repo.find_tree(oid)?;
+    let mut builder = repo.treebuilder(Some(&tree))?;
+
+    if contents.iter().all(u8::is_ascii_whitespace) {
+        if builder.get(".gitmodules")?.is_some() {
+            builder.remove(".gitmodules")?;
+        }
+    } else {
+        builder.insert(".gitmodules", repo.blob(&contents)?, entry.1)?;
+    }
+
+    let oid = builder.write()?;
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Removes the lines of the `.gitignore` and `.gitattributes` files in a
+/// filtered tree that only refer to paths that were in the original tree but
+/// were left out. Nothing has been moved yet, so both trees have the same
+/// layout. A file is removed if nothing but whitespace is left in it.
+fn prune_patterns(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    original: &git2::Tree<'_>,
+    tree: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    if tree == original.id() {
+        return Ok(tree);
+    }
+
+    let mut data = b"prune-patterns ".to_vec();
+    data.extend_from_slice(original.id().as_bytes());
+    data.extend_from_slice(tree.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let filtered = repo.find_tree(tree)?;
+
+    // A path is gone if it, or one of its parents, was in the original tree
+    // but isn't in the filtered one.
+    let keep = |components: &[&str]| {
+        for n in 1..=components.len() {
+            let path: std::path::PathBuf = components[..n].iter().collect();
+
+            match (original.get_path(&path), filtered.get_path(&path)) {
+                (Ok(_), Err(_)) => return false,
+                (Ok(_), Ok(_)) => continue,
+                (Err(_), _) => return true,
+            }
+        }
+
+        true
+    };
+
+    let mut builder = repo.treebuilder(Some(&filtered))?;
+
+    for entry in filtered.iter() {
+        let name = match entry.name() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if entry.kind() == Some(git2::ObjectType::Tree) {
+            let subtree = match original.get_name(name) {
+                Some(original) => match original.to_object(repo)?.into_tree() {
+                    Ok(subtree) => subtree,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+
+            let oid = prune_patterns(repo, map, &subtree, entry.id())?;
+
+            if oid != entry.id() {
+                builder.insert(name, oid, entry.filemode())?;
+            }
+        } else if let (Some(kind), true) =
+            (patterns::Kind::from_name(name), is_file(entry.filemode()))
+        {
+            let blob = repo.find_blob(entry.id())?;
+
+            if let Some(contents) = patterns::retain(blob.content(), kind, keep)
+            {
+                if contents.iter().all(u8::is_ascii_whitespace) {
+                    builder.remove(name)?;
+                } else {
+                    builder.insert(
+                        name,
+                        repo.blob(&contents)?,
+                        entry.filemode(),
+                    )?;
+                }
+            }
+        }
+    }
+
+    let oid = builder.write()?;
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Applies the rules for moving things around to a filtered tree.
+fn move_entries(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    oid: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    if filter.subdirectory.is_none()
+        && filter.strip_components == 0
+        && filter.renames.is_empty()
+        && filter.prefix.is_empty()
+    {
+        return Ok(oid);
+    }
+
+    // Moving things around only depends on the filtered tree, so it is cached
+    // by it.
+    let mut data = b"rename ".to_vec();
+    data.extend_from_slice(oid.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let oid = match &filter.subdirectory {
+        Some(path) => match subtree(repo, oid, path)? {
+            Some(oid) => oid,
+            None => repo.treebuilder(None)?.write()?,
+        },
+        None => oid,
+    };
+
+    let oid = strip_components(repo, oid, filter.strip_components)?;
+    let oid = rename_tree(repo, &filter.renames, oid)?;
+    let oid = prefix_tree(repo, oid, &filter.prefix)?;
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Asks the entry filter, the plugin, and the Lua script what to do with every
+/// file in a tree, changes the contents of the files that are left, and
+/// returns the new tree. Files that are moved are put into place after all
+/// others are dealt with, so it is an error if one ends up where a file already
+/// is.
+fn hook_tree(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    report: &mut Report,
+    tree: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let mut moved = Vec::new();
+
+    let scope = Scope::default();
+    let oid = hook_subtree(repo, map, filter, report, tree, scope, &mut moved)?;
+
+    let mut oid = match oid {
+        Some(oid) => oid,
+        None => repo.treebuilder(None)?.write()?,
+    };
+
+    for (path, entry) in moved {
+        let mut path = split_path(&path, &path).map_err(|err| {
+            git2::Error::from_str(&format!(
+                "Plugin returned a bad path: {}",
+                err
+            ))
+        })?;
+
+        if filter.anonymize_paths {
+            for name in &mut path {
+                *name = anonymize::path_name(
+                    filter.anonymize_seed,
+                    name.as_bytes(),
+                );
+            }
+        }
+
+        oid = put(repo, oid, "", &path, entry)?;
+    }
+
+    if !filter.lfs.is_empty() && !repo.find_tree(oid)?.is_empty() {
+        oid = lfs_attributes(repo, map, &filter.lfs, oid)?;
+    }
+
+    Ok(oid)
+}
+
+fn is_file(mode: i32) -> bool {
+    mode == i32::from(git2::FileMode::Blob)
+        || mode == i32::from(git2::FileMode::BlobExecutable)
+}
+
+/// Returns `true` if the contents look like those of a binary file. Like Git,
+/// this looks for a null byte near the start.
+pub fn is_binary(contents: &[u8]) -> bool {
+    contents[..contents.len().min(8000)].contains(&0)
+}
+
+/// Returns `true` if the contents of a blob match any of the regular
+/// expressions for files to leave out, in which case it is recorded in the
+/// report along with its path. The result is cached by the blob.
+fn drop_content(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    report: &mut Report,
+    blob: git2::Oid,
+    path: &str,
+) -> Result<bool, git2::Error> {
+    let mut data = b"drop-content ".to_vec();
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    let dropped = match map.get(&key) {
+        Some(oid) => oid.is_none(),
+        None => {
+            let contents = repo.find_blob(blob)?;
+            let dropped = filter
+                .drop_contents
+                .iter()
+                .any(|regex| regex.is_match(contents.content()));
+
+            map.insert(key, if dropped { None } else { Some(blob) });
+            dropped
+        }
+    };
+
+    if dropped {
+        report.matched_content(blob, path);
+    }
+
+    Ok(dropped)
+}
+
+/// Runs a blob through the transforms and returns the new blob. The `text`
+/// attribute of the file decides whether it is text, unless it is unspecified,
+/// in which case binary blobs aren't. The result is cached by the blob.
+fn transform_blob(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    transforms: &Pipeline,
+    text: AttrState,
+    blob: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let mut hasher = DefaultHasher::new();
+    transforms.hash(&mut hasher);
+    text.hash(&mut hasher);
+
+    let mut data = b"transform ".to_vec();
+    data.extend_from_slice(&hasher.finish().to_le_bytes());
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let contents = repo.find_blob(blob)?;
+
+    let text = match text {
+        AttrState::Set => true,
+        AttrState::Unset => false,
+        _ => !is_binary(contents.content()),
+    };
+
+    let oid = match transforms.apply(blob, contents.content(), text)? {
+        Some(contents) => repo.blob(&contents)?,
+        None => blob,
+    };
+
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Redacts the secrets in a blob and returns the new blob. The secrets that are
+/// found are recorded in the report along with the path of the blob. The
+/// result is cached by the blob.
+fn redact_secrets(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    secrets: &Secrets,
+    report: &mut Report,
+    blob: git2::Oid,
+    path: &str,
+) -> Result<git2::Oid, git2::Error> {
+    let mut data = b"redact-secrets ".to_vec();
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        if oid != blob {
+            report.secret_path(blob, path);
+        }
+
+        return Ok(oid);
+    }
+
+    let contents = repo.find_blob(blob)?;
+
+    let oid = if is_binary(contents.content()) {
+        blob
+    } else {
+        let (redacted, found) = secrets.redact(contents.content());
+
+        for kind in found {
+            report.secret(blob, kind, path);
+        }
+
+        match redacted {
+            Cow::Borrowed(_) => blob,
+            Cow::Owned(redacted) => repo.blob(&redacted)?,
+        }
+    };
+
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Scrambles the contents of a blob and returns the new blob. The result is
+/// cached by the blob.
+fn anonymize_blob(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    seed: Seed,
+    blob: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let mut data = b"anonymize ".to_vec();
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let oid = repo.blob(&anonymize::contents(seed, blob))?;
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Converts a blob to an LFS pointer and returns the pointer. The result is
+/// cached by the blob.
+fn lfs_convert(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    lfs: &LfsConvert,
+    blob: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let mut data = b"lfs ".to_vec();
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(Some(oid)) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let oid = match lfs.convert(repo, repo.find_blob(blob)?.content())? {
+        Some(pointer) => repo.blob(&pointer)?,
+        None => blob,
+    };
+
+    map.insert(key, Some(oid));
+
+    Ok(oid)
+}
+
+/// Adds the LFS patterns to the `.gitattributes` file at the root of a tree,
+/// creating it if needed.
+fn lfs_attributes(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    lfs: &LfsConvert,
+    tree: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let tree = repo.find_tree(tree)?;
+
+    let (blob, mode) = match tree.get_name(".gitattributes") {
+        Some(entry) if is_file(entry.filemode()) => {
+            (entry.id(), entry.filemode())
+        }
+        Some(_) => return Ok(tree.id()),
+        None => (repo.blob(b"")?, i32::from(git2::FileMode::Blob)),
+    };
+
+    let mut data = b"lfs-attributes ".to_vec();
+    data.extend_from_slice(blob.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    let new = match map.get(&key) {
+        Some(Some(oid)) => oid,
+        _ => {
+            let contents = repo.find_blob(blob)?;
+
+            let oid = match lfs.attributes(contents.content()) {
+                Some(contents) => repo.blob(&contents)?,
+                None => blob,
+            };
+
+            map.insert(key, Some(oid));
+            oid
+        }
+    };
+
+    if tree.get_name(".gitattributes").map(|e| e.id()) == Some(new) {
+        return Ok(tree.id());
+    }
+
+    let mut builder = repo.treebuilder(Some(&tree))?;
+    builder.insert(".gitattributes", new, mode)?;
+    builder.write()
+}
+
+/// Rebuilds a tree at the given path without the files that are dropped or
+/// moved, which are added to `moved` along with where they go. Returns `None`
+/// if nothing is left.
+///
+/// The hooks only see paths and contents, so the result for a tree at a path
+/// is cached, unless something in it is moved.
+fn hook_subtree(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    report: &mut Report,
+    tree: git2::Oid,
+    mut scope: Scope<'_>,
+    moved: &mut Vec<(String, Entry)>,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    // Only the path and attributes of the scope are used here. The same tree
+    // can have different attributes depending on where it is.
+    let mut data = b"hooks ".to_vec();
+    data.extend_from_slice(cache_key(tree, &scope)?.as_bytes());
+    let key = git2::Oid::hash_object(git2::ObjectType::Blob, &data)?;
+
+    if let Some(oid) = map.get(&key) {
+        return Ok(oid);
+    }
+
+    let count = moved.len();
+    let mut builder = repo.treebuilder(None)?;
+    let tree = repo.find_tree(tree)?;
+
+    if !filter.transforms.is_empty() {
+        if let Some(entry) = tree.get_name(".gitattributes") {
+            if let Ok(blob) = repo.find_blob(entry.id()) {
+                scope.attributes.push(Rc::new(AttrFile::parse(
+                    &scope.path,
+                    blob.id(),
+                    blob.content(),
+                )));
+            }
+        }
+    }
+
+    for entry in tree.iter() {
+        let name = String::from_utf8_lossy(entry.name_bytes());
+        let path = format!("{}{}", scope.path, name);
+
+        if entry.kind() == Some(git2::ObjectType::Tree) {
+            let subtree = hook_subtree(
+                repo,
+                map,
+                filter,
+                report,
+                entry.id(),
+                Scope {
+                    path: path + "/",
+                    attributes: scope.attributes.clone(),
+                    ..Scope::default()
+                },
+                moved,
+            )?;
+
+            if let Some(subtree) = subtree {
+                builder.insert(
+                    &*filter.entry_name(entry.name_bytes()),
+                    subtree,
+                    entry.filemode(),
+                )?;
+            }
+
+            continue;
+        }
+
+        if let Some(EntryFilter(keep)) = &filter.entry_filter {
+            if !keep(&path, &entry) {
+                continue;
+            }
+        }
+
+        let mut to = None;
+
+        if let Some(plugin) = &filter.plugin {
+            match plugin.filter_path(&path)? {
+                PathAction::Keep => {}
+                PathAction::Drop => continue,
+                PathAction::Rename(path) => to = Some(path),
+            }
+        }
+
+        let mut oid = entry.id();
+
+        if is_file(entry.filemode())
+            && !filter.drop_contents.is_empty()
+            && drop_content(repo, map, filter, report, oid, &path)?
+        {
+            continue;
+        }
+
+        if is_file(entry.filemode()) && !filter.transforms.is_empty() {
+            let text = lookup(&scope.attributes, &path, "text", filter.icase);
+            oid = transform_blob(repo, map, &filter.transforms, text, oid)?;
+        }
+
+        if let (true, Some(secrets)) =
+            (is_file(entry.filemode()), &filter.secrets)
+        {
+            oid = redact_secrets(repo, map, secrets, report, oid, &path)?;
+        }
+
+        if let (Some(lua), Some(git2::ObjectType::Blob)) =
+            (&filter.lua, entry.kind())
+        {
+            let blob = repo.find_blob(oid)?;
+            let path = to.as_deref().unwrap_or(&path);
+
+            match lua.on_blob(path, blob.content())? {
+                BlobAction::Keep => {}
+                BlobAction::Drop => continue,
+                BlobAction::Replace(contents) => oid = repo.blob(&contents)?,
+            }
+        }
+
+        if let (true, Some(git2::ObjectType::Blob)) =
+            (filter.anonymize_contents, entry.kind())
+        {
+            oid = anonymize_blob(repo, map, filter.anonymize_seed, oid)?;
+        }
+
+        if is_file(entry.filemode()) {
+            let path = to.as_deref().unwrap_or(&path);
+
+            if filter.lfs.matches(path) {
+                oid = lfs_convert(repo, map, &filter.lfs, oid)?;
+            }
+        }
+
+        match to {
+            Some(to) => moved.push((to, (oid, entry.filemode()))),
+            None => {
+                let name = filter.entry_name(entry.name_bytes());
+                builder.insert(&*name, oid, entry.filemode())?;
+            }
+        }
+    }
+
+    let oid = if builder.is_empty() {
+        None
+    } else {
+        Some(builder.write()?)
+    };
+
+    if moved.len() == count {
+        map.insert(key, oid);
+    }
+
+    Ok(oid)
+}
+
+fn filter_tree_impl(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    filter: &Filter,
+    report: &mut Report,
+    mut scope: Scope<'_>,
+    tree: &git2::Tree<'_>,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    Active::expand(&mut scope.active);
+
+    let key = cache_key(tree.id(), &scope)?;
+
+    if let Some(oid) = map.get(&key) {
+        // The work has already been done. Skip it.
+        return Ok(oid);
+    }
+
+    if filter.uses_attributes() {
+        if let Some(entry) = tree.get_name(".gitattributes") {
+            if let Ok(blob) = repo.find_blob(entry.id()) {
+                scope.attributes.push(Rc::new(AttrFile::parse(
+                    &scope.path,
+                    blob.id(),
+                    blob.content(),
+                )));
+            }
+        }
+    }
+
+    let mut builder = repo.treebuilder(None)?;
+
+    for entry in tree {
+        let name = String::from_utf8_lossy(entry.name_bytes());
+        let path = format!("{}{}", scope.path, name);
+        let is_tree = entry.kind() == Some(git2::ObjectType::Tree);
+        let is_gitlink = entry.filemode() == i32::from(git2::FileMode::Commit);
+
+        if is_gitlink && filter.submodules == Submodules::Drop {
+            continue;
+        }
+
+        let mut excluded = filter.is_stripped(entry.filemode())
+            || filter.is_excluded(&path, is_tree)
+            || (!is_tree && filter.has_excluded_attr(&scope.attributes, &path));
+
+        if !excluded && !is_tree && filter.stripped_blobs.contains(&entry.id())
+        {
+            if !filter.invert {
+                report.stripped_blob(entry.id(), &path);
+            }
+
+            excluded = true;
+        }
+
+        if let (false, Some(max), Some(git2::ObjectType::Blob)) =
+            (excluded, filter.max_blob_size, entry.kind())
+        {
+            // Only the header needs to be read to find out the size.
+            let (size, _) = repo.odb()?.read_header(entry.id())?;
+
+            if size > max {
+                if !filter.invert {
+                    report.big_blob(entry.id(), size, &path);
+                }
+
+                excluded = true;
+            }
+        }
+
+        if excluded {
+            if filter.invert {
+                if is_gitlink && filter.submodules == Submodules::Error {
+                    report.submodule(&path);
+                }
+
+                builder.insert(
+                    entry.name_bytes(),
+                    entry.id(),
+                    entry.filemode(),
+                )?;
+            }
+
+            continue;
+        }
+
+        let matches = Active::match_entry(&scope.active, &name, filter.icase);
+
+        let included = scope.included
+            || matches.iter().any(|active| active.node.is_empty())
+            || filter.match_anywhere(&path, &name)
+            || (!is_tree && filter.has_attr(&scope.attributes, &path));
+
+        if included && (!is_tree || !filter.has_excludes()) {
+            // There are no sub-filters. Match this tree entirely.
+            if !filter.invert {
+                if is_gitlink && filter.submodules == Submodules::Error {
+                    report.submodule(&path);
+                }
+
+                builder.insert(
+                    entry.name_bytes(),
+                    entry.id(),
+                    entry.filemode(),
+                )?;
+            }
+        } else if is_tree
+            && (included || !matches.is_empty() || filter.matches_anywhere())
+        {
+            // There are sub-filters and this is a tree object. Recurse into
+            // the tree with the sub-filters for further matching. Regular
+            // expressions and name patterns can match anything below this tree
+            // and exclude rules can remove anything below it, so we have to
+            // look inside of it in those cases too.
+            let obj = entry.to_object(repo)?;
+            let tree = obj.as_tree().unwrap();
+
+            let scope = Scope {
+                path: path + "/",
+                active: if included { Vec::new() } else { matches },
+                included,
+                attributes: scope.attributes.clone(),
+            };
+
+            if let Some(newtree) =
+                filter_tree_impl(repo, map, filter, report, scope, tree)?
+            {
+                builder.insert(
+                    entry.name_bytes(),
+                    newtree,
+                    entry.filemode(),
+                )?;
+            }
+        } else if filter.invert {
+            // Nothing in here is matched, so all of it is kept.
+            builder.insert(entry.name_bytes(), entry.id(), entry.filemode())?;
+        }
+    }
+
+    if builder.is_empty() {
+        // There are no entries in this tree. Don't write it out. This is
+        // cached too so that we don't have to look at the tree again.
+        map.insert(key, None);
+        Ok(None)
+    } else {
+        let oid = builder.write()?;
+
+        // Cache it.
+        map.insert(key, Some(oid));
+
+        Ok(Some(oid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `true` if the filter keeps the given path. A path is kept if
+    /// it or any of its parent directories is matched entirely.
+    fn keeps(filter: &Filter, path: &str) -> bool {
+        if filter.is_excluded(path, false) {
+            return false;
+        }
+
+        let name = path.rsplit('/').next().unwrap();
+
+        if filter.match_anywhere(path, name) {
+            return true;
+        }
+
+        let mut set = vec![Active::new(&filter.paths, false)];
+
+        for name in path.split('/') {
+            Active::expand(&mut set);
+            set = Active::match_entry(&set, name, filter.icase);
+
+            if set.iter().any(|a| a.node.is_empty()) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    #[test]
+    fn test_match_name() {
+        assert!(Filter::match_name("", "foo"));
+        assert!(Filter::match_name("foo", "foo"));
+        assert!(!Filter::match_name("foo", "bar"));
+
+        assert!(Filter::match_name("*", "foo"));
+        assert!(Filter::match_name("**", "foo"));
+        assert!(Filter::match_name("*.rs", "main.rs"));
+        assert!(Filter::match_name("*.rs", ".rs"));
+        assert!(!Filter::match_name("*.rs", "main.rsx"));
+        assert!(Filter::match_name("*a*b*", "xaybz"));
+        assert!(!Filter::match_name("*a*b", "xaybz"));
+
+        assert!(Filter::match_name("?.c", "a.c"));
+        assert!(!Filter::match_name("?.c", "ab.c"));
+
+        assert!(Filter::match_name("[abc].txt", "b.txt"));
+        assert!(!Filter::match_name("[abc].txt", "d.txt"));
+        assert!(Filter::match_name("v[0-9]", "v7"));
+        assert!(!Filter::match_name("v[!0-9]", "v7"));
+        assert!(Filter::match_name("[]]", "]"));
+        assert!(Filter::match_name("[", "["));
+        assert!(Filter::match_name("\\*", "*"));
+        assert!(!Filter::match_name("\\*", "a"));
+    }
+
+    #[test]
+    fn test_double_star() {
+        let mut filter = Filter::new();
+        filter.insert(Path::new("**/testdata"));
+        filter.insert(Path::new("vendor/**/*.min.js"));
+        filter.insert(Path::new("docs/**"));
+
+        assert!(keeps(&filter, "testdata"));
+        assert!(keeps(&filter, "src/testdata/foo"));
+        assert!(keeps(&filter, "a/b/c/testdata"));
+        assert!(!keeps(&filter, "src/main.rs"));
+
+        assert!(keeps(&filter, "vendor/app.min.js"));
+        assert!(keeps(&filter, "vendor/a/b/app.min.js"));
+        assert!(!keeps(&filter, "vendor/a/b/app.js"));
+        assert!(!keeps(&filter, "app.min.js"));
+
+        assert!(keeps(&filter, "docs/a/b"));
+        assert!(!keeps(&filter, "docs"));
+    }
+
+    #[test]
+    fn test_regex() {
+        let filter = Filter::from_reader(
+            &b"regex:src/.*\\.rs\nregex:(foo|bar)/baz\n"[..],
+        )
+        .unwrap();
+
+        assert!(keeps(&filter, "src/main.rs"));
+        assert!(keeps(&filter, "src/a/b.rs"));
+        assert!(!keeps(&filter, "src/main.rsx"));
+        assert!(!keeps(&filter, "lib/src/main.rs"));
+        assert!(keeps(&filter, "bar/baz"));
+        assert!(!keeps(&filter, "bar/bazz"));
+
+        assert!(Filter::from_reader(&b"regex:(\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_excludes() {
+        let mut filter = Filter::new();
+        filter.insert(Path::new("src"));
+        filter
+            .read_excludes(
+                &b"# Comment\n*.o\n!keep.o\nbuild/\n/src/gen\n\\!bang\n"[..],
+            )
+            .unwrap();
+
+        assert!(filter.is_excluded("src/main.o", false));
+        assert!(filter.is_excluded("src/a/b/main.o", false));
+        assert!(!filter.is_excluded("src/keep.o", false));
+        assert!(filter.is_excluded("src/build", true));
+        assert!(!filter.is_excluded("src/build", false));
+        assert!(filter.is_excluded("src/gen", true));
+        assert!(!filter.is_excluded("src/a/src/gen", true));
+        assert!(filter.is_excluded("src/!bang", false));
+
+        assert!(keeps(&filter, "src/main.c"));
+        assert!(!keeps(&filter, "src/main.o"));
+    }
+
+    #[test]
+    fn test_glob() {
+        let filter =
+            Filter::from_reader(&b"glob:*.proto\nglob:Cargo.toml\n"[..])
+                .unwrap();
+
+        assert!(keeps(&filter, "a.proto"));
+        assert!(keeps(&filter, "a/b/c.proto"));
+        assert!(keeps(&filter, "Cargo.toml"));
+        assert!(keeps(&filter, "src/Cargo.toml"));
+        assert!(!keeps(&filter, "src/Cargo.lock"));
+
+        assert!(Filter::from_reader(&b"glob:src/*.rs\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_icase() {
+        let mut filter = Filter::from_reader(
+            &b"icase:docs/readme*\nicase:DOCS/guide\nicase:glob:*.PNG\nsrc\n"[..],
+        )
+        .unwrap();
+
+        assert!(keeps(&filter, "Docs/README.md"));
+        assert!(keeps(&filter, "DOCS/readme"));
+        assert!(keeps(&filter, "docs/Guide"));
+        assert!(keeps(&filter, "a/b.png"));
+        assert!(keeps(&filter, "src/main.rs"));
+        assert!(!keeps(&filter, "SRC/main.rs"));
+
+        // Rules differing only in case end up in the same node.
+        assert_eq!(filter.paths.children.len(), 2);
+
+        filter.set_icase();
+        filter.insert_regex("lib/.*").unwrap();
+        filter.insert_exclude("*.TMP").unwrap();
+
+        assert!(keeps(&filter, "SRC/main.rs"));
+        assert!(keeps(&filter, "LIB/foo"));
+        assert!(!keeps(&filter, "src/foo.tmp"));
+    }
+
+    #[test]
+    fn test_pathspec() {
+        let filter = Filter::from_reader(
+            &b"src\n:(exclude)src/gen\n:(glob)**/*.md\nicase::readme\n"[..],
+        )
+        .unwrap();
+
+        assert!(keeps(&filter, "src/main.rs"));
+        assert!(!keeps(&filter, "src/gen"));
+        assert!(keeps(&filter, "docs/guide.md"));
+        assert!(keeps(&filter, "README"));
+        assert!(!keeps(&filter, "docs/guide.txt"));
+    }
+
+    #[test]
+    fn test_strip() {
+        let mut filter = Filter::new();
+        filter.insert_exclude("type:symlink").unwrap();
+        filter.insert_exclude("type:submodule").unwrap();
+        assert!(filter.insert_exclude("type:socket").is_err());
+
+        assert!(filter.is_stripped(0o120000));
+        assert!(filter.is_stripped(0o160000));
+        assert!(!filter.is_stripped(0o100644));
+        assert!(!filter.is_stripped(0o040000));
     }
 }
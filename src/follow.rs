@@ -0,0 +1,138 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Following renamed files and directories back through history.
+
+use crate::filter::Filter;
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Looks for files and directories to keep that were renamed at some point
+/// and extends the filter with their former paths, so that their history from
+/// before the rename is kept too. The commits must be given newest first so
+/// that a directory renamed more than once can be followed all the way back.
+///
+/// Only plain paths without wildcards are followed. A path counts as renamed
+/// in a commit if it doesn't exist in a parent commit and git's rename
+/// detection finds that the file (or the files in the directory) came from
+/// somewhere else.
+///
+/// Returns pairs of the paths that were followed and their former paths.
+pub fn follow_renames<I>(
+    repo: &git2::Repository,
+    commits: I,
+    filter: &mut Filter,
+) -> Result<Vec<(String, String)>, git2::Error>
+where
+    I: IntoIterator<Item = git2::Oid>,
+{
+    let mut followed = filter.literal_paths();
+    let mut found = Vec::new();
+
+    for id in commits {
+        let commit = repo.find_commit(id)?;
+        let tree = commit.tree()?;
+
+        for parent in commit.parents() {
+            let parent = parent.tree()?;
+
+            // Paths found here are followed further back too.
+            let mut i = 0;
+
+            while i < followed.len() {
+                let path = followed[i].clone();
+                i += 1;
+
+                let followable = match tree.get_path(Path::new(&path)) {
+                    Ok(entry) => matches!(
+                        entry.kind(),
+                        Some(git2::ObjectType::Tree)
+                            | Some(git2::ObjectType::Blob)
+                    ),
+                    Err(_) => false,
+                };
+
+                if !followable || parent.get_path(Path::new(&path)).is_ok() {
+                    continue;
+                }
+
+                for old in former_paths(repo, &parent, &tree, &path)? {
+                    if !followed.contains(&old) {
+                        filter.insert(Path::new(&old));
+                        followed.push(old.clone());
+                        found.push((path.clone(), old));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// Returns the paths that the file at `path` (or the files in the directory
+/// at `path`) were renamed from between two trees.
+fn former_paths(
+    repo: &git2::Repository,
+    old: &git2::Tree<'_>,
+    new: &git2::Tree<'_>,
+    path: &str,
+) -> Result<BTreeSet<String>, git2::Error> {
+    let mut diff = repo.diff_tree_to_tree(Some(old), Some(new), None)?;
+    diff.find_similar(Some(git2::DiffFindOptions::new().renames(true)))?;
+
+    let mut paths = BTreeSet::new();
+
+    for delta in diff.deltas() {
+        if delta.status() != git2::Delta::Renamed {
+            continue;
+        }
+
+        let (old, new) =
+            match (delta.old_file().path(), delta.new_file().path()) {
+                (Some(old), Some(new)) => (old, new),
+                _ => continue,
+            };
+
+        // The part of the path below the directory must stay the same for
+        // this to count as the directory being renamed. For a file, this is
+        // empty.
+        let rest = match new.strip_prefix(path) {
+            Ok(rest) => rest,
+            Err(_) => continue,
+        };
+
+        let old = old.to_str().unwrap_or_default();
+        let rest = rest.to_str().unwrap_or_default();
+
+        if let Some(former) = old.strip_suffix(rest) {
+            let former = former.trim_end_matches('/');
+
+            // Moving the root of the repository into a directory can't be
+            // followed, since that would keep everything.
+            if !former.is_empty() && former != path {
+                paths.insert(former.to_string());
+            }
+        }
+    }
+
+    Ok(paths)
+}
@@ -0,0 +1,210 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting `.gitmodules` files so that they only list the submodules that
+//! are left, at the paths they were moved to.
+
+/// A `[submodule "<name>"]` section of a `.gitmodules` file.
+struct Section<'a> {
+    /// The lines of the section, including the header.
+    lines: Vec<&'a [u8]>,
+
+    /// The value of its `path` key, if any.
+    path: Option<String>,
+}
+
+/// Returns a `path = <value>` line with another value, keeping everything up
+/// to the `=` and the line ending as they are.
+fn set_path(line: &[u8], path: &str) -> Vec<u8> {
+    let equals = line.iter().position(|&b| b == b'=').unwrap_or(line.len());
+    let end = line.len() - line.trim_ascii_end().len();
+
+    let mut out = line[..equals].to_vec();
+    out.extend_from_slice(b"= ");
+    out.extend_from_slice(path.as_bytes());
+    out.extend_from_slice(&line[line.len() - end..]);
+    out
+}
+
+/// Returns the value of a `path = <value>` line, if it is one.
+fn parse_path(line: &[u8]) -> Option<String> {
+    let line = String::from_utf8_lossy(line);
+    let (key, value) = line.split_once('=')?;
+
+    if !key.trim().eq_ignore_ascii_case("path") {
+        return None;
+    }
+
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+
+    Some(value.to_string())
+}
+
+/// Removes the sections of a `.gitmodules` file whose path `keep` returns
+/// `false` for. Lines before the first section and sections without a path are
+/// kept as they are. Returns `None` if nothing is removed.
+pub fn retain<F>(contents: &[u8], mut keep: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&str) -> bool,
+{
+    move_paths(contents, |path| keep(path).then(|| path.to_string()))
+}
+
+/// Changes the path of each section of a `.gitmodules` file to what `map`
+/// returns for it, or removes the section if it returns `None`. Lines before
+/// the first section and sections without a path are kept as they are.
+/// Returns `None` if nothing is changed.
+pub fn move_paths<F>(contents: &[u8], mut map: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut preamble = Vec::new();
+    let mut sections: Vec<Section<'_>> = Vec::new();
+
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        if line.trim_ascii_start().starts_with(b"[") {
+            sections.push(Section {
+                lines: Vec::new(),
+                path: None,
+            });
+        }
+
+        match sections.last_mut() {
+            Some(section) => {
+                if section.path.is_none() {
+                    section.path = parse_path(line);
+                }
+
+                section.lines.push(line);
+            }
+            None => preamble.push(line),
+        }
+    }
+
+    let mut changed = false;
+    let mut out = preamble.concat();
+
+    for section in sections {
+        let path = match &section.path {
+            Some(path) => path,
+            None => {
+                out.extend(section.lines.concat());
+                continue;
+            }
+        };
+
+        let new = match map(path) {
+            Some(new) => new,
+            None => {
+                changed = true;
+                continue;
+            }
+        };
+
+        for line in section.lines {
+            if new != *path && parse_path(line).as_ref() == Some(path) {
+                out.extend(set_path(line, &new));
+                changed = true;
+            } else {
+                out.extend_from_slice(line);
+            }
+        }
+    }
+
+    if changed {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retain() {
+        let contents = b"\
+            # Submodules\n\
+            [submodule \"a\"]\n\
+            \tpath = lib/a\n\
+            \turl = https://example.com/a.git\n\
+            [submodule \"b\"]\n\
+            \tpath = \"lib/b\"\n\
+            \turl = https://example.com/b.git\n";
+
+        assert_eq!(retain(contents, |_| true), None);
+
+        assert_eq!(
+            retain(contents, |path| path != "lib/b").unwrap(),
+            &b"\
+            # Submodules\n\
+            [submodule \"a\"]\n\
+            \tpath = lib/a\n\
+            \turl = https://example.com/a.git\n"[..]
+        );
+
+        assert_eq!(
+            retain(contents, |_| false).unwrap(),
+            &b"# Submodules\n"[..]
+        );
+    }
+
+    #[test]
+    fn test_move_paths() {
+        let contents = b"\
+            [submodule \"a\"]\n\
+            \tpath = lib/a\n\
+            \turl = https://example.com/a.git\n\
+            [submodule \"b\"]\n\
+            \tpath = \"lib/b\"\r\n\
+            \turl = https://example.com/b.git\n";
+
+        assert_eq!(move_paths(contents, |path| Some(path.into())), None);
+
+        assert_eq!(
+            move_paths(contents, |path| path
+                .strip_prefix("lib/")
+                .map(|name| format!("vendor/{}", name)))
+            .unwrap(),
+            &b"\
+            [submodule \"a\"]\n\
+            \tpath = vendor/a\n\
+            \turl = https://example.com/a.git\n\
+            [submodule \"b\"]\n\
+            \tpath = vendor/b\r\n\
+            \turl = https://example.com/b.git\n"[..]
+        );
+
+        assert_eq!(
+            move_paths(contents, |path| (path == "lib/a")
+                .then(|| "a".to_string()))
+            .unwrap(),
+            &b"\
+            [submodule \"a\"]\n\
+            \tpath = a\n\
+            \turl = https://example.com/a.git\n"[..]
+        );
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Glob pattern matching.
+
+/// Matches `name` against a glob `pattern`.
+///
+/// `*` matches any sequence of characters, `?` matches any single character,
+/// and `[...]` matches any single character in the set. A set starting with `!`
+/// or `^` is negated. A backslash escapes the following character.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+
+    // Position to backtrack to when a mismatch occurs after a `*`. That is,
+    // the index of the pattern after the `*` and the index of the name that
+    // the `*` should be extended to cover.
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let matched = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern, p, name[n]),
+            Some('\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == name[n] {
+                    Some(p + 2)
+                } else {
+                    None
+                }
+            }
+            Some(&c) if c == name[n] => Some(p + 1),
+            _ => None,
+        };
+
+        match matched {
+            Some(next) => {
+                p = next;
+                n += 1;
+            }
+            None => match backtrack {
+                Some((bp, bn)) => {
+                    // Let the last `*` consume one more character.
+                    backtrack = Some((bp, bn + 1));
+                    p = bp;
+                    n = bn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    // Any remaining pattern characters must all be `*`.
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Like `glob_match`, but ignores case.
+pub fn glob_match_icase(pattern: &str, name: &str) -> bool {
+    glob_match(&pattern.to_lowercase(), &name.to_lowercase())
+}
+
+/// Matches the character class starting at `pattern[start]` (which must be a
+/// `[`) against `c`. On a match, returns the index just past the closing `]`.
+/// If the class is not terminated, the `[` is treated as a literal character.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start + 1;
+
+    let negated = match pattern.get(i) {
+        Some('!') | Some('^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let lo = match pattern.get(i) {
+            // A `]` immediately after the opening bracket is a literal.
+            Some(']') if !first => break,
+            Some(&lo) => lo,
+            None => {
+                // Unterminated class. Treat the `[` literally.
+                return if c == '[' { Some(start + 1) } else { None };
+            }
+        };
+
+        first = false;
+
+        if pattern.get(i + 1) == Some(&'-')
+            && pattern.get(i + 2).is_some_and(|&hi| hi != ']')
+        {
+            let hi = pattern[i + 2];
+            if lo <= c && c <= hi {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    if matched != negated {
+        Some(i + 1)
+    } else {
+        None
+    }
+}
+
+/// Matches a path against a glob pattern, both given as lists of components.
+/// Wildcards in a component never match a `/`. A component consisting of only
+/// `**` matches zero or more components. If `icase` is `true`, case is ignored.
+pub fn glob_match_path<P, N>(pattern: &[P], path: &[N], icase: bool) -> bool
+where
+    P: AsRef<str>,
+    N: AsRef<str>,
+{
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first.as_ref() == "**" => {
+            // Try to let the `**` swallow as few components as possible.
+            (0..=path.len()).any(|i| glob_match_path(rest, &path[i..], icase))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                let matched = if icase {
+                    glob_match_icase(first.as_ref(), name.as_ref())
+                } else {
+                    glob_match(first.as_ref(), name.as_ref())
+                };
+
+                matched && glob_match_path(rest, path, icase)
+            }
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_path(pattern: &str, path: &str) -> bool {
+        let pattern: Vec<_> = pattern.split('/').collect();
+        let path: Vec<_> = path.split('/').collect();
+        glob_match_path(&pattern, &path, false)
+    }
+
+    #[test]
+    fn test_glob_match_path() {
+        assert!(match_path("src/*.rs", "src/main.rs"));
+        assert!(!match_path("src/*.rs", "src/a/main.rs"));
+        assert!(!match_path("*", "src/main.rs"));
+        assert!(match_path("**/foo", "foo"));
+        assert!(match_path("**/foo", "a/b/foo"));
+        assert!(match_path("a/**/b", "a/b"));
+        assert!(match_path("a/**/b", "a/x/y/b"));
+        assert!(!match_path("a/**/b", "a/x/y/c"));
+        assert!(match_path("a/**", "a/x/y"));
+    }
+
+    #[test]
+    fn test_glob_match_icase() {
+        assert!(glob_match_icase("readme*", "README.md"));
+        assert!(glob_match_icase("[A-C]", "b"));
+        assert!(!glob_match("readme*", "README.md"));
+    }
+}
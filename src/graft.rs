@@ -0,0 +1,229 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Replace references and grafts, which change what commits look like without
+//! rewriting them.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+
+use crate::map::{saved_maps, OidMap};
+
+/// How many replacements of a replacement are followed, as with Git.
+const MAX_REPLACE_DEPTH: usize = 5;
+
+/// Commits replaced with `git replace` and parents given to commits in
+/// `info/grafts`. Git shows history as if these changes were made, so history
+/// is walked the same way here. Replacements of objects other than commits are
+/// ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Grafts {
+    /// Commits and the commits they are replaced by.
+    replaced: HashMap<git2::Oid, git2::Oid>,
+
+    /// Commits and the parents they are given instead of their own.
+    parents: HashMap<git2::Oid, Vec<git2::Oid>>,
+}
+
+impl Grafts {
+    pub fn new() -> Grafts {
+        Grafts::default()
+    }
+
+    /// Loads the replace references under `refs/replace/` and the grafts in
+    /// `info/grafts`, if there are any.
+    pub fn load(repo: &git2::Repository) -> Result<Grafts, git2::Error> {
+        let mut grafts = Grafts::new();
+        let odb = repo.odb()?;
+
+        let is_commit = |oid: git2::Oid| {
+            odb.read_header(oid)
+                .is_ok_and(|(_, kind)| kind == git2::ObjectType::Commit)
+        };
+
+        for reference in repo.references_glob("refs/replace/*")? {
+            let reference = reference?;
+
+            let (name, target) = match (reference.name(), reference.target()) {
+                (Some(name), Some(target)) => (name, target),
+                _ => continue,
+            };
+
+            if let Ok(replaced) =
+                git2::Oid::from_str(&name["refs/replace/".len()..])
+            {
+                if is_commit(replaced) && is_commit(target) {
+                    grafts.replaced.insert(replaced, target);
+                }
+            }
+        }
+
+        if let Ok(contents) =
+            fs::read_to_string(repo.path().join("info").join("grafts"))
+        {
+            grafts.parse(&contents)?;
+        }
+
+        Ok(grafts)
+    }
+
+    /// Parses grafts, one per line. Each line is the commit followed by its
+    /// new parents, separated by spaces. A commit without parents becomes a
+    /// root commit.
+    fn parse(&mut self, contents: &str) -> Result<(), git2::Error> {
+        for line in contents.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut oids = line.split_whitespace().map(|oid| {
+                git2::Oid::from_str(oid).map_err(|_| {
+                    git2::Error::from_str(&format!("Invalid graft '{}'", line))
+                })
+            });
+
+            if let Some(commit) = oids.next() {
+                self.parents
+                    .insert(commit?, oids.collect::<Result<_, _>>()?);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replaced.is_empty() && self.parents.is_empty()
+    }
+
+    /// Leaves out the replacements by commits that the map rewrote other
+    /// commits to. These are the ones written by `--replace-refs`.
+    pub fn ignore_rewritten(&mut self, map: &OidMap) {
+        if self.replaced.is_empty() {
+            return;
+        }
+
+        let targets: HashSet<_> = self.replaced.values().copied().collect();
+
+        let rewritten: HashSet<_> = map
+            .iter()
+            .filter_map(|(k, v)| v.filter(|v| *v != k && targets.contains(v)))
+            .collect();
+
+        self.replaced
+            .retain(|_, target| !rewritten.contains(target));
+    }
+
+    /// Leaves out the replacements by commits that any of the maps saved
+    /// under `.git/subset/` rewrote other commits to. Not only the map for the
+    /// current filter is checked, since the replace references may have been
+    /// written while rewriting with another one.
+    pub fn ignore_saved(&mut self, repo: &git2::Repository) -> io::Result<()> {
+        if self.replaced.is_empty() {
+            return Ok(());
+        }
+
+        for saved in saved_maps(repo)? {
+            let in_db = saved.paths.iter().any(|path| {
+                path.extension().is_some_and(|ext| ext == "sqlite")
+            });
+
+            let map = if in_db && cfg!(feature = "sqlite") {
+                OidMap::open_sqlite(repo, &saved.name)?
+            } else {
+                OidMap::from_repo(repo, &saved.name)?
+            };
+
+            self.ignore_rewritten(&map);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the commit that is shown in place of the given one.
+    pub fn find_commit<'r>(
+        &self,
+        repo: &'r git2::Repository,
+        mut id: git2::Oid,
+    ) -> Result<git2::Commit<'r>, git2::Error> {
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match self.replaced.get(&id) {
+                Some(&replacement) => id = replacement,
+                None => break,
+            }
+        }
+
+        repo.find_commit(id)
+    }
+
+    /// Returns the parents of a commit, given the commit found for it with
+    /// `find_commit`. Grafts apply to the original commit, not to what it is
+    /// replaced by.
+    pub fn parent_ids(
+        &self,
+        id: git2::Oid,
+        commit: &git2::Commit<'_>,
+    ) -> Vec<git2::Oid> {
+        match self.parents.get(&id) {
+            Some(parents) => parents.clone(),
+            None => commit.parent_ids().collect(),
+        }
+    }
+}
+
+impl Hash for Grafts {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Hash maps have no order of their own.
+        let mut replaced: Vec<_> = self.replaced.iter().collect();
+        replaced.sort();
+        replaced.hash(state);
+
+        let mut parents: Vec<_> = self.parents.iter().collect();
+        parents.sort();
+        parents.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let a = "0000000000000000000000000000000000000001";
+        let b = "0000000000000000000000000000000000000002";
+
+        let mut grafts = Grafts::new();
+        grafts
+            .parse(&format!("# Grafts\n\n{} {}\n{}\n", a, b, b))
+            .unwrap();
+
+        let a = git2::Oid::from_str(a).unwrap();
+        let b = git2::Oid::from_str(b).unwrap();
+
+        assert_eq!(grafts.parents[&a], [b]);
+        assert!(grafts.parents[&b].is_empty());
+
+        assert!(grafts.parse("xyz").is_err());
+    }
+}
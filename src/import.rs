@@ -0,0 +1,636 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reading history from a `git fast-import` stream, such as one written by
+//! `git fast-export`.
+//!
+//! Blobs, commits, tags, resets, and all of the file commands are supported.
+//! Commands that ask for a response, such as `cat-blob`, are not. Dates must
+//! be in the `raw` format, which is what `git fast-export` writes. Commits and
+//! tags are written exactly as they are described, so importing an export of a
+//! repository gives the same hashes.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead};
+
+use crate::Error;
+
+/// The changes to a tree that haven't been written yet.
+struct TreeEdit<'r> {
+    tree: git2::Tree<'r>,
+    pending: BTreeMap<Vec<u8>, Option<(git2::Oid, git2::FileMode)>>,
+}
+
+impl<'r> TreeEdit<'r> {
+    /// Returns true if a path is one of the pending ones or is inside or
+    /// above one of them.
+    fn overlaps(&self, path: &[u8]) -> bool {
+        let inside = |parent: &[u8], child: &[u8]| {
+            child.len() > parent.len()
+                && child.starts_with(parent)
+                && child[parent.len()] == b'/'
+        };
+
+        self.pending.keys().any(|other| {
+            other == path || inside(other, path) || inside(path, other)
+        })
+    }
+
+    /// Queues up a change, writing the pending ones first if they would get in
+    /// the way of it.
+    fn set(
+        &mut self,
+        repo: &'r git2::Repository,
+        path: Vec<u8>,
+        entry: Option<(git2::Oid, git2::FileMode)>,
+    ) -> Result<(), git2::Error> {
+        if self.overlaps(&path) {
+            self.write(repo)?;
+        }
+
+        self.pending.insert(path, entry);
+        Ok(())
+    }
+
+    /// Writes the pending changes, returning the new tree.
+    fn write(
+        &mut self,
+        repo: &'r git2::Repository,
+    ) -> Result<git2::Oid, git2::Error> {
+        if self.pending.is_empty() {
+            return Ok(self.tree.id());
+        }
+
+        let mut builder = git2::build::TreeUpdateBuilder::new();
+
+        for (path, entry) in std::mem::take(&mut self.pending) {
+            match entry {
+                Some((id, mode)) => builder.upsert(path, id, mode),
+                None => builder.remove(path),
+            };
+        }
+
+        let id = builder.create_updated(repo, &self.tree)?;
+        self.tree = repo.find_tree(id)?;
+        Ok(id)
+    }
+
+    /// Returns the entry at a path, taking the pending changes into account.
+    fn get(
+        &mut self,
+        repo: &'r git2::Repository,
+        path: &[u8],
+    ) -> Result<(git2::Oid, git2::FileMode), Error> {
+        self.write(repo)?;
+
+        let name = String::from_utf8_lossy(path);
+        let entry = self.tree.get_path(name.as_ref().as_ref())?;
+        let mode = file_mode(&format!("{:o}", entry.filemode()))?;
+        Ok((entry.id(), mode))
+    }
+}
+
+/// Parses a file mode, including the short forms `644` and `755`.
+fn file_mode(mode: &str) -> Result<git2::FileMode, Error> {
+    Ok(match mode {
+        "644" | "100644" => git2::FileMode::Blob,
+        "755" | "100755" => git2::FileMode::BlobExecutable,
+        "120000" => git2::FileMode::Link,
+        "160000" => git2::FileMode::Commit,
+        "40000" | "040000" => git2::FileMode::Tree,
+        _ => {
+            return Err(Error::invalid(
+                format!("file mode '{}'", mode),
+                "unknown",
+            ))
+        }
+    })
+}
+
+/// Parses a path at the start of a line, which may be quoted like a C string,
+/// returning it along with the rest of the line. If it isn't quoted, it ends
+/// at the first space if `last` is false, or at the end of the line otherwise.
+fn parse_path(line: &[u8], last: bool) -> Result<(Vec<u8>, &[u8]), Error> {
+    let invalid = || {
+        Error::invalid(
+            format!("path '{}'", String::from_utf8_lossy(line)),
+            "it is not quoted properly",
+        )
+    };
+
+    if !line.starts_with(b"\"") {
+        return Ok(match line.iter().position(|&c| c == b' ') {
+            Some(i) if !last => (line[..i].to_vec(), &line[i + 1..]),
+            _ => (line.to_vec(), &[]),
+        });
+    }
+
+    let mut path = Vec::new();
+    let mut i = 1;
+
+    while i < line.len() {
+        match line[i] {
+            b'"' => {
+                let rest = &line[i + 1..];
+                return Ok((path, rest.strip_prefix(b" ").unwrap_or(rest)));
+            }
+            b'\\' => {
+                let c = *line.get(i + 1).ok_or_else(invalid)?;
+                i += 2;
+
+                path.push(match c {
+                    b'a' => 7,
+                    b'b' => 8,
+                    b'f' => 12,
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'v' => 11,
+                    b'0'..=b'3' => {
+                        let digits =
+                            line.get(i - 1..i + 2).ok_or_else(invalid)?;
+                        i += 2;
+                        std::str::from_utf8(digits)
+                            .ok()
+                            .and_then(|digits| {
+                                u8::from_str_radix(digits, 8).ok()
+                            })
+                            .ok_or_else(invalid)?
+                    }
+                    c => c,
+                });
+            }
+            c => {
+                path.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    Err(invalid())
+}
+
+fn unexpected(line: &[u8]) -> Error {
+    Error::invalid(
+        format!("command '{}'", String::from_utf8_lossy(line)),
+        "it is not supported",
+    )
+}
+
+/// Writes the objects described by a stream into a repository. References are
+/// only tracked, not created, since that is up to the caller.
+pub struct Importer<'r, R> {
+    repo: &'r git2::Repository,
+    reader: R,
+
+    /// A line that was read but not used yet.
+    peeked: Option<Vec<u8>>,
+
+    marks: HashMap<u64, git2::Oid>,
+
+    /// Every reference the stream updates, in the order they first appear. A
+    /// reference is reset if it has no target.
+    refs: Vec<(String, Option<git2::Oid>)>,
+}
+
+impl<'r, R: BufRead> Importer<'r, R> {
+    pub fn new(repo: &'r git2::Repository, reader: R) -> Self {
+        Importer {
+            repo,
+            reader,
+            peeked: None,
+            marks: HashMap::new(),
+            refs: Vec::new(),
+        }
+    }
+
+    /// Adds marks from an earlier stream, such as those read from a marks file,
+    /// so that this stream can refer to them.
+    pub fn import_marks(&mut self, marks: &[(u64, git2::Oid)]) {
+        self.marks.extend(marks.iter().cloned());
+    }
+
+    /// Returns the mark of every object, including those from earlier streams.
+    pub fn marks(&self) -> Vec<(u64, git2::Oid)> {
+        self.marks.iter().map(|(&mark, &id)| (mark, id)).collect()
+    }
+
+    /// Returns the references that were updated, in the order they first
+    /// appeared, leaving out those that were reset and never updated again.
+    pub fn refs(&self) -> Vec<(String, git2::Oid)> {
+        self.refs
+            .iter()
+            .filter_map(|(name, id)| id.map(|id| (name.clone(), id)))
+            .collect()
+    }
+
+    /// Returns the next line without its newline, or `None` at the end.
+    fn next_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = Vec::new();
+
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+
+        Ok(Some(line))
+    }
+
+    /// Returns the next line if it starts with the given prefix, without the
+    /// prefix. Otherwise, the line is left for later.
+    fn next_if(&mut self, prefix: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.next_line()? {
+            Some(line) if line.starts_with(prefix.as_bytes()) => {
+                Ok(Some(line[prefix.len()..].to_vec()))
+            }
+            line => {
+                self.peeked = line;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads the contents given by a `data` command.
+    fn read_data(&mut self) -> Result<Vec<u8>, Error> {
+        let spec = self
+            .next_if("data ")?
+            .ok_or_else(|| Error::Other("Expected a `data` command".into()))?;
+
+        if let Some(delimiter) = spec.strip_prefix(b"<<") {
+            let mut data = Vec::new();
+
+            loop {
+                match self.next_line()? {
+                    Some(line) if line == delimiter => return Ok(data),
+                    Some(line) => {
+                        data.extend(line);
+                        data.push(b'\n');
+                    }
+                    None => {
+                        return Err(Error::Other(
+                            "Unexpected end of stream in `data`".into(),
+                        ))
+                    }
+                }
+            }
+        }
+
+        let len = std::str::from_utf8(&spec)
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(|| {
+                Error::invalid(
+                    format!("data '{}'", String::from_utf8_lossy(&spec)),
+                    "expected a length",
+                )
+            })?;
+
+        let mut data = vec![0; len];
+        self.reader.read_exact(&mut data)?;
+
+        // The data may be followed by a newline.
+        if self.reader.fill_buf()?.starts_with(b"\n") {
+            self.reader.consume(1);
+        }
+
+        Ok(data)
+    }
+
+    /// Reads an optional `mark :<n>` command.
+    fn read_mark(&mut self) -> Result<Option<u64>, Error> {
+        match self.next_if("mark :")? {
+            Some(mark) => std::str::from_utf8(&mark)
+                .ok()
+                .and_then(|mark| mark.parse().ok())
+                .map(Some)
+                .ok_or_else(|| {
+                    Error::invalid(
+                        format!("mark ':{}'", String::from_utf8_lossy(&mark)),
+                        "expected a number",
+                    )
+                }),
+            None => Ok(None),
+        }
+    }
+
+    /// Resolves a mark, an object ID, or the name of a branch.
+    fn resolve(&self, spec: &[u8]) -> Result<git2::Oid, Error> {
+        let spec = String::from_utf8_lossy(spec);
+
+        let found = if let Some(mark) = spec.strip_prefix(':') {
+            mark.parse()
+                .ok()
+                .and_then(|mark| self.marks.get(&mark).cloned())
+        } else if let Ok(id) = git2::Oid::from_str(&spec) {
+            Some(id).filter(|_| spec.len() == 40)
+        } else {
+            self.refs
+                .iter()
+                .find(|(name, _)| *name == spec)
+                .and_then(|(_, id)| *id)
+        };
+
+        match found {
+            Some(id) => Ok(id),
+            None => Ok(self.repo.revparse_single(&spec)?.id()),
+        }
+    }
+
+    /// Points a reference at a new target, or resets it.
+    fn set_ref(&mut self, name: &str, target: Option<git2::Oid>) {
+        match self.refs.iter_mut().find(|(other, _)| other == name) {
+            Some((_, id)) => *id = target,
+            None => self.refs.push((name.to_string(), target)),
+        }
+    }
+
+    /// Returns the current target of a reference in the stream.
+    fn get_ref(&self, name: &str) -> Option<git2::Oid> {
+        self.refs
+            .iter()
+            .find(|(other, _)| other == name)
+            .and_then(|(_, id)| *id)
+    }
+
+    /// Imports the whole stream.
+    pub fn import(&mut self) -> Result<(), Error> {
+        while let Some(line) = self.next_line()? {
+            let command = String::from_utf8_lossy(&line).into_owned();
+
+            if command == "blob" {
+                self.blob()?;
+            } else if let Some(name) = command.strip_prefix("commit ") {
+                self.commit(name)?;
+            } else if let Some(name) = command.strip_prefix("tag ") {
+                self.tag(name)?;
+            } else if let Some(name) = command.strip_prefix("reset ") {
+                let target = match self.next_if("from ")? {
+                    Some(from) => Some(self.resolve(&from)?),
+                    None => None,
+                };
+
+                self.set_ref(name, target);
+            } else if command == "alias" {
+                let mark = self.read_mark()?;
+                let to =
+                    self.next_if("to ")?.ok_or_else(|| unexpected(&line))?;
+                let to = self.resolve(&to)?;
+
+                if let Some(mark) = mark {
+                    self.marks.insert(mark, to);
+                }
+            } else if command == "done" {
+                break;
+            } else if command.is_empty()
+                || command.starts_with('#')
+                || command.starts_with("feature ")
+                || command.starts_with("option ")
+                || command.starts_with("progress ")
+                || command == "checkpoint"
+            {
+                continue;
+            } else {
+                return Err(unexpected(&line));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn blob(&mut self) -> Result<(), Error> {
+        let mark = self.read_mark()?;
+        self.next_if("original-oid ")?;
+
+        let id = self.repo.blob(&self.read_data()?)?;
+
+        if let Some(mark) = mark {
+            self.marks.insert(mark, id);
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self, name: &str) -> Result<(), Error> {
+        let mark = self.read_mark()?;
+        self.next_if("original-oid ")?;
+
+        let author = self.next_if("author ")?;
+        let committer = self.next_if("committer ")?.ok_or_else(|| {
+            Error::Other(format!("Expected a committer for '{}'", name))
+        })?;
+        let encoding = self.next_if("encoding ")?;
+        let message = self.read_data()?;
+
+        // Without `from`, the commit continues the branch.
+        let mut parents = Vec::new();
+
+        match self.next_if("from ")? {
+            Some(from) => parents.push(self.resolve(&from)?),
+            None => parents.extend(self.get_ref(name)),
+        }
+
+        while let Some(merge) = self.next_if("merge ")? {
+            parents.push(self.resolve(&merge)?);
+        }
+
+        let repo = self.repo;
+
+        let tree = match parents.first() {
+            Some(parent) => repo.find_commit(*parent)?.tree()?,
+            None => repo.find_tree(repo.treebuilder(None)?.write()?)?,
+        };
+
+        let mut edit = TreeEdit {
+            tree,
+            pending: BTreeMap::new(),
+        };
+
+        self.file_commands(&mut edit)?;
+
+        let tree = edit.write(repo)?;
+
+        let mut raw = format!("tree {}\n", tree).into_bytes();
+
+        for parent in &parents {
+            raw.extend(format!("parent {}\n", parent).as_bytes());
+        }
+
+        for (kind, ident) in
+            [("author", author.as_ref()), ("committer", Some(&committer))]
+        {
+            raw.extend(kind.as_bytes());
+            raw.push(b' ');
+            raw.extend(ident.unwrap_or(&committer));
+            raw.push(b'\n');
+        }
+
+        if let Some(encoding) = encoding {
+            raw.extend(b"encoding ");
+            raw.extend(encoding);
+            raw.push(b'\n');
+        }
+
+        raw.push(b'\n');
+        raw.extend(message);
+
+        let id = repo.odb()?.write(git2::ObjectType::Commit, &raw)?;
+
+        if let Some(mark) = mark {
+            self.marks.insert(mark, id);
+        }
+
+        self.set_ref(name, Some(id));
+
+        Ok(())
+    }
+
+    /// Applies the file commands of a commit.
+    fn file_commands(&mut self, edit: &mut TreeEdit<'r>) -> Result<(), Error> {
+        let repo = self.repo;
+
+        while let Some(line) = self.next_line()? {
+            if let Some(rest) = line.strip_prefix(b"M ") {
+                let mut parts = rest.splitn(3, |&c| c == b' ');
+                let mode = parts.next().unwrap_or_default();
+                let dataref = parts.next().unwrap_or_default().to_vec();
+                let (path, _) =
+                    parse_path(parts.next().unwrap_or_default(), true)?;
+
+                let mode = file_mode(&String::from_utf8_lossy(mode))?;
+
+                let id = if dataref == b"inline" {
+                    repo.blob(&self.read_data()?)?
+                } else {
+                    self.resolve(&dataref)?
+                };
+
+                edit.set(repo, path, Some((id, mode)))?;
+            } else if let Some(rest) = line.strip_prefix(b"D ") {
+                let (path, _) = parse_path(rest, true)?;
+                edit.set(repo, path, None)?;
+            } else if let Some(rest) = line
+                .strip_prefix(b"C ")
+                .or_else(|| line.strip_prefix(b"R "))
+            {
+                let (source, rest) = parse_path(rest, false)?;
+                let (dest, _) = parse_path(rest, true)?;
+
+                let entry = edit.get(repo, &source)?;
+
+                if line.starts_with(b"R ") {
+                    edit.set(repo, source, None)?;
+                }
+
+                edit.set(repo, dest, Some(entry))?;
+            } else if line == b"deleteall" {
+                edit.pending.clear();
+                edit.tree = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+            } else if let Some(rest) = line.strip_prefix(b"N ") {
+                // Notes are stored under the hash of the commit they are on.
+                let mut parts = rest.splitn(2, |&c| c == b' ');
+                let dataref = parts.next().unwrap_or_default().to_vec();
+                let commit = self.resolve(parts.next().unwrap_or_default())?;
+
+                let id = if dataref == b"inline" {
+                    repo.blob(&self.read_data()?)?
+                } else {
+                    self.resolve(&dataref)?
+                };
+
+                let path = commit.to_string().into_bytes();
+                edit.set(repo, path, Some((id, git2::FileMode::Blob)))?;
+            } else if line.is_empty() {
+                break;
+            } else {
+                self.peeked = Some(line);
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn tag(&mut self, name: &str) -> Result<(), Error> {
+        let mark = self.read_mark()?;
+
+        let from = self.next_if("from ")?.ok_or_else(|| {
+            Error::Other(format!("Expected `from` for tag '{}'", name))
+        })?;
+        let target = self.resolve(&from)?;
+
+        self.next_if("original-oid ")?;
+        let tagger = self.next_if("tagger ")?;
+        let message = self.read_data()?;
+
+        let (_, kind) = self.repo.odb()?.read_header(target)?;
+
+        let mut raw =
+            format!("object {}\ntype {}\ntag {}\n", target, kind.str(), name)
+                .into_bytes();
+
+        if let Some(tagger) = tagger {
+            raw.extend(b"tagger ");
+            raw.extend(tagger);
+            raw.push(b'\n');
+        }
+
+        raw.push(b'\n');
+        raw.extend(message);
+
+        let id = self.repo.odb()?.write(git2::ObjectType::Tag, &raw)?;
+
+        if let Some(mark) = mark {
+            self.marks.insert(mark, id);
+        }
+
+        self.set_ref(&format!("refs/tags/{}", name), Some(id));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path(b"a b c", true).unwrap(),
+            (b"a b c".to_vec(), &b""[..])
+        );
+        assert_eq!(
+            parse_path(b"a b c", false).unwrap(),
+            (b"a".to_vec(), &b"b c"[..])
+        );
+        assert_eq!(
+            parse_path(b"\"a\\n\\\"b\\303\\251\" c", false).unwrap(),
+            (b"a\n\"b\xc3\xa9".to_vec(), &b"c"[..])
+        );
+        assert!(parse_path(b"\"a", true).is_err());
+    }
+}
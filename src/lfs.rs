@@ -0,0 +1,235 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Converting files to Git LFS pointers.
+//!
+//! The contents of each file that is converted are moved to
+//! `.git/lfs/objects`, where `git lfs` expects to find them, and the file
+//! itself is replaced with a small pointer to them:
+//!
+//! ```text
+//! version https://git-lfs.github.com/spec/v1
+//! oid sha256:<hash of the contents>
+//! size <size of the contents>
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::glob::glob_match_path;
+
+/// The first line of every LFS pointer.
+const VERSION: &[u8] = b"version https://git-lfs.github.com/spec/v1\n";
+
+/// The attributes that make `git lfs` take care of a file.
+const ATTRIBUTES: &str = "filter=lfs diff=lfs merge=lfs -text";
+
+/// Patterns in `.gitattributes` syntax for the files to convert to LFS
+/// pointers.
+#[derive(Clone, Debug, Default, Hash)]
+pub struct LfsConvert {
+    patterns: Vec<String>,
+}
+
+impl LfsConvert {
+    pub fn new() -> LfsConvert {
+        LfsConvert::default()
+    }
+
+    /// Adds a pattern for files to convert, such as `*.psd` or `assets/**`.
+    /// As in `.gitattributes`, a pattern without a `/` matches a file name at
+    /// any depth.
+    pub fn insert(&mut self, pattern: &str) -> Result<(), Error> {
+        if pattern.is_empty()
+            || pattern.starts_with('!')
+            || pattern.starts_with('#')
+            || pattern.contains(char::is_whitespace)
+        {
+            return Err(Error::invalid(
+                format!("LFS pattern '{}'", pattern),
+                "it must not be empty, start with '!' or '#', or contain \
+                 whitespace",
+            ));
+        }
+
+        self.patterns.push(pattern.to_string());
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns `true` if the file at the given path should be converted.
+    pub fn matches(&self, path: &str) -> bool {
+        let components: Vec<&str> = path.split('/').collect();
+
+        self.patterns.iter().any(|pattern| {
+            if pattern.contains('/') {
+                let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+                let pattern: Vec<&str> = pattern.split('/').collect();
+                glob_match_path(&pattern, &components, false)
+            } else {
+                components.last().is_some_and(|name| {
+                    glob_match_path(&[pattern], &[name], false)
+                })
+            }
+        })
+    }
+
+    /// Stores the contents of a file in the LFS objects of the repository and
+    /// returns the pointer to replace it with. Returns `None` if the file
+    /// already is a pointer.
+    pub fn convert(
+        &self,
+        repo: &git2::Repository,
+        contents: &[u8],
+    ) -> Result<Option<Vec<u8>>, git2::Error> {
+        if contents.starts_with(VERSION) {
+            return Ok(None);
+        }
+
+        let hash: String = Sha256::digest(contents)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        store(repo, &hash, contents).map_err(|err| {
+            git2::Error::from_str(&format!(
+                "Failed to store LFS object {}: {}",
+                hash, err
+            ))
+        })?;
+
+        let mut pointer = VERSION.to_vec();
+        pointer.extend_from_slice(
+            format!("oid sha256:{}\nsize {}\n", hash, contents.len())
+                .as_bytes(),
+        );
+
+        Ok(Some(pointer))
+    }
+
+    /// Adds a line to a `.gitattributes` file for each pattern that isn't
+    /// tracked by LFS yet. Returns `None` if nothing needs to be added.
+    pub fn attributes(&self, contents: &[u8]) -> Option<Vec<u8>> {
+        let existing = String::from_utf8_lossy(contents);
+
+        let missing: Vec<&String> = self
+            .patterns
+            .iter()
+            .filter(|pattern| {
+                !existing.lines().any(|line| {
+                    let mut words = line.split_whitespace();
+                    words.next() == Some(pattern.as_str())
+                        && words.any(|attr| attr == "filter=lfs")
+                })
+            })
+            .collect();
+
+        if missing.is_empty() {
+            return None;
+        }
+
+        let mut contents = contents.to_vec();
+
+        if !contents.is_empty() && !contents.ends_with(b"\n") {
+            contents.push(b'\n');
+        }
+
+        for pattern in missing {
+            contents.extend_from_slice(
+                format!("{} {}\n", pattern, ATTRIBUTES).as_bytes(),
+            );
+        }
+
+        Some(contents)
+    }
+}
+
+/// Writes an object to `lfs/objects/<aa>/<bb>/<hash>` in the Git directory,
+/// unless it is already there.
+fn store(
+    repo: &git2::Repository,
+    hash: &str,
+    contents: &[u8],
+) -> io::Result<()> {
+    let dir: PathBuf = repo
+        .path()
+        .join("lfs")
+        .join("objects")
+        .join(&hash[0..2])
+        .join(&hash[2..4]);
+
+    let path = dir.join(hash);
+
+    if path.exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(&dir)?;
+
+    // Write to a temporary file first so that a partially written object is
+    // never mistaken for a complete one.
+    let tmp = dir.join(format!("{}.tmp", hash));
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let mut lfs = LfsConvert::new();
+        lfs.insert("*.psd").unwrap();
+        lfs.insert("/assets/**").unwrap();
+
+        assert!(lfs.matches("logo.psd"));
+        assert!(lfs.matches("a/b/logo.psd"));
+        assert!(lfs.matches("assets/a/b.png"));
+        assert!(!lfs.matches("src/assets/b.png"));
+        assert!(!lfs.matches("logo.png"));
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut lfs = LfsConvert::new();
+        lfs.insert("*.psd").unwrap();
+        lfs.insert("*.zip").unwrap();
+
+        let existing = b"*.txt text\n*.psd filter=lfs diff=lfs merge=lfs -text";
+
+        assert_eq!(
+            lfs.attributes(existing).unwrap(),
+            b"*.txt text\n\
+              *.psd filter=lfs diff=lfs merge=lfs -text\n\
+              *.zip filter=lfs diff=lfs merge=lfs -text\n"
+        );
+
+        let existing = lfs.attributes(existing).unwrap();
+        assert_eq!(lfs.attributes(&existing), None);
+    }
+}
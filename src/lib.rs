@@ -0,0 +1,70 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Super fast Git tree filtering. This is the library behind the `git-subset`
+//! command.
+
+pub mod anonymize;
+pub mod args;
+pub mod attributes;
+pub mod builder;
+pub mod callback;
+pub mod commands;
+pub mod commit;
+pub mod copy;
+pub mod eol;
+mod error;
+pub mod export;
+pub mod filter;
+pub mod follow;
+pub mod gitmodules;
+pub mod glob;
+pub mod graft;
+pub mod import;
+pub mod interrupt;
+pub mod lfs;
+pub mod lua;
+pub mod manifest;
+pub mod map;
+pub mod mapfile;
+pub mod marks;
+pub mod pack;
+pub mod patches;
+pub mod pathspec;
+pub mod patterns;
+pub mod plugin;
+pub mod refs;
+pub mod remote;
+pub mod rename;
+pub mod replace;
+pub mod report;
+pub mod run;
+pub mod secrets;
+pub mod serve;
+pub mod sign;
+pub mod state;
+pub mod subset;
+pub mod transform;
+
+pub use crate::builder::SubsetBuilder;
+pub use crate::error::Error;
+pub use crate::filter::{filter_tree, Filter};
+pub use crate::map::OidMap;
+pub use crate::subset::repo_subset;
@@ -0,0 +1,313 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Lua scripts that edit commits and files in-process, which is much faster
+//! than running a command for each of them. This needs the `lua` feature.
+//!
+//! A script may define either or both of these functions:
+//!
+//! ```lua
+//! function on_commit(commit)
+//!   -- commit.id, commit.tree, commit.parents, and commit.original_parents
+//!   -- are hex strings (or lists of them). commit.author and commit.committer
+//!   -- are tables with name, email, time, and offset. These and
+//!   -- commit.message can be changed in place. Returning false skips the
+//!   -- commit.
+//! end
+//!
+//! function on_blob(path, contents)
+//!   -- Returns nothing to keep the file, false to leave it out, or a string
+//!   -- to replace its contents with.
+//! end
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::commit::Metadata;
+use crate::error::Error;
+
+/// What to do with a file according to a script.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "lua"), allow(dead_code))]
+pub enum BlobAction {
+    Keep,
+    Drop,
+    Replace(Vec<u8>),
+}
+
+/// A loaded Lua script.
+pub struct LuaScript {
+    path: PathBuf,
+
+    /// Hash of the script, so that changing it changes the map.
+    digest: git2::Oid,
+
+    #[cfg(feature = "lua")]
+    lua: mlua::Lua,
+
+    has_on_commit: bool,
+    has_on_blob: bool,
+}
+
+impl fmt::Debug for LuaScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LuaScript")
+            .field("path", &self.path)
+            .field("digest", &self.digest)
+            .finish()
+    }
+}
+
+impl Hash for LuaScript {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.digest.hash(state);
+    }
+}
+
+#[cfg(feature = "lua")]
+fn lua_error(err: mlua::Error) -> git2::Error {
+    git2::Error::from_str(&format!("Lua script failed: {}", err))
+}
+
+#[cfg(feature = "lua")]
+fn signature_table<'lua>(
+    lua: &'lua mlua::Lua,
+    signature: &git2::Signature<'_>,
+) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("name", lua.create_string(signature.name_bytes())?)?;
+    table.set("email", lua.create_string(signature.email_bytes())?)?;
+    table.set("time", signature.when().seconds())?;
+    table.set("offset", signature.when().offset_minutes())?;
+    Ok(table)
+}
+
+#[cfg(feature = "lua")]
+fn table_signature(
+    table: &mlua::Table<'_>,
+) -> Result<git2::Signature<'static>, git2::Error> {
+    let name: mlua::String = table.get("name").map_err(lua_error)?;
+    let email: mlua::String = table.get("email").map_err(lua_error)?;
+    let time: i64 = table.get("time").map_err(lua_error)?;
+    let offset: i32 = table.get("offset").map_err(lua_error)?;
+
+    git2::Signature::new(
+        &String::from_utf8_lossy(name.as_bytes()),
+        &String::from_utf8_lossy(email.as_bytes()),
+        &git2::Time::new(time, offset),
+    )
+}
+
+impl LuaScript {
+    /// Loads and runs the script at the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LuaScript, Error> {
+        let path = path.as_ref();
+        let source = fs::read(path).map_err(|err| {
+            Error::failed(format!("read Lua script '{}'", path.display()), err)
+        })?;
+
+        let digest = git2::Oid::hash_object(git2::ObjectType::Blob, &source)?;
+
+        LuaScript::run(path, digest, &source).map_err(|err| {
+            Error::failed(format!("load Lua script '{}'", path.display()), err)
+        })
+    }
+
+    #[cfg(feature = "lua")]
+    fn run(
+        path: &Path,
+        digest: git2::Oid,
+        source: &[u8],
+    ) -> Result<LuaScript, String> {
+        let lua = mlua::Lua::new();
+
+        lua.load(source)
+            .set_name(path.display().to_string())
+            .exec()
+            .map_err(|err| err.to_string())?;
+
+        let defines = |name: &str| {
+            matches!(
+                lua.globals().get::<_, mlua::Value>(name),
+                Ok(mlua::Value::Function(_))
+            )
+        };
+
+        let has_on_commit = defines("on_commit");
+        let has_on_blob = defines("on_blob");
+
+        Ok(LuaScript {
+            path: path.to_path_buf(),
+            digest,
+            lua,
+            has_on_commit,
+            has_on_blob,
+        })
+    }
+
+    #[cfg(not(feature = "lua"))]
+    fn run(
+        _path: &Path,
+        _digest: git2::Oid,
+        _source: &[u8],
+    ) -> Result<LuaScript, String> {
+        Err("git-subset was built without the `lua` feature".to_string())
+    }
+
+    /// Whether the script defines `on_commit`.
+    pub fn has_on_commit(&self) -> bool {
+        self.has_on_commit
+    }
+
+    /// Whether the script defines `on_blob`.
+    pub fn has_on_blob(&self) -> bool {
+        self.has_on_blob
+    }
+
+    /// Calls `on_commit` for the commit `original`, which is rewritten with
+    /// the given tree, parents, and metadata. The metadata is edited as the
+    /// script says. Returns `false` if the commit is to be skipped.
+    pub fn on_commit(
+        &self,
+        original: &git2::Commit<'_>,
+        tree: git2::Oid,
+        parents: &[git2::Oid],
+        metadata: &mut Metadata,
+    ) -> Result<bool, git2::Error> {
+        #[cfg(feature = "lua")]
+        {
+            if !self.has_on_commit {
+                return Ok(true);
+            }
+
+            let lua = &self.lua;
+            let ids = |ids: Vec<git2::Oid>| {
+                ids.iter().map(|id| id.to_string()).collect::<Vec<_>>()
+            };
+
+            let commit = (|| {
+                let commit = lua.create_table()?;
+                commit.set("id", original.id().to_string())?;
+                commit.set("tree", tree.to_string())?;
+                commit.set("parents", ids(parents.to_vec()))?;
+                commit.set(
+                    "original_parents",
+                    ids(original.parent_ids().collect()),
+                )?;
+                commit
+                    .set("author", signature_table(lua, &metadata.author)?)?;
+                commit.set(
+                    "committer",
+                    signature_table(lua, &metadata.committer)?,
+                )?;
+                commit.set("message", lua.create_string(&metadata.message)?)?;
+                Ok(commit)
+            })()
+            .map_err(lua_error)?;
+
+            let on_commit: mlua::Function =
+                lua.globals().get("on_commit").map_err(lua_error)?;
+
+            let result: mlua::Value =
+                on_commit.call(commit.clone()).map_err(|err| {
+                    git2::Error::from_str(&format!(
+                        "Lua on_commit failed for {}: {}",
+                        original.id(),
+                        err
+                    ))
+                })?;
+
+            if let mlua::Value::Boolean(false) = result {
+                return Ok(false);
+            }
+
+            let message: mlua::String =
+                commit.get("message").map_err(lua_error)?;
+            metadata.message = message.as_bytes().to_vec();
+
+            let author: mlua::Table =
+                commit.get("author").map_err(lua_error)?;
+            metadata.author = table_signature(&author)?;
+
+            let committer: mlua::Table =
+                commit.get("committer").map_err(lua_error)?;
+            metadata.committer = table_signature(&committer)?;
+
+            Ok(true)
+        }
+
+        #[cfg(not(feature = "lua"))]
+        {
+            let _ = (original, tree, parents, metadata);
+            Ok(true)
+        }
+    }
+
+    /// Calls `on_blob` for a file.
+    pub fn on_blob(
+        &self,
+        path: &str,
+        contents: &[u8],
+    ) -> Result<BlobAction, git2::Error> {
+        #[cfg(feature = "lua")]
+        {
+            if !self.has_on_blob {
+                return Ok(BlobAction::Keep);
+            }
+
+            let lua = &self.lua;
+            let on_blob: mlua::Function =
+                lua.globals().get("on_blob").map_err(lua_error)?;
+            let contents = lua.create_string(contents).map_err(lua_error)?;
+
+            let result: mlua::Value =
+                on_blob.call((path, contents)).map_err(|err| {
+                    git2::Error::from_str(&format!(
+                        "Lua on_blob failed for '{}': {}",
+                        path, err
+                    ))
+                })?;
+
+            match result {
+                mlua::Value::Nil | mlua::Value::Boolean(true) => {
+                    Ok(BlobAction::Keep)
+                }
+                mlua::Value::Boolean(false) => Ok(BlobAction::Drop),
+                mlua::Value::String(s) => {
+                    Ok(BlobAction::Replace(s.as_bytes().to_vec()))
+                }
+                _ => Err(git2::Error::from_str(&format!(
+                    "Lua on_blob returned something other than nothing, \
+                     false, or a string for '{}'",
+                    path
+                ))),
+            }
+        }
+
+        #[cfg(not(feature = "lua"))]
+        {
+            let _ = (path, contents);
+            Ok(BlobAction::Keep)
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Manifests of several filters, each with its own branch.
+
+use crate::filter::Filter;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Loads a manifest from a file.
+pub fn read_manifest<P: AsRef<Path>>(
+    path: P,
+) -> io::Result<Vec<(String, Filter)>> {
+    read_manifest_from(io::BufReader::new(fs::File::open(path)?))
+}
+
+/// Loads a manifest from a reader. A manifest consists of sections, each
+/// starting with the name of a branch in brackets (e.g., `[foo]`) followed by
+/// the rules of its filter, as in a filter file (see `Filter::insert_rule`).
+/// Blank lines and lines starting with a "#" are ignored.
+pub fn read_manifest_from<R: io::BufRead>(
+    reader: R,
+) -> io::Result<Vec<(String, Filter)>> {
+    let mut manifest: Vec<(String, Filter)> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            // Ignore blank lines and comments
+            continue;
+        }
+
+        if let Some(branch) =
+            line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+        {
+            let branch = branch.trim();
+
+            if branch.is_empty() {
+                return Err(invalid("Empty branch name".to_string()));
+            }
+
+            if manifest.iter().any(|(b, _)| b == branch) {
+                return Err(invalid(format!(
+                    "Branch '{}' is listed more than once",
+                    branch
+                )));
+            }
+
+            manifest.push((branch.to_string(), Filter::new()));
+            continue;
+        }
+
+        match manifest.last_mut() {
+            Some((_, filter)) => filter
+                .insert_rule(line)
+                .map_err(|err| invalid(err.to_string()))?,
+            None => {
+                return Err(invalid(format!(
+                    "Rule '{}' is not in a [branch] section",
+                    line
+                )))
+            }
+        }
+    }
+
+    if let Some((branch, _)) =
+        manifest.iter().find(|(_, filter)| filter.is_empty())
+    {
+        return Err(invalid(format!("Branch '{}' has no rules", branch)));
+    }
+
+    Ok(manifest)
+}
+
+fn invalid(err: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest() {
+        let manifest = read_manifest_from(
+            &b"# Components\n[foo]\nsrc/foo\nglob:*.proto\n\n[bar]\nsrc/bar\n"
+                [..],
+        )
+        .unwrap();
+
+        let branches: Vec<_> =
+            manifest.iter().map(|(b, _)| b.as_str()).collect();
+        assert_eq!(branches, ["foo", "bar"]);
+
+        assert!(read_manifest_from(&b"src/foo\n"[..]).is_err());
+        assert!(read_manifest_from(&b"[foo]\n"[..]).is_err());
+        assert!(read_manifest_from(&b"[foo]\na\n[foo]\nb\n"[..]).is_err());
+    }
+}
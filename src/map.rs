@@ -18,12 +18,415 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
 
 use git2::{Oid, Repository};
 
+/// The start of a map file in the binary format. Map files without it are in
+/// the older text format, where each line is an OID and what it maps to.
+const MAGIC: &[u8; 8] = b"GSUBMAP\0";
+
+/// The start of a zstd frame. Map files are compressed when they are written,
+/// but uncompressed ones are still read.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// The version of the binary format. Version 1 didn't say which version of
+/// git-subset wrote it or the object format, and version 2 didn't have the tips
+/// of the last run. Both are still read.
+const VERSION: u32 = 3;
+
+/// The length of the OIDs in the map. Only SHA-1 is supported for now.
+const OID_LEN: usize = 20;
+
+/// Returns the key of the random seed kept in a map, which is the hash of
+/// something that no rewrite is cached by.
+fn seed_key() -> Oid {
+    Oid::hash_object(git2::ObjectType::Blob, b"seed").unwrap()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the hash of the filter that a map file name is derived from.
+fn filter_hash(name: &str) -> u64 {
+    u64::from_str_radix(name, 16).unwrap_or(0)
+}
+
+/// Returns the object format of a repository, such as `sha1`.
+fn object_format(repo: &Repository) -> String {
+    repo.config()
+        .and_then(|config| config.get_string("extensions.objectformat"))
+        .unwrap_or_else(|_| "sha1".to_string())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn newer_version(version: u32) -> io::Error {
+    invalid_data(&format!(
+        "the object map has version {}, which is newer than this version of \
+         git-subset supports; remove it or use `--nomap`",
+        version
+    ))
+}
+
+/// Reads a string that is preceded by its length.
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let [len] = read_array(reader)?;
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("invalid object map"))
+}
+
+fn write_string<W: Write>(out: &mut W, s: &str) -> io::Result<()> {
+    let len = s.len().min(u8::MAX as usize);
+    out.write_all(&[len as u8])?;
+    out.write_all(&s.as_bytes()[..len])
+}
+
+/// Reads what an entry maps to, given its tag.
+fn read_value<R: Read>(reader: &mut R, tag: u8) -> io::Result<Option<Oid>> {
+    match tag {
+        0 => Ok(None),
+        1 => {
+            let value: [u8; OID_LEN] = read_array(reader)?;
+            Ok(Some(Oid::from_bytes(&value).unwrap()))
+        }
+        _ => Err(invalid_data("invalid object map entry")),
+    }
+}
+
+/// Writes an entry: the key, followed by a tag that is 1 if another OID
+/// follows and 0 if the object was discarded.
+fn write_entry<W: Write>(
+    out: &mut W,
+    key: Oid,
+    value: Option<Oid>,
+) -> io::Result<()> {
+    out.write_all(key.as_bytes())?;
+
+    match value {
+        Some(value) => {
+            out.write_all(&[1])?;
+            out.write_all(value.as_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+/// What a map says about how it was written, so that it isn't misread by a
+/// version of git-subset that doesn't understand it, or used for the wrong
+/// filter or repository.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The version of the format.
+    pub version: u32,
+
+    /// The version of git-subset that wrote the map, if known.
+    pub tool_version: Option<String>,
+
+    /// The hash of the filter and the other changes made to commits, which the
+    /// name of the map is derived from.
+    pub filter: u64,
+
+    /// The object format of the repository, such as `sha1`.
+    pub object_format: String,
+
+    /// The commits that the last run walked the history of, along with what
+    /// they mapped to.
+    pub tips: Vec<(Oid, Option<Oid>)>,
+}
+
+impl Header {
+    /// Returns the header for a map written by this version of git-subset.
+    pub fn new(filter: u64, object_format: &str) -> Header {
+        Header {
+            version: VERSION,
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            filter,
+            object_format: object_format.to_string(),
+            tips: Vec::new(),
+        }
+    }
+
+    /// Reads the header of a map in the binary format, including the magic
+    /// bytes. Maps from newer versions of git-subset are refused since they
+    /// can't be read correctly.
+    fn read<R: Read>(reader: &mut R) -> io::Result<Header> {
+        let magic: [u8; 8] = read_array(reader)?;
+
+        if &magic != MAGIC {
+            return Err(invalid_data("not an object map"));
+        }
+
+        let version = u32::from_le_bytes(read_array(reader)?);
+
+        let header = match version {
+            1 => {
+                let filter = u64::from_le_bytes(read_array(reader)?);
+                let [oid_len] = read_array(reader)?;
+
+                if oid_len as usize != OID_LEN {
+                    return Err(invalid_data(&format!(
+                        "unsupported OID length {}",
+                        oid_len
+                    )));
+                }
+
+                Header {
+                    version,
+                    tool_version: None,
+                    filter,
+                    object_format: "sha1".to_string(),
+                    tips: Vec::new(),
+                }
+            }
+            2 | VERSION => {
+                let mut header = Header {
+                    version,
+                    tool_version: Some(read_string(reader)?),
+                    filter: u64::from_le_bytes(read_array(reader)?),
+                    object_format: read_string(reader)?,
+                    tips: Vec::new(),
+                };
+
+                if version >= 3 {
+                    let count = u32::from_le_bytes(read_array(reader)?);
+
+                    for _ in 0..count {
+                        let tip: [u8; OID_LEN] = read_array(reader)?;
+                        let [tag] = read_array(reader)?;
+                        header.tips.push((
+                            Oid::from_bytes(&tip).unwrap(),
+                            read_value(reader, tag)?,
+                        ));
+                    }
+                }
+
+                header
+            }
+            _ => return Err(newer_version(version)),
+        };
+
+        if header.object_format != "sha1" {
+            return Err(invalid_data(&format!(
+                "object maps for {} repositories are not supported",
+                header.object_format
+            )));
+        }
+
+        Ok(header)
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        write_string(out, self.tool_version.as_deref().unwrap_or_default())?;
+        out.write_all(&self.filter.to_le_bytes())?;
+        write_string(out, &self.object_format)?;
+        out.write_all(&(self.tips.len() as u32).to_le_bytes())?;
+
+        for &(tip, value) in &self.tips {
+            write_entry(out, tip, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the map is for the given filter in a repository with the
+    /// given object format.
+    pub fn check(&self, filter: u64, object_format: &str) -> io::Result<()> {
+        if self.filter != filter {
+            return Err(invalid_data(
+                "the object map was written for a different filter",
+            ));
+        }
+
+        if self.object_format != object_format {
+            return Err(invalid_data(&format!(
+                "the object map is for a {} repository, but this one uses {}",
+                self.object_format, object_format
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// A map saved inside a repository, as listed by `saved_maps`.
+#[derive(Debug)]
+pub struct SavedMap {
+    /// The name of the map, which is derived from the filter it is for.
+    pub name: String,
+
+    /// The files that make up the map. A map can be in both a file and a
+    /// database, and may have a list of where its filter came from.
+    pub paths: Vec<PathBuf>,
+
+    /// The size of those files, in bytes.
+    pub size: u64,
+
+    /// When the map was last written, which is at the end of every run that
+    /// uses it.
+    pub modified: SystemTime,
+
+    /// The filter files and manifests that the last run using the map was
+    /// given, as recorded by `record_sources`.
+    pub sources: Vec<PathBuf>,
+}
+
+impl SavedMap {
+    /// Deletes the files that make up the map.
+    pub fn remove(&self) -> io::Result<()> {
+        for path in &self.paths {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the path of the file listing where a map's filter came from.
+fn sources_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.sources", name))
+}
+
+/// Returns the maps saved inside the given repository, ordered by name. A map
+/// in a database has the same name as it would have in a file.
+pub fn saved_maps(repo: &Repository) -> io::Result<Vec<SavedMap>> {
+    let dir = repo.path().join("subset");
+
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut maps: BTreeMap<String, SavedMap> = BTreeMap::new();
+
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        let name = file_name.strip_suffix(".sqlite").unwrap_or(&file_name);
+
+        if u64::from_str_radix(name, 16).is_err() {
+            continue;
+        }
+
+        let metadata = entry.metadata()?;
+        let modified = metadata.modified()?;
+
+        let map = maps.entry(name.to_string()).or_insert_with(|| SavedMap {
+            name: name.to_string(),
+            paths: Vec::new(),
+            size: 0,
+            modified,
+            sources: Vec::new(),
+        });
+
+        map.paths.push(entry.path());
+        map.size += metadata.len();
+        map.modified = map.modified.max(modified);
+    }
+
+    for map in maps.values_mut() {
+        let path = sources_path(&dir, &map.name);
+
+        match fs::read_to_string(&path) {
+            Ok(sources) => {
+                map.sources = sources.lines().map(PathBuf::from).collect();
+                map.size += sources.len() as u64;
+                map.paths.push(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(maps.into_values().collect())
+}
+
+/// Records the filter files and manifests that a map's filter was loaded from,
+/// so that the map can be pruned once they are gone.
+pub fn record_sources(
+    repo: &Repository,
+    name: &str,
+    sources: &[&Path],
+) -> io::Result<()> {
+    let mut list = String::new();
+
+    for source in sources {
+        list.push_str(&fs::canonicalize(source)?.to_string_lossy());
+        list.push('\n');
+    }
+
+    fs::write(sources_path(&repo.path().join("subset"), name), list)
+}
+
+/// Something wrong with a map, as found by `OidMap::verify`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// An entry maps to an object that isn't in the repository, such as one
+    /// that was garbage collected.
+    Dangling { key: Oid, value: Oid },
+
+    /// Following the entries from this one leads back to it, so it never
+    /// resolves.
+    Cycle(Oid),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Dangling { key, value } => write!(
+                f,
+                "{} maps to {}, which isn't in the repository",
+                key, value
+            ),
+            Problem::Cycle(key) => {
+                write!(f, "{} maps back to itself through other entries", key)
+            }
+        }
+    }
+}
+
+/// Where object maps are kept between runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MapBackend {
+    /// A file that is read into memory and written back afterwards.
+    #[default]
+    File,
+
+    /// An SQLite database, which is only read as needed. This needs the
+    /// `sqlite` feature.
+    Sqlite,
+}
+
+impl FromStr for MapBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MapBackend, String> {
+        match s {
+            "file" => Ok(MapBackend::File),
+            "sqlite" => Ok(MapBackend::Sqlite),
+            _ => Err(format!("Expected 'file' or 'sqlite' but got '{}'", s)),
+        }
+    }
+}
+
 /// An OID mapping. This is simply a mapping between original commit hashes and
 /// rewritten commit hashes.
 ///
@@ -33,53 +436,229 @@ use git2::{Oid, Repository};
 /// is, a mapping like "A -> B -> C" may be possible. In such a case, looking up
 /// a key will resolve to the deepest value. This is so that pruning empty
 /// commits works correctly when remapping parents.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct OidMap {
     map: HashMap<Oid, Option<Oid>>,
+
+    /// The commits that the last run walked the history of, along with what
+    /// they mapped to.
+    tips: Vec<(Oid, Option<Oid>)>,
+
+    /// The database the map is kept in instead, if any.
+    #[cfg(feature = "sqlite")]
+    db: Option<sqlite::Database>,
 }
 
 impl OidMap {
     pub fn new() -> OidMap {
+        OidMap::default()
+    }
+
+    fn from_map(map: HashMap<Oid, Option<Oid>>) -> OidMap {
         OidMap {
-            map: HashMap::new(),
+            map,
+            tips: Vec::new(),
+            #[cfg(feature = "sqlite")]
+            db: None,
+        }
+    }
+
+    /// Opens the map inside the given repository with the given backend.
+    pub fn open(
+        repo: &Repository,
+        name: &str,
+        backend: MapBackend,
+    ) -> io::Result<OidMap> {
+        match backend {
+            MapBackend::File => Self::from_repo(repo, name),
+            MapBackend::Sqlite => Self::open_sqlite(repo, name),
         }
     }
 
+    /// Opens the map in an SQLite database inside the given repository,
+    /// creating it if needed. Instead of holding every mapping in memory, they
+    /// are looked up as needed. A new database starts out with the mappings
+    /// from the map file of the same name, if there is one.
+    #[cfg(feature = "sqlite")]
+    pub fn open_sqlite(repo: &Repository, name: &str) -> io::Result<OidMap> {
+        let dir = repo.path().join("subset");
+        fs::create_dir_all(&dir)?;
+
+        let db = sqlite::Database::open(&dir.join(format!("{}.sqlite", name)))
+            .map_err(sqlite::io_error)?;
+
+        let mut header = Header {
+            version: sqlite::VERSION,
+            ..Header::new(filter_hash(name), &object_format(repo))
+        };
+
+        if let Some(existing) = db.header().map_err(sqlite::io_error)? {
+            if existing.version > sqlite::VERSION {
+                return Err(newer_version(existing.version));
+            }
+
+            existing.check(header.filter, &header.object_format)?;
+            header.tips = existing.tips;
+        }
+
+        // Records which version of git-subset wrote to it last.
+        db.set_header(&header).map_err(sqlite::io_error)?;
+
+        if db.is_empty().map_err(sqlite::io_error)? {
+            let map = Self::from_repo(repo, name)?;
+
+            for (k, v) in map.iter() {
+                db.insert(&k, v);
+            }
+
+            header.tips = map.tips;
+        }
+
+        Ok(OidMap {
+            map: HashMap::new(),
+            tips: header.tips,
+            db: Some(db),
+        })
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn open_sqlite(_repo: &Repository, _name: &str) -> io::Result<OidMap> {
+        Err(io::Error::other(
+            "git-subset was built without the `sqlite` feature",
+        ))
+    }
+
     /// Reads the map from a file inside the given repository. The name of the
     /// file is derived from the hash of the file filter. Thus, when the file
-    /// filter changes, we also get a different map.
+    /// filter changes, we also get a different map. Maps in the text format
+    /// are still read, and are written in the binary format next time.
     pub fn from_repo(repo: &Repository, name: &str) -> io::Result<OidMap> {
         let mut path = repo.path().join("subset");
         path.push(name);
 
-        if let Ok(f) = fs::File::open(&path) {
-            Self::from_reader(io::BufReader::new(f))
-        } else {
-            Ok(Self::new())
+        let f = match fs::File::open(&path) {
+            Ok(f) => f,
+            Err(_) => return Ok(Self::new()),
+        };
+
+        Self::read_checked(repo, name, io::BufReader::new(f))
+    }
+
+    /// Reads a map that was written by `write_repo` or `encode`, checking that
+    /// it is for the filter with the given name.
+    pub fn from_bytes(
+        repo: &Repository,
+        name: &str,
+        bytes: &[u8],
+    ) -> io::Result<OidMap> {
+        Self::read_checked(repo, name, bytes)
+    }
+
+    fn read_checked<R: BufRead>(
+        repo: &Repository,
+        name: &str,
+        reader: R,
+    ) -> io::Result<OidMap> {
+        let (header, map) = Self::read(reader)?;
+
+        if let Some(header) = header {
+            header.check(filter_hash(name), &object_format(repo))?;
         }
+
+        Ok(map)
     }
 
-    /// Writes the map to a file inside the given repository.
+    /// Writes the map to a file inside the given repository, in the binary
+    /// format compressed with zstd. The file is replaced all at once, so that
+    /// it is never left half written. For a map in a database, its changes are
+    /// committed instead.
     pub fn write_repo(&self, repo: &Repository, name: &str) -> io::Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            let header = Header {
+                version: sqlite::VERSION,
+                tips: self.tips.clone(),
+                ..Header::new(filter_hash(name), &object_format(repo))
+            };
+
+            return db
+                .set_header(&header)
+                .and_then(|()| db.commit())
+                .map_err(sqlite::io_error);
+        }
+
         let mut path = repo.path().join("subset");
 
         fs::create_dir_all(&path)?;
 
         path.push(name);
 
-        let mut f = io::BufWriter::new(fs::File::create(&path)?);
-        self.write(&mut f)
+        let temp = path.with_extension("tmp");
+        let f = io::BufWriter::new(fs::File::create(&temp)?);
+        self.write_compressed(repo, name, f)?
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+
+        fs::rename(&temp, &path)
+    }
+
+    /// Returns the map as `write_repo` writes it.
+    pub fn encode(&self, repo: &Repository, name: &str) -> io::Result<Vec<u8>> {
+        self.write_compressed(repo, name, Vec::new())
+    }
+
+    fn write_compressed<W: Write>(
+        &self,
+        repo: &Repository,
+        name: &str,
+        out: W,
+    ) -> io::Result<W> {
+        let mut encoder = zstd::Encoder::new(out, 0)?;
+        let header = Header {
+            tips: self.tips.clone(),
+            ..Header::new(filter_hash(name), &object_format(repo))
+        };
+        self.write_binary(&mut encoder, &header)?;
+        encoder.finish()
+    }
+
+    /// Reads the mapping from a file in any format, compressed or not.
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<OidMap> {
+        Ok(Self::read(reader)?.1)
     }
 
-    /// Reads the mapping from a file.
-    pub fn from_reader<R: io::BufRead>(reader: R) -> io::Result<OidMap> {
+    /// Reads a map in any format, along with its header if the format has
+    /// one.
+    fn read<R: BufRead>(mut reader: R) -> io::Result<(Option<Header>, OidMap)> {
+        if reader.fill_buf()?.starts_with(&ZSTD_MAGIC) {
+            let decoder = zstd::Decoder::with_buffer(reader)?;
+            return Self::read_uncompressed(io::BufReader::new(decoder));
+        }
+
+        Self::read_uncompressed(reader)
+    }
+
+    fn read_uncompressed<R: BufRead>(
+        mut reader: R,
+    ) -> io::Result<(Option<Header>, OidMap)> {
+        if reader.fill_buf()?.starts_with(MAGIC) {
+            let (header, map) = Self::read_binary(reader)?;
+            return Ok((Some(header), map));
+        }
+
+        Ok((None, Self::read_text(reader)?))
+    }
+
+    /// Reads a map in the text format.
+    fn read_text<R: BufRead>(reader: R) -> io::Result<OidMap> {
         let mut map = HashMap::new();
 
         for line in reader.lines() {
             let line = line?;
             let line = line.trim();
 
-            if line.is_empty() || line.starts_with("#") {
+            if line.is_empty() || line.starts_with('#') {
                 // Ignore blank lines and comments.
                 continue;
             }
@@ -100,50 +679,429 @@ impl OidMap {
             };
         }
 
-        Ok(OidMap { map: map })
+        Ok(OidMap::from_map(map))
+    }
+
+    /// Reads a map in the binary format, along with its header.
+    ///
+    /// After the header, each entry is an OID followed by a tag, which is 1 if
+    /// another OID follows and 0 if the object was discarded.
+    fn read_binary<R: Read>(mut reader: R) -> io::Result<(Header, OidMap)> {
+        let header = Header::read(&mut reader)?;
+
+        let mut map = HashMap::new();
+        let mut entry = [0; OID_LEN + 1];
+
+        loop {
+            // Stop at the end of the file, as long as it isn't in the middle
+            // of an entry.
+            let read = reader.read(&mut entry[..1])?;
+            if read == 0 {
+                break;
+            }
+
+            reader.read_exact(&mut entry[1..])?;
+            let key = Oid::from_bytes(&entry[..OID_LEN]).unwrap();
+            let value = read_value(&mut reader, entry[OID_LEN])?;
+
+            map.insert(key, value);
+        }
+
+        let mut map = OidMap::from_map(map);
+        map.tips = header.tips.clone();
+
+        Ok((header, map))
     }
 
-    /// Writes this OidMap to a file.
-    pub fn write<W: io::Write>(&self, f: &mut W) -> io::Result<()> {
-        for (k, v) in &self.map {
+    /// Writes this OidMap in the binary format, after the given header. The
+    /// entries are sorted so that the same map is always written the same way.
+    pub fn write_binary<W: Write>(
+        &self,
+        f: &mut W,
+        header: &Header,
+    ) -> io::Result<()> {
+        header.write(f)?;
+
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort_unstable();
+
+        for (k, v) in entries {
+            write_entry(f, k, v)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes this OidMap to a file in the text format.
+    pub fn write<W: Write>(&self, f: &mut W) -> io::Result<()> {
+        for (k, v) in self.iter() {
             write!(f, "{}", k)?;
 
             if let Some(v) = v {
-                write!(f, " {}\n", v)?;
+                writeln!(f, " {}", v)?;
             } else {
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
         }
 
         Ok(())
     }
 
-    pub fn get(&self, k: &Oid) -> Option<&Option<Oid>> {
-        self.map.get(k)
+    pub fn get(&self, k: &Oid) -> Option<Option<Oid>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.get(k);
+        }
+
+        self.map.get(k).cloned()
     }
 
     /// Resolves an OID through multiple indirections.
-    pub fn resolve(&self, k: &Oid) -> Option<&Option<Oid>> {
-        match self.map.get(k) {
-            Some(value) => match value {
-                Some(oid) => {
-                    if k == oid {
-                        // Break cycles.
-                        Some(value)
-                    } else if self.map.contains_key(&oid) {
-                        self.resolve(&oid)
-                    } else {
-                        Some(value)
-                    }
+    pub fn resolve(&self, k: &Oid) -> Option<Option<Oid>> {
+        let mut key = *k;
+        let mut value = self.get(k)?;
+
+        while let Some(oid) = value {
+            // Break cycles.
+            if oid == key {
+                break;
+            }
+
+            match self.get(&oid) {
+                Some(next) => {
+                    key = oid;
+                    value = next;
                 }
-                None => Some(value),
+                None => break,
+            }
+        }
+
+        Some(value)
+    }
+
+    /// Returns the commit that was created for the given commit, unless it
+    /// was left out. Unlike `resolve`, an empty commit is not resolved to its
+    /// parent, since empty commits are mapped to the commit that was created
+    /// for them, which is in turn mapped to its parent.
+    pub fn kept(&self, k: &Oid) -> Option<Oid> {
+        match self.get(k) {
+            Some(Some(new)) => match self.get(&new) {
+                Some(Some(next)) if next != new => None,
+                Some(None) => None,
+                _ => Some(new),
             },
-            None => None,
+            _ => None,
+        }
+    }
+
+    /// Returns the commits from the last run whose history is in the map, and
+    /// so doesn't need to be walked again. Those that no longer map to what
+    /// they did then, such as after `git-subset map import`, are left out.
+    pub fn tips(&self) -> Vec<Oid> {
+        self.tips
+            .iter()
+            .filter(|&&(tip, value)| self.get(&tip) == Some(value))
+            .map(|&(tip, _)| tip)
+            .collect()
+    }
+
+    /// Records the commits whose history was walked in full, once they have
+    /// all been rewritten.
+    pub fn set_tips(&mut self, tips: &[Oid]) {
+        self.tips = tips
+            .iter()
+            .filter_map(|&tip| Some((tip, self.get(&tip)?)))
+            .collect();
+    }
+
+    /// Returns a random value kept in the map, which is made the first time
+    /// it is asked for. It stays the same for as long as the map is kept, but
+    /// can't be worked out without the map.
+    pub fn seed(&mut self) -> io::Result<Oid> {
+        if let Some(Some(seed)) = self.get(&seed_key()) {
+            return Ok(seed);
         }
+
+        let mut bytes = [0; OID_LEN];
+        getrandom::fill(&mut bytes).map_err(io::Error::other)?;
+        let seed = Oid::from_bytes(&bytes).map_err(io::Error::other)?;
+
+        self.insert(seed_key(), Some(seed));
+
+        Ok(seed)
     }
 
-    pub fn insert(&mut self, k: Oid, v: Option<Oid>) -> Option<Option<Oid>> {
-        self.map.insert(k, v)
+    /// Iterates over every mapping, in no particular order.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (Oid, Option<Oid>)> + '_> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return Box::new(db.entries().into_iter());
+        }
+
+        Box::new(self.map.iter().map(|(&k, &v)| (k, v)))
+    }
+
+    /// Checks that every object the map refers to is in the object database
+    /// and that every entry resolves. Keys aren't checked, since many of them
+    /// are hashes of what went into a rewrite rather than objects.
+    pub fn verify(&self, odb: &git2::Odb) -> Vec<Problem> {
+        let mut entries: Vec<_> = self.iter().collect();
+        entries.sort();
+
+        let mut problems = Vec::new();
+
+        for &(key, value) in &entries {
+            // The seed isn't an object at all.
+            if key == seed_key() {
+                continue;
+            }
+
+            if let Some(value) = value {
+                if !odb.exists(value) {
+                    problems.push(Problem::Dangling { key, value });
+                }
+            }
+        }
+
+        // Entries that are known to either resolve or be part of a cycle, so
+        // that each chain is only followed once.
+        let mut done = HashSet::new();
+
+        for &(start, _) in &entries {
+            let mut chain = HashSet::new();
+            let mut key = start;
+
+            while !done.contains(&key) {
+                if !chain.insert(key) {
+                    problems.push(Problem::Cycle(key));
+                    break;
+                }
+
+                match self.get(&key) {
+                    Some(Some(next)) if next != key => key = next,
+                    _ => break,
+                }
+            }
+
+            done.extend(chain);
+        }
+
+        problems
+    }
+
+    pub fn insert(&mut self, k: Oid, v: Option<Oid>) {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.insert(&k, v);
+        }
+
+        self.map.insert(k, v);
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::Path;
+
+    use git2::Oid;
+    use rusqlite::OptionalExtension;
+
+    use super::{Header, OidMap};
+
+    /// The version of the database schema.
+    pub const VERSION: u32 = 1;
+
+    pub fn io_error(err: rusqlite::Error) -> io::Error {
+        io::Error::other(err)
+    }
+
+    /// A map in an SQLite database. Changes are made in a transaction that is
+    /// only committed when the map is saved, so that an interrupted run leaves
+    /// the database as it was.
+    ///
+    /// Since lookups can't fail, the first error is kept and returned when the
+    /// map is saved. A failed lookup is treated as a miss.
+    #[derive(Debug)]
+    pub struct Database {
+        conn: rusqlite::Connection,
+        error: RefCell<Option<rusqlite::Error>>,
+    }
+
+    impl Database {
+        pub fn open(path: &Path) -> rusqlite::Result<Database> {
+            let conn = rusqlite::Connection::open(path)?;
+
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS map (
+                    key BLOB PRIMARY KEY NOT NULL,
+                    value BLOB
+                ) WITHOUT ROWID;
+                CREATE TABLE IF NOT EXISTS meta (
+                    key TEXT PRIMARY KEY NOT NULL,
+                    value TEXT NOT NULL
+                );
+                BEGIN;",
+            )?;
+
+            Ok(Database {
+                conn,
+                error: RefCell::new(None),
+            })
+        }
+
+        /// Returns the header kept in the database, unless it is new.
+        pub fn header(&self) -> rusqlite::Result<Option<Header>> {
+            let mut statement =
+                self.conn.prepare("SELECT key, value FROM meta")?;
+            let meta = statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<rusqlite::Result<HashMap<String, String>>>()?;
+
+            if meta.is_empty() {
+                return Ok(None);
+            }
+
+            Ok(Some(Header {
+                version: meta
+                    .get("version")
+                    .and_then(|version| version.parse().ok())
+                    .unwrap_or(0),
+                tool_version: meta.get("tool_version").cloned(),
+                filter: meta
+                    .get("filter")
+                    .and_then(|filter| u64::from_str_radix(filter, 16).ok())
+                    .unwrap_or(0),
+                object_format: meta
+                    .get("object_format")
+                    .cloned()
+                    .unwrap_or_default(),
+                tips: meta
+                    .get("tips")
+                    .and_then(|tips| OidMap::from_reader(tips.as_bytes()).ok())
+                    .map_or_else(Vec::new, |tips| tips.iter().collect()),
+            }))
+        }
+
+        pub fn set_header(&self, header: &Header) -> rusqlite::Result<()> {
+            let mut statement = self.conn.prepare(
+                "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            )?;
+
+            statement.execute(["version", &header.version.to_string()])?;
+            statement.execute([
+                "tool_version",
+                header.tool_version.as_deref().unwrap_or_default(),
+            ])?;
+            statement.execute(["filter", &format!("{:x}", header.filter)])?;
+            statement.execute(["object_format", &header.object_format])?;
+
+            // The tips are kept in the text format of maps.
+            let mut tips = String::new();
+
+            for (tip, value) in &header.tips {
+                match value {
+                    Some(value) => {
+                        tips.push_str(&format!("{} {}\n", tip, value))
+                    }
+                    None => tips.push_str(&format!("{}\n", tip)),
+                }
+            }
+
+            statement.execute(["tips", &tips])?;
+
+            Ok(())
+        }
+
+        /// Keeps the first error.
+        fn check<T>(&self, result: rusqlite::Result<T>) -> Option<T> {
+            match result {
+                Ok(value) => Some(value),
+                Err(err) => {
+                    self.error.borrow_mut().get_or_insert(err);
+                    None
+                }
+            }
+        }
+
+        pub fn is_empty(&self) -> rusqlite::Result<bool> {
+            self.conn
+                .query_row("SELECT 1 FROM map LIMIT 1", [], |_| Ok(()))
+                .optional()
+                .map(|row| row.is_none())
+        }
+
+        pub fn get(&self, k: &Oid) -> Option<Option<Oid>> {
+            let value = self
+                .conn
+                .prepare_cached("SELECT value FROM map WHERE key = ?1")
+                .and_then(|mut statement| {
+                    statement
+                        .query_row([k.as_bytes()], |row| {
+                            row.get::<_, Option<[u8; 20]>>(0)
+                        })
+                        .optional()
+                });
+
+            self.check(value)?.map(|value| {
+                value.map(|value| Oid::from_bytes(&value).unwrap())
+            })
+        }
+
+        pub fn insert(&self, k: &Oid, v: Option<Oid>) {
+            let result = self
+                .conn
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO map (key, value) VALUES (?1, ?2)",
+                )
+                .and_then(|mut statement| {
+                    statement.execute(rusqlite::params![
+                        k.as_bytes(),
+                        v.as_ref().map(|v| v.as_bytes())
+                    ])
+                });
+
+            self.check(result);
+        }
+
+        pub fn entries(&self) -> Vec<(Oid, Option<Oid>)> {
+            let entries = self
+                .conn
+                .prepare_cached("SELECT key, value FROM map")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], |row| {
+                            Ok((
+                                row.get::<_, [u8; 20]>(0)?,
+                                row.get::<_, Option<[u8; 20]>>(1)?,
+                            ))
+                        })?
+                        .collect::<rusqlite::Result<Vec<_>>>()
+                });
+
+            self.check(entries)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(k, v)| {
+                    (
+                        Oid::from_bytes(&k).unwrap(),
+                        v.map(|v| Oid::from_bytes(&v).unwrap()),
+                    )
+                })
+                .collect()
+        }
+
+        /// Commits the changes so far, or returns the first error since the
+        /// last commit.
+        pub fn commit(&self) -> rusqlite::Result<()> {
+            if let Some(err) = self.error.borrow_mut().take() {
+                return Err(err);
+            }
+
+            self.conn.execute_batch("COMMIT; BEGIN;")
+        }
     }
 }
 
@@ -164,16 +1122,152 @@ mod tests {
             Oid::from_str("0000000000000000000000000000000000000002").unwrap();
 
         map.insert(a, Some(b));
-        assert_eq!(map.resolve(&a), Some(&Some(b)));
+        assert_eq!(map.resolve(&a), Some(Some(b)));
         assert_eq!(map.resolve(&b), None);
 
         map.insert(b, Some(c));
-        assert_eq!(map.resolve(&a), Some(&Some(c)));
-        assert_eq!(map.resolve(&b), Some(&Some(c)));
+        assert_eq!(map.resolve(&a), Some(Some(c)));
+        assert_eq!(map.resolve(&b), Some(Some(c)));
 
         map.insert(c, None);
-        assert_eq!(map.resolve(&a), Some(&None));
-        assert_eq!(map.resolve(&b), Some(&None));
-        assert_eq!(map.resolve(&c), Some(&None));
+        assert_eq!(map.resolve(&a), Some(None));
+        assert_eq!(map.resolve(&b), Some(None));
+        assert_eq!(map.resolve(&c), Some(None));
+    }
+
+    #[test]
+    fn test_tips() {
+        let a =
+            Oid::from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d").unwrap();
+        let b =
+            Oid::from_str("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391").unwrap();
+
+        let mut map = OidMap::new();
+        map.insert(a, Some(b));
+        map.set_tips(&[a, b]);
+        assert_eq!(map.tips(), vec![a]);
+
+        let mut binary = Vec::new();
+        let header = Header {
+            tips: map.tips.clone(),
+            ..Header::new(42, "sha1")
+        };
+        map.write_binary(&mut binary, &header).unwrap();
+        let (_, read) = OidMap::read_binary(&binary[..]).unwrap();
+        assert_eq!(read.tips(), vec![a]);
+
+        // Tips that map to something else since aren't used.
+        map.insert(a, None);
+        assert_eq!(map.tips(), vec![]);
+    }
+
+    #[test]
+    fn test_seed() {
+        let mut map = OidMap::new();
+        let seed = map.seed().unwrap();
+        assert_eq!(map.seed().unwrap(), seed);
+        assert_ne!(OidMap::new().seed().unwrap(), seed);
+
+        let odb = git2::Odb::new().unwrap();
+        assert_eq!(map.verify(&odb), vec![]);
+    }
+
+    #[test]
+    fn test_verify() {
+        let odb = git2::Odb::new().unwrap();
+        odb.add_new_mempack_backend(1).unwrap();
+
+        let a = odb.write(git2::ObjectType::Blob, b"a").unwrap();
+        let b = odb.write(git2::ObjectType::Blob, b"b").unwrap();
+        let c = Oid::hash_object(git2::ObjectType::Blob, b"c").unwrap();
+
+        let mut map = OidMap::new();
+        map.insert(a, Some(b));
+        map.insert(b, Some(b));
+        map.insert(c, None);
+        assert_eq!(map.verify(&odb), vec![]);
+
+        map.insert(c, Some(c));
+        assert_eq!(
+            map.verify(&odb),
+            vec![Problem::Dangling { key: c, value: c }]
+        );
+
+        map.insert(c, Some(a));
+        map.insert(b, Some(c));
+        let problems = map.verify(&odb);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0], Problem::Dangling { key: b, value: c });
+        assert!(matches!(problems[1], Problem::Cycle(_)));
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut map = OidMap::new();
+
+        let a =
+            Oid::from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d").unwrap();
+        let b =
+            Oid::from_str("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391").unwrap();
+
+        map.insert(a, Some(b));
+        map.insert(b, None);
+
+        let header = Header::new(42, "sha1");
+
+        let mut binary = Vec::new();
+        map.write_binary(&mut binary, &header).unwrap();
+
+        let (read_header, read) = OidMap::read_binary(&binary[..]).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(read.get(&a), Some(Some(b)));
+        assert_eq!(read.get(&b), Some(None));
+
+        // Any format can be read, compressed or not.
+        let mut text = Vec::new();
+        map.write(&mut text).unwrap();
+
+        let compressed = zstd::encode_all(&binary[..], 0).unwrap();
+        assert!(compressed.starts_with(&ZSTD_MAGIC));
+
+        for bytes in [&binary, &text, &compressed] {
+            let read = OidMap::from_reader(&bytes[..]).unwrap();
+            assert_eq!(read.iter().count(), 2);
+            assert_eq!(read.get(&a), Some(Some(b)));
+        }
+
+        // A truncated entry is an error.
+        assert!(OidMap::read_binary(&binary[..binary.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        // Version 1 only had the filter and the length of OIDs.
+        let mut v1 = MAGIC.to_vec();
+        v1.extend(1u32.to_le_bytes());
+        v1.extend(42u64.to_le_bytes());
+        v1.push(20);
+
+        let header = Header::read(&mut &v1[..]).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.tool_version, None);
+        assert!(header.check(42, "sha1").is_ok());
+        assert!(header.check(43, "sha1").is_err());
+        assert!(header.check(42, "sha256").is_err());
+
+        // Newer versions are refused.
+        let mut newer = MAGIC.to_vec();
+        newer.extend((VERSION + 1).to_le_bytes());
+        let err = Header::read(&mut &newer[..]).unwrap_err();
+        assert!(err.to_string().contains("newer"), "{}", err);
+
+        let mut out = Vec::new();
+        Header::new(42, "sha1").write(&mut out).unwrap();
+        let header = Header::read(&mut &out[..]).unwrap();
+        assert_eq!(header.version, VERSION);
+        assert_eq!(
+            header.tool_version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
     }
 }
@@ -0,0 +1,257 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writing and reading the commits in an object map in formats that other
+//! tools understand, rather than the one it is saved in.
+
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+
+use crate::map::OidMap;
+use crate::Error;
+
+/// A format for the commits in an object map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapFormat {
+    /// A JSON object where each key is an original commit and its value is the
+    /// commit it became, or `null` if it was left out.
+    Json,
+
+    /// The `commit-map` file written by `git filter-repo`, with an original
+    /// commit and the commit it became on each line. Commits that were left
+    /// out become all zeros.
+    CommitMap,
+}
+
+impl FromStr for MapFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MapFormat, String> {
+        match s {
+            "json" => Ok(MapFormat::Json),
+            "commit-map" => Ok(MapFormat::CommitMap),
+            _ => {
+                Err(format!("Expected 'json' or 'commit-map' but got '{}'", s))
+            }
+        }
+    }
+}
+
+/// Returns every original commit in the map along with the commit that stands
+/// for it in the new history, sorted by the original. Empty commits that were
+/// pruned stand for their nearest rewritten ancestor. Commits that were left
+/// out entirely have `None`. Other entries, such as those for trees or for
+/// rewritten commits that were then discarded, are left out.
+pub fn commits(
+    repo: &git2::Repository,
+    map: &OidMap,
+) -> Result<Vec<(git2::Oid, Option<git2::Oid>)>, Error> {
+    let odb = repo.odb()?;
+    let mut commits = Vec::new();
+
+    // Rewritten commits are only in the map if they were discarded for being
+    // empty, in which case they map to something else. Commits that were kept
+    // as they are map to themselves, and are original commits as well.
+    let rewritten: HashSet<git2::Oid> = map
+        .iter()
+        .filter_map(|(old, new)| new.filter(|&new| new != old))
+        .collect();
+
+    for (old, new) in map.iter() {
+        if rewritten.contains(&old) && new != Some(old) {
+            continue;
+        }
+
+        let is_commit = odb
+            .read_header(old)
+            .map(|(_, kind)| kind == git2::ObjectType::Commit)
+            .unwrap_or(false);
+
+        if is_commit {
+            commits.push((old, map.resolve(&old).flatten()));
+        }
+    }
+
+    commits.sort();
+
+    Ok(commits)
+}
+
+/// Writes the commits in a map, returning how many there were.
+pub fn export<W: Write>(
+    repo: &git2::Repository,
+    map: &OidMap,
+    format: MapFormat,
+    mut out: W,
+) -> Result<usize, Error> {
+    let commits = commits(repo, map)?;
+
+    match format {
+        MapFormat::Json => {
+            write!(out, "{{")?;
+
+            for (i, (old, new)) in commits.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+
+                match new {
+                    Some(new) => write!(
+                        out,
+                        "{}\n  \"{}\": \"{}\"",
+                        separator, old, new
+                    )?,
+                    None => write!(out, "{}\n  \"{}\": null", separator, old)?,
+                }
+            }
+
+            writeln!(out, "\n}}")?;
+        }
+        MapFormat::CommitMap => {
+            writeln!(out, "{:<40} new", "old")?;
+
+            for (old, new) in &commits {
+                writeln!(
+                    out,
+                    "{} {}",
+                    old,
+                    new.unwrap_or_else(git2::Oid::zero)
+                )?;
+            }
+        }
+    }
+
+    out.flush()?;
+
+    Ok(commits.len())
+}
+
+/// Parses a commit in a file being imported.
+fn parse_oid(s: &str) -> Result<git2::Oid, Error> {
+    git2::Oid::from_str(s).map_err(|_| {
+        Error::invalid(format!("commit '{}'", s), "expected a hash")
+    })
+}
+
+/// Adds the commits in a file to a map, returning how many there were.
+pub fn import<R: BufRead>(
+    map: &mut OidMap,
+    format: MapFormat,
+    reader: R,
+) -> Result<usize, Error> {
+    let mut commits = Vec::new();
+
+    match format {
+        MapFormat::Json => {
+            let object: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_reader(reader)
+                    .map_err(|err| Error::invalid("JSON object map", err))?;
+
+            for (old, new) in &object {
+                let new = match new {
+                    serde_json::Value::String(new) => Some(parse_oid(new)?),
+                    serde_json::Value::Null => None,
+                    _ => {
+                        return Err(Error::invalid(
+                            format!("mapping for '{}'", old),
+                            "expected a hash or null",
+                        ))
+                    }
+                };
+
+                commits.push((parse_oid(old)?, new));
+            }
+        }
+        MapFormat::CommitMap => {
+            for line in reader.lines() {
+                let line = line?;
+                let line = line.trim();
+
+                if line.is_empty() || line.split_whitespace().eq(["old", "new"])
+                {
+                    continue;
+                }
+
+                let (old, new) = line.split_once(' ').ok_or_else(|| {
+                    Error::invalid(
+                        format!("commit-map line '{}'", line),
+                        "expected '<old> <new>'",
+                    )
+                })?;
+
+                let new = parse_oid(new.trim())?;
+                let new = if new.is_zero() { None } else { Some(new) };
+
+                commits.push((parse_oid(old)?, new));
+            }
+        }
+    }
+
+    for &(old, new) in &commits {
+        map.insert(old, new);
+    }
+
+    Ok(commits.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_import_json() {
+        let a = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let b = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let json = format!("{{\"{}\": \"{}\", \"{}\": null}}", a, b, b);
+
+        let mut map = OidMap::new();
+        let imported =
+            import(&mut map, MapFormat::Json, json.as_bytes()).unwrap();
+        assert_eq!(imported, 2);
+
+        let (a, b) = (a.parse().unwrap(), b.parse().unwrap());
+        assert_eq!(map.get(&a), Some(Some(b)));
+        assert_eq!(map.get(&b), Some(None));
+
+        let json = format!("{{\"{}\": 1}}", a);
+        assert!(import(&mut map, MapFormat::Json, json.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_import_commit_map() {
+        let a = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let b = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+        let zero = git2::Oid::zero();
+        let file = format!("{:<40} new\n{} {}\n{} {}\n", "old", a, b, b, zero);
+
+        let mut map = OidMap::new();
+        let imported =
+            import(&mut map, MapFormat::CommitMap, file.as_bytes()).unwrap();
+        assert_eq!(imported, 2);
+
+        let (a, b) = (a.parse().unwrap(), b.parse().unwrap());
+        assert_eq!(map.get(&a), Some(Some(b)));
+        assert_eq!(map.get(&b), Some(None));
+
+        let file = format!("{}\n", a);
+        assert!(
+            import(&mut map, MapFormat::CommitMap, file.as_bytes()).is_err()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reading and writing marks files, as used by `git fast-import` and `git
+//! fast-export` to carry marks over from one stream to the next.
+
+use std::io::{self, BufRead, Write};
+
+use crate::Error;
+
+/// Parses a line such as `:1 <id>`.
+fn parse_line(line: &str) -> Option<(u64, git2::Oid)> {
+    let (mark, id) = line.strip_prefix(':')?.split_once(' ')?;
+    Some((mark.parse().ok()?, git2::Oid::from_str(id.trim()).ok()?))
+}
+
+/// Reads the marks in a marks file, where each line is a mark and the object
+/// it stands for.
+pub fn read<R: BufRead>(reader: R) -> Result<Vec<(u64, git2::Oid)>, Error> {
+    let mut marks = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+
+        if line.is_empty() {
+            continue;
+        }
+
+        match parse_line(&line) {
+            Some(mark) => marks.push(mark),
+            None => {
+                return Err(Error::invalid(
+                    format!("mark '{}'", line),
+                    "expected ':<mark> <id>'",
+                ))
+            }
+        }
+    }
+
+    Ok(marks)
+}
+
+/// Writes marks in the same format, in order.
+pub fn write<W: Write>(
+    mut out: W,
+    marks: &[(u64, git2::Oid)],
+) -> io::Result<()> {
+    let mut marks = marks.to_vec();
+    marks.sort();
+
+    for (mark, id) in marks {
+        writeln!(out, ":{} {}", mark, id)?;
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let a = git2::Oid::from_str("8ab686eafeb1f44702738c8b0f24f2567c36da6d")
+            .unwrap();
+        let b = git2::Oid::from_str("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391")
+            .unwrap();
+
+        let mut out = Vec::new();
+        write(&mut out, &[(2, b), (1, a)]).unwrap();
+        assert_eq!(out, format!(":1 {}\n:2 {}\n", a, b).as_bytes());
+
+        assert_eq!(read(&out[..]).unwrap(), vec![(1, a), (2, b)]);
+        assert!(read(&b"1 abc\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Packing objects, like `git repack -a -d`.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::copy::{reachable, reference_tips};
+
+/// Returns the packs in a repository.
+fn packs(objects: &Path) -> io::Result<HashSet<PathBuf>> {
+    let mut packs = HashSet::new();
+
+    for entry in fs::read_dir(objects.join("pack"))? {
+        let path = entry?.path();
+
+        if path.extension() == Some("pack".as_ref()) {
+            packs.insert(path);
+        }
+    }
+
+    Ok(packs)
+}
+
+/// Removes a file, doing nothing if it doesn't exist.
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Packs every object reachable from a reference into a single new pack,
+/// returning the number of objects in it. The old packs are removed, as are
+/// the loose copies of the objects that were packed. Unreachable loose objects
+/// are left for `git gc` to deal with.
+pub fn repack(repo: &git2::Repository) -> Result<usize, git2::Error> {
+    let objects = reachable(repo, reference_tips(repo)?)?;
+
+    if objects.is_empty() {
+        return Ok(0);
+    }
+
+    let mut builder = repo.packbuilder()?;
+
+    for (id, _) in &objects {
+        builder.insert_object(*id, None)?;
+    }
+
+    let dir = repo.path().join("objects");
+    let io_error = |err: io::Error| git2::Error::from_str(&err.to_string());
+
+    let old = packs(&dir).map_err(io_error)?;
+
+    let odb = repo.odb()?;
+    let mut writer = odb.packwriter()?;
+    let mut result = Ok(());
+
+    builder.foreach(|buf| {
+        result = writer.write_all(buf);
+        result.is_ok()
+    })?;
+
+    result.map_err(io_error)?;
+    writer.commit()?;
+
+    // If the new pack has the same contents as an old one, it has the same
+    // name too, and nothing can be removed safely.
+    if packs(&dir).map_err(io_error)?.is_subset(&old) {
+        return Ok(objects.len());
+    }
+
+    // Packs marked with a `.keep` file are left alone, like Git does.
+    for pack in old
+        .iter()
+        .filter(|pack| !pack.with_extension("keep").exists())
+    {
+        remove(&pack.with_extension("idx")).map_err(io_error)?;
+        remove(pack).map_err(io_error)?;
+    }
+
+    for (id, _) in &objects {
+        let hex = id.to_string();
+        let (prefix, rest) = hex.split_at(2);
+        remove(&dir.join(prefix).join(rest)).map_err(io_error)?;
+
+        // Like Git, clean up the directory once it's empty.
+        let _ = fs::remove_dir(dir.join(prefix));
+    }
+
+    Ok(objects.len())
+}
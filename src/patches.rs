@@ -0,0 +1,316 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Writing history as a series of patches, as `git format-patch` does.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct",
+    "Nov", "Dec",
+];
+
+/// Formats a time as in an email, such as `Thu, 1 Jan 1970 00:00:00 +0000`.
+fn format_date(time: git2::Time) -> String {
+    let offset = time.offset_minutes() as i64;
+    let local = time.seconds() + offset * 60;
+
+    let days = local.div_euclid(86400);
+    let seconds = local.rem_euclid(86400);
+
+    // Converts days since 1970-01-01 to a date in the Gregorian calendar.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}{:02}{:02}",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Encodes a header value as in RFC 2047 if it isn't plain ASCII.
+fn encode_header(value: &[u8]) -> String {
+    if value.iter().all(|&c| (b' '..=b'~').contains(&c)) {
+        return String::from_utf8_lossy(value).into_owned();
+    }
+
+    let mut encoded = String::from("=?UTF-8?q?");
+
+    for &c in value {
+        match c {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'*'
+            | b'+'
+            | b'-'
+            | b'/' => encoded.push(c as char),
+            _ => encoded.push_str(&format!("={:02X}", c)),
+        }
+    }
+
+    encoded.push_str("?=");
+    encoded
+}
+
+/// Returns what follows the first paragraph of a message, which is its summary.
+fn body(message: &[u8]) -> &[u8] {
+    let mut rest = message;
+
+    while rest.first() == Some(&b'\n') {
+        rest = &rest[1..];
+    }
+
+    match rest.windows(2).position(|w| w == b"\n\n") {
+        Some(i) => {
+            rest = &rest[i..];
+
+            while rest.first() == Some(&b'\n') {
+                rest = &rest[1..];
+            }
+
+            rest
+        }
+        None => b"",
+    }
+}
+
+/// Returns the name of a patch file, such as `0001-Fix-a-bug.patch`.
+pub fn file_name(number: usize, summary: &str) -> String {
+    let mut name = format!("{:04}-", number);
+    let start = name.len();
+    let mut dash = false;
+
+    for c in summary.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if dash && name.len() > start {
+                name.push('-');
+            }
+
+            name.push(c);
+            dash = false;
+        } else {
+            dash = true;
+        }
+
+        if name.len() >= start + 52 {
+            break;
+        }
+    }
+
+    name.truncate(start + 52);
+
+    while name.ends_with('.') || name.ends_with('-') {
+        name.pop();
+    }
+
+    name.push_str(".patch");
+    name
+}
+
+/// Writes a commit as an email with its changes relative to its parent.
+fn write_patch<W: Write>(
+    repo: &git2::Repository,
+    commit: &git2::Commit,
+    number: usize,
+    total: usize,
+    out: &mut W,
+) -> Result<(), Error> {
+    let parent = match commit.parents().next() {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+
+    let mut options = git2::DiffOptions::new();
+    options.show_binary(true);
+
+    let diff = repo.diff_tree_to_tree(
+        parent.as_ref(),
+        Some(&commit.tree()?),
+        Some(&mut options),
+    )?;
+
+    let author = commit.author();
+    let summary = commit.summary_bytes().unwrap_or_default();
+    let body = body(commit.message_bytes());
+
+    writeln!(out, "From {} Mon Sep 17 00:00:00 2001", commit.id())?;
+    writeln!(
+        out,
+        "From: {} <{}>",
+        encode_header(author.name_bytes()),
+        String::from_utf8_lossy(author.email_bytes())
+    )?;
+    writeln!(out, "Date: {}", format_date(author.when()))?;
+
+    let prefix = if total == 1 {
+        "[PATCH]".to_string()
+    } else {
+        format!("[PATCH {}/{}]", number, total)
+    };
+
+    writeln!(out, "Subject: {} {}", prefix, encode_header(summary))?;
+
+    if !body.is_ascii() {
+        writeln!(out, "MIME-Version: 1.0")?;
+        writeln!(out, "Content-Type: text/plain; charset=UTF-8")?;
+        writeln!(out, "Content-Transfer-Encoding: 8bit")?;
+    }
+
+    writeln!(out)?;
+
+    if !body.is_empty() {
+        out.write_all(body)?;
+
+        if !body.ends_with(b"\n") {
+            writeln!(out)?;
+        }
+    }
+
+    writeln!(out, "---")?;
+
+    let stats = diff.stats()?.to_buf(
+        git2::DiffStatsFormat::FULL | git2::DiffStatsFormat::INCLUDE_SUMMARY,
+        72,
+    )?;
+    out.write_all(&stats)?;
+    writeln!(out)?;
+
+    let mut patch = Vec::new();
+    diff.print(git2::DiffFormat::Patch, |_, _, line| {
+        if let '+' | '-' | ' ' = line.origin() {
+            patch.push(line.origin() as u8);
+        }
+        patch.extend(line.content());
+        true
+    })?;
+    out.write_all(&patch)?;
+
+    writeln!(out, "-- \ngit-subset {}\n", env!("CARGO_PKG_VERSION"))?;
+
+    Ok(())
+}
+
+/// Writes every commit in the history of `tips` to a file of its own in `dir`,
+/// oldest first, returning the paths of the files. Merge commits are left out
+/// since they can't be written as a patch.
+pub fn export_patches(
+    repo: &git2::Repository,
+    tips: &[git2::Oid],
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+
+    for &tip in tips {
+        walk.push(tip)?;
+    }
+
+    let mut commits = Vec::new();
+
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+
+        if commit.parent_count() <= 1 {
+            commits.push(commit);
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+
+    let mut paths = Vec::new();
+
+    for (i, commit) in commits.iter().enumerate() {
+        let summary =
+            String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default());
+        let path = dir.join(file_name(i + 1, &summary));
+
+        let mut out = std::io::BufWriter::new(fs::File::create(&path)?);
+        write_patch(repo, commit, i + 1, commits.len(), &mut out)?;
+        out.flush()?;
+
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(
+            format_date(git2::Time::new(0, 0)),
+            "Thu, 1 Jan 1970 00:00:00 +0000"
+        );
+        assert_eq!(
+            format_date(git2::Time::new(951782400, -330)),
+            "Mon, 28 Feb 2000 18:30:00 -0530"
+        );
+    }
+
+    #[test]
+    fn test_body() {
+        assert_eq!(body(b"Summary\n"), b"");
+        assert_eq!(body(b"Summary\nmore\n\n\nBody\n"), b"Body\n");
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name(1, "Fix a bug"), "0001-Fix-a-bug.patch");
+        assert_eq!(
+            file_name(12, "[x] Don't crash... "),
+            "0012-x-Don-t-crash.patch"
+        );
+        assert_eq!(file_name(3, &"a".repeat(60)).len(), 5 + 52 + 6);
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(encode_header(b"Jane Doe"), "Jane Doe");
+        assert_eq!(encode_header("Zoë S".as_bytes()), "=?UTF-8?q?Zo=C3=AB_S?=");
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Git pathspecs with magic signatures.
+
+use crate::error::Error;
+use crate::glob::glob_match_path;
+
+use std::fmt;
+use std::path::Path;
+
+/// A single git pathspec, such as `src/*.c`, `:(exclude)src/gen`, or `:!docs`.
+///
+/// The magic signature is parsed here, since libgit2 does not understand it.
+/// Matching itself is delegated to `git2::Pathspec`, except for the `glob`
+/// magic which libgit2 doesn't support either.
+pub struct Pathspec {
+    /// The pathspec as it was given.
+    source: String,
+
+    /// The components of the pattern. Only used with the `glob` magic.
+    components: Vec<String>,
+
+    spec: git2::Pathspec,
+
+    exclude: bool,
+    icase: bool,
+    literal: bool,
+    glob: bool,
+}
+
+impl fmt::Debug for Pathspec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Pathspec").field(&self.source).finish()
+    }
+}
+
+impl Clone for Pathspec {
+    fn clone(&self) -> Pathspec {
+        // `git2::Pathspec` can't be cloned, but parsing it again can't fail
+        // either.
+        let spec = Pathspec::new(&self.source).unwrap();
+
+        if self.icase {
+            spec.ignore_case()
+        } else {
+            spec
+        }
+    }
+}
+
+impl Pathspec {
+    /// Parses a pathspec. The supported magic words are `top`, `literal`,
+    /// `icase`, `glob`, and `exclude`, in either their long form (e.g.,
+    /// `:(exclude,icase)foo`) or short form (e.g., `:!foo` or `:/foo`).
+    pub fn new(source: &str) -> Result<Pathspec, Error> {
+        let invalid =
+            |reason| Error::invalid(format!("pathspec '{}'", source), reason);
+
+        let mut exclude = false;
+        let mut icase = false;
+        let mut literal = false;
+        let mut glob = false;
+
+        let pattern = if let Some(rest) = source.strip_prefix(":(") {
+            let end = rest.find(')').ok_or_else(|| {
+                invalid("missing ')' at the end of its magic".to_string())
+            })?;
+
+            for word in rest[..end].split(',') {
+                match word.trim() {
+                    "top" | "" => {}
+                    "exclude" => exclude = true,
+                    "icase" => icase = true,
+                    "literal" => literal = true,
+                    "glob" => glob = true,
+                    word => {
+                        return Err(invalid(format!(
+                            "unsupported magic '{}'",
+                            word
+                        )));
+                    }
+                }
+            }
+
+            &rest[end + 1..]
+        } else if let Some(rest) = source.strip_prefix(':') {
+            let end = rest
+                .find(|c| !matches!(c, '/' | '!' | '^'))
+                .unwrap_or(rest.len());
+
+            if rest[..end].contains(['!', '^']) {
+                exclude = true;
+            }
+
+            let rest = &rest[end..];
+            rest.strip_prefix(':').unwrap_or(rest)
+        } else {
+            source
+        };
+
+        if literal && glob {
+            return Err(invalid(
+                "'literal' and 'glob' magic are incompatible".to_string(),
+            ));
+        }
+
+        let spec = git2::Pathspec::new(Some(pattern))
+            .map_err(|err| invalid(err.message().to_string()))?;
+
+        Ok(Pathspec {
+            source: source.to_string(),
+            components: pattern.split('/').map(String::from).collect(),
+            spec,
+            exclude,
+            icase,
+            literal,
+            glob,
+        })
+    }
+
+    /// Makes the pathspec ignore case, as if it had the `icase` magic.
+    pub fn ignore_case(mut self) -> Pathspec {
+        self.icase = true;
+        self
+    }
+
+    /// The pathspec as it was given.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns `true` if this pathspec has the `exclude` magic.
+    pub fn is_exclude(&self) -> bool {
+        self.exclude
+    }
+
+    /// Returns `true` if the given path matches. A path also matches if one of
+    /// its parent directories matches. Case is ignored if the pathspec has the
+    /// `icase` magic or if `icase` is `true`.
+    pub fn matches(&self, path: &str, icase: bool) -> bool {
+        let icase = self.icase || icase;
+
+        if self.glob {
+            let path: Vec<&str> = path.split('/').collect();
+
+            return (1..=path.len())
+                .any(|i| glob_match_path(&self.components, &path[..i], icase));
+        }
+
+        let mut flags = git2::PathspecFlags::DEFAULT;
+
+        if icase {
+            flags |= git2::PathspecFlags::IGNORE_CASE;
+        }
+
+        if self.literal {
+            flags |= git2::PathspecFlags::NO_GLOB;
+        }
+
+        self.spec.matches_path(Path::new(path), flags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magic() {
+        let spec = Pathspec::new(":(exclude)src/gen").unwrap();
+        assert!(spec.is_exclude());
+        assert!(spec.matches("src/gen", false));
+        assert!(spec.matches("src/gen/foo.c", false));
+        assert!(!spec.matches("src/generated", false));
+
+        assert!(Pathspec::new(":!docs").unwrap().is_exclude());
+        assert!(Pathspec::new(":^docs").unwrap().is_exclude());
+        assert!(!Pathspec::new(":/docs").unwrap().is_exclude());
+
+        let spec = Pathspec::new(":(icase)readme*").unwrap();
+        assert!(spec.matches("README.md", false));
+
+        // Without the glob magic, `*` matches across directories.
+        let spec = Pathspec::new("*.md").unwrap();
+        assert!(spec.matches("docs/guide.md", false));
+
+        let spec = Pathspec::new(":(glob)*.md").unwrap();
+        assert!(!spec.matches("docs/guide.md", false));
+        assert!(spec.matches("README.md", false));
+
+        let spec = Pathspec::new(":(glob)**/*.md").unwrap();
+        assert!(spec.matches("docs/guide.md", false));
+
+        let spec = Pathspec::new(":(literal)*.md").unwrap();
+        assert!(!spec.matches("README.md", false));
+        assert!(spec.matches("*.md", false));
+
+        assert!(Pathspec::new(":(attr:foo)bar").is_err());
+        assert!(Pathspec::new(":(glob,literal)bar").is_err());
+        assert!(Pathspec::new(":(glob").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting `.gitignore` and `.gitattributes` files so that they don't refer
+//! to paths that are gone.
+
+/// The kind of file with patterns in it, which decides where the pattern is on
+/// each line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Ignore,
+    Attributes,
+}
+
+impl Kind {
+    /// Returns the kind of a file by its name, if it is one with patterns.
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            ".gitignore" => Some(Kind::Ignore),
+            ".gitattributes" => Some(Kind::Attributes),
+            _ => None,
+        }
+    }
+
+    /// Returns the pattern on a line, if there is one. Quoted patterns in
+    /// `.gitattributes` are skipped, as are macro definitions.
+    fn pattern(self, line: &str) -> Option<&str> {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        match self {
+            Kind::Ignore => Some(line.strip_prefix('!').unwrap_or(line)),
+            Kind::Attributes => {
+                if line.starts_with('"') || line.starts_with("[attr]") {
+                    None
+                } else {
+                    line.split_whitespace().next()
+                }
+            }
+        }
+    }
+}
+
+/// Returns the leading components of a pattern that are plain names. A pattern
+/// only refers to paths in the directory of the file it is in if it has a `/`
+/// anywhere but at the end. Otherwise, it can match at any depth and nothing is
+/// returned.
+fn literal_prefix(pattern: &str) -> Vec<&str> {
+    let pattern = pattern.strip_suffix('/').unwrap_or(pattern);
+
+    if !pattern.contains('/') {
+        return Vec::new();
+    }
+
+    pattern
+        .trim_start_matches('/')
+        .split('/')
+        .take_while(|name| {
+            !name.is_empty() && !name.contains(|c| "*?[\\".contains(c))
+        })
+        .collect()
+}
+
+/// Removes the lines whose pattern `keep` returns `false` for. `keep` is given
+/// the leading components of the pattern that are plain names, relative to the
+/// directory of the file. Lines without a pattern, or whose pattern can match
+/// at any depth, are kept as they are. Returns `None` if nothing is removed.
+pub fn retain<F>(contents: &[u8], kind: Kind, mut keep: F) -> Option<Vec<u8>>
+where
+    F: FnMut(&[&str]) -> bool,
+{
+    let mut out = Vec::with_capacity(contents.len());
+    let mut removed = false;
+
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        let prefix = std::str::from_utf8(line)
+            .ok()
+            .and_then(|line| kind.pattern(line))
+            .map(literal_prefix)
+            .unwrap_or_default();
+
+        if !prefix.is_empty() && !keep(&prefix) {
+            removed = true;
+        } else {
+            out.extend_from_slice(line);
+        }
+    }
+
+    if removed {
+        Some(out)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("/internal/build"), ["internal", "build"]);
+        assert_eq!(literal_prefix("internal/*.o"), ["internal"]);
+        assert_eq!(literal_prefix("/secret.txt"), ["secret.txt"]);
+        assert!(literal_prefix("build/").is_empty());
+        assert!(literal_prefix("*.o").is_empty());
+        assert!(literal_prefix("**/internal").is_empty());
+    }
+
+    #[test]
+    fn test_retain() {
+        let ignore = b"\
+            # Build output\n\
+            *.o\n\
+            /internal/build/\n\
+            !/public/keep.o\n\
+            internal\n";
+
+        assert_eq!(retain(ignore, Kind::Ignore, |_| true), None);
+        assert_eq!(
+            retain(ignore, Kind::Ignore, |prefix| prefix[0] != "internal")
+                .unwrap(),
+            &b"# Build output\n*.o\n!/public/keep.o\ninternal\n"[..]
+        );
+
+        let attributes = b"\
+            [attr]binary -diff\n\
+            /internal/*.bin binary\n\
+            \"/internal/a b\" text\n\
+            *.txt text\n";
+
+        assert_eq!(
+            retain(attributes, Kind::Attributes, |prefix| prefix[0]
+                != "internal")
+            .unwrap(),
+            &b"[attr]binary -diff\n\"/internal/a b\" text\n*.txt text\n"[..]
+        );
+    }
+}
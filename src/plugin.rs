@@ -0,0 +1,311 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Plugins compiled to WebAssembly that decide what to do with paths and
+//! commit messages. This needs the `wasm` feature.
+//!
+//! A plugin is a module that exports its `memory` and an `alloc` function,
+//! plus any of the hooks below:
+//!
+//! ```text
+//! alloc(len: i32) -> i32
+//! filter_path(ptr: i32, len: i32) -> i64
+//! rewrite_message(ptr: i32, len: i32) -> i64
+//! ```
+//!
+//! For every call to a hook, its input is copied into a buffer returned by
+//! `alloc`, which is never freed by git-subset. Strings are passed as UTF-8.
+//!
+//! `filter_path` is called with the path of every file in a filtered tree. It
+//! returns `0` to keep the file, `-1` to leave it out, or the location of a new
+//! path to move it to, with the pointer in the upper 32 bits and the length in
+//! the lower 32 bits.
+//!
+//! `rewrite_message` is called with the message of every rewritten commit and
+//! returns the location of the new message in the same way, or `0` to leave it
+//! as it is.
+//!
+//! Plugins don't have access to anything besides their own memory, so they
+//! can't import any functions.
+
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "wasm")]
+use std::cell::RefCell;
+
+use crate::error::Error;
+
+/// What to do with a file according to a plugin.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub enum PathAction {
+    Keep,
+    Drop,
+    Rename(String),
+}
+
+/// A loaded plugin.
+pub struct Plugin {
+    path: PathBuf,
+
+    /// Hash of the module, so that changing the plugin changes the map.
+    digest: git2::Oid,
+
+    #[cfg(feature = "wasm")]
+    runtime: RefCell<Runtime>,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("path", &self.path)
+            .field("digest", &self.digest)
+            .finish()
+    }
+}
+
+impl Hash for Plugin {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.digest.hash(state);
+    }
+}
+
+impl Plugin {
+    /// Loads the plugin at the given path.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Plugin, Error> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|err| {
+            Error::failed(format!("read plugin '{}'", path.display()), err)
+        })?;
+
+        let digest = git2::Oid::hash_object(git2::ObjectType::Blob, &bytes)?;
+
+        Plugin::instantiate(path, digest, &bytes).map_err(|err| {
+            Error::failed(format!("load plugin '{}'", path.display()), err)
+        })
+    }
+
+    #[cfg(feature = "wasm")]
+    fn instantiate(
+        path: &Path,
+        digest: git2::Oid,
+        bytes: &[u8],
+    ) -> Result<Plugin, String> {
+        Ok(Plugin {
+            path: path.to_path_buf(),
+            digest,
+            runtime: RefCell::new(Runtime::new(bytes)?),
+        })
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn instantiate(
+        _path: &Path,
+        _digest: git2::Oid,
+        _bytes: &[u8],
+    ) -> Result<Plugin, String> {
+        Err("git-subset was built without the `wasm` feature".to_string())
+    }
+
+    /// Whether the plugin exports `filter_path`.
+    pub fn filters_paths(&self) -> bool {
+        #[cfg(feature = "wasm")]
+        {
+            self.runtime.borrow().filter_path.is_some()
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        {
+            false
+        }
+    }
+
+    /// Whether the plugin exports `rewrite_message`.
+    pub fn rewrites_messages(&self) -> bool {
+        #[cfg(feature = "wasm")]
+        {
+            self.runtime.borrow().rewrite_message.is_some()
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        {
+            false
+        }
+    }
+
+    /// Asks the plugin what to do with the file at the given path.
+    pub fn filter_path(&self, path: &str) -> Result<PathAction, git2::Error> {
+        #[cfg(feature = "wasm")]
+        {
+            let mut runtime = self.runtime.borrow_mut();
+            let hook = match runtime.filter_path {
+                Some(hook) => hook,
+                None => return Ok(PathAction::Keep),
+            };
+
+            match runtime.call(hook, path.as_bytes())? {
+                Output::Unchanged => Ok(PathAction::Keep),
+                Output::Dropped => Ok(PathAction::Drop),
+                Output::Bytes(bytes) => match String::from_utf8(bytes) {
+                    Ok(path) => Ok(PathAction::Rename(path)),
+                    Err(_) => Err(git2::Error::from_str(
+                        "Plugin returned a path that isn't valid UTF-8",
+                    )),
+                },
+            }
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        {
+            let _ = path;
+            Ok(PathAction::Keep)
+        }
+    }
+
+    /// Lets the plugin rewrite a commit message. Returns `None` if it is left
+    /// as it is.
+    pub fn rewrite_message(
+        &self,
+        message: &[u8],
+    ) -> Result<Option<Vec<u8>>, git2::Error> {
+        #[cfg(feature = "wasm")]
+        {
+            let mut runtime = self.runtime.borrow_mut();
+            let hook = match runtime.rewrite_message {
+                Some(hook) => hook,
+                None => return Ok(None),
+            };
+
+            match runtime.call(hook, message)? {
+                Output::Bytes(bytes) => Ok(Some(bytes)),
+                _ => Ok(None),
+            }
+        }
+
+        #[cfg(not(feature = "wasm"))]
+        {
+            let _ = message;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+type Hook = wasmi::TypedFunc<(i32, i32), i64>;
+
+/// What a hook returned.
+#[cfg(feature = "wasm")]
+enum Output {
+    Unchanged,
+    Dropped,
+    Bytes(Vec<u8>),
+}
+
+/// An instance of a plugin along with its exports.
+#[cfg(feature = "wasm")]
+struct Runtime {
+    store: wasmi::Store<()>,
+    memory: wasmi::Memory,
+    alloc: wasmi::TypedFunc<i32, i32>,
+    filter_path: Option<Hook>,
+    rewrite_message: Option<Hook>,
+}
+
+#[cfg(feature = "wasm")]
+impl Runtime {
+    fn new(bytes: &[u8]) -> Result<Runtime, String> {
+        let engine = wasmi::Engine::default();
+        let module =
+            wasmi::Module::new(&engine, bytes).map_err(|e| e.to_string())?;
+        let mut store = wasmi::Store::new(&engine, ());
+        let linker = wasmi::Linker::<()>::new(&engine);
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| e.to_string())?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("Plugin doesn't export its memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| format!("Plugin has no usable `alloc`: {}", e))?;
+
+        let filter_path = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "filter_path")
+            .ok();
+        let rewrite_message = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "rewrite_message")
+            .ok();
+
+        Ok(Runtime {
+            store,
+            memory,
+            alloc,
+            filter_path,
+            rewrite_message,
+        })
+    }
+
+    /// Copies the input into the plugin and calls the hook with it.
+    fn call(
+        &mut self,
+        hook: Hook,
+        input: &[u8],
+    ) -> Result<Output, git2::Error> {
+        let error = |err: &dyn fmt::Display| {
+            git2::Error::from_str(&format!("Plugin failed: {}", err))
+        };
+
+        let len = input.len() as i32;
+        let ptr = self
+            .alloc
+            .call(&mut self.store, len)
+            .map_err(|e| error(&e))?;
+
+        self.memory
+            .write(&mut self.store, ptr as u32 as usize, input)
+            .map_err(|e| error(&e))?;
+
+        let result = hook
+            .call(&mut self.store, (ptr, len))
+            .map_err(|e| error(&e))?;
+
+        match result {
+            0 => Ok(Output::Unchanged),
+            -1 => Ok(Output::Dropped),
+            _ => {
+                let ptr = (result as u64 >> 32) as usize;
+                let len = (result as u64 & 0xffff_ffff) as usize;
+
+                let mut output = vec![0; len];
+                self.memory
+                    .read(&self.store, ptr, &mut output)
+                    .map_err(|e| error(&e))?;
+
+                Ok(Output::Bytes(output))
+            }
+        }
+    }
+}
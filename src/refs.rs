@@ -0,0 +1,765 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting references other than the branch being created.
+
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+
+use crate::commit::format_signature;
+use crate::error::Error;
+use crate::glob::glob_match;
+use crate::map::OidMap;
+
+use regex::Regex;
+
+/// A reference selected for rewriting along with the commit it points to.
+pub struct Ref {
+    pub name: String,
+
+    /// The name of the reference after renaming tags. The rewritten reference
+    /// is created with this name.
+    pub renamed: String,
+
+    /// The object the reference points to. This is either the commit or an
+    /// annotated tag.
+    pub target: git2::Oid,
+
+    pub commit: git2::Oid,
+}
+
+/// Returns the references matching any of the given glob patterns, such as
+/// `refs/heads/*`, but none of the exclude patterns. As with
+/// `Repository::references_glob`, a `*` in an exclude pattern also matches
+/// `/`. References that don't point to a commit are skipped.
+pub fn select_refs(
+    repo: &git2::Repository,
+    patterns: &[String],
+    excludes: &[String],
+) -> Result<Vec<Ref>, git2::Error> {
+    let mut refs: Vec<Ref> = Vec::new();
+
+    for pattern in patterns {
+        for reference in repo.references_glob(pattern)? {
+            let reference = reference?;
+
+            let name = match reference.name() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            if refs.iter().any(|r| r.name == name)
+                || excludes.iter().any(|glob| glob_match(glob, &name))
+            {
+                continue;
+            }
+
+            let target = match reference.resolve()?.target() {
+                Some(target) => target,
+                None => continue,
+            };
+
+            if let Ok(commit) = reference.peel_to_commit() {
+                refs.push(Ref {
+                    renamed: name.clone(),
+                    name,
+                    target,
+                    commit: commit.id(),
+                });
+            }
+        }
+    }
+
+    Ok(refs)
+}
+
+/// A rule for renaming tags.
+pub enum TagRename {
+    /// Replaces a prefix of the name, such as `v` with `subset-v`.
+    Prefix { old: String, new: String },
+
+    /// Replaces the matches of a regular expression. The replacement can refer
+    /// to capture groups, such as `$1`.
+    Regex { regex: Regex, replacement: String },
+}
+
+impl TagRename {
+    /// Parses a rule of the form `<old>:<new>` that replaces the prefix `<old>`
+    /// with `<new>`. Either one may be empty. Since tag names can't contain a
+    /// `:`, the rule can't be ambiguous.
+    pub fn prefix(rule: &str) -> Result<TagRename, Error> {
+        let (old, new) = rule.split_once(':').ok_or_else(|| {
+            Error::invalid(
+                format!("tag rename '{}'", rule),
+                "expected '<old>:<new>'",
+            )
+        })?;
+
+        Ok(TagRename::Prefix {
+            old: old.to_string(),
+            new: new.to_string(),
+        })
+    }
+
+    /// Parses a rule of the form `<regex>:<replacement>`. The rule is split at
+    /// the last `:`, since the replacement can't contain one.
+    pub fn regex(rule: &str) -> Result<TagRename, Error> {
+        let invalid =
+            |reason| Error::invalid(format!("tag rename '{}'", rule), reason);
+
+        let (regex, replacement) = rule.rsplit_once(':').ok_or_else(|| {
+            invalid("expected '<regex>:<replacement>'".to_string())
+        })?;
+
+        Ok(TagRename::Regex {
+            regex: Regex::new(regex).map_err(|err| invalid(err.to_string()))?,
+            replacement: replacement.to_string(),
+        })
+    }
+
+    /// Returns the new name of the tag, or `None` if the rule doesn't apply
+    /// to it.
+    fn apply(&self, name: &str) -> Option<String> {
+        match self {
+            TagRename::Prefix { old, new } => name
+                .strip_prefix(old.as_str())
+                .map(|rest| format!("{}{}", new, rest)),
+            TagRename::Regex { regex, replacement } => {
+                if regex.is_match(name) {
+                    Some(regex.replace(name, replacement.as_str()).into_owned())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Renames the tags among the references with the first rule that applies to
+/// each of them.
+pub fn rename_tags(refs: &mut [Ref], renames: &[TagRename]) {
+    for r in refs {
+        if let Some(tag) = r.name.strip_prefix("refs/tags/") {
+            if let Some(new) = renames.iter().find_map(|rule| rule.apply(tag)) {
+                r.renamed = format!("refs/tags/{}", new);
+            }
+        }
+    }
+}
+
+/// Returns the name of the rewritten counterpart of a reference. For example,
+/// with the prefix `refs/subset/foo/`, `refs/heads/main` becomes
+/// `refs/subset/foo/heads/main`.
+pub fn rewritten_name(prefix: &str, name: &str) -> String {
+    format!("{}{}", prefix, name.strip_prefix("refs/").unwrap_or(name))
+}
+
+/// Reference updates to apply all at once in a single transaction, so that an
+/// interrupted run never leaves some references pointing to the rewritten
+/// history and others to the original history.
+#[derive(Default)]
+pub struct RefUpdates {
+    /// The name of each reference along with its new target and reflog
+    /// message. The reference is deleted if there is no target.
+    updates: Vec<(String, Option<git2::Oid>, String)>,
+}
+
+impl RefUpdates {
+    pub fn new() -> RefUpdates {
+        RefUpdates::default()
+    }
+
+    /// Queues up pointing a reference at a new target. Unless `force` is
+    /// `true`, it is an error if the reference already exists.
+    pub fn set(
+        &mut self,
+        repo: &git2::Repository,
+        name: &str,
+        target: git2::Oid,
+        force: bool,
+        message: &str,
+    ) -> Result<(), git2::Error> {
+        if !force && repo.find_reference(name).is_ok() {
+            return Err(git2::Error::from_str(&format!(
+                "Reference '{}' already exists. Use --force to overwrite it",
+                name
+            )));
+        }
+
+        if self.updates.iter().any(|(other, _, _)| other == name) {
+            return Err(git2::Error::from_str(&format!(
+                "Reference '{}' would be written more than once",
+                name
+            )));
+        }
+
+        self.updates.push((
+            name.to_string(),
+            Some(target),
+            message.to_string(),
+        ));
+
+        Ok(())
+    }
+
+    /// Queues up deleting a reference.
+    pub fn remove(&mut self, name: &str) {
+        self.updates.push((name.to_string(), None, String::new()));
+    }
+
+    /// Applies all of the updates. Either all of them are applied or none of
+    /// them are.
+    pub fn commit(self, repo: &git2::Repository) -> Result<(), git2::Error> {
+        if self.updates.is_empty() {
+            return Ok(());
+        }
+
+        let mut transaction = repo.transaction()?;
+
+        for (name, _, _) in &self.updates {
+            transaction.lock_ref(name)?;
+        }
+
+        for (name, target, message) in &self.updates {
+            match target {
+                Some(target) => {
+                    transaction.set_target(name, *target, None, message)?
+                }
+                None => transaction.remove(name)?,
+            }
+        }
+
+        transaction.commit()
+    }
+}
+
+/// Queues up creating the rewritten counterparts of the references. References
+/// whose commits didn't make it into the subset are skipped. Annotated tags
+/// are recreated such that they point to the rewritten commits. Returns the
+/// number of references to be created.
+pub fn rewrite_refs(
+    repo: &git2::Repository,
+    map: &OidMap,
+    refs: &[Ref],
+    prefix: &str,
+    force: bool,
+    updates: &mut RefUpdates,
+) -> Result<usize, git2::Error> {
+    let mut count = 0;
+
+    for r in refs {
+        let target = match rewritten_target(repo, map, r)? {
+            Some(target) => target,
+            None => continue,
+        };
+
+        updates.set(
+            repo,
+            &rewritten_name(prefix, &r.renamed),
+            target,
+            force,
+            &format!("git-subset: rewrite {}", r.name),
+        )?;
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Queues up pointing the references at their rewritten commits. The original
+/// targets are saved under `refs/original/`, as with `git filter-branch`.
+/// References whose commits didn't make it into the subset are deleted.
+/// Returns the number of references to be rewritten.
+///
+/// Existing backups are only overwritten if `force` is `true`.
+pub fn rewrite_refs_in_place(
+    repo: &git2::Repository,
+    map: &OidMap,
+    refs: &[Ref],
+    force: bool,
+    updates: &mut RefUpdates,
+) -> Result<usize, git2::Error> {
+    let mut count = 0;
+
+    for r in refs {
+        let backup = format!("refs/original/{}", r.name);
+
+        if !force && repo.find_reference(&backup).is_ok() {
+            return Err(git2::Error::from_str(&format!(
+                "A backup of '{}' already exists in '{}'. Use --force to \
+                 overwrite it",
+                r.name, backup
+            )));
+        }
+
+        updates.set(repo, &backup, r.target, true, "git-subset: backup")?;
+
+        match rewritten_target(repo, map, r)? {
+            Some(target) => {
+                if r.renamed != r.name {
+                    updates.remove(&r.name);
+                }
+
+                updates.set(
+                    repo,
+                    &r.renamed,
+                    target,
+                    true,
+                    "git-subset: rewrite",
+                )?;
+
+                count += 1;
+            }
+            None => updates.remove(&r.name),
+        }
+    }
+
+    Ok(count)
+}
+
+/// Queues up creating a copy of the notes in `notes` whose notes are attached
+/// to the rewritten commits instead. With a prefix, the copy is created next
+/// to the other rewritten references. Otherwise, the notes are rewritten in
+/// place and the original is saved under `refs/original/`.
+///
+/// Notes on commits that didn't make it into the subset are dropped. If several
+/// commits were squashed into one, their notes are joined by a blank line.
+/// Returns the name of the new notes reference and the number of notes in it,
+/// or `None` if there are no notes to rewrite.
+pub fn rewrite_notes(
+    repo: &git2::Repository,
+    map: &OidMap,
+    notes: &str,
+    prefix: Option<&str>,
+    force: bool,
+    updates: &mut RefUpdates,
+) -> Result<Option<(String, usize)>, git2::Error> {
+    let original = match repo.find_reference(notes) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => return Ok(None),
+    };
+
+    let mut rewritten: BTreeMap<git2::Oid, Vec<git2::Oid>> = BTreeMap::new();
+
+    for note in repo.notes(Some(notes))? {
+        let (blob, commit) = note?;
+
+        if let Some(commit) = map.kept(&commit) {
+            rewritten.entry(commit).or_default().push(blob);
+        }
+    }
+
+    if rewritten.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = repo.treebuilder(None)?;
+
+    for (commit, blobs) in &rewritten {
+        let blob = match blobs.as_slice() {
+            [blob] => *blob,
+            blobs => {
+                let mut text = Vec::new();
+
+                for blob in blobs {
+                    if !text.is_empty() {
+                        text.push(b'\n');
+                    }
+
+                    text.extend_from_slice(repo.find_blob(*blob)?.content());
+                }
+
+                repo.blob(&text)?
+            }
+        };
+
+        builder.insert(commit.to_string(), blob, 0o100644)?;
+    }
+
+    let tree = repo.find_tree(builder.write()?)?;
+
+    // The original author and committer are kept so that rewriting the same
+    // notes again creates the same commit.
+    let commit = repo.commit(
+        None,
+        &original.author(),
+        &original.committer(),
+        "Notes rewritten by git-subset\n",
+        &tree,
+        &[],
+    )?;
+
+    let name = match prefix {
+        Some(prefix) => {
+            let name = rewritten_name(prefix, notes);
+            updates.set(
+                repo,
+                &name,
+                commit,
+                force,
+                &format!("git-subset: rewrite {}", notes),
+            )?;
+            name
+        }
+        None => {
+            let backup = format!("refs/original/{}", notes);
+
+            if !force && repo.find_reference(&backup).is_ok() {
+                return Err(git2::Error::from_str(&format!(
+                    "A backup of '{}' already exists in '{}'. Use --force \
+                     to overwrite it",
+                    notes, backup
+                )));
+            }
+
+            updates.set(
+                repo,
+                &backup,
+                original.id(),
+                true,
+                "git-subset: backup",
+            )?;
+            updates.set(repo, notes, commit, true, "git-subset: rewrite")?;
+            notes.to_string()
+        }
+    };
+
+    Ok(Some((name, rewritten.len())))
+}
+
+/// What to do with replace references (i.e., `refs/replace/*`) after the
+/// commits are rewritten, as with `git filter-repo --replace-refs`. The first
+/// half says what happens to existing ones and the second half whether new
+/// ones are added that replace each original commit with its rewritten
+/// counterpart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplaceRefs {
+    DeleteNoAdd,
+    DeleteAndAdd,
+    UpdateNoAdd,
+
+    /// Only adds them for commits that aren't already replaced.
+    UpdateOrAdd,
+    UpdateAndAdd,
+}
+
+impl ReplaceRefs {
+    fn deletes(self) -> bool {
+        matches!(self, ReplaceRefs::DeleteNoAdd | ReplaceRefs::DeleteAndAdd)
+    }
+
+    fn adds(self) -> bool {
+        !matches!(self, ReplaceRefs::DeleteNoAdd | ReplaceRefs::UpdateNoAdd)
+    }
+
+    /// Returns `true` if new ones are added even for commits that are already
+    /// replaced, overwriting the existing ones.
+    fn overwrites(self) -> bool {
+        matches!(self, ReplaceRefs::DeleteAndAdd | ReplaceRefs::UpdateAndAdd)
+    }
+}
+
+impl FromStr for ReplaceRefs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReplaceRefs, String> {
+        match s {
+            "delete-no-add" => Ok(ReplaceRefs::DeleteNoAdd),
+            "delete-and-add" => Ok(ReplaceRefs::DeleteAndAdd),
+            "update-no-add" => Ok(ReplaceRefs::UpdateNoAdd),
+            "update-or-add" => Ok(ReplaceRefs::UpdateOrAdd),
+            "update-and-add" => Ok(ReplaceRefs::UpdateAndAdd),
+            _ => Err(format!(
+                "Expected 'delete-no-add', 'delete-and-add', \
+                 'update-no-add', 'update-or-add', or 'update-and-add' but \
+                 got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Queues up changing the replace references after the commits in the map are
+/// rewritten. Updating an existing one points it at the rewritten counterparts
+/// of the commit it replaces and of its replacement, and deletes it if the
+/// commit it replaces was left out. Returns the number of replace references
+/// written and the number deleted.
+pub fn rewrite_replace_refs(
+    repo: &git2::Repository,
+    map: &OidMap,
+    mode: ReplaceRefs,
+    updates: &mut RefUpdates,
+) -> Result<(usize, usize), git2::Error> {
+    let odb = repo.odb()?;
+
+    // The map also has trees and cached results in it, which are skipped.
+    let is_commit = |oid: &git2::Oid| {
+        odb.read_header(*oid)
+            .is_ok_and(|(_, kind)| kind == git2::ObjectType::Commit)
+    };
+
+    let rewritten = |oid: &git2::Oid| match map.kept(oid) {
+        Some(new) if new != *oid && is_commit(oid) => Some(new),
+        _ => None,
+    };
+
+    let message = "git-subset: replace";
+    let mut written = 0;
+    let mut deleted = 0;
+    let mut existing = HashSet::new();
+
+    for reference in repo.references_glob("refs/replace/*")? {
+        let reference = reference?;
+
+        let (name, target) = match (reference.name(), reference.target()) {
+            (Some(name), Some(target)) => (name.to_string(), target),
+            _ => continue,
+        };
+
+        let replaced = match git2::Oid::from_str(&name["refs/replace/".len()..])
+        {
+            Ok(replaced) => replaced,
+            Err(_) => continue,
+        };
+
+        existing.insert(replaced);
+
+        // It is about to be overwritten anyway.
+        let overwritten = mode.overwrites() && rewritten(&replaced).is_some();
+
+        if mode.deletes() {
+            if !overwritten {
+                updates.remove(&name);
+                deleted += 1;
+            }
+
+            continue;
+        }
+
+        let new_target = map.kept(&target).unwrap_or(target);
+
+        match rewritten(&replaced) {
+            None if map.get(&replaced).is_some()
+                && map.kept(&replaced).is_none()
+                && is_commit(&replaced) =>
+            {
+                updates.remove(&name);
+                deleted += 1;
+            }
+            None if new_target != target => {
+                updates.set(repo, &name, new_target, true, message)?;
+                written += 1;
+            }
+            Some(new) => {
+                if !overwritten {
+                    updates.remove(&name);
+                }
+
+                updates.set(
+                    repo,
+                    &format!("refs/replace/{}", new),
+                    new_target,
+                    true,
+                    message,
+                )?;
+                written += 1;
+            }
+            None => {}
+        }
+    }
+
+    if mode.adds() {
+        for (old, _) in map.iter() {
+            if !mode.overwrites() && existing.contains(&old) {
+                continue;
+            }
+
+            if let Some(new) = rewritten(&old) {
+                updates.set(
+                    repo,
+                    &format!("refs/replace/{}", old),
+                    new,
+                    true,
+                    message,
+                )?;
+                written += 1;
+            }
+        }
+    }
+
+    Ok((written, deleted))
+}
+
+/// Returns what the rewritten counterpart of a reference should point to, or
+/// `None` if its commit didn't make it into the subset. Branches move to the
+/// nearest ancestor that was kept, but tags are dropped along with their
+/// commits, since they would otherwise mark a commit they weren't made for.
+fn rewritten_target(
+    repo: &git2::Repository,
+    map: &OidMap,
+    r: &Ref,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    let commit = if r.name.starts_with("refs/tags/") {
+        map.kept(&r.commit)
+    } else {
+        map.resolve(&r.commit).flatten()
+    };
+
+    let commit = match commit {
+        Some(commit) => commit,
+        None => return Ok(None),
+    };
+
+    if r.target == r.commit {
+        Ok(Some(commit))
+    } else {
+        let name = r.renamed.strip_prefix("refs/tags/");
+        rewrite_tag(repo, r.target, name, r.commit, commit).map(Some)
+    }
+}
+
+/// Creates a copy of an annotated tag that points to the rewritten commit
+/// instead. The tagger and message are kept as they are. If `name` is given,
+/// the tag is renamed. A tag pointing to another tag is copied recursively.
+/// Signatures are removed, since they would no longer be valid.
+fn rewrite_tag(
+    repo: &git2::Repository,
+    tag: git2::Oid,
+    name: Option<&str>,
+    old: git2::Oid,
+    new: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    if tag == old {
+        return Ok(new);
+    }
+
+    let odb = repo.odb()?;
+    let object = odb.read(tag)?;
+
+    let data = object.data();
+
+    // The header ends at the first blank line. The message follows it.
+    let (header, message) = match find(data, b"\n\n") {
+        Some(i) => (&data[..i + 1], &data[i + 2..]),
+        None => (data, &[][..]),
+    };
+
+    let mut rewritten = Vec::with_capacity(data.len());
+
+    for line in header.split_inclusive(|&b| b == b'\n') {
+        if let Some(target) = line.strip_prefix(b"object ") {
+            let target = std::str::from_utf8(target)
+                .ok()
+                .and_then(|t| git2::Oid::from_str(t.trim()).ok())
+                .ok_or_else(|| git2::Error::from_str("Invalid tag object"))?;
+
+            let target = rewrite_tag(repo, target, None, old, new)?;
+
+            rewritten
+                .extend_from_slice(format!("object {}\n", target).as_bytes());
+        } else if let (Some(name), true) = (name, line.starts_with(b"tag ")) {
+            rewritten.extend_from_slice(format!("tag {}\n", name).as_bytes());
+        } else {
+            rewritten.extend_from_slice(line);
+        }
+    }
+
+    rewritten.push(b'\n');
+
+    let signature = [
+        &b"-----BEGIN PGP SIGNATURE-----"[..],
+        &b"-----BEGIN SSH SIGNATURE-----"[..],
+    ]
+    .iter()
+    .filter_map(|marker| find(message, marker))
+    .min();
+
+    rewritten.extend_from_slice(&message[..signature.unwrap_or(message.len())]);
+
+    odb.write(git2::ObjectType::Tag, &rewritten)
+}
+
+/// Creates an annotated tag object pointing to a commit. Unlike
+/// `Repository::tag`, this doesn't create the reference too, so that it can be
+/// part of a transaction.
+pub fn create_tag(
+    repo: &git2::Repository,
+    name: &str,
+    commit: git2::Oid,
+    tagger: &git2::Signature<'_>,
+    message: &str,
+) -> Result<git2::Oid, git2::Error> {
+    let mut data =
+        format!("object {}\ntype commit\ntag {}\ntagger ", commit, name)
+            .into_bytes();
+
+    data.extend_from_slice(&format_signature(tagger));
+    data.extend_from_slice(b"\n\n");
+    data.extend_from_slice(message.as_bytes());
+
+    if !message.ends_with('\n') {
+        data.push(b'\n');
+    }
+
+    repo.odb()?.write(git2::ObjectType::Tag, &data)
+}
+
+/// Returns the position of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_rename() {
+        let renames = [
+            TagRename::prefix("v:subset-v").unwrap(),
+            TagRename::regex("^release-(.*)$:$1-subset").unwrap(),
+        ];
+
+        let rename =
+            |name: &str| renames.iter().find_map(|rule| rule.apply(name));
+
+        assert_eq!(rename("v1.2.3"), Some("subset-v1.2.3".to_string()));
+        assert_eq!(rename("release-1.0"), Some("1.0-subset".to_string()));
+        assert_eq!(rename("other"), None);
+
+        assert!(TagRename::prefix("v").is_err());
+        assert!(TagRename::regex("(:x").is_err());
+    }
+
+    #[test]
+    fn test_rewritten_name() {
+        assert_eq!(
+            rewritten_name("refs/subset/foo/", "refs/heads/main"),
+            "refs/subset/foo/heads/main"
+        );
+        assert_eq!(
+            rewritten_name("refs/subset/foo/", "refs/tags/v1.0"),
+            "refs/subset/foo/tags/v1.0"
+        );
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Talking to remotes.
+
+use std::cell::RefCell;
+
+/// Returns callbacks that authenticate like Git does: with the SSH agent for
+/// SSH remotes and with the configured credential helpers for everything else.
+/// Each method is only tried once so that bad credentials fail instead of
+/// being asked for forever.
+pub fn callbacks<'a>(config: git2::Config) -> git2::RemoteCallbacks<'a> {
+    let mut tried = git2::CredentialType::empty();
+
+    let mut callbacks = git2::RemoteCallbacks::new();
+
+    callbacks.credentials(move |url, username, allowed| {
+        // SSH needs a user name, which is almost always `git`. Credential
+        // helpers look up the user themselves when there is none in the URL.
+        let ssh_username = username.unwrap_or("git");
+
+        if allowed.contains(git2::CredentialType::USERNAME) {
+            return git2::Cred::username(ssh_username);
+        }
+
+        for kind in [
+            git2::CredentialType::SSH_KEY,
+            git2::CredentialType::USER_PASS_PLAINTEXT,
+            git2::CredentialType::DEFAULT,
+        ] {
+            if !allowed.contains(kind) || tried.contains(kind) {
+                continue;
+            }
+
+            tried |= kind;
+
+            return if kind == git2::CredentialType::SSH_KEY {
+                git2::Cred::ssh_key_from_agent(ssh_username)
+            } else if kind == git2::CredentialType::USER_PASS_PLAINTEXT {
+                git2::Cred::credential_helper(&config, url, username)
+            } else {
+                git2::Cred::default()
+            };
+        }
+
+        Err(git2::Error::from_str(&format!(
+            "Failed to authenticate with '{}'",
+            url
+        )))
+    });
+
+    callbacks
+}
+
+/// Finds a remote by name, or treats it as a URL if there is no such remote.
+pub fn find_remote<'r>(
+    repo: &'r git2::Repository,
+    remote: &str,
+) -> Result<git2::Remote<'r>, git2::Error> {
+    repo.find_remote(remote)
+        .or_else(|_| repo.remote_anonymous(remote))
+}
+
+/// Fetches a remote with its configured refspecs, updating its remote-tracking
+/// branches.
+pub fn fetch(repo: &git2::Repository, remote: &str) -> Result<(), git2::Error> {
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(callbacks(repo.config()?));
+
+    find_remote(repo, remote)?.fetch::<&str>(&[], Some(&mut options), None)
+}
+
+/// Pushes the given refspecs to a remote. Fails if the remote rejects any of
+/// the references.
+pub fn push(
+    repo: &git2::Repository,
+    remote: &str,
+    refspecs: &[String],
+) -> Result<(), git2::Error> {
+    let rejected = RefCell::new(Vec::new());
+
+    {
+        let mut callbacks = callbacks(repo.config()?);
+
+        callbacks.push_update_reference(|name, status| {
+            if let Some(status) = status {
+                rejected
+                    .borrow_mut()
+                    .push(format!("'{}' ({})", name, status));
+            }
+
+            Ok(())
+        });
+
+        let mut options = git2::PushOptions::new();
+        options.remote_callbacks(callbacks);
+
+        find_remote(repo, remote)?.push(refspecs, Some(&mut options))?;
+    }
+
+    let rejected = rejected.into_inner();
+
+    if rejected.is_empty() {
+        Ok(())
+    } else {
+        Err(git2::Error::from_str(&format!(
+            "The remote rejected {}",
+            rejected.join(", ")
+        )))
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Moving entries around in a tree after it has been filtered.
+
+use crate::error::Error;
+
+/// A rule for moving a file or directory somewhere else, such as
+/// `libfoo/src/:src/`. An empty path stands for the root of the repository.
+#[derive(Clone, Debug, Hash)]
+pub struct Rename {
+    from: Vec<String>,
+    to: Vec<String>,
+}
+
+impl Rename {
+    /// Parses a rule of the form `<old>:<new>`.
+    pub fn parse(rule: &str) -> Result<Rename, Error> {
+        let (from, to) = rule.split_once(':').ok_or_else(|| {
+            Error::invalid(
+                format!("path rename '{}'", rule),
+                "expected '<old>:<new>'",
+            )
+        })?;
+
+        Ok(Rename {
+            from: split_path(from, rule)?,
+            to: split_path(to, rule)?,
+        })
+    }
+}
+
+/// Splits up a path given as part of `rule` into its components.
+pub fn split_path(path: &str, rule: &str) -> Result<Vec<String>, Error> {
+    let components: Vec<String> = path
+        .split('/')
+        .filter(|c| !c.is_empty())
+        .map(String::from)
+        .collect();
+
+    if components.iter().any(|c| c == "." || c == "..") {
+        return Err(Error::invalid(
+            format!("path '{}'", rule),
+            "it must not contain '.' or '..'",
+        ));
+    }
+
+    Ok(components)
+}
+
+/// A tree entry that is being moved.
+pub type Entry = (git2::Oid, i32);
+
+fn is_tree(mode: i32) -> bool {
+    mode == i32::from(git2::FileMode::Tree)
+}
+
+fn empty_tree(repo: &git2::Repository) -> Result<git2::Oid, git2::Error> {
+    repo.treebuilder(None)?.write()
+}
+
+/// Returns the subtree at the given path, or `None` if there is no such
+/// directory.
+pub fn subtree(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    path: &[String],
+) -> Result<Option<git2::Oid>, git2::Error> {
+    match take(repo, tree, path)? {
+        Some(((oid, mode), _)) if is_tree(mode) => Ok(Some(oid)),
+        _ => Ok(None),
+    }
+}
+
+/// Applies the renames to a tree and returns the new tree.
+///
+/// All entries to move are taken out of the tree first, so each entry is moved
+/// by the first rule that matches it. Directories that are moved to where a
+/// directory already exists are merged together. If two different entries
+/// would end up at the same path, an error is returned instead of overwriting
+/// one of them.
+pub fn rename_tree(
+    repo: &git2::Repository,
+    renames: &[Rename],
+    mut tree: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    let mut moved = Vec::new();
+
+    for rename in renames {
+        if let Some((entry, rest)) = take(repo, tree, &rename.from)? {
+            moved.push((&rename.to, entry));
+            tree = rest;
+        }
+    }
+
+    for (to, entry) in moved {
+        tree = put(repo, tree, "", to, entry)?;
+    }
+
+    Ok(tree)
+}
+
+/// Returns where `rename_tree` moves the entry at the given path. It is moved
+/// by the first rule for it or any of its parents, since the entries for
+/// earlier rules are taken out of the tree first.
+pub fn rename_path(renames: &[Rename], path: &[String]) -> Vec<String> {
+    for rename in renames {
+        if let Some(rest) = path.strip_prefix(&rename.from[..]) {
+            return rename.to.iter().chain(rest).cloned().collect();
+        }
+    }
+
+    path.to_vec()
+}
+
+/// Removes the given number of leading components from every path in a tree.
+/// Files with too few components are left out. Directories that end up at the
+/// same path are merged, and it is an error if two different files do.
+pub fn strip_components(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    n: usize,
+) -> Result<git2::Oid, git2::Error> {
+    if n == 0 {
+        return Ok(tree);
+    }
+
+    let mut stripped = empty_tree(repo)?;
+
+    for entry in repo.find_tree(tree)?.iter() {
+        if is_tree(entry.filemode()) {
+            let subtree = strip_components(repo, entry.id(), n - 1)?;
+            stripped = merge(repo, stripped, "", subtree)?;
+        }
+    }
+
+    Ok(stripped)
+}
+
+/// Moves everything in a tree into the directory at the given path. An empty
+/// tree stays empty.
+pub fn prefix_tree(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    path: &[String],
+) -> Result<git2::Oid, git2::Error> {
+    if repo.find_tree(tree)?.is_empty() {
+        return Ok(tree);
+    }
+
+    let entry = (tree, i32::from(git2::FileMode::Tree));
+    put(repo, empty_tree(repo)?, "", path, entry)
+}
+
+/// Takes the entry at the given path out of a tree. Returns the entry and the
+/// tree without it, or `None` if there is no such entry. Directories that
+/// become empty are removed as well.
+pub fn take(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    path: &[String],
+) -> Result<Option<(Entry, git2::Oid)>, git2::Error> {
+    let (name, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            let entry = (tree, i32::from(git2::FileMode::Tree));
+            return Ok(Some((entry, empty_tree(repo)?)));
+        }
+    };
+
+    let tree = repo.find_tree(tree)?;
+
+    let entry = match tree.get_name(name) {
+        Some(entry) => (entry.id(), entry.filemode()),
+        None => return Ok(None),
+    };
+
+    let mut builder = repo.treebuilder(Some(&tree))?;
+
+    let taken = if rest.is_empty() {
+        builder.remove(name)?;
+        entry
+    } else if is_tree(entry.1) {
+        match take(repo, entry.0, rest)? {
+            Some((taken, subtree)) => {
+                if repo.find_tree(subtree)?.is_empty() {
+                    builder.remove(name)?;
+                } else {
+                    builder.insert(name, subtree, entry.1)?;
+                }
+
+                taken
+            }
+            None => return Ok(None),
+        }
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some((taken, builder.write()?)))
+}
+
+/// Puts an entry into a tree at the given path, creating any directories
+/// leading up to it. `base` is the path of the tree itself, which is used for
+/// error messages.
+///
+/// It is an error if something else is already at the path, unless both are
+/// directories (which are then merged) or both are the same.
+pub fn put(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    base: &str,
+    path: &[String],
+    entry: Entry,
+) -> Result<git2::Oid, git2::Error> {
+    let (name, rest) = match path.split_first() {
+        Some(split) => split,
+        None if is_tree(entry.1) => return merge(repo, tree, base, entry.0),
+        None => {
+            return Err(git2::Error::from_str(
+                "Cannot move a file to the root of the repository",
+            ))
+        }
+    };
+
+    let path_name = format!("{}{}", base, name);
+
+    let tree = repo.find_tree(tree)?;
+    let existing = tree
+        .get_name(name)
+        .map(|existing| (existing.id(), existing.filemode()));
+
+    let mut builder = repo.treebuilder(Some(&tree))?;
+
+    let (oid, mode) = match existing {
+        Some(existing) if existing == entry => existing,
+        Some(existing) if is_tree(existing.1) => {
+            let subtree = if !rest.is_empty() {
+                put(repo, existing.0, &(path_name + "/"), rest, entry)?
+            } else if is_tree(entry.1) {
+                merge(repo, existing.0, &(path_name + "/"), entry.0)?
+            } else {
+                return Err(collision(&path_name));
+            };
+
+            (subtree, existing.1)
+        }
+        Some(_) => return Err(collision(&path_name)),
+        None if rest.is_empty() => entry,
+        None => {
+            let subtree =
+                put(repo, empty_tree(repo)?, &(path_name + "/"), rest, entry)?;
+
+            (subtree, i32::from(git2::FileMode::Tree))
+        }
+    };
+
+    builder.insert(name, oid, mode)?;
+    builder.write()
+}
+
+fn collision(path: &str) -> git2::Error {
+    git2::Error::from_str(&format!(
+        "Path collision: more than one entry would end up at '{}'",
+        path
+    ))
+}
+
+/// Merges the entries of one tree into another.
+fn merge(
+    repo: &git2::Repository,
+    mut into: git2::Oid,
+    base: &str,
+    from: git2::Oid,
+) -> Result<git2::Oid, git2::Error> {
+    for entry in repo.find_tree(from)?.iter() {
+        let name = String::from_utf8_lossy(entry.name_bytes()).into_owned();
+        let entry = (entry.id(), entry.filemode());
+        into = put(repo, into, base, &[name], entry)?;
+    }
+
+    Ok(into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let rename = Rename::parse("libfoo/src/:src/").unwrap();
+        assert_eq!(rename.from, vec!["libfoo", "src"]);
+        assert_eq!(rename.to, vec!["src"]);
+
+        let rename = Rename::parse("lib/:").unwrap();
+        assert!(rename.to.is_empty());
+
+        assert!(Rename::parse("foo").is_err());
+        assert!(Rename::parse("foo:../bar").is_err());
+    }
+
+    #[test]
+    fn test_rename_path() {
+        let renames = [
+            Rename::parse("lib/a/:a/").unwrap(),
+            Rename::parse("lib/:vendor/").unwrap(),
+        ];
+        let path = |path: &str| split_path(path, path).unwrap();
+
+        assert_eq!(rename_path(&renames, &path("lib/a/x")), path("a/x"));
+        assert_eq!(rename_path(&renames, &path("lib/b")), path("vendor/b"));
+        assert_eq!(rename_path(&renames, &path("libs/c")), path("libs/c"));
+    }
+}
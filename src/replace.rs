@@ -0,0 +1,163 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Replacing text, such as in commit messages.
+
+use std::borrow::Cow;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+use regex::bytes::{NoExpand, Regex};
+
+use crate::error::Error;
+
+/// The text that matches are replaced with if a rule doesn't say otherwise.
+pub const REMOVED: &str = "***REMOVED***";
+
+/// A single replacement rule.
+#[derive(Clone, Debug)]
+struct Rule {
+    regex: Regex,
+    replacement: Vec<u8>,
+
+    /// Whether the replacement can refer to capture groups, such as `$1`.
+    expand: bool,
+}
+
+/// A list of text replacements in the same format as BFG's `--replace-text`.
+/// Each line is a rule of the form `<pattern>==><replacement>`. The pattern is
+/// literal text unless it starts with `regex:`, in which case the replacement
+/// can refer to capture groups. If `==><replacement>` is left out, matches are
+/// replaced with `***REMOVED***`. Blank lines are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct Replacements {
+    /// The rules as they were given. Used for hashing.
+    source: String,
+
+    rules: Vec<Rule>,
+}
+
+impl Hash for Replacements {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl Replacements {
+    /// Loads the rules from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Replacements, Error> {
+        Replacements::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses the rules, one per line.
+    pub fn parse(contents: &str) -> Result<Replacements, Error> {
+        let mut rules = Vec::new();
+
+        for line in contents.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (pattern, replacement) = match line.rsplit_once("==>") {
+                Some((pattern, replacement)) => (pattern, replacement),
+                None => (line, REMOVED),
+            };
+
+            let (regex, expand) = match pattern.strip_prefix("regex:") {
+                Some(regex) => (regex.to_string(), true),
+                None => (regex::escape(pattern), false),
+            };
+
+            let regex = Regex::new(&regex).map_err(|err| {
+                Error::invalid(format!("regex in rule '{}'", line), err)
+            })?;
+
+            rules.push(Rule {
+                regex,
+                replacement: replacement.as_bytes().to_vec(),
+                expand,
+            });
+        }
+
+        Ok(Replacements {
+            source: contents.to_string(),
+            rules,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies every rule in order to the text. The text doesn't need to be
+    /// valid UTF-8.
+    pub fn apply<'a>(&self, text: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut text = Cow::Borrowed(text);
+
+        for rule in &self.rules {
+            let replaced = if rule.expand {
+                rule.regex.replace_all(&text, rule.replacement.as_slice())
+            } else {
+                rule.regex
+                    .replace_all(&text, NoExpand(rule.replacement.as_slice()))
+            };
+
+            if let Cow::Owned(replaced) = replaced {
+                text = Cow::Owned(replaced);
+            }
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replacements() {
+        let replacements = Replacements::parse(
+            "PROJ-1234\n\
+             \n\
+             codename==>project\n\
+             regex:https://jira\\.internal/browse/([A-Z]+-[0-9]+)==>$1\n\
+             $1==>literal\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            replacements.apply(b"Fix PROJ-1234 in codename"),
+            &b"Fix ***REMOVED*** in project"[..]
+        );
+        assert_eq!(
+            replacements.apply(b"See https://jira.internal/browse/ABC-1"),
+            &b"See ABC-1"[..]
+        );
+        assert_eq!(replacements.apply(b"Costs $1"), &b"Costs literal"[..]);
+        assert!(matches!(
+            replacements.apply(b"Nothing to see"),
+            Cow::Borrowed(_)
+        ));
+
+        assert!(Replacements::parse("regex:(==>x").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A record of what was left out or redacted while rewriting.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use git2::Oid;
+
+/// Collects the objects that were left out or redacted while rewriting so that
+/// the user can find out what happened to them.
+///
+/// *Note*: Trees that were already rewritten by a previous run are not looked
+/// at again. Thus, the report only covers the work done by the current run.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Blobs that were too big, along with their size and the paths they were
+    /// found at.
+    big_blobs: BTreeMap<Oid, (usize, BTreeSet<String>)>,
+
+    /// Blobs that were left out by their ID, along with the paths they were
+    /// found at.
+    stripped_blobs: BTreeMap<Oid, BTreeSet<String>>,
+
+    /// Blobs that were left out because of what is in them, along with the
+    /// paths they were found at.
+    matched_content: BTreeMap<Oid, BTreeSet<String>>,
+
+    /// Blobs that secrets were redacted from, along with the kinds of secrets
+    /// and the paths they were found at.
+    secrets: BTreeMap<Oid, (BTreeSet<&'static str>, BTreeSet<String>)>,
+
+    /// Paths of submodules that were found in a tree when they aren't allowed.
+    /// These are taken out again once the tree has been filtered.
+    submodules: Vec<String>,
+}
+
+impl Report {
+    pub fn new() -> Report {
+        Report::default()
+    }
+
+    /// Records a blob that was left out because it was too big.
+    pub fn big_blob(&mut self, oid: Oid, size: usize, path: &str) {
+        self.big_blobs
+            .entry(oid)
+            .or_insert_with(|| (size, BTreeSet::new()))
+            .1
+            .insert(path.to_string());
+    }
+
+    /// Records a blob that was left out because its ID was given.
+    pub fn stripped_blob(&mut self, oid: Oid, path: &str) {
+        self.stripped_blobs
+            .entry(oid)
+            .or_default()
+            .insert(path.to_string());
+    }
+
+    /// Records a blob that was left out because its contents matched.
+    pub fn matched_content(&mut self, oid: Oid, path: &str) {
+        self.matched_content
+            .entry(oid)
+            .or_default()
+            .insert(path.to_string());
+    }
+
+    /// Records a secret that was redacted from a blob.
+    pub fn secret(&mut self, oid: Oid, kind: &'static str, path: &str) {
+        let (kinds, paths) = self.secrets.entry(oid).or_default();
+        kinds.insert(kind);
+        paths.insert(path.to_string());
+    }
+
+    /// Records another path of a blob that secrets were redacted from, if it
+    /// is known to have any.
+    pub fn secret_path(&mut self, oid: Oid, path: &str) {
+        if let Some((_, paths)) = self.secrets.get_mut(&oid) {
+            paths.insert(path.to_string());
+        }
+    }
+
+    /// Records a submodule that isn't allowed.
+    pub fn submodule(&mut self, path: &str) {
+        self.submodules.push(path.to_string());
+    }
+
+    /// Takes out the submodules that were found since this was last called.
+    pub fn take_submodules(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.submodules)
+    }
+
+    /// Prints a short summary of the report.
+    pub fn print_summary(&self) {
+        if !self.big_blobs.is_empty() {
+            let total: usize =
+                self.big_blobs.values().map(|(size, _)| size).sum();
+
+            println!(
+                "Stripped {} blob(s) totaling {} bytes for being too big.",
+                self.big_blobs.len(),
+                total
+            );
+        }
+
+        if !self.stripped_blobs.is_empty() {
+            println!("Stripped {} blob(s) by ID.", self.stripped_blobs.len());
+        }
+
+        if !self.matched_content.is_empty() {
+            println!(
+                "Dropped {} blob(s) for what is in them.",
+                self.matched_content.len()
+            );
+        }
+
+        if !self.secrets.is_empty() {
+            println!(
+                "Redacted secrets from {} blob(s). See the report for where.",
+                self.secrets.len()
+            );
+        }
+    }
+
+    /// Writes out the full report to a file.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        self.write(&mut f)?;
+        f.flush()
+    }
+
+    /// Writes out the full report. Each line describes one object that was
+    /// left out, preceded by the reason it was left out.
+    pub fn write<W: io::Write>(&self, f: &mut W) -> io::Result<()> {
+        for (oid, (size, paths)) in &self.big_blobs {
+            for path in paths {
+                writeln!(f, "too-big {} {} {}", oid, size, path)?;
+            }
+        }
+
+        for (oid, paths) in &self.stripped_blobs {
+            for path in paths {
+                writeln!(f, "stripped {} {}", oid, path)?;
+            }
+        }
+
+        for (oid, paths) in &self.matched_content {
+            for path in paths {
+                writeln!(f, "content {} {}", oid, path)?;
+            }
+        }
+
+        for (oid, (kinds, paths)) in &self.secrets {
+            let kinds: Vec<_> = kinds.iter().cloned().collect();
+
+            for path in paths {
+                writeln!(f, "secret {} {} {}", oid, kinds.join(","), path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
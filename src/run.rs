@@ -0,0 +1,1595 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Creating subsets as asked for by the arguments of `git-subset`.
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str;
+use std::time::Duration;
+
+use crate::args::{parse_date, Args};
+use crate::callback::{BlobCallback, CommitCallback};
+use crate::commit::{CommitRewrite, Cutoff};
+use crate::copy::{copy_reachable, reference_tips};
+use crate::export::Exporter;
+use crate::filter::{EntryType, Filter};
+use crate::follow::follow_renames;
+use crate::graft::Grafts;
+use crate::import::Importer;
+use crate::lua::LuaScript;
+use crate::manifest::read_manifest;
+use crate::map::{record_sources, MapBackend, OidMap};
+use crate::marks;
+use crate::patches::export_patches;
+use crate::plugin::Plugin;
+use crate::refs::{rename_tags, select_refs, Ref, TagRename};
+use crate::remote;
+use crate::replace::Replacements;
+use crate::report::Report;
+use crate::sign::Signer;
+use crate::state;
+use crate::subset::{
+    map_name, repo_subset, rewrite_subsets, walk_commits, Checkpoint,
+    Revisions, Subset, Target,
+};
+use crate::Error;
+
+/// Inserts the paths to keep given on the command line into the filter.
+fn insert_paths(filter: &mut Filter, args: &Args) -> Result<(), Error> {
+    for path in &args.paths {
+        match path.to_str() {
+            Some(spec) if spec.starts_with(':') => {
+                filter.insert_pathspec(spec)?;
+            }
+            _ => filter.insert(path),
+        }
+    }
+
+    for regex in &args.path_regexes {
+        filter.insert_regex(regex).map_err(|err| {
+            Error::invalid(format!("path regex '{}'", regex), err)
+        })?;
+    }
+
+    for glob in &args.path_globs {
+        filter.insert_glob(glob)?;
+    }
+
+    for attr in &args.path_attrs {
+        filter.insert_attr(attr)?;
+    }
+
+    Ok(())
+}
+
+/// Applies the options given on the command line that aren't about paths to
+/// keep. These apply to every filter.
+fn apply_options(filter: &mut Filter, args: &Args) -> Result<(), Error> {
+    for path in &args.exclude_files {
+        filter.read_exclude_file(path).map_err(|err| {
+            let action = format!("load exclude file '{}'", path.display());
+            Error::failed(action, err)
+        })?;
+    }
+
+    for rule in &args.excludes {
+        filter.insert_exclude(rule)?;
+    }
+
+    if args.strip_symlinks {
+        filter.strip(EntryType::Symlink);
+    }
+
+    if args.strip_gitlinks {
+        filter.strip(EntryType::Gitlink);
+    }
+
+    filter.set_submodules(args.submodules);
+
+    if args.prune_patterns {
+        filter.set_prune_patterns();
+    }
+
+    if let Some(path) = &args.subdirectory_filter {
+        filter.set_subdirectory(path)?;
+    }
+
+    if let Some(n) = args.strip_components {
+        filter.set_strip_components(n);
+    }
+
+    for rename in &args.path_renames {
+        filter.insert_rename(rename)?;
+    }
+
+    if let Some(path) = &args.to_subdirectory_filter {
+        filter.set_prefix(path)?;
+    }
+
+    if args.icase {
+        filter.set_icase();
+    }
+
+    if args.invert {
+        filter.set_invert();
+    }
+
+    if let Some(size) = args.strip_blobs_bigger_than {
+        filter.set_max_blob_size(size);
+    }
+
+    if let Some(path) = &args.strip_blobs_with_ids {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            Error::failed(format!("load blob IDs '{}'", path.display()), err)
+        })?;
+
+        for oid in parse_ids(&contents, "blob")? {
+            filter.strip_blob(oid);
+        }
+    }
+
+    for regex in &args.drop_files_matching_content {
+        filter.insert_drop_content(regex).map_err(|err| {
+            Error::invalid(format!("content regex '{}'", regex), err)
+        })?;
+    }
+
+    if args.strip_bom {
+        filter.set_strip_bom();
+    }
+
+    if let Some(eol) = args.normalize_eol {
+        filter.set_normalize_eol(eol);
+    }
+
+    if let Some(path) = &args.replace_text {
+        let replacements = Replacements::from_file(path).map_err(|err| {
+            let action = format!("load text replacements '{}'", path.display());
+            Error::failed(action, err)
+        })?;
+
+        filter.set_replace_text(replacements);
+    }
+
+    if args.redact_secrets {
+        filter.set_redact_secrets();
+    }
+
+    if args.anonymize {
+        filter.set_anonymize(args.anonymize_contents);
+    }
+
+    if let Some(command) = &args.blob_callback {
+        filter.set_blob_callback(BlobCallback::new(command));
+    }
+
+    for pattern in &args.lfs_convert {
+        filter.insert_lfs(pattern)?;
+    }
+
+    Ok(())
+}
+
+/// Parses a list of object IDs, one per line. Anything after the ID on a line
+/// is ignored, such that the output of `git rev-list --objects` can be used as
+/// is. Empty lines and lines starting with `#` are skipped.
+fn parse_ids(contents: &str, kind: &str) -> Result<Vec<git2::Oid>, Error> {
+    let mut oids = Vec::new();
+
+    for line in contents.lines() {
+        let id = match line.split_whitespace().next() {
+            Some(id) if !id.starts_with('#') => id,
+            _ => continue,
+        };
+
+        let oid = git2::Oid::from_str(id).ok().filter(|_| id.len() == 40);
+
+        match oid {
+            Some(oid) => oids.push(oid),
+            None => {
+                let what = format!("{} ID '{}'", kind, id);
+                return Err(Error::invalid(what, "expected 40 hex digits"));
+            }
+        }
+    }
+
+    Ok(oids)
+}
+
+/// Gathers the changes to make to each commit besides rewriting its tree.
+fn commit_rewrite(
+    repo: &git2::Repository,
+    args: &Args,
+    revisions: &Revisions,
+) -> Result<CommitRewrite, Error> {
+    let mut rewrite = CommitRewrite::new();
+
+    let mut mailmap = String::new();
+
+    if args.use_mailmap {
+        // As with `git log --use-mailmap` in a bare repository, the mailmap is
+        // read from the tip of the history.
+        let blob = repo
+            .find_commit(revisions.include[0])
+            .and_then(|commit| commit.tree())
+            .and_then(|tree| tree.get_path(Path::new(".mailmap")))
+            .and_then(|entry| entry.to_object(repo))
+            .and_then(|object| object.peel_to_blob());
+
+        if let Ok(blob) = blob {
+            mailmap.push_str(&String::from_utf8_lossy(blob.content()));
+            mailmap.push('\n');
+        }
+    }
+
+    if let Some(path) = &args.mailmap {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            Error::failed(format!("load mailmap '{}'", path.display()), err)
+        })?;
+
+        mailmap.push_str(&contents);
+    }
+
+    if args.use_mailmap || args.mailmap.is_some() {
+        rewrite
+            .set_mailmap(&mailmap)
+            .map_err(|err| Error::invalid("mailmap", err))?;
+    }
+
+    for (key, value) in &args.add_trailers {
+        rewrite.add_trailer(key, value);
+    }
+
+    if args.original_oid {
+        rewrite.set_original_oid_trailer(&args.original_oid_key);
+    }
+
+    if args.rewrite_hashes {
+        rewrite.set_rewrite_hashes();
+    }
+
+    if let Some((name, email)) = &args.author {
+        rewrite.set_author(name, email);
+    }
+
+    if let Some((name, email)) = &args.committer {
+        rewrite.set_committer(name, email);
+    }
+
+    rewrite.set_prune_empty(args.prune_empty);
+    rewrite.set_prune_degenerate(args.prune_degenerate);
+
+    if args.no_prune_merges {
+        rewrite.set_keep_merges();
+    }
+
+    if args.first_parent {
+        rewrite.set_first_parent();
+    }
+
+    if args.linearize {
+        rewrite.set_linearize();
+    }
+
+    if let Some(time) = args.since {
+        rewrite.set_since(time);
+    }
+
+    if let Some(time) = args.until {
+        rewrite.set_until(time);
+    }
+
+    if args.no_merges {
+        rewrite.set_no_merges();
+    }
+
+    if let Some(command) = &args.commit_callback {
+        rewrite.set_callback(CommitCallback::new(command));
+    }
+
+    if args.anonymize {
+        rewrite.set_anonymize();
+    }
+
+    if args.merges_only {
+        rewrite.set_merges_only();
+    }
+
+    for pattern in &args.drop_commits_by {
+        let regex = regex::bytes::Regex::new(pattern).map_err(|err| {
+            Error::invalid(format!("regex '{}'", pattern), err)
+        })?;
+
+        rewrite.add_drop_commits_by(regex);
+    }
+
+    for pattern in &args.drop_message_regexes {
+        let regex = regex::bytes::Regex::new(pattern).map_err(|err| {
+            Error::invalid(format!("regex '{}'", pattern), err)
+        })?;
+
+        rewrite.add_drop_message_regex(regex);
+    }
+
+    if let Some(spec) = &args.graft_onto {
+        let commit = repo
+            .revparse_single(spec)
+            .and_then(|o| o.peel_to_commit())
+            .map_err(|err| Error::invalid(format!("graft '{}'", spec), err))?;
+
+        rewrite.set_graft_onto(commit.id());
+    }
+
+    for spec in &args.keep_commits {
+        let commit = repo
+            .revparse_single(spec)
+            .and_then(|o| o.peel_to_commit())
+            .map_err(|err| Error::invalid(format!("commit '{}'", spec), err))?;
+
+        rewrite.add_keep_commit(commit.id());
+    }
+
+    if let Some(path) = &args.keep_commits_from {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            Error::failed(format!("load commit IDs '{}'", path.display()), err)
+        })?;
+
+        for oid in parse_ids(&contents, "commit")? {
+            rewrite.add_keep_commit(oid);
+        }
+    }
+
+    if let Some(spec) = &args.squash_before {
+        let cutoff = match parse_date(spec) {
+            Some(time) => Ok(Cutoff::Time(time)),
+            None => repo
+                .revparse_single(spec)
+                .and_then(|object| object.peel_to_commit())
+                .map(|commit| Cutoff::Commit(commit.id())),
+        };
+
+        let cutoff = cutoff
+            .map_err(|err| Error::invalid(format!("cutoff '{}'", spec), err))?;
+
+        rewrite.set_squash_before(cutoff);
+    }
+
+    if args.committer_date_is_author_date {
+        rewrite.set_committer_date_is_author_date();
+    }
+
+    if let Some(time) = args.reset_dates {
+        rewrite.set_reset_dates(time);
+    }
+
+    if let Some(key) = &args.sign {
+        rewrite.set_signer(Signer::from_config(repo, key.as_deref())?);
+    }
+
+    if let Some(path) = &args.replace_message {
+        let replacements = Replacements::from_file(path).map_err(|err| {
+            let action =
+                format!("load message replacements '{}'", path.display());
+            Error::failed(action, err)
+        })?;
+
+        rewrite.set_message_replacements(replacements);
+    }
+
+    Ok(rewrite)
+}
+
+/// Extends the filter with the former paths of renamed files and directories
+/// to keep.
+fn follow(
+    repo: &git2::Repository,
+    commits: &[git2::Oid],
+    filter: &mut Filter,
+    quiet: bool,
+) -> Result<(), Error> {
+    let found = follow_renames(repo, commits.iter().cloned(), filter)
+        .map_err(|err| Error::failed("follow renames", err))?;
+
+    if !quiet {
+        for (path, old) in found {
+            println!("Following '{}' back to '{}'", path, old);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns a message for an annotated tag describing how the subset was
+/// created. This is the command line along with the contents of the filter
+/// file, if there is one.
+fn describe_filter(args: &Args) -> String {
+    let command: Vec<String> = std::env::args()
+        .skip(1)
+        .map(|arg| {
+            if arg.is_empty()
+                || arg.contains(|c: char| {
+                    c.is_whitespace() || "'\"\\$*?[]{}()<>|&;#~!`".contains(c)
+                })
+            {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            } else {
+                arg
+            }
+        })
+        .collect();
+
+    let mut message =
+        format!("Created with:\n\n    git-subset {}\n", command.join(" "));
+
+    if let Some(path) = &args.filter_file {
+        if let Ok(contents) = fs::read_to_string(path) {
+            message
+                .push_str(&format!("\nFilter file '{}':\n\n", path.display()));
+
+            for line in contents.lines() {
+                message.push_str(&format!("    {}\n", line));
+            }
+        }
+    }
+
+    message
+}
+
+/// Points `HEAD` at a branch. If the repository has a working tree, the branch
+/// is checked out first. This fails instead of overwriting local changes.
+fn set_head(repo: &git2::Repository, branch: &str) -> Result<(), git2::Error> {
+    let name = format!("refs/heads/{}", branch);
+
+    if !repo.is_bare() {
+        let commit = repo.find_reference(&name)?.peel_to_commit()?;
+        repo.checkout_tree(
+            commit.as_object(),
+            Some(git2::build::CheckoutBuilder::new().safe()),
+        )?;
+    }
+
+    repo.set_head(&name)
+}
+
+/// Opens the repository to write the subset into, initializing a bare one if
+/// nothing is at the path yet. The source repository is added as an alternate
+/// so that its objects can be read without writing anything to it.
+fn open_target(
+    source: &git2::Repository,
+    path: &Path,
+) -> Result<git2::Repository, git2::Error> {
+    let target = if path.exists() {
+        git2::Repository::open(path)?
+    } else {
+        git2::Repository::init_bare(path)?
+    };
+
+    let objects = source.path().join("objects");
+    let objects = objects.to_str().ok_or_else(|| {
+        git2::Error::from_str("Path to objects is not valid UTF-8")
+    })?;
+
+    target.odb()?.add_disk_alternate(objects)?;
+
+    Ok(target)
+}
+
+/// Copies the objects reachable from the references in the target repository
+/// into its own object database, so that it no longer needs the source.
+fn copy_objects(target: &git2::Repository) -> Result<usize, git2::Error> {
+    // A fresh handle doesn't have the source repository as an alternate.
+    let own = git2::Repository::open(target.path())?;
+    let odb = own.odb()?;
+
+    copy_reachable(target, &odb, reference_tips(target)?)
+}
+
+/// Returns the remote and refspecs to push for `--push <remote>[:<refspec>]`,
+/// given the names of the references that were created.
+fn push_refspecs(
+    push: &str,
+    created: &[String],
+    force: bool,
+) -> Result<(String, Vec<String>), Error> {
+    let (remote, refspecs) = match push.split_once(':') {
+        Some((remote, refspec)) if refspec.contains(':') => {
+            (remote, vec![refspec.to_string()])
+        }
+        Some((remote, refspec)) => match created.first() {
+            Some(first) if !refspec.is_empty() => {
+                // Like Git, a short name is a branch or tag like the source.
+                let namespace = if refspec.starts_with("refs/") {
+                    ""
+                } else if first.starts_with("refs/tags/") {
+                    "refs/tags/"
+                } else {
+                    "refs/heads/"
+                };
+
+                (remote, vec![format!("{}:{}{}", first, namespace, refspec)])
+            }
+            _ => {
+                return Err(Error::invalid(
+                    format!("push '{}'", push),
+                    "expected `<remote>[:<refspec>]`",
+                ))
+            }
+        },
+        None => (
+            push,
+            created
+                .iter()
+                .map(|name| format!("{}:{}", name, name))
+                .collect(),
+        ),
+    };
+
+    let refspecs = refspecs
+        .into_iter()
+        .map(|refspec| {
+            if force && !refspec.starts_with('+') {
+                format!("+{}", refspec)
+            } else {
+                refspec
+            }
+        })
+        .collect();
+
+    Ok((remote.to_string(), refspecs))
+}
+
+/// Imports a stream for `git fast-import` from a file, or from standard input
+/// if the path is `-`, returning the references it updates.
+fn import_stream(
+    repo: &git2::Repository,
+    path: &Path,
+    import_marks: Option<&Path>,
+    export_marks: Option<&Path>,
+) -> Result<Vec<(String, git2::Oid)>, Error> {
+    let reader: Box<dyn BufRead> = if path == Path::new("-") {
+        Box::new(io::BufReader::new(io::stdin()))
+    } else {
+        Box::new(io::BufReader::new(fs::File::open(path)?))
+    };
+
+    let mut importer = Importer::new(repo, reader);
+
+    if let Some(path) = import_marks {
+        importer.import_marks(&read_marks(path)?);
+    }
+
+    importer.import()?;
+
+    if let Some(path) = export_marks {
+        write_marks(path, &importer.marks())?;
+    }
+
+    let refs = importer.refs();
+
+    if refs.is_empty() {
+        return Err(Error::Other(
+            "The stream doesn't update any references.".into(),
+        ));
+    }
+
+    Ok(refs)
+}
+
+/// Reads a marks file.
+fn read_marks(path: &Path) -> Result<Vec<(u64, git2::Oid)>, Error> {
+    let file = fs::File::open(path).map_err(|err| {
+        Error::failed(format!("open marks file '{}'", path.display()), err)
+    })?;
+
+    marks::read(io::BufReader::new(file))
+}
+
+/// Writes a marks file.
+fn write_marks(path: &Path, marks: &[(u64, git2::Oid)]) -> Result<(), Error> {
+    let file = fs::File::create(path).map_err(|err| {
+        Error::failed(format!("create marks file '{}'", path.display()), err)
+    })?;
+
+    Ok(marks::write(io::BufWriter::new(file), marks)?)
+}
+
+/// Replaces revisions that name imported references with what they point to,
+/// such as `main` for `refs/heads/main`. Without any revisions, all of the
+/// imported references are used.
+fn imported_revspecs(
+    revspecs: &[String],
+    imported: &[(String, git2::Oid)],
+) -> Vec<String> {
+    if revspecs.is_empty() {
+        return imported.iter().map(|(_, id)| id.to_string()).collect();
+    }
+
+    revspecs
+        .iter()
+        .map(|revspec| {
+            let (prefix, name) = match revspec.strip_prefix('^') {
+                Some(name) => ("^", name),
+                None => ("", revspec.as_str()),
+            };
+
+            let found = imported.iter().find(|(imported, _)| {
+                imported == name
+                    || imported.strip_prefix("refs/heads/") == Some(name)
+                    || imported.strip_prefix("refs/tags/") == Some(name)
+            });
+
+            match found {
+                Some((_, id)) => format!("{}{}", prefix, id),
+                None => revspec.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Returns the reference each subset would create and the commit it would
+/// point to, failing if any of them would be empty.
+fn exported_tips(
+    subsets: &[Subset],
+) -> Result<Vec<(String, git2::Oid)>, Error> {
+    let mut tips = Vec::new();
+    let mut empty = Vec::new();
+
+    for subset in subsets {
+        match (&subset.target, subset.tip) {
+            (Some(target), Some(tip)) => tips.push((target.ref_name(), tip)),
+            (Some(target), None) => empty.push(format!(
+                "{} '{}'",
+                target.kind().to_lowercase(),
+                target.name()
+            )),
+            _ => {}
+        }
+    }
+
+    if !empty.is_empty() {
+        return Err(Error::EmptySubset(empty));
+    }
+
+    Ok(tips)
+}
+
+/// Writes the history of each subset as a stream for `git fast-import`, to a
+/// file or to standard output if the path is `-`.
+fn export_stream(
+    repo: &git2::Repository,
+    tips: &[(String, git2::Oid)],
+    path: &Path,
+    import_marks: Option<&Path>,
+    export_marks: Option<&Path>,
+) -> Result<(), Error> {
+    let out: Box<dyn Write> = if path == Path::new("-") {
+        Box::new(io::stdout())
+    } else {
+        Box::new(fs::File::create(path)?)
+    };
+
+    let mut exporter = Exporter::new(repo, io::BufWriter::new(out))?;
+
+    if let Some(path) = import_marks {
+        exporter.import_marks(&read_marks(path)?);
+    }
+
+    for (name, tip) in tips {
+        exporter.export(name, *tip)?;
+    }
+
+    if let Some(path) = export_marks {
+        write_marks(path, &exporter.marks())?;
+    }
+
+    exporter.finish()?;
+
+    Ok(())
+}
+
+/// Creates a linked worktree at the given path with the branch checked out, as
+/// with `git worktree add <path> <branch>`. Like Git, the worktree is named
+/// after the last component of the path.
+fn add_worktree(
+    repo: &git2::Repository,
+    path: &Path,
+    branch: &str,
+) -> Result<(), git2::Error> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| git2::Error::from_str("Invalid worktree path"))?;
+
+    let reference = repo.find_reference(&format!("refs/heads/{}", branch))?;
+
+    let mut options = git2::WorktreeAddOptions::new();
+    options.reference(Some(&reference));
+
+    repo.worktree(name, path, Some(&options))?;
+
+    Ok(())
+}
+
+/// Splits an upstream of the form `<remote>/<branch>` into the remote and the
+/// reference on it. Unlike with `git2::Branch::set_upstream`, the remote
+/// branch doesn't need to exist yet.
+fn parse_upstream(
+    repo: &git2::Repository,
+    upstream: &str,
+) -> Result<(String, String), git2::Error> {
+    let remotes = repo.remotes()?;
+
+    // Remote names may contain slashes, so the longest one that fits wins.
+    let (remote, name) = remotes
+        .iter()
+        .flatten()
+        .filter_map(|remote| {
+            upstream
+                .strip_prefix(remote)
+                .and_then(|rest| rest.strip_prefix('/'))
+                .map(|name| (remote, name))
+        })
+        .max_by_key(|(remote, _)| remote.len())
+        .ok_or_else(|| {
+            git2::Error::from_str(&format!(
+                "'{}' isn't of the form <remote>/<branch> with an existing \
+                 remote",
+                upstream
+            ))
+        })?;
+
+    Ok((remote.to_string(), format!("refs/heads/{}", name)))
+}
+
+/// Sets the upstream of a branch by writing the `branch.<name>.remote` and
+/// `branch.<name>.merge` settings.
+fn write_upstream(
+    repo: &git2::Repository,
+    branch: &str,
+    remote: &str,
+    merge: &str,
+) -> Result<(), git2::Error> {
+    let mut config = repo.config()?;
+    config.set_str(&format!("branch.{}.remote", branch), remote)?;
+    config.set_str(&format!("branch.{}.merge", branch), merge)
+}
+
+/// Returns the upstream settings of the local branch that the revision names,
+/// if it has any.
+fn find_upstream(
+    repo: &git2::Repository,
+    spec: &str,
+) -> Result<Option<(String, String)>, git2::Error> {
+    let reference = repo.resolve_reference_from_short_name(spec)?;
+
+    let name =
+        match reference.name().and_then(|n| n.strip_prefix("refs/heads/")) {
+            Some(name) => name,
+            None => {
+                return Err(git2::Error::from_str(&format!(
+                    "'{}' isn't a local branch",
+                    spec
+                )))
+            }
+        };
+
+    let config = repo.config()?.snapshot()?;
+    let remote = config.get_string(&format!("branch.{}.remote", name));
+    let merge = config.get_string(&format!("branch.{}.merge", name));
+
+    Ok(match (remote, merge) {
+        (Ok(remote), Ok(merge)) => Some((remote, merge)),
+        _ => None,
+    })
+}
+
+/// Makes sure that it is safe to rewrite the references in place and asks the
+/// user for confirmation unless `yes` is `true`.
+fn confirm_in_place(
+    repo: &git2::Repository,
+    refs: &[Ref],
+    force: bool,
+    yes: bool,
+) -> Result<(), Error> {
+    if refs.is_empty() {
+        return Err(Error::Other(
+            "None of the revisions name a reference to rewrite in place."
+                .to_string(),
+        ));
+    }
+
+    // Rewriting the checked out branch would leave the index and working tree
+    // out of sync with it.
+    if !force && !repo.is_bare() {
+        if let Some(head) = repo
+            .head()
+            .ok()
+            .and_then(|head| head.name().map(String::from))
+        {
+            if refs.iter().any(|r| r.name == head) {
+                return Err(Error::Other(format!(
+                    "Refusing to rewrite the checked out branch '{}' in \
+                     place. Use `--force` to rewrite it anyway. The index and \
+                     working tree won't be updated.",
+                    head
+                )));
+            }
+        }
+    }
+
+    if yes {
+        return Ok(());
+    }
+
+    println!("The following references will be rewritten in place:");
+
+    for r in refs {
+        println!("    {}", r.name);
+    }
+
+    print!("Continue? [y/N] ");
+    io::stdout().flush().unwrap();
+
+    let mut answer = String::new();
+    let _ = io::stdin().read_line(&mut answer);
+
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(Error::Aborted);
+    }
+
+    Ok(())
+}
+
+/// Returns how a subset's map is saved while its commits are being rewritten,
+/// if it is.
+fn checkpoint(
+    repo: &git2::Repository,
+    args: &Args,
+    name: &str,
+) -> Result<Option<Checkpoint>, Error> {
+    let interval = Some(args.checkpoint_interval)
+        .filter(|&seconds| seconds > 0)
+        .map(Duration::from_secs);
+
+    // Streams and patches are made from objects that are only kept in memory,
+    // so a saved map would refer to objects that don't exist.
+    if (args.checkpoint_commits.is_none() && interval.is_none())
+        || args.export_stream.is_some()
+        || args.export_patches.is_some()
+    {
+        return Ok(None);
+    }
+
+    let repo = git2::Repository::open(repo.path())?;
+    let name = name.to_string();
+
+    // Even with a state branch, checkpoints go under `.git/subset/`, since
+    // committing the whole map every time would fill up the repository. The
+    // state branch is only updated once the run is over.
+    let save = move |map: &OidMap| map.write_repo(&repo, &name);
+
+    Ok(Some(Checkpoint::new(
+        args.checkpoint_commits,
+        interval,
+        Box::new(save),
+    )))
+}
+
+/// Returns the tips of the last run that are in every subset's map and that
+/// are still in the repository.
+fn last_tips(repo: &git2::Repository, subsets: &[Subset]) -> Vec<git2::Oid> {
+    let (first, rest) = match subsets.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+
+    first
+        .map
+        .tips()
+        .into_iter()
+        .filter(|tip| rest.iter().all(|subset| subset.map.tips().contains(tip)))
+        .filter(|&tip| repo.find_commit(tip).is_ok())
+        .collect()
+}
+
+/// Creates the subsets asked for by the command line arguments.
+pub fn run(args: &Args) -> Result<(), Error> {
+    let repo = git2::Repository::open(&args.repo)
+        .map_err(|err| Error::failed("open repository", err))?;
+
+    if args.export_stream.is_none()
+        && args.import_stream.is_none()
+        && (args.import_marks.is_some() || args.export_marks.is_some())
+    {
+        return Err(Error::invalid(
+            "marks file",
+            "it needs `--export-stream` or `--import-stream`",
+        ));
+    }
+
+    if let Some(branch) = &args.state_branch {
+        if !branch.starts_with("refs/")
+            || !git2::Reference::is_valid_name(branch)
+        {
+            return Err(Error::invalid(
+                format!("state branch '{}'", branch),
+                "expected a full reference name such as \
+                 `refs/subset/state/main`",
+            ));
+        }
+
+        if args.map_backend == MapBackend::Sqlite {
+            return Err(Error::invalid(
+                "state branch",
+                "it can't be used with `--map-backend sqlite`",
+            ));
+        }
+    }
+
+    // Anything new is only kept in memory instead of being written to the
+    // repository. The backend stays with the repository until it is closed.
+    if args.export_stream.is_some() || args.export_patches.is_some() {
+        repo.odb()?.add_new_mempack_backend(1000)?;
+    }
+
+    let mut filter = match &args.filter_file {
+        Some(path) => Filter::from_file(path).map_err(|err| {
+            Error::failed(format!("load filter file '{}'", path.display()), err)
+        })?,
+        None => Filter::new(),
+    };
+
+    insert_paths(&mut filter, args)?;
+    apply_options(&mut filter, args)?;
+
+    // The plugin is shared by the filter and the commit rewrite, since it is
+    // a single instance of the module.
+    let plugin = match &args.plugin {
+        Some(path) => Some(Rc::new(Plugin::load(path)?)),
+        None => None,
+    };
+
+    if let Some(plugin) = &plugin {
+        filter.set_plugin(plugin.clone());
+    }
+
+    let lua = match &args.lua {
+        Some(path) => Some(Rc::new(LuaScript::load(path)?)),
+        None => None,
+    };
+
+    if let Some(lua) = &lua {
+        filter.set_lua(lua.clone());
+    }
+
+    let manifest = match &args.manifest {
+        Some(path) => read_manifest(path).map_err(|err| {
+            Error::failed(format!("load manifest '{}'", path.display()), err)
+        })?,
+        None => Vec::new(),
+    };
+
+    let target = match (&args.branch, &args.tag) {
+        (Some(branch), _) => Some(Target::Branch(branch.clone())),
+        (None, Some(tag)) => Some(Target::Tag {
+            name: tag.clone(),
+            message: if args.annotate {
+                Some(describe_filter(args))
+            } else {
+                None
+            },
+        }),
+        (None, None) => args.reference.clone().map(Target::Ref),
+    };
+
+    if let Some(Target::Ref(name)) = &target {
+        if !name.starts_with("refs/") || !git2::Reference::is_valid_name(name) {
+            return Err(Error::Other(format!(
+                "'{}' is not a valid fully qualified reference name, such as \
+                 'refs/subset/foo'.",
+                name
+            )));
+        }
+    }
+
+    if args.in_place
+        && (target.is_some()
+            || args.branch_excluded.is_some()
+            || !manifest.is_empty())
+    {
+        return Err(Error::Other(
+            "`--in-place` can't be combined with `--branch`, `--tag`, \
+             `--ref`, `--branch-excluded`, or `--manifest`."
+                .to_string(),
+        ));
+    }
+
+    if target.is_none()
+        && args.branch_excluded.is_none()
+        && manifest.is_empty()
+        && !args.in_place
+    {
+        return Err(Error::Other(
+            "Please specify a branch to create with `--branch`, `--tag`, \
+             `--ref`, `--branch-excluded`, or `--manifest`."
+                .to_string(),
+        ));
+    }
+
+    let mut outputs = Vec::new();
+
+    if target.is_some() || args.branch_excluded.is_some() || args.in_place {
+        if filter.is_empty() {
+            return Err(Error::Other(
+                "Please specify paths to include with `--filter-file`, \
+                 `--path`, `--path-regex`, `--path-glob`, or `--path-attr`, \
+                 or paths to exclude with `--exclude-file` or `--exclude`."
+                    .to_string(),
+            ));
+        }
+
+        if args.in_place {
+            outputs.push((None, filter.clone()));
+        }
+
+        if let Some(target) = target {
+            outputs.push((Some(target), filter.clone()));
+        }
+
+        if let Some(branch) = &args.branch_excluded {
+            outputs.push((
+                Some(Target::Branch(branch.clone())),
+                filter.inverted(),
+            ));
+        }
+    }
+
+    for (branch, mut filter) in manifest {
+        apply_options(&mut filter, args)?;
+        outputs.push((Some(Target::Branch(branch)), filter));
+    }
+
+    // Each subset would replace the same commits.
+    if args.replace_refs.is_some() && outputs.len() > 1 {
+        return Err(Error::Other(
+            "`--replace-refs` can only be used when creating a single subset."
+                .to_string(),
+        ));
+    }
+
+    if let Some(remote) = &args.fetch {
+        if !args.quiet {
+            println!("Fetching '{}'...", remote);
+        }
+
+        remote::fetch(&repo, remote)
+            .map_err(|err| Error::failed(format!("fetch '{}'", remote), err))?;
+    }
+
+    let revspecs = if let Some(path) = &args.import_stream {
+        // Marks are for the stream being written, if there is one.
+        let (import_marks, export_marks) = if args.export_stream.is_none() {
+            (args.import_marks.as_deref(), args.export_marks.as_deref())
+        } else {
+            (None, None)
+        };
+
+        let imported = import_stream(&repo, path, import_marks, export_marks)
+            .map_err(|err| {
+            Error::failed(format!("import stream '{}'", path.display()), err)
+        })?;
+
+        imported_revspecs(&args.revspecs, &imported)
+    } else if args.revspecs.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        args.revspecs.clone()
+    };
+
+    let mut revisions = Revisions::parse(&repo, &revspecs, &args.not)?;
+
+    if let Some(path) = &args.worktree {
+        if path.exists() {
+            return Err(Error::invalid(
+                format!("worktree '{}'", path.display()),
+                "it already exists",
+            ));
+        }
+    }
+
+    // The upstream is worked out before rewriting anything, so that a bad one
+    // doesn't leave a branch behind. A branch without an upstream has nothing
+    // to copy.
+    let upstream = match (&args.track, args.copy_upstream) {
+        (Some(upstream), _) => Some(
+            parse_upstream(&repo, upstream)
+                .map_err(|err| Error::invalid("upstream", err))?,
+        ),
+        (None, true) => find_upstream(&repo, &revspecs[0])
+            .map_err(|err| Error::failed("copy upstream", err))?,
+        (None, false) => None,
+    };
+
+    if revisions.include.is_empty() {
+        return Err(Error::Other(
+            "Please specify a revision to rewrite.".to_string(),
+        ));
+    }
+
+    revisions.first_parent = args.first_parent;
+
+    if args.no_replace_objects {
+        revisions.grafts = Grafts::new();
+    }
+
+    let mut ref_patterns = args.refs.clone();
+
+    // Other revisions that name references are rewritten like `--refs`. When
+    // rewriting in place, this includes the first one.
+    let skip = if args.in_place { 0 } else { 1 };
+
+    for spec in revspecs.iter().skip(skip) {
+        if let Ok(reference) = repo
+            .resolve_reference_from_short_name(spec)
+            .and_then(|reference| reference.resolve())
+        {
+            if let Some(name) = reference.name() {
+                ref_patterns.push(name.to_string());
+            }
+        }
+    }
+
+    if args.all {
+        ref_patterns.push("refs/heads/*".to_string());
+        ref_patterns.push("refs/tags/*".to_string());
+    }
+
+    let mut refs = select_refs(&repo, &ref_patterns, &args.exclude_refs)
+        .map_err(|err| Error::failed("list references", err))?;
+
+    let mut tag_renames = Vec::new();
+
+    for rule in &args.tag_renames {
+        tag_renames.push(TagRename::prefix(rule)?);
+    }
+
+    for rule in &args.tag_rename_regexes {
+        tag_renames.push(TagRename::regex(rule)?);
+    }
+
+    rename_tags(&mut refs, &tag_renames);
+
+    // Don't rewrite what was created by a previous run.
+    refs.retain(|r| {
+        !r.name.starts_with(&args.ref_prefix)
+            && !r.name.starts_with("refs/original/")
+            && !outputs.iter().any(|(target, _)| {
+                target.as_ref().is_some_and(|t| r.name == t.ref_name())
+            })
+    });
+
+    if args.in_place {
+        confirm_in_place(&repo, &refs, args.force, args.yes)?;
+    }
+
+    if args.follow_renames {
+        let commits =
+            walk_commits(&repo, &revisions, &refs, git2::Sort::TOPOLOGICAL)
+                .map_err(|err| Error::failed("follow renames", err))?;
+
+        for (_, filter) in &mut outputs {
+            follow(&repo, &commits, filter, args.quiet)?;
+        }
+    }
+
+    let mut rewrite = commit_rewrite(&repo, args, &revisions)?;
+
+    if let Some(plugin) = plugin {
+        rewrite.set_plugin(plugin);
+    }
+
+    if let Some(lua) = lua {
+        rewrite.set_lua(lua);
+    }
+
+    // History before a cutoff commit doesn't need to be walked.
+    if let Some(Cutoff::Commit(cutoff)) = rewrite.squash_before() {
+        revisions.exclude.push(cutoff);
+    }
+
+    let notes = if args.notes {
+        Some(
+            repo.note_default_ref()
+                .map_err(|err| Error::failed("find notes", err))?,
+        )
+    } else {
+        None
+    };
+
+    // From here on, everything is written into the target repository.
+    let repo = match &args.target {
+        Some(path) => open_target(&repo, path).map_err(|err| {
+            Error::failed(format!("open target '{}'", path.display()), err)
+        })?,
+        None => repo,
+    };
+
+    // Replace references written by `--replace-refs` point at commits that
+    // were already rewritten. Honoring them would rewrite those again.
+    revisions
+        .grafts
+        .ignore_saved(&repo)
+        .map_err(|err| Error::failed("load object map", err))?;
+
+    if let Some(branch) = &args.state_branch {
+        for (_, map) in state::load_all(&repo, branch).map_err(|err| {
+            Error::failed(format!("load state from '{}'", branch), err)
+        })? {
+            revisions.grafts.ignore_rewritten(&map);
+        }
+    }
+
+    let mut subsets = Vec::new();
+
+    for (target, filter) in outputs {
+        let name = map_name(&filter, &rewrite, &revisions.grafts);
+
+        let state = match &args.state_branch {
+            // A checkpoint is only left behind by a run that didn't finish,
+            // so it is newer than what is on the state branch.
+            Some(_) if repo.path().join("subset").join(&name).is_file() => {
+                Some(
+                    OidMap::from_repo(&repo, &name)
+                        .map_err(|err| Error::failed("load object map", err))?,
+                )
+            }
+            Some(branch) => {
+                state::load(&repo, branch, &name).map_err(|err| {
+                    Error::failed(format!("load state from '{}'", branch), err)
+                })?
+            }
+            None => None,
+        };
+
+        let map = match state {
+            Some(map) => map,
+            None if args.nomap => OidMap::new(),
+            None => OidMap::open(&repo, &name, args.map_backend)
+                .map_err(|err| Error::failed("load object map", err))?,
+        };
+
+        subsets.push(Subset {
+            ref_prefix: target
+                .as_ref()
+                .map(|target| target.ref_prefix(&args.ref_prefix)),
+            notes: notes.clone(),
+            replace_refs: args.replace_refs,
+            reflog_message: args.reflog_message.clone(),
+            target,
+            filter,
+            rewrite: rewrite.clone(),
+            map,
+            tip: None,
+            checkpoint: checkpoint(&repo, args, &name)?,
+        });
+    }
+
+    // Every commit reachable from what is walked is rewritten, unless some of
+    // the history is left out.
+    let walked: Vec<_> = if revisions.exclude.is_empty() && !args.first_parent {
+        revisions
+            .include
+            .iter()
+            .copied()
+            .chain(refs.iter().map(|r| r.commit))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // The history of the last run's tips is already in the maps, so only
+    // what is new since needs to be walked.
+    revisions.exclude.extend(last_tips(&repo, &subsets));
+
+    let mut report = Report::new();
+
+    if !args.quiet {
+        println!("Getting list of commits...");
+    }
+
+    let mut progress = |commit, done, total| {
+        if done == total {
+            // Print the final status.
+            println!("\rRewriting {} ({}/{}) - 100%", commit, done, total);
+        } else if !args.quiet {
+            print!(
+                "\rRewriting {} ({}/{}) - {:3.0}%",
+                commit,
+                done + 1,
+                total,
+                ((done + 1) as f32) / (total as f32) * 100.0
+            );
+            io::stdout().flush().unwrap();
+        }
+    };
+
+    if args.export_stream.is_some() || args.export_patches.is_some() {
+        // The stream itself may be going to standard output.
+        let stdout = args.export_stream.as_deref() == Some(Path::new("-"));
+        let mut silent = |_, _, _| {};
+
+        match rewrite_subsets(
+            &repo,
+            &mut subsets,
+            &mut report,
+            &revisions,
+            &refs,
+            if stdout { &mut silent } else { &mut progress },
+        ) {
+            Ok(()) => {}
+            Err(Error::Interrupted) => {
+                return save_interrupted(
+                    &repo,
+                    args,
+                    &revisions.grafts,
+                    &subsets,
+                )
+            }
+            Err(err) => {
+                return Err(Error::failed("create repository subset", err))
+            }
+        }
+
+        let tips = exported_tips(&subsets)?;
+
+        if let Some(path) = &args.export_stream {
+            return export_stream(
+                &repo,
+                &tips,
+                path,
+                args.import_marks.as_deref(),
+                args.export_marks.as_deref(),
+            )
+            .map_err(|err| Error::failed("export stream", err));
+        }
+
+        if let Some(dir) = &args.export_patches {
+            let tips: Vec<_> = tips.iter().map(|&(_, tip)| tip).collect();
+
+            let paths = export_patches(&repo, &tips, dir).map_err(|err| {
+                Error::failed(
+                    format!("export patches to '{}'", dir.display()),
+                    err,
+                )
+            })?;
+
+            if !args.quiet {
+                for path in paths {
+                    println!("{}", path.display());
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    let messages = match repo_subset(
+        &repo,
+        &mut subsets,
+        &mut report,
+        &revisions,
+        &refs,
+        args.force,
+        &mut progress,
+    ) {
+        Ok(messages) => messages,
+        Err(Error::Interrupted) => {
+            return save_interrupted(&repo, args, &revisions.grafts, &subsets)
+        }
+        Err(err) => return Err(Error::failed("create repository subset", err)),
+    };
+
+    if !args.quiet {
+        for message in messages {
+            println!("{}", message);
+        }
+    }
+
+    if let Some(path) = &args.target {
+        let copied = copy_objects(&repo)
+            .map_err(|err| Error::failed("copy objects", err))?;
+
+        if !args.quiet {
+            println!("Copied {} objects into '{}'.", copied, path.display());
+        }
+    }
+
+    let mut empty = Vec::new();
+
+    for subset in &subsets {
+        let target = match &subset.target {
+            Some(target) => target,
+            None => continue,
+        };
+
+        if subset.tip.is_some() {
+            println!("{} '{}' created.", target.kind(), target.name());
+        } else {
+            // FIXME: Create an orphaned branch instead?
+            empty.push(format!(
+                "{} '{}'",
+                target.kind().to_lowercase(),
+                target.name()
+            ));
+        }
+    }
+
+    if !empty.is_empty() {
+        return Err(Error::EmptySubset(empty));
+    }
+
+    if let (true, Some(branch)) = (args.set_head, &args.branch) {
+        set_head(&repo, branch)
+            .map_err(|err| Error::failed("set HEAD", err))?;
+
+        if !args.quiet {
+            println!("HEAD now points to '{}'.", branch);
+        }
+    }
+
+    if let (Some(path), Some(branch)) = (&args.worktree, &args.branch) {
+        add_worktree(&repo, path, branch)
+            .map_err(|err| Error::failed("add worktree", err))?;
+
+        if !args.quiet {
+            println!("Checked out '{}' in '{}'.", branch, path.display());
+        }
+    }
+
+    if let (Some((remote, merge)), Some(branch)) = (upstream, &args.branch) {
+        write_upstream(&repo, branch, &remote, &merge)
+            .map_err(|err| Error::failed("set upstream", err))?;
+
+        if !args.quiet {
+            println!(
+                "Branch '{}' now tracks '{}' on '{}'.",
+                branch, merge, remote
+            );
+        }
+    }
+
+    if let Some(push) = &args.push {
+        let created: Vec<_> = subsets
+            .iter()
+            .filter_map(|subset| subset.target.as_ref())
+            .map(|target| target.ref_name())
+            .collect();
+
+        let (remote, refspecs) = push_refspecs(push, &created, args.force)?;
+
+        remote::push(&repo, &remote, &refspecs).map_err(|err| {
+            Error::failed(format!("push to '{}'", remote), err)
+        })?;
+
+        if !args.quiet {
+            for refspec in &refspecs {
+                println!("Pushed '{}' to '{}'.", refspec, remote);
+            }
+        }
+    }
+
+    report.print_summary();
+
+    if let Some(path) = &args.report {
+        report.write_file(path).map_err(|err| {
+            Error::failed(format!("write report '{}'", path.display()), err)
+        })?;
+    }
+
+    if !walked.is_empty() {
+        for subset in &mut subsets {
+            subset.map.set_tips(&walked);
+        }
+    }
+
+    // Save the mapping for super fast filtering next time.
+    save_maps(&repo, args, &revisions.grafts, &subsets)
+}
+
+/// Saves the object maps after being interrupted, since the commits rewritten
+/// so far don't need to be rewritten again.
+fn save_interrupted(
+    repo: &git2::Repository,
+    args: &Args,
+    grafts: &Grafts,
+    subsets: &[Subset],
+) -> Result<(), Error> {
+    save_maps(repo, args, grafts, subsets)?;
+
+    eprintln!(
+        "The object maps were saved. Run the same command again to carry on \
+         from where it stopped."
+    );
+
+    Err(Error::Interrupted)
+}
+
+/// Saves the object map of each subset, either under `.git/subset/` or on the
+/// state branch.
+fn save_maps(
+    repo: &git2::Repository,
+    args: &Args,
+    grafts: &Grafts,
+    subsets: &[Subset],
+) -> Result<(), Error> {
+    if let Some(branch) = &args.state_branch {
+        let maps: Vec<_> = subsets
+            .iter()
+            .map(|subset| {
+                (
+                    map_name(&subset.filter, &subset.rewrite, grafts),
+                    &subset.map,
+                )
+            })
+            .collect();
+
+        state::save(repo, branch, &maps).map_err(|err| {
+            Error::failed(format!("save state to '{}'", branch), err)
+        })?;
+
+        // Any checkpoints are on the state branch now.
+        for (name, _) in &maps {
+            match fs::remove_file(repo.path().join("subset").join(name)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(Error::failed("remove checkpoint", err));
+                }
+                _ => {}
+            }
+        }
+    } else {
+        // Where the filters came from, so that `git-subset map prune` can tell
+        // when they are gone.
+        let sources: Vec<&Path> = args
+            .filter_file
+            .iter()
+            .chain(&args.manifest)
+            .map(PathBuf::as_path)
+            .collect();
+
+        for subset in subsets {
+            let name = map_name(&subset.filter, &subset.rewrite, grafts);
+
+            subset
+                .map
+                .write_repo(repo, &name)
+                .map_err(|err| Error::failed("write object map", err))?;
+
+            if !sources.is_empty() {
+                record_sources(repo, &name, &sources)
+                    .map_err(|err| Error::failed("write object map", err))?;
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,468 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Serving subsets over Git's smart HTTP protocol.
+//!
+//! Only what `git clone` and `git fetch` need is supported: the reference
+//! advertisement and `git-upload-pack` with `multi_ack_detailed`, without any
+//! of the other capabilities, such as `side-band` or shallow clones.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+
+use crate::Error;
+
+/// The most a request body may take up, after being decoded. Requests only
+/// list the commits that are wanted and those that the client has.
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// How long to wait for a client to send or receive anything before giving
+/// up on it, so that an idle connection can't hold up the server.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Appends a pkt-line to the output.
+fn pkt_line(out: &mut Vec<u8>, line: &[u8]) {
+    out.extend(format!("{:04x}", line.len() + 4).as_bytes());
+    out.extend(line);
+}
+
+/// Appends a flush packet to the output.
+fn flush(out: &mut Vec<u8>) {
+    out.extend(b"0000");
+}
+
+/// Splits data into pkt-lines, leaving out flush packets and the trailing
+/// newline of each line.
+fn pkt_lines(mut data: &[u8]) -> io::Result<Vec<&[u8]>> {
+    let mut lines = Vec::new();
+
+    while !data.is_empty() {
+        let len = data
+            .get(..4)
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| usize::from_str_radix(len, 16).ok())
+            .filter(|&len| len == 0 || (4..=data.len()).contains(&len))
+            .ok_or_else(|| invalid("Invalid pkt-line"))?;
+
+        if len > 0 {
+            let line = &data[4..len];
+            lines.push(line.strip_suffix(b"\n").unwrap_or(line));
+            data = &data[len..];
+        } else {
+            data = &data[4..];
+        }
+    }
+
+    Ok(lines)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Returns the reference advertisement for a subset, with its branch as both
+/// `HEAD` and `refs/heads/<name>`.
+fn advertise(name: &str, tip: git2::Oid) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    pkt_line(&mut out, b"# service=git-upload-pack\n");
+    flush(&mut out);
+
+    let head = format!(
+        "{} HEAD\0multi_ack_detailed symref=HEAD:refs/heads/{} \
+         agent=git-subset/{}\n",
+        tip,
+        name,
+        env!("CARGO_PKG_VERSION")
+    );
+    pkt_line(&mut out, head.as_bytes());
+    pkt_line(
+        &mut out,
+        format!("{} refs/heads/{}\n", tip, name).as_bytes(),
+    );
+    flush(&mut out);
+
+    out
+}
+
+/// Parses the object ID after a keyword, such as `want <oid>`.
+fn parse_id(line: &[u8], keyword: &str) -> Option<git2::Oid> {
+    let rest = line.strip_prefix(keyword.as_bytes())?;
+    let hex = rest.get(..40)?;
+    git2::Oid::from_str(std::str::from_utf8(hex).ok()?).ok()
+}
+
+/// Answers a `git-upload-pack` request for the history of `tip`, like Git
+/// does with the stateless protocol. Every commit the client has in common
+/// with the subset is acknowledged, and the client is told to stop as soon as
+/// there is one. Once the client is done, the pack is sent, leaving out
+/// everything the client already has.
+fn upload_pack(
+    repo: &git2::Repository,
+    tip: git2::Oid,
+    request: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let lines = pkt_lines(request)
+        .map_err(|err| Error::failed("read upload-pack request", err))?;
+
+    let mut out = Vec::new();
+    let mut done = false;
+    let mut common = Vec::new();
+
+    for line in lines {
+        if let Some(want) = parse_id(line, "want ") {
+            // Anything else could be outside of the subset.
+            if want != tip {
+                return Err(Error::invalid(
+                    format!("want '{}'", want),
+                    "it is not the tip of the subset",
+                ));
+            }
+        } else if let Some(have) = parse_id(line, "have ") {
+            // Only admit to having commits that are in the subset, so that
+            // nothing can be learned about the rest of the repository. The
+            // client may also have commits of its own that aren't here at
+            // all.
+            if have == tip
+                || repo.graph_descendant_of(tip, have).unwrap_or(false)
+            {
+                pkt_line(&mut out, format!("ACK {} common\n", have).as_bytes());
+                common.push(have);
+            }
+        } else if line == b"done" {
+            done = true;
+            break;
+        }
+    }
+
+    let last = common.last();
+
+    if !done {
+        // Any common commit is an ancestor of the tip, which is enough to
+        // leave most of the history out of the pack.
+        if let Some(last) = last {
+            pkt_line(&mut out, format!("ACK {} ready\n", last).as_bytes());
+        }
+
+        pkt_line(&mut out, b"NAK\n");
+    } else {
+        match last {
+            Some(last) => {
+                pkt_line(&mut out, format!("ACK {}\n", last).as_bytes())
+            }
+            None => pkt_line(&mut out, b"NAK\n"),
+        }
+
+        let mut walk = repo.revwalk()?;
+        walk.push(tip)?;
+
+        for id in common {
+            walk.hide(id)?;
+        }
+
+        let mut builder = repo.packbuilder()?;
+        builder.insert_walk(&mut walk)?;
+
+        let mut buf = git2::Buf::new();
+        builder.write_buf(&mut buf)?;
+        out.extend(&*buf);
+    }
+
+    Ok(out)
+}
+
+/// An HTTP request.
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request, decoding the body if it is chunked or compressed.
+    fn read(stream: &TcpStream) -> io::Result<Request> {
+        // The headers are limited too, so that they can't go on forever.
+        let mut reader = BufReader::new(stream.take(2 * MAX_BODY as u64));
+
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let (path, query) = (path.to_string(), query.to_string());
+
+        let mut headers = HashMap::new();
+
+        loop {
+            let mut line = String::new();
+
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(
+                    name.trim().to_ascii_lowercase(),
+                    value.trim().to_string(),
+                );
+            }
+        }
+
+        let header = |name: &str| headers.get(name).map(String::as_str);
+
+        let mut body = Vec::new();
+
+        if header("transfer-encoding") == Some("chunked") {
+            loop {
+                let mut size = String::new();
+                reader.read_line(&mut size)?;
+
+                let size = size.split(';').next().unwrap_or_default().trim();
+                let size = usize::from_str_radix(size, 16)
+                    .ok()
+                    .filter(|size| body.len() + size <= MAX_BODY)
+                    .ok_or_else(|| invalid("Invalid chunk size"))?;
+
+                let mut chunk = vec![0; size + 2];
+                reader.read_exact(&mut chunk)?;
+
+                if size == 0 {
+                    break;
+                }
+
+                body.extend(&chunk[..size]);
+            }
+        } else if let Some(len) = header("content-length") {
+            let len = len
+                .parse()
+                .ok()
+                .filter(|&len| len <= MAX_BODY)
+                .ok_or_else(|| invalid("Invalid content length"))?;
+            body.resize(len, 0);
+            reader.read_exact(&mut body)?;
+        }
+
+        if header("content-encoding") == Some("gzip") {
+            let mut decoded = Vec::new();
+            GzDecoder::new(&body[..])
+                .take(MAX_BODY as u64 + 1)
+                .read_to_end(&mut decoded)?;
+
+            if decoded.len() > MAX_BODY {
+                return Err(invalid("Request body is too large"));
+            }
+
+            body = decoded;
+        }
+
+        Ok(Request {
+            method,
+            path,
+            query,
+            body,
+        })
+    }
+}
+
+/// An HTTP response.
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: &'static str, message: String) -> Response {
+        Response {
+            status,
+            content_type: "text/plain",
+            body: format!("{}\n", message).into_bytes(),
+        }
+    }
+
+    /// Writes the response, closing the connection afterwards.
+    fn write(&self, mut stream: &TcpStream) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// Serves subsets over Git's smart HTTP protocol, so that they can be cloned
+/// and fetched with Git. A subset named `foo` is served at `/foo`.
+///
+/// The function given to the server is asked for the tip of a subset each
+/// time its references are listed, which is a chance to bring it up to date.
+/// It returns `None` if there is no such subset. Only the tips that were
+/// listed can be fetched, so nothing outside of the subsets is ever sent.
+pub struct Server<'r, F> {
+    repo: &'r git2::Repository,
+    tip: F,
+    tips: HashMap<String, git2::Oid>,
+}
+
+impl<'r, F> Server<'r, F>
+where
+    F: FnMut(&str) -> Result<Option<git2::Oid>, Error>,
+{
+    pub fn new(repo: &'r git2::Repository, tip: F) -> Server<'r, F> {
+        Server {
+            repo,
+            tip,
+            tips: HashMap::new(),
+        }
+    }
+
+    /// Handles connections one at a time until listening fails. Failures
+    /// with a single connection are printed and otherwise ignored.
+    pub fn serve(&mut self, listener: &TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+
+            let result = stream
+                .set_read_timeout(Some(TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(TIMEOUT)))
+                .and_then(|()| Request::read(&stream))
+                .and_then(|request| self.handle(&request).write(&stream));
+
+            if let Err(err) = result {
+                println!("Error: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the tip of a subset, bringing it up to date if asked to.
+    fn tip(
+        &mut self,
+        name: &str,
+        update: bool,
+    ) -> Result<Option<git2::Oid>, Error> {
+        if let (false, Some(&tip)) = (update, self.tips.get(name)) {
+            return Ok(Some(tip));
+        }
+
+        let tip = (self.tip)(name)?;
+
+        if let Some(tip) = tip {
+            self.tips.insert(name.to_string(), tip);
+        }
+
+        Ok(tip)
+    }
+
+    fn handle(&mut self, request: &Request) -> Response {
+        let path = request.path.trim_start_matches('/');
+
+        let (name, service) = match path.rsplit_once('/') {
+            Some((name, "git-upload-pack")) if request.method == "POST" => {
+                (name, None)
+            }
+            Some((name, "refs")) if name.ends_with("/info") => {
+                (&name[..name.len() - "/info".len()], Some(&request.query))
+            }
+            _ => return Response::text("404 Not Found", "Not found".into()),
+        };
+
+        if let Some(query) = service {
+            if query != "service=git-upload-pack" {
+                return Response::text(
+                    "403 Forbidden",
+                    "Only fetching with the smart HTTP protocol is supported"
+                        .into(),
+                );
+            }
+        }
+
+        let name = name.strip_suffix(".git").unwrap_or(name);
+
+        let result = self.tip(name, service.is_some()).and_then(|tip| {
+            let tip = match tip {
+                Some(tip) => tip,
+                None => return Ok(None),
+            };
+
+            Ok(Some(match service {
+                Some(_) => Response::ok(
+                    "application/x-git-upload-pack-advertisement",
+                    advertise(name, tip),
+                ),
+                None => Response::ok(
+                    "application/x-git-upload-pack-result",
+                    upload_pack(self.repo, tip, &request.body)?,
+                ),
+            }))
+        });
+
+        match result {
+            Ok(Some(response)) => response,
+            Ok(None) => Response::text(
+                "404 Not Found",
+                format!("No subset named '{}'", name),
+            ),
+            Err(err) => {
+                println!("Error: {}", err);
+                Response::text("500 Internal Server Error", err.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkt_lines() {
+        let mut out = Vec::new();
+        pkt_line(&mut out, b"want 1234\n");
+        flush(&mut out);
+        pkt_line(&mut out, b"done\n");
+
+        assert_eq!(out, b"000ewant 1234\n00000009done\n");
+        assert_eq!(pkt_lines(&out).unwrap(), [&b"want 1234"[..], &b"done"[..]]);
+        assert!(pkt_lines(b"00").is_err());
+        assert!(pkt_lines(b"0003").is_err());
+        assert!(pkt_lines(b"0010short").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Signing rewritten commits with GPG or SSH keys, like `git commit -S`.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The kind of signature to create, as in the `gpg.format` config.
+#[derive(Clone, Copy, Debug, Hash, PartialEq)]
+enum Format {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+/// Signs commits with an external program, as configured for git.
+#[derive(Clone, Debug, Hash)]
+pub struct Signer {
+    format: Format,
+    program: String,
+    key: String,
+}
+
+impl Signer {
+    /// Creates a signer from the repository's config. The key defaults to
+    /// `user.signingKey`, or to the committer's identity for GPG.
+    pub fn from_config(
+        repo: &git2::Repository,
+        key: Option<&str>,
+    ) -> Result<Signer, git2::Error> {
+        let config = repo.config()?;
+        let get = |name: &str| config.get_string(name).ok();
+
+        let format = match get("gpg.format").as_deref() {
+            None | Some("openpgp") => Format::OpenPgp,
+            Some("x509") => Format::X509,
+            Some("ssh") => Format::Ssh,
+            Some(format) => {
+                return Err(git2::Error::from_str(&format!(
+                    "Unsupported gpg.format '{}'",
+                    format
+                )));
+            }
+        };
+
+        let program = match format {
+            Format::OpenPgp => get("gpg.openpgp.program")
+                .or_else(|| get("gpg.program"))
+                .unwrap_or_else(|| "gpg".to_string()),
+            Format::X509 => {
+                get("gpg.x509.program").unwrap_or_else(|| "gpgsm".to_string())
+            }
+            Format::Ssh => get("gpg.ssh.program")
+                .unwrap_or_else(|| "ssh-keygen".to_string()),
+        };
+
+        let key = match key.map(String::from).or_else(|| get("user.signingkey"))
+        {
+            Some(key) => key,
+            None if format == Format::Ssh => {
+                return Err(git2::Error::from_str(
+                    "Signing with SSH requires a key. Set user.signingKey or \
+                     use --sign=<key>",
+                ));
+            }
+            None => {
+                let signature = repo.signature()?;
+                format!(
+                    "{} <{}>",
+                    signature.name().unwrap_or(""),
+                    signature.email().unwrap_or("")
+                )
+            }
+        };
+
+        if format == Format::Ssh && key.starts_with("key::") {
+            return Err(git2::Error::from_str(
+                "Literal SSH keys are not supported. Use the path to the key \
+                 instead",
+            ));
+        }
+
+        Ok(Signer {
+            format,
+            program,
+            key,
+        })
+    }
+
+    /// Returns an armored signature of the data.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>, git2::Error> {
+        let mut command = Command::new(&self.program);
+
+        match self.format {
+            Format::OpenPgp | Format::X509 => {
+                command.args(["--status-fd=2", "-bsau", &self.key]);
+            }
+            Format::Ssh => {
+                command.args(["-Y", "sign", "-n", "git", "-f", &self.key]);
+            }
+        }
+
+        let error = |err: std::io::Error| {
+            git2::Error::from_str(&format!(
+                "Failed to run '{}': {}",
+                self.program, err
+            ))
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(error)?;
+
+        // The signature can only be written once all of the data has been
+        // read, so this can't deadlock. If the program exits early, the
+        // reason is on stderr.
+        let written = child.stdin.take().unwrap().write_all(data);
+
+        let output = child.wait_with_output().map_err(error)?;
+
+        if written.is_err()
+            || !output.status.success()
+            || output.stdout.is_empty()
+        {
+            return Err(git2::Error::from_str(&format!(
+                "Failed to sign commit with '{}': {}",
+                self.program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(output.stdout)
+    }
+}
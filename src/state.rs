@@ -0,0 +1,125 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Keeping object maps in a branch of the repository itself, as with `git
+//! filter-branch --state-branch`, so that they survive cloning the repository
+//! again and can be fetched and pushed like anything else.
+//!
+//! The tree of each commit on the branch has one file for each filter, named
+//! like the map file under `.git/subset/` and with the same contents.
+
+use crate::map::OidMap;
+use crate::Error;
+
+/// Loads the map with the given name from the state branch. Returns `None` if
+/// there is no such branch or it doesn't have the map.
+pub fn load(
+    repo: &git2::Repository,
+    branch: &str,
+    name: &str,
+) -> Result<Option<OidMap>, Error> {
+    let commit = match repo.find_reference(branch) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => return Ok(None),
+    };
+
+    let tree = commit.tree()?;
+
+    let entry = match tree.get_name(name) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    let blob = repo.find_blob(entry.id())?;
+
+    Ok(Some(OidMap::from_bytes(repo, name, blob.content())?))
+}
+
+/// Loads every map on the state branch along with its name.
+pub fn load_all(
+    repo: &git2::Repository,
+    branch: &str,
+) -> Result<Vec<(String, OidMap)>, Error> {
+    let commit = match repo.find_reference(branch) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut maps = Vec::new();
+
+    for entry in commit.tree()?.iter() {
+        if let Some(name) = entry.name() {
+            let blob = repo.find_blob(entry.id())?;
+            let map = OidMap::from_bytes(repo, name, blob.content())?;
+            maps.push((name.to_string(), map));
+        }
+    }
+
+    Ok(maps)
+}
+
+/// Commits maps to the state branch, keeping the maps for other filters that
+/// are already on it. Nothing is committed if none of them changed.
+pub fn save(
+    repo: &git2::Repository,
+    branch: &str,
+    maps: &[(String, &OidMap)],
+) -> Result<(), Error> {
+    let parent = match repo.find_reference(branch) {
+        Ok(reference) => Some(reference.peel_to_commit()?),
+        Err(_) => None,
+    };
+
+    let parent_tree = match &parent {
+        Some(parent) => Some(parent.tree()?),
+        None => None,
+    };
+
+    let mut builder = repo.treebuilder(parent_tree.as_ref())?;
+
+    for (name, map) in maps {
+        let blob = repo.blob(&map.encode(repo, name)?)?;
+        builder.insert(name, blob, 0o100644)?;
+    }
+
+    let tree = builder.write()?;
+
+    if parent_tree.map(|tree| tree.id()) == Some(tree) {
+        return Ok(());
+    }
+
+    let signature = match repo.signature() {
+        Ok(signature) => signature,
+        Err(_) => git2::Signature::now("git-subset", "git-subset@localhost")?,
+    };
+
+    let parents: Vec<_> = parent.iter().collect();
+
+    repo.commit(
+        Some(branch),
+        &signature,
+        &signature,
+        "Update git-subset state\n",
+        &repo.find_tree(tree)?,
+        &parents,
+    )?;
+
+    Ok(())
+}
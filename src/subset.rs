@@ -0,0 +1,1115 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting the commits of one or more subsets of a repository in a single
+//! pass.
+
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::anonymize::Seed;
+use crate::commit::{CommitRewrite, Cutoff, Prune};
+use crate::error::Error;
+use crate::filter::{filter_tree, Filter};
+use crate::graft::Grafts;
+use crate::interrupt;
+use crate::map::OidMap;
+use crate::refs::{
+    create_tag, rewrite_notes, rewrite_refs, rewrite_refs_in_place,
+    rewrite_replace_refs, rewritten_name, Ref, RefUpdates, ReplaceRefs,
+};
+use crate::report::Report;
+
+/// Returns `true` if a commit with the given tree and parent trees is
+/// considered empty. A commit is empty if its tree is the same as all of its
+/// parent's trees, or if it has no parents and the tree itself is empty.
+fn is_empty_commit(
+    tree: git2::Oid,
+    parent_trees: &[git2::Oid],
+    empty_tree: &git2::Oid,
+) -> bool {
+    if parent_trees.is_empty() {
+        tree == *empty_tree
+    } else {
+        parent_trees.iter().all(|&parent| parent == tree)
+    }
+}
+
+/// A commit to rewrite, as it looks after replacements and grafts.
+struct Original<'r> {
+    /// The ID of the commit. If it was replaced, this isn't the ID of
+    /// `commit`.
+    id: git2::Oid,
+
+    commit: git2::Commit<'r>,
+
+    /// Its parents after grafts.
+    parents: Vec<git2::Oid>,
+}
+
+impl<'r> Original<'r> {
+    fn find(
+        repo: &'r git2::Repository,
+        grafts: &Grafts,
+        id: git2::Oid,
+    ) -> Result<Original<'r>, git2::Error> {
+        let commit = grafts.find_commit(repo, id)?;
+        let parents = grafts.parent_ids(id, &commit);
+
+        Ok(Original {
+            id,
+            commit,
+            parents,
+        })
+    }
+}
+
+/// Returns the rewritten counterparts of the given original parents. Parents
+/// that were pruned resolve to their nearest ancestors that were kept (or
+/// nothing, if there are none), and parents that end up the same are only
+/// included once. Parents that weren't rewritten at all, such as those
+/// excluded by a range, are replaced by `graft` if there is one.
+fn rewritten_parents(
+    map: &OidMap,
+    originals: &[git2::Oid],
+    graft: Option<git2::Oid>,
+) -> Vec<git2::Oid> {
+    let mut parents = Vec::with_capacity(originals.len());
+
+    for original in originals {
+        let parent = match map.resolve(original) {
+            Some(parent) => parent,
+            None => graft,
+        };
+
+        if let Some(parent) = parent {
+            if !parents.contains(&parent) {
+                parents.push(parent);
+            }
+        }
+    }
+
+    parents
+}
+
+/// Returns the given parents without those that are redundant, meaning that
+/// they are duplicates or ancestors of other parents. The order of the
+/// remaining parents is kept.
+fn independent_parents(
+    repo: &git2::Repository,
+    parents: &[git2::Oid],
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let mut independent = Vec::with_capacity(parents.len());
+
+    for (i, &parent) in parents.iter().enumerate() {
+        if parents[..i].contains(&parent) {
+            continue;
+        }
+
+        let mut redundant = false;
+
+        for &other in parents {
+            if other != parent && repo.graph_descendant_of(other, parent)? {
+                redundant = true;
+                break;
+            }
+        }
+
+        if !redundant {
+            independent.push(parent);
+        }
+    }
+
+    Ok(independent)
+}
+
+/// The commits to rewrite, as given on the command line.
+pub struct Revisions {
+    /// Commits to rewrite along with their ancestors. The branch or tag is
+    /// created on the first one.
+    pub include: Vec<git2::Oid>,
+
+    /// Commits whose ancestors are not rewritten.
+    pub exclude: Vec<git2::Oid>,
+
+    /// Only walks the first parent of merge commits.
+    pub first_parent: bool,
+
+    /// Replacements and grafts to honor while walking history.
+    pub grafts: Grafts,
+}
+
+impl Revisions {
+    /// Parses revisions in the same way as `git rev-list`. Each revision is
+    /// either a single commit, a range such as `a..b`, or a commit to exclude
+    /// such as `^a`. The commits in `not` are excluded too.
+    pub fn parse(
+        repo: &git2::Repository,
+        revspecs: &[String],
+        not: &[String],
+    ) -> Result<Revisions, git2::Error> {
+        let mut revisions = Revisions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            first_parent: false,
+            grafts: Grafts::load(repo)?,
+        };
+
+        for spec in not {
+            revisions.exclude.push(repo.revparse_single(spec)?.id());
+        }
+
+        for spec in revspecs {
+            if let Some(spec) = spec.strip_prefix('^') {
+                revisions.exclude.push(repo.revparse_single(spec)?.id());
+                continue;
+            }
+
+            let revspec = repo.revparse(spec)?;
+
+            match (revspec.from(), revspec.to()) {
+                (Some(from), Some(to)) => {
+                    revisions.exclude.push(from.id());
+                    revisions.include.push(to.id());
+                }
+                (Some(from), None) => {
+                    revisions.include.push(from.id());
+                }
+                _ => {
+                    return Err(git2::Error::from_str(&format!(
+                        "Invalid revision '{}'",
+                        spec
+                    )));
+                }
+            }
+        }
+
+        // Tags must be peeled to the commits they point to.
+        for oid in revisions
+            .include
+            .iter_mut()
+            .chain(revisions.exclude.iter_mut())
+        {
+            *oid = repo.find_object(*oid, None)?.peel_to_commit()?.id();
+        }
+
+        Ok(revisions)
+    }
+}
+
+/// Returns the commits to rewrite, along with the commits reachable from the
+/// given references.
+pub fn walk_commits(
+    repo: &git2::Repository,
+    revisions: &Revisions,
+    refs: &[Ref],
+    sorting: git2::Sort,
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    if !revisions.grafts.is_empty() {
+        let mut commits = walk_grafted(repo, revisions, refs)?;
+
+        if !sorting.contains(git2::Sort::REVERSE) {
+            commits.reverse();
+        }
+
+        return Ok(commits);
+    }
+
+    let mut commits = repo.revwalk()?;
+    let _ = commits.set_sorting(sorting);
+
+    if revisions.first_parent {
+        commits.simplify_first_parent()?;
+    }
+
+    for oid in &revisions.include {
+        commits.push(*oid)?;
+    }
+
+    for r in refs {
+        commits.push(r.commit)?;
+    }
+
+    for oid in &revisions.exclude {
+        commits.hide(*oid)?;
+    }
+
+    commits.collect()
+}
+
+/// Walks the commits like `walk_commits` does, but with the parents that
+/// commits have after replacements and grafts. libgit2 doesn't know about
+/// those, so this is done by hand. The commits are returned parents first.
+fn walk_grafted(
+    repo: &git2::Repository,
+    revisions: &Revisions,
+    refs: &[Ref],
+) -> Result<Vec<git2::Oid>, git2::Error> {
+    let parents = |id, first_parent| {
+        Original::find(repo, &revisions.grafts, id).map(|original| {
+            let mut parents = original.parents;
+
+            if first_parent {
+                parents.truncate(1);
+            }
+
+            parents
+        })
+    };
+
+    // Everything reachable from an excluded commit is hidden.
+    let mut hidden = HashSet::new();
+    let mut stack = revisions.exclude.clone();
+
+    while let Some(id) = stack.pop() {
+        if hidden.insert(id) {
+            stack.extend(parents(id, false)?);
+        }
+    }
+
+    // A depth-first search that adds each commit after all of its parents.
+    let mut commits = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack: Vec<_> = revisions
+        .include
+        .iter()
+        .chain(refs.iter().map(|r| &r.commit))
+        .rev()
+        .map(|&id| (id, false))
+        .collect();
+
+    while let Some((id, visited)) = stack.pop() {
+        if visited {
+            commits.push(id);
+            continue;
+        }
+
+        if hidden.contains(&id) || !seen.insert(id) {
+            continue;
+        }
+
+        stack.push((id, true));
+
+        for parent in parents(id, revisions.first_parent)?.into_iter().rev() {
+            if !seen.contains(&parent) && !hidden.contains(&parent) {
+                stack.push((parent, false));
+            }
+        }
+    }
+
+    Ok(commits)
+}
+
+/// What to create on the rewritten counterpart of the first revision.
+pub enum Target {
+    Branch(String),
+
+    /// A tag, which is annotated if there is a message.
+    Tag {
+        name: String,
+        message: Option<String>,
+    },
+
+    /// Any other reference, given by its full name.
+    Ref(String),
+}
+
+impl Target {
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Branch(name) => name,
+            Target::Tag { name, .. } => name,
+            Target::Ref(name) => name,
+        }
+    }
+
+    /// The full name of the reference, such as `refs/heads/foo`.
+    pub fn ref_name(&self) -> String {
+        match self {
+            Target::Branch(name) => format!("refs/heads/{}", name),
+            Target::Tag { name, .. } => format!("refs/tags/{}", name),
+            Target::Ref(name) => name.clone(),
+        }
+    }
+
+    /// The prefix for the rewritten counterparts of other references. For a
+    /// fully qualified reference, the leading `refs/` is left out, since the
+    /// reference itself can't also be a directory of references.
+    pub fn ref_prefix(&self, prefix: &str) -> String {
+        match self {
+            Target::Ref(name) => rewritten_name(prefix, name) + "/",
+            _ => format!("{}{}/", prefix, self.name()),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Target::Branch(_) => "Branch",
+            Target::Tag { .. } => "Tag",
+            Target::Ref(_) => "Reference",
+        }
+    }
+
+    /// Queues up creating the branch, tag, or reference on the given commit
+    /// with the given reflog message.
+    fn create(
+        &self,
+        repo: &git2::Repository,
+        commit: git2::Oid,
+        force: bool,
+        message: &str,
+        updates: &mut RefUpdates,
+    ) -> Result<(), git2::Error> {
+        let name = self.ref_name();
+
+        // Like `git branch --force`, refuse to move the checked out branch out
+        // from under the index and working tree.
+        if let Target::Branch(_) = self {
+            let head = repo.head().ok();
+
+            if !repo.is_bare()
+                && head.and_then(|h| h.name().map(String::from))
+                    == Some(name.clone())
+            {
+                return Err(git2::Error::from_str(&format!(
+                    "Cannot overwrite the checked out branch '{}'",
+                    self.name()
+                )));
+            }
+        }
+
+        let target = match self {
+            Target::Tag {
+                name,
+                message: Some(message),
+            } => create_tag(repo, name, commit, &repo.signature()?, message)?,
+            _ => commit,
+        };
+
+        updates.set(repo, &name, target, force, message)
+    }
+}
+
+type SaveFn = dyn FnMut(&OidMap) -> io::Result<()>;
+
+/// Saves a subset's map every so often while its commits are being rewritten,
+/// so that a run that is cut short can carry on from about where it stopped.
+pub struct Checkpoint {
+    /// Saves the map after this many commits, if given.
+    commits: Option<usize>,
+
+    /// Saves the map after this much time, if given.
+    interval: Option<Duration>,
+
+    /// Writes the map to wherever it is kept.
+    save: Box<SaveFn>,
+
+    /// The number of commits rewritten since the map was last saved.
+    pending: usize,
+
+    /// When the map was last saved.
+    last: Instant,
+}
+
+impl Checkpoint {
+    pub fn new(
+        commits: Option<usize>,
+        interval: Option<Duration>,
+        save: Box<SaveFn>,
+    ) -> Checkpoint {
+        Checkpoint {
+            commits,
+            interval,
+            save,
+            pending: 0,
+            last: Instant::now(),
+        }
+    }
+
+    /// Saves the map if it is time to, once a commit is done with. The map
+    /// only has whole commits in it at this point.
+    fn commit_done(&mut self, map: &OidMap) -> Result<(), Error> {
+        self.pending += 1;
+
+        let due = self.commits.is_some_and(|commits| self.pending >= commits)
+            || self
+                .interval
+                .is_some_and(|interval| self.last.elapsed() >= interval);
+
+        if due {
+            (self.save)(map)
+                .map_err(|err| Error::failed("save checkpoint", err))?;
+            self.pending = 0;
+            self.last = Instant::now();
+        }
+
+        Ok(())
+    }
+}
+
+/// A subset of the repository to create. Several subsets can be created with a
+/// single pass over the commits.
+pub struct Subset {
+    /// The branch or tag to create on the rewritten commits, if any.
+    pub target: Option<Target>,
+
+    pub filter: Filter,
+
+    pub rewrite: CommitRewrite,
+
+    /// Mapping of old objects to new objects. Since it depends on the filter,
+    /// each subset has its own.
+    pub map: OidMap,
+
+    /// The new tip commit, if there are any non-empty commits.
+    pub tip: Option<git2::Oid>,
+
+    /// Prefix for the names of rewritten references. If there is none, the
+    /// references are rewritten in place.
+    pub ref_prefix: Option<String>,
+
+    /// The notes reference whose notes are copied onto the rewritten commits,
+    /// such as `refs/notes/commits`.
+    pub notes: Option<String>,
+
+    /// What to do with replace references after the commits are rewritten,
+    /// if anything.
+    pub replace_refs: Option<ReplaceRefs>,
+
+    /// The reflog message for creating the branch, tag, or reference. By
+    /// default, it says what was filtered and with which filter.
+    pub reflog_message: Option<String>,
+
+    /// When to save the map before all of the commits are rewritten, if at
+    /// all.
+    pub checkpoint: Option<Checkpoint>,
+}
+
+/// Rewrites the trees of the commits for each subset. Progress is reported
+/// with the commit being rewritten, the number of commits rewritten so far, and
+/// the total number of commits.
+fn process_commits(
+    repo: &git2::Repository,
+    revisions: &Revisions,
+    refs: &[Ref],
+    subsets: &mut [Subset],
+    report: &mut Report,
+    progress: &mut dyn FnMut(git2::Oid, usize, usize),
+) -> Result<(), Error> {
+    let commits = walk_commits(
+        repo,
+        revisions,
+        refs,
+        git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE,
+    )?;
+
+    // Scrambled names are salted with a seed kept in the map, so that they
+    // come out the same in the next run that uses it.
+    for subset in subsets.iter_mut() {
+        if subset.filter.anonymize() || subset.rewrite.anonymize() {
+            let seed = Seed::from(subset.map.seed()?);
+            subset.filter.set_anonymize_seed(seed);
+            subset.rewrite.set_anonymize_seed(seed);
+        }
+    }
+
+    // An empty tree OID
+    let empty_tree =
+        git2::Oid::from_str("4b825dc642cb6eb9a060e54bf8d69288fbee4904")?;
+
+    // Tags and notes point to specific commits, which are kept even if they
+    // are empty. Otherwise, tags would end up on commits that they weren't
+    // made for. Every tag counts, not just those being rewritten, so that
+    // tags can still be mapped onto the new history afterwards.
+    let mut protected = HashSet::new();
+
+    for tag in repo.references_glob("refs/tags/*")? {
+        if let Ok(commit) = tag?.peel_to_commit() {
+            protected.insert(commit.id());
+        }
+    }
+
+    if let Ok(notes) = repo
+        .note_default_ref()
+        .and_then(|notes| repo.notes(Some(&notes)))
+    {
+        for note in notes {
+            protected.insert(note?.1);
+        }
+    }
+
+    // We want to (at most) report the status for each percentage point.
+    // Reporting the status too often can slow down the program.
+    let status_step = cmp::max(commits.len() / 100, 1);
+
+    for (i, id) in commits.iter().enumerate() {
+        // Stopping here leaves only whole commits in the maps.
+        if interrupt::requested() {
+            return Err(Error::Interrupted);
+        }
+
+        let id = *id;
+        let previous = i.checked_sub(1).map(|i| commits[i]);
+
+        if i % status_step == 0 {
+            progress(id, i, commits.len());
+        }
+
+        let original = Original::find(repo, &revisions.grafts, id)?;
+
+        for subset in subsets.iter_mut() {
+            // Commits older than the cutoff are left to be squashed when
+            // rewriting their children.
+            if let Some(Cutoff::Time(time)) = subset.rewrite.squash_before() {
+                if original.commit.committer().when().seconds() < time {
+                    continue;
+                }
+            }
+
+            let map = &mut subset.map;
+            let time = original.commit.committer().when().seconds();
+
+            // Commits before the window are left out like those outside of a
+            // range.
+            if subset.rewrite.since().is_some_and(|since| time < since) {
+                continue;
+            }
+
+            // Commits after the window resolve to the newest commit in the
+            // window on their first-parent chain.
+            if subset.rewrite.until().is_some_and(|until| time > until) {
+                let parent = original
+                    .parents
+                    .first()
+                    .and_then(|parent| map.resolve(parent));
+                map.insert(id, parent.flatten());
+                continue;
+            }
+
+            // Commits rewritten by an earlier run have already been pruned or
+            // kept.
+            if map.get(&id).is_some() {
+                continue;
+            }
+
+            // Get the original parents to rewrite onto. Only the first parent
+            // has been walked if that is all that is wanted.
+            let originals: Vec<_> = if subset.rewrite.linearize() {
+                match previous {
+                    Some(previous) => vec![previous],
+                    None => original.parents.iter().take(1).cloned().collect(),
+                }
+            } else if subset.rewrite.first_parent() {
+                original.parents.iter().take(1).cloned().collect()
+            } else {
+                original.parents.clone()
+            };
+
+            // Parents older than the squash cutoff haven't been rewritten.
+            // They are replaced by a root commit with their rewritten tree,
+            // which is shared by all of their children.
+            for &parent in &originals {
+                squash_commit(
+                    repo,
+                    &revisions.grafts,
+                    map,
+                    parent,
+                    &subset.filter,
+                    &subset.rewrite,
+                    report,
+                )?;
+            }
+
+            let commit = match process_commit(
+                repo,
+                map,
+                &original,
+                &originals,
+                &subset.filter,
+                &subset.rewrite,
+                report,
+            )? {
+                Some(commit) => repo.find_commit(commit)?,
+                None => continue,
+            };
+
+            // Store mapping between the old commit and new commit. This is
+            // used to remap parent commits.
+            map.insert(id, Some(commit.id()));
+
+            // Discard this commit if its tree is the same as its parent's
+            // tree. There may be multiple levels of indirection if several
+            // commits in a row are discarded.
+            let prune = match subset.rewrite.prune_empty() {
+                // Merges are only discarded with `--no-merges`, since that
+                // cuts off all but one of the merged branches.
+                _ if subset.rewrite.no_merges()
+                    && original.parents.len() > 1 =>
+                {
+                    true
+                }
+                // Commits that are dropped are discarded the same way, which
+                // folds their changes into the commits that follow. Dropped
+                // merges are mapped to their first parent, like with
+                // `--no-merges`.
+                _ if subset.rewrite.drops(&original.commit) => true,
+                // Degenerate merges have already been collapsed into regular
+                // commits, so any merge left here is kept.
+                _ if commit.parent_count() > 1 => false,
+                _ if subset.rewrite.keep_merges()
+                    && original.parents.len() > 1 =>
+                {
+                    false
+                }
+                _ if protected.contains(&id) || subset.rewrite.keeps(id) => {
+                    false
+                }
+                Prune::Always => is_empty_commit(
+                    commit.tree_id(),
+                    &trees(&commit),
+                    &empty_tree,
+                ),
+                Prune::Auto => {
+                    let original_trees = original
+                        .parents
+                        .iter()
+                        .map(|&parent| {
+                            revisions
+                                .grafts
+                                .find_commit(repo, parent)
+                                .map(|parent| parent.tree_id())
+                        })
+                        .collect::<Result<Vec<_>, git2::Error>>()?;
+
+                    is_empty_commit(
+                        commit.tree_id(),
+                        &trees(&commit),
+                        &empty_tree,
+                    ) && !is_empty_commit(
+                        original.commit.tree_id(),
+                        &original_trees,
+                        &empty_tree,
+                    )
+                }
+                Prune::Never => false,
+            };
+
+            if prune {
+                // Map it to its parent so that subsequent commits resolve to
+                // the parent of this commit instead.
+                //
+                // *Note*: Even though this commit has already been created, it
+                // is left behind as an unreferenced dangling commit to be
+                // garbage collected.
+                if let Some(parent) = commit.parents().next() {
+                    map.insert(commit.id(), Some(parent.id()));
+                } else {
+                    // If this is a root commit, we need to make the next
+                    // commit become the root commit. Thus, we mark this commit
+                    // as discarded.
+                    map.insert(commit.id(), None);
+                }
+            }
+        }
+
+        for subset in subsets.iter_mut() {
+            if let Some(checkpoint) = &mut subset.checkpoint {
+                checkpoint.commit_done(&subset.map)?;
+            }
+        }
+    }
+
+    // Revisions and references that are entirely older than the squash cutoff
+    // point to the squashed history.
+    if subsets.iter().any(|s| s.rewrite.squash_before().is_some()) {
+        for subset in subsets.iter_mut() {
+            let tips = revisions
+                .include
+                .iter()
+                .chain(refs.iter().map(|r| &r.commit));
+
+            for &tip in tips {
+                squash_commit(
+                    repo,
+                    &revisions.grafts,
+                    &mut subset.map,
+                    tip,
+                    &subset.filter,
+                    &subset.rewrite,
+                    report,
+                )?;
+            }
+        }
+    }
+
+    if let Some(&commit) = commits.last() {
+        // Report the final status.
+        progress(commit, commits.len(), commits.len());
+    }
+
+    Ok(())
+}
+
+/// Returns the trees of the parents of a commit.
+fn trees(commit: &git2::Commit<'_>) -> Vec<git2::Oid> {
+    commit.parents().map(|parent| parent.tree_id()).collect()
+}
+
+/// Rewrites a single commit onto the rewritten counterparts of the given
+/// original parents. Returns the new OID for the commit, or `None` if the
+/// commit callback skipped it.
+fn process_commit(
+    repo: &git2::Repository,
+    map: &mut OidMap,
+    original: &Original<'_>,
+    originals: &[git2::Oid],
+    filter: &Filter,
+    rewrite: &CommitRewrite,
+    report: &mut Report,
+) -> Result<Option<git2::Oid>, git2::Error> {
+    let id = original.id;
+
+    // Don't bother if it has already been done.
+    if let Some(Some(newid)) = map.resolve(&id) {
+        return Ok(Some(newid));
+    }
+
+    let commit = &original.commit;
+
+    let tree = commit.tree()?;
+
+    let newtree =
+        filter_tree(repo, map, filter, report, &tree).map_err(|err| {
+            git2::Error::from_str(&format!(
+                "Failed to rewrite commit {}: {}",
+                id,
+                err.message()
+            ))
+        })?;
+
+    // A merge whose parents all end up the same becomes a regular commit.
+    let parents = rewritten_parents(map, originals, rewrite.graft_onto());
+
+    // Collapse the merge if it is degenerate, leaving only the parents that
+    // actually diverged. If only one is left, it becomes a regular commit,
+    // which may then be pruned for being empty.
+    let parents = match rewrite.prune_degenerate() {
+        _ if parents.len() < 2 || rewrite.keep_merges() => parents,
+        Prune::Always => independent_parents(repo, &parents)?,
+        Prune::Auto => {
+            // Parents that were already redundant are kept, so that only what
+            // became degenerate due to filtering is collapsed. For octopus
+            // merges, this may be some of the parents but not others.
+            let independent = independent_parents(repo, originals)?;
+            let redundant: Vec<_> = originals
+                .iter()
+                .filter(|p| !independent.contains(p))
+                .cloned()
+                .collect();
+            let kept = rewritten_parents(map, &redundant, rewrite.graft_onto());
+            let reduced = independent_parents(repo, &parents)?;
+
+            parents
+                .into_iter()
+                .filter(|p| reduced.contains(p) || kept.contains(p))
+                .collect()
+        }
+        Prune::Never => parents,
+    };
+
+    // If nothing changed, the commit is its own counterpart. This keeps
+    // untouched stretches of history (and their signatures) as they are
+    // without writing anything. A replaced commit becomes its replacement.
+    if newtree == commit.tree_id()
+        && parents.iter().cloned().eq(commit.parent_ids())
+        && !rewrite.changes_metadata()
+    {
+        return Ok(Some(commit.id()));
+    }
+
+    let mut metadata =
+        rewrite.metadata(commit, |hash| rewritten_hash(repo, map, hash))?;
+
+    let mut keep = true;
+
+    if let Some(lua) = rewrite.lua() {
+        keep = lua.on_commit(commit, newtree, &parents, &mut metadata)?;
+    }
+
+    if let (true, Some(callback)) = (keep, rewrite.callback()) {
+        let changed = changed_paths(repo, newtree, parents.first().cloned())?;
+
+        keep = callback.call(
+            commit,
+            newtree,
+            &parents,
+            &changed,
+            &mut metadata,
+        )?;
+    }
+
+    if !keep {
+        // Skipped commits are left out like dropped commits, but nothing is
+        // written for them.
+        map.insert(id, parents.first().cloned());
+        return Ok(None);
+    }
+
+    rewrite
+        .write(repo, commit, newtree, &parents, &metadata)
+        .map(Some)
+}
+
+/// Returns the paths of the files that differ between the tree and the tree of
+/// the given commit, or all files in the tree if there is no commit.
+fn changed_paths(
+    repo: &git2::Repository,
+    tree: git2::Oid,
+    parent: Option<git2::Oid>,
+) -> Result<Vec<String>, git2::Error> {
+    let old = match parent {
+        Some(parent) => Some(repo.find_commit(parent)?.tree()?),
+        None => None,
+    };
+
+    let diff = repo.diff_tree_to_tree(
+        old.as_ref(),
+        Some(&repo.find_tree(tree)?),
+        None,
+    )?;
+
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| {
+            delta.new_file().path().or_else(|| delta.old_file().path())
+        })
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect())
+}
+
+/// Maps a commit older than the squash cutoff to a root commit with its
+/// rewritten tree, unless it has been rewritten already.
+fn squash_commit(
+    repo: &git2::Repository,
+    grafts: &Grafts,
+    map: &mut OidMap,
+    id: git2::Oid,
+    filter: &Filter,
+    rewrite: &CommitRewrite,
+    report: &mut Report,
+) -> Result<(), git2::Error> {
+    if map.get(&id).is_some() {
+        return Ok(());
+    }
+
+    let commit = grafts.find_commit(repo, id)?;
+
+    if rewrite.squashes(repo, &commit)? {
+        let tree = filter_tree(repo, map, filter, report, &commit.tree()?)?;
+        let root = rewrite.write_squashed(repo, &commit, tree)?;
+        map.insert(id, Some(root));
+    }
+
+    Ok(())
+}
+
+/// Returns what to replace a commit hash found in a commit message with, or
+/// `None` if it isn't the hash of a commit. Since commits are rewritten parents
+/// first, the commits that are usually referred to (e.g., by reverts) have
+/// already been rewritten. Hashes of commits that aren't in the subset are
+/// annotated as such.
+fn rewritten_hash(
+    repo: &git2::Repository,
+    map: &OidMap,
+    hash: &str,
+) -> Option<String> {
+    let odb = repo.odb().ok()?;
+    let oid = odb
+        .exists_prefix(git2::Oid::from_str(hash).ok()?, hash.len())
+        .ok()?;
+
+    if odb.read_header(oid).ok()?.1 != git2::ObjectType::Commit {
+        return None;
+    }
+
+    Some(match map.kept(&oid) {
+        Some(new) => new.to_string()[..hash.len()].to_string(),
+        None => format!("{} (not in this subset)", hash),
+    })
+}
+
+/// Creates subsets of a repository. A branch or tag is created for each subset
+/// on the rewritten counterpart of the first revision, unless it was left out
+/// entirely. The given references are rewritten for each subset as well.
+///
+/// Returns a message for each subset saying how many references were
+/// rewritten.
+pub fn repo_subset(
+    repo: &git2::Repository,
+    subsets: &mut [Subset],
+    report: &mut Report,
+    revisions: &Revisions,
+    refs: &[Ref],
+    force: bool,
+    progress: &mut dyn FnMut(git2::Oid, usize, usize),
+) -> Result<Vec<String>, Error> {
+    rewrite_subsets(repo, subsets, report, revisions, refs, progress)?;
+
+    // All references are updated at once after everything else is done.
+    let mut updates = RefUpdates::new();
+    let mut messages = Vec::new();
+
+    for subset in subsets.iter_mut() {
+        if let (Some(target), Some(oid)) = (&subset.target, subset.tip) {
+            let message = match &subset.reflog_message {
+                Some(message) => message.clone(),
+                None => format!(
+                    "git-subset: filtered from {} with filter {}",
+                    revisions.include[0],
+                    map_name(
+                        &subset.filter,
+                        &subset.rewrite,
+                        &revisions.grafts
+                    )
+                ),
+            };
+
+            target.create(repo, oid, force, &message, &mut updates)?;
+        }
+
+        if let Some(notes) = &subset.notes {
+            if let Some((name, count)) = rewrite_notes(
+                repo,
+                &subset.map,
+                notes,
+                subset.ref_prefix.as_deref(),
+                force,
+                &mut updates,
+            )? {
+                messages
+                    .push(format!("Rewrote {} notes into '{}'.", count, name));
+            }
+        }
+
+        if let Some(mode) = subset.replace_refs {
+            let (written, deleted) =
+                rewrite_replace_refs(repo, &subset.map, mode, &mut updates)?;
+
+            messages.push(format!(
+                "Wrote {} and deleted {} replace references.",
+                written, deleted
+            ));
+        }
+
+        if refs.is_empty() {
+            continue;
+        }
+
+        match &subset.ref_prefix {
+            Some(prefix) => {
+                let count = rewrite_refs(
+                    repo,
+                    &subset.map,
+                    refs,
+                    prefix,
+                    force,
+                    &mut updates,
+                )?;
+
+                messages.push(format!(
+                    "Rewrote {} of {} references into '{}'.",
+                    count,
+                    refs.len(),
+                    prefix
+                ));
+            }
+            None => {
+                let count = rewrite_refs_in_place(
+                    repo,
+                    &subset.map,
+                    refs,
+                    force,
+                    &mut updates,
+                )?;
+
+                messages.push(format!(
+                    "Rewrote {} of {} references in place. The original \
+                     references are saved under 'refs/original/'.",
+                    count,
+                    refs.len()
+                ));
+            }
+        }
+    }
+
+    updates.commit(repo)?;
+
+    Ok(messages)
+}
+
+/// Rewrites the commits for each subset and finds its tip, without creating or
+/// updating any references.
+pub fn rewrite_subsets(
+    repo: &git2::Repository,
+    subsets: &mut [Subset],
+    report: &mut Report,
+    revisions: &Revisions,
+    refs: &[Ref],
+    progress: &mut dyn FnMut(git2::Oid, usize, usize),
+) -> Result<(), Error> {
+    process_commits(repo, revisions, refs, subsets, report, progress)?;
+
+    for subset in subsets.iter_mut() {
+        // Empty commits resolve to their parents. If all of them are empty,
+        // there is nothing to create.
+        subset.tip = match revisions.include.first() {
+            Some(tip) => subset.map.resolve(tip).flatten(),
+            None => None,
+        };
+    }
+
+    Ok(())
+}
+
+/// Returns the name of the map file for a filter, the other changes made to
+/// commits and the grafts honored while walking history.
+pub fn map_name(
+    filter: &Filter,
+    rewrite: &CommitRewrite,
+    grafts: &Grafts,
+) -> String {
+    // The map path is derived from the hash of the filter so that we don't
+    // use an invalid object mapping for subsequent runs.
+    let mut hasher = DefaultHasher::new();
+    filter.hash(&mut hasher);
+    rewrite.hash(&mut hasher);
+
+    // Grafts change the history that is rewritten. Without any, the name stays
+    // what it was before they were honored.
+    if !grafts.is_empty() {
+        grafts.hash(&mut hasher);
+    }
+
+    format!("{:x}", hasher.finish())
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rewriting the contents of files.
+//!
+//! Every transform only looks at the contents of a file, so the result of
+//! running a blob through the whole pipeline is computed once and then cached
+//! by the blob. The built-in transforms always run in the same order:
+//!
+//!  1. Stripping the UTF-8 byte order mark.
+//!  2. Normalizing line endings.
+//!  3. Replacing text.
+//!  4. Custom transforms, such as the blob callback, in the order they were
+//!     added.
+
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::callback::BlobCallback;
+use crate::eol::Eol;
+use crate::error::Error;
+use crate::replace::Replacements;
+
+/// Where a transform goes in the pipeline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    StripBom,
+    NormalizeEol,
+    ReplaceText,
+    Custom,
+}
+
+/// A change to the contents of a file that only depends on the contents.
+pub trait BlobTransform: fmt::Debug {
+    /// Identifies the transform along with its settings. Cached results are
+    /// only reused for transforms with the same key.
+    fn key(&self) -> String;
+
+    /// Where the transform goes in the pipeline. Only one transform of each
+    /// built-in stage is kept.
+    fn stage(&self) -> Stage {
+        Stage::Custom
+    }
+
+    /// Returns `true` if the transform only applies to text files.
+    fn text_only(&self) -> bool {
+        true
+    }
+
+    /// Transforms the contents of the blob `id`.
+    fn apply<'a>(
+        &self,
+        id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error>;
+}
+
+/// Removes the UTF-8 byte order mark from the start of a file.
+#[derive(Clone, Copy, Debug)]
+pub struct StripBom;
+
+impl BlobTransform for StripBom {
+    fn key(&self) -> String {
+        "strip-bom".to_string()
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::StripBom
+    }
+
+    fn apply<'a>(
+        &self,
+        _id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error> {
+        Ok(match contents.strip_prefix(b"\xEF\xBB\xBF") {
+            Some(contents) => Cow::Owned(contents.to_vec()),
+            None => Cow::Borrowed(contents),
+        })
+    }
+}
+
+impl BlobTransform for Eol {
+    fn key(&self) -> String {
+        format!("normalize-eol {:?}", self)
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::NormalizeEol
+    }
+
+    fn apply<'a>(
+        &self,
+        _id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error> {
+        Ok(self.normalize(contents))
+    }
+}
+
+impl BlobTransform for Replacements {
+    fn key(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        format!("replace-text {:016x}", hasher.finish())
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::ReplaceText
+    }
+
+    fn apply<'a>(
+        &self,
+        _id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error> {
+        Ok(Replacements::apply(self, contents))
+    }
+}
+
+impl BlobTransform for BlobCallback {
+    fn key(&self) -> String {
+        format!("blob-callback {}", self.command())
+    }
+
+    fn text_only(&self) -> bool {
+        false
+    }
+
+    fn apply<'a>(
+        &self,
+        id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error> {
+        let output = self.call(id, contents)?;
+
+        Ok(if output == contents {
+            Cow::Borrowed(contents)
+        } else {
+            Cow::Owned(output)
+        })
+    }
+}
+
+/// The transforms to run every file through, in order.
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    transforms: Vec<Rc<dyn BlobTransform>>,
+}
+
+impl Hash for Pipeline {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for transform in &self.transforms {
+            transform.key().hash(state);
+        }
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transforms.is_empty()
+    }
+
+    /// Adds a transform at its stage. A transform of a built-in stage replaces
+    /// the one already there, if any.
+    pub fn insert(&mut self, transform: Rc<dyn BlobTransform>) {
+        let stage = transform.stage();
+
+        if stage != Stage::Custom {
+            self.transforms.retain(|t| t.stage() != stage);
+        }
+
+        let i = self.transforms.partition_point(|t| t.stage() <= stage);
+        self.transforms.insert(i, transform);
+    }
+
+    /// Parses a transform in the form `<name>[=<value>]` and adds it, such as
+    /// `strip-bom` or `normalize-eol=lf`.
+    pub fn insert_spec(&mut self, spec: &str) -> Result<(), Error> {
+        let invalid =
+            |reason| Error::invalid(format!("transform '{}'", spec), reason);
+
+        let (name, value) = match spec.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (spec, None),
+        };
+
+        let transform: Rc<dyn BlobTransform> = match (name, value) {
+            ("strip-bom", None) => Rc::new(StripBom),
+            ("normalize-eol", Some(eol)) => {
+                Rc::new(eol.parse::<Eol>().map_err(invalid)?)
+            }
+            ("replace-text", Some(path)) => {
+                let replacements =
+                    Replacements::from_file(path).map_err(|err| {
+                        let action =
+                            format!("load text replacements '{}'", path);
+                        Error::failed(action, err)
+                    })?;
+                Rc::new(replacements)
+            }
+            ("blob-callback", Some(command)) => {
+                Rc::new(BlobCallback::new(command))
+            }
+            _ => return Err(invalid("there is no such transform".to_string())),
+        };
+
+        self.insert(transform);
+        Ok(())
+    }
+
+    /// Runs the contents of a blob through every transform. Transforms that
+    /// only apply to text are skipped if `text` is `false`. Returns `None` if
+    /// nothing changed.
+    pub fn apply(
+        &self,
+        id: git2::Oid,
+        contents: &[u8],
+        text: bool,
+    ) -> Result<Option<Vec<u8>>, git2::Error> {
+        let mut contents = Cow::Borrowed(contents);
+
+        for transform in &self.transforms {
+            if transform.text_only() && !text {
+                continue;
+            }
+
+            if let Cow::Owned(new) = transform.apply(id, &contents)? {
+                contents = Cow::Owned(new);
+            }
+        }
+
+        Ok(match contents {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(contents) => Some(contents),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let mut pipeline = Pipeline::new();
+        pipeline.insert_spec("normalize-eol=crlf").unwrap();
+        pipeline.insert(Rc::new(Replacements::parse("a==>b").unwrap()));
+        pipeline.insert_spec("strip-bom").unwrap();
+        pipeline.insert_spec("normalize-eol=lf").unwrap();
+
+        let stages: Vec<_> =
+            pipeline.transforms.iter().map(|t| t.stage()).collect();
+        assert_eq!(
+            stages,
+            [Stage::StripBom, Stage::NormalizeEol, Stage::ReplaceText]
+        );
+
+        let id = git2::Oid::zero();
+
+        assert_eq!(
+            pipeline.apply(id, b"\xEF\xBB\xBFa\r\nc\n", true).unwrap(),
+            Some(b"b\nc\n".to_vec())
+        );
+        assert_eq!(
+            pipeline.apply(id, b"\xEF\xBB\xBFa\r\n", false).unwrap(),
+            None
+        );
+        assert_eq!(pipeline.apply(id, b"c\n", true).unwrap(), None);
+
+        assert!(pipeline.insert_spec("bogus").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for scrambling everything with `--anonymize`.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns the names of the entries of a tree, and of those of its subtrees.
+fn shape(repo: &git2::Repository, tree: &git2::Tree<'_>) -> Vec<String> {
+    let mut names = Vec::new();
+
+    for entry in tree {
+        let name = entry.name().unwrap().to_string();
+
+        if let Ok(subtree) = repo.find_tree(entry.id()) {
+            for path in shape(repo, &subtree) {
+                names.push(format!("{}/{}", name, path));
+            }
+        } else {
+            names.push(name);
+        }
+    }
+
+    names
+}
+
+#[test]
+fn test_anonymize() {
+    let repo = TestRepo::new("anonymize");
+    let a = repo.commit_as(
+        "Alice",
+        &[("src/main.c", "int main;\n"), ("README", "secret\n")],
+        &[],
+        "Add the secret sauce\n",
+    );
+    let b = repo.commit_as(
+        "Bob",
+        &[("src/main.c", "int main;\n"), ("README", "more secret\n")],
+        &[a],
+        "Add more secret sauce\n",
+    );
+    let c = repo.commit_as(
+        "Alice",
+        &[("src/main.c", "int main();\n"), ("README", "more secret\n")],
+        &[b],
+        "Fix main\n",
+    );
+
+    repo.subset(&[
+        &c.to_string(),
+        "-b",
+        "out",
+        "--anonymize",
+        "--anonymize-contents",
+    ]);
+
+    let log = repo.log("out");
+    assert_eq!(log.len(), 3);
+    assert!(log.iter().all(|message| message.starts_with("Commit ")));
+    assert_ne!(log[0], log[1]);
+
+    // The same person gets the same name every time.
+    let out = repo.find("out");
+    let first = out.parent(0).unwrap().parent(0).unwrap();
+    assert_eq!(out.author().name(), first.author().name());
+    assert_ne!(out.author().name(), out.parent(0).unwrap().author().name());
+    assert!(out.author().name().unwrap().starts_with("User "));
+    assert!(!out.author().email().unwrap().contains("alice"));
+
+    // The shape of the tree is kept, and names are the same across commits.
+    let tree = out.tree().unwrap();
+    let paths = shape(&repo.repo, &tree);
+    assert_eq!(paths.len(), 2);
+    assert!(paths.iter().any(|path| path.contains('/')));
+    assert!(paths.iter().all(|path| !path.contains("main")));
+    assert_eq!(paths, shape(&repo.repo, &first.tree().unwrap()));
+
+    for path in &paths {
+        assert!(repo.read("out", path).starts_with("Blob "));
+    }
+}
+
+#[test]
+fn test_anonymize_seed() {
+    let repo = TestRepo::new("anonymize-seed");
+    let a = repo.commit_as("Alice", &[("src/main.c", "1")], &[], "first");
+    let a = a.to_string();
+
+    // Without a map to keep the seed in, every run scrambles differently, so
+    // names can't be found by hashing guesses.
+    repo.subset(&[&a, "-b", "out", "--anonymize"]);
+    repo.subset(&[&a, "-b", "out2", "--anonymize"]);
+
+    let out = repo.find("out");
+    let out2 = repo.find("out2");
+    assert_ne!(out.author().name(), out2.author().name());
+    assert_ne!(
+        shape(&repo.repo, &out.tree().unwrap()),
+        shape(&repo.repo, &out2.tree().unwrap())
+    );
+}
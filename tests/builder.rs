@@ -0,0 +1,105 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for creating subsets with the library.
+
+mod common;
+
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::rc::Rc;
+
+use common::TestRepo;
+use git_subset::builder::SubsetBuilder;
+use git_subset::transform::BlobTransform;
+
+#[test]
+fn test_builder() {
+    let repo = TestRepo::new("builder");
+    let a = repo.commit(
+        &[("a/f", "1"), ("a/g.key", "1"), ("b", "1")],
+        &[],
+        "first",
+    );
+    let b = repo.commit(
+        &[("a/f", "2"), ("a/g.key", "2"), ("b", "1")],
+        &[a],
+        "second",
+    );
+    repo.commit(&[("a/f", "2"), ("a/g.key", "2"), ("b", "2")], &[b], "third");
+
+    let calls = Rc::new(Cell::new(0));
+    let progress = calls.clone();
+
+    let tip = SubsetBuilder::new()
+        .path("a")
+        .revision(&b.to_string())
+        .branch("out")
+        .map_message(|message| message.to_ascii_uppercase())
+        .filter_entry(|path, _| !path.ends_with(".key"))
+        .on_progress(move |_, _| progress.set(progress.get() + 1))
+        .run(&repo.repo)
+        .unwrap();
+
+    assert_eq!(tip, Some(repo.find("out").id()));
+    assert_eq!(repo.log("out"), ["SECOND", "FIRST"]);
+    assert_eq!(repo.read("out", "a/f"), "2");
+    assert!(repo
+        .find("out")
+        .tree()
+        .unwrap()
+        .get_path("a/g.key".as_ref())
+        .is_err());
+    assert!(calls.get() > 0);
+}
+
+/// Upper-cases every text file.
+#[derive(Debug)]
+struct Shout;
+
+impl BlobTransform for Shout {
+    fn key(&self) -> String {
+        "shout".to_string()
+    }
+
+    fn apply<'a>(
+        &self,
+        _id: git2::Oid,
+        contents: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, git2::Error> {
+        Ok(Cow::Owned(contents.to_ascii_uppercase()))
+    }
+}
+
+#[test]
+fn test_builder_transform() {
+    let repo = TestRepo::new("builder-transform");
+    let a = repo.commit(&[("f", "hello"), ("g", "bin\0")], &[], "first");
+
+    SubsetBuilder::new()
+        .revision(&a.to_string())
+        .branch("out")
+        .transform(Shout)
+        .run(&repo.repo)
+        .unwrap();
+
+    assert_eq!(repo.read("out", "f"), "HELLO");
+    assert_eq!(repo.read("out", "g"), "bin\0");
+}
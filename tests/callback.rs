@@ -0,0 +1,81 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for editing commits and files with `--commit-callback` and
+//! `--blob-callback`.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_commit_callback() {
+    let repo = TestRepo::new("commit-callback");
+    let a = repo.commit(&[("f", "1")], &[], "first\n");
+    let b = repo.commit(&[("f", "2")], &[a], "skip me\n");
+    let c = repo.commit(&[("f", "3"), ("g", "1")], &[b], "third\n");
+    let c = c.to_string();
+
+    // Skips the second commit, and gives the others a new author and a
+    // message listing the paths they changed.
+    let callback = r#"
+        input=$(cat)
+        case "$input" in
+            *'"message":"skip me\n"'*) echo '{"skip": true}' ;;
+            *) paths=$(printf "%s" "$input" |
+                   sed 's/.*"changed_paths":\[\([^]]*\)\].*/\1/' |
+                   tr -d '"')
+               echo "{\"message\": \"$paths\", \"author\": {\"name\": \"Bot\"}}"
+               ;;
+        esac
+    "#;
+
+    repo.subset(&[&c, "-b", "out", "--commit-callback", callback, "-x", "x"]);
+
+    assert_eq!(repo.log("out"), ["f,g", "f"]);
+    assert_eq!(repo.find("out").author().name(), Some("Bot"));
+    assert_eq!(repo.find("out").author().email(), Some("test@example.com"));
+    assert_eq!(repo.read("out", "f"), "3");
+}
+
+#[test]
+fn test_blob_callback() {
+    let repo = TestRepo::new("blob-callback");
+    let a = repo.commit(
+        &[("a/x", "hello\n"), ("a/y", "hello\n"), ("b/z", "hello\n")],
+        &[],
+        "first",
+    );
+
+    repo.subset(&[
+        &a.to_string(),
+        "-b",
+        "out",
+        "-p",
+        "a",
+        "--blob-callback",
+        "tr a-z A-Z",
+    ]);
+
+    assert_eq!(repo.read("out", "a/x"), "HELLO\n");
+    assert_eq!(repo.read("out", "a/y"), "HELLO\n");
+}
@@ -0,0 +1,140 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for saving the object map while commits are being rewritten.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::process::{Command, Output};
+
+use common::TestRepo;
+
+/// Runs `git-subset` on a repository without `--nomap`.
+fn subset_with_map(repo: &TestRepo, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_checkpoint() {
+    let repo = TestRepo::new("checkpoint");
+    let a = repo.commit(&[("a/x", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "3")], &[b], "third");
+    let c = c.to_string();
+
+    // Fails on the last commit for as long as the file is there.
+    let crash = repo.path.join("crash");
+    fs::write(&crash, "").unwrap();
+    let callback = format!(
+        "case \"$(cat)\" in *third*) test -e '{}' && exit 1;; esac; true",
+        crash.display()
+    );
+
+    let args = [
+        c.as_str(),
+        "-f",
+        "-b",
+        "out",
+        "-p",
+        "a",
+        "--commit-callback",
+        &callback,
+        "--checkpoint-commits",
+        "1",
+    ];
+
+    let output = subset_with_map(&repo, &args);
+    assert!(!output.status.success());
+
+    // The commits before it were saved.
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["map", "--repo", repo.path.to_str().unwrap(), "export"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    let commits = json.as_object().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert!(commits.contains_key(&a.to_string()));
+    assert!(commits.contains_key(&b.to_string()));
+
+    // Carries on from there.
+    fs::remove_file(&crash).unwrap();
+    assert!(subset_with_map(&repo, &args).status.success());
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(
+        repo.find("out").parent_id(0).unwrap().to_string(),
+        commits[&b.to_string()].as_str().unwrap()
+    );
+}
+
+#[test]
+fn test_checkpoint_state_branch() {
+    let repo = TestRepo::new("checkpoint-state");
+    let a = repo.commit(&[("a/x", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "3")], &[b], "third");
+    let c = c.to_string();
+
+    let crash = repo.path.join("crash");
+    fs::write(&crash, "").unwrap();
+    let callback = format!(
+        "case \"$(cat)\" in *third*) test -e '{}' && exit 1;; esac; true",
+        crash.display()
+    );
+
+    let state = "refs/subset/state/main";
+    let args = [
+        c.as_str(),
+        "-f",
+        "-b",
+        "out",
+        "-p",
+        "a",
+        "--commit-callback",
+        &callback,
+        "--checkpoint-commits",
+        "1",
+        "--state-branch",
+        state,
+    ];
+
+    // Checkpoints aren't committed to the state branch.
+    assert!(!subset_with_map(&repo, &args).status.success());
+    assert!(repo.repo.find_reference(state).is_err());
+    let checkpoints = fs::read_dir(repo.path.join("subset")).unwrap().count();
+    assert_eq!(checkpoints, 1);
+
+    // The next run carries on from the checkpoint and commits the map once.
+    fs::remove_file(&crash).unwrap();
+    let output = subset_with_map(&repo, &args);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(repo.find(state).parent_count(), 0);
+    let checkpoints = fs::read_dir(repo.path.join("subset")).unwrap().count();
+    assert_eq!(checkpoints, 0);
+}
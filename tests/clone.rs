@@ -0,0 +1,102 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for cloning a repository and creating the subset in one go.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::TestRepo;
+
+/// Runs `git-subset clone` and panics if it fails.
+fn clone(url: &Path, dir: &Path, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .arg("clone")
+        .arg(url)
+        .arg(dir)
+        .arg("--quiet")
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(
+        output.status.success(),
+        "git-subset clone {:?} failed:\n{}",
+        args,
+        String::from_utf8_lossy(&output.stdout)
+    );
+}
+
+/// Returns the names of the entries in a directory.
+fn list(dir: &Path) -> Vec<String> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_clone() {
+    let repo = TestRepo::new("clone");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "2"), ("b", "2")], &[b], "third");
+
+    let commit = repo.repo.find_commit(c).unwrap();
+    repo.repo.branch("master", &commit, true).unwrap();
+    repo.repo.set_head("refs/heads/master").unwrap();
+
+    let dir = repo.path.join("out-clone");
+    clone(&repo.path, &dir, &["--bare", "-b", "subset", "-p", "a"]);
+
+    let out = git2::Repository::open_bare(&dir).unwrap();
+    let tip = out
+        .revparse_single("subset")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(tip.summary(), Some("third"));
+    assert_eq!(tip.parent(0).unwrap().summary(), Some("first"));
+
+    // Everything reachable is in a single pack.
+    let objects = dir.join("objects");
+    assert_eq!(list(&objects.join("pack")).len(), 2);
+
+    for id in [tip.id(), tip.tree_id(), a, c] {
+        let hex = id.to_string();
+        assert!(!objects.join(&hex[..2]).join(&hex[2..]).exists());
+    }
+
+    // The clone has a working tree unless it is bare.
+    let dir = repo.path.join("out-clone-worktree");
+    clone(
+        &repo.path,
+        &dir,
+        &["-b", "subset", "-p", "a", "--no-repack"],
+    );
+    assert_eq!(list(&dir), [".git", "a", "b"]);
+    let out = git2::Repository::open(&dir).unwrap();
+    assert!(out.find_branch("subset", git2::BranchType::Local).is_ok());
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Helpers for building small repositories to run `git-subset` on.
+
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A bare repository in a temporary directory that is removed when dropped.
+pub struct TestRepo {
+    pub path: PathBuf,
+    pub repo: git2::Repository,
+
+    /// Seconds to add to the time of the next commit so that every commit
+    /// gets a different (but predictable) time.
+    time: Cell<i64>,
+}
+
+impl TestRepo {
+    /// Creates an empty repository. The name must be unique among the tests.
+    pub fn new(name: &str) -> TestRepo {
+        let path = std::env::temp_dir().join("git-subset-tests").join(format!(
+            "{}-{}",
+            name,
+            std::process::id()
+        ));
+
+        let _ = fs::remove_dir_all(&path);
+
+        let repo = git2::Repository::init_bare(&path).unwrap();
+
+        TestRepo {
+            path,
+            repo,
+            time: Cell::new(0),
+        }
+    }
+
+    /// Creates a commit with the given files, each of which is a path and its
+    /// contents.
+    pub fn commit(
+        &self,
+        files: &[(&str, &str)],
+        parents: &[git2::Oid],
+        message: &str,
+    ) -> git2::Oid {
+        self.commit_as("Test", files, parents, message)
+    }
+
+    /// Creates a commit as `commit` does, but by someone else. Their email
+    /// address is their name at `example.com`.
+    pub fn commit_as(
+        &self,
+        name: &str,
+        files: &[(&str, &str)],
+        parents: &[git2::Oid],
+        message: &str,
+    ) -> git2::Oid {
+        self.commit_with_gitlinks(name, files, &[], parents, message)
+    }
+
+    /// Creates a commit as `commit_as` does, but also with submodules, each of
+    /// which is a path and the commit it points to.
+    pub fn commit_with_gitlinks(
+        &self,
+        name: &str,
+        files: &[(&str, &str)],
+        gitlinks: &[(&str, git2::Oid)],
+        parents: &[git2::Oid],
+        message: &str,
+    ) -> git2::Oid {
+        let mut index = self.repo.index().unwrap();
+        index.clear().unwrap();
+
+        for (path, id) in gitlinks {
+            index
+                .add(&git2::IndexEntry {
+                    ctime: git2::IndexTime::new(0, 0),
+                    mtime: git2::IndexTime::new(0, 0),
+                    dev: 0,
+                    ino: 0,
+                    mode: 0o160000,
+                    uid: 0,
+                    gid: 0,
+                    file_size: 0,
+                    id: *id,
+                    flags: 0,
+                    flags_extended: 0,
+                    path: path.as_bytes().to_vec(),
+                })
+                .unwrap();
+        }
+
+        for (path, contents) in files {
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: git2::Oid::zero(),
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            };
+
+            index.add_frombuffer(&entry, contents.as_bytes()).unwrap();
+        }
+
+        let tree = self.repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let parents: Vec<_> = parents
+            .iter()
+            .map(|&parent| self.repo.find_commit(parent).unwrap())
+            .collect();
+
+        let time = self.time.get();
+        self.time.set(time + 60);
+
+        let signature = git2::Signature::new(
+            name,
+            &format!("{}@example.com", name.to_lowercase()),
+            &git2::Time::new(1500000000 + time, 0),
+        )
+        .unwrap();
+
+        self.repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap()
+    }
+
+    /// Runs `git-subset` on the repository and panics if it fails.
+    pub fn subset(&self, args: &[&str]) {
+        let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+            .arg("--quiet")
+            .arg("--nomap")
+            .arg("--repo")
+            .arg(&self.path)
+            .args(args)
+            .output()
+            .unwrap();
+
+        assert!(
+            output.status.success(),
+            "git-subset {:?} failed:\n{}",
+            args,
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    /// Runs `git-subset` on the repository, panics if it succeeds, and returns
+    /// what it printed.
+    pub fn subset_err(&self, args: &[&str]) -> String {
+        let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+            .arg("--quiet")
+            .arg("--nomap")
+            .arg("--repo")
+            .arg(&self.path)
+            .args(args)
+            .output()
+            .unwrap();
+
+        assert!(!output.status.success(), "git-subset {:?} succeeded", args);
+
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Returns the commit that the revision points to.
+    pub fn find(&self, rev: &str) -> git2::Commit<'_> {
+        self.repo
+            .revparse_single(rev)
+            .and_then(|object| object.peel_to_commit())
+            .unwrap()
+    }
+
+    /// Returns the messages of the commits reachable from the revision, from
+    /// newest to oldest.
+    pub fn log(&self, rev: &str) -> Vec<String> {
+        let mut walk = self.repo.revwalk().unwrap();
+        walk.set_sorting(git2::Sort::TOPOLOGICAL).unwrap();
+        walk.push(self.find(rev).id()).unwrap();
+
+        walk.map(|id| {
+            let commit = self.repo.find_commit(id.unwrap()).unwrap();
+            commit.message().unwrap().to_string()
+        })
+        .collect()
+    }
+
+    /// Returns the contents of a file in the tree of the revision.
+    pub fn read(&self, rev: &str, path: &str) -> String {
+        let entry = self
+            .find(rev)
+            .tree()
+            .unwrap()
+            .get_path(std::path::Path::new(path))
+            .unwrap();
+        let blob = self.repo.find_blob(entry.id()).unwrap();
+        String::from_utf8(blob.content().to_vec()).unwrap()
+    }
+
+    /// Returns the messages of the parents of the commit that the revision
+    /// points to.
+    pub fn parents(&self, rev: &str) -> Vec<String> {
+        self.find(rev)
+            .parents()
+            .map(|parent| parent.message().unwrap().to_string())
+            .collect()
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
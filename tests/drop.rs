@@ -0,0 +1,162 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for dropping commits from the rewritten history while keeping their
+//! changes.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_drop_commits_by() {
+    let repo = TestRepo::new("drop-commits-by");
+    let a = repo.commit(&[("f", "1")], &[], "first");
+    let b = repo.commit_as("Contractor", &[("f", "2")], &[a], "second");
+    let c = repo.commit(&[("f", "2"), ("g", "1")], &[b], "third");
+    let c = c.to_string();
+
+    repo.subset(&[
+        &c,
+        "-b",
+        "out",
+        "--drop-commits-by",
+        r"contractor@example\.com",
+        "-x",
+        "nothing",
+    ]);
+
+    // The change made by the dropped commit is now part of the next one.
+    assert_eq!(repo.log("out"), ["third", "first"]);
+    assert_eq!(repo.read("out", "f"), "2");
+    assert_eq!(repo.read("out~", "f"), "1");
+}
+
+#[test]
+fn test_drop_message_regex() {
+    let repo = TestRepo::new("drop-message-regex");
+    let a = repo.commit(&[("f", "1")], &[], "first");
+    let b = repo.commit(&[("f", "2")], &[a], "fixup! first");
+    let c = repo.commit(&[("f", "3")], &[b], "second");
+    let d = repo.commit(&[("f", "4")], &[c], "Update f [bot]");
+    let d = d.to_string();
+
+    repo.subset(&[
+        &d,
+        "-b",
+        "out",
+        "--drop-message-regex",
+        "^fixup!",
+        "--drop-message-regex",
+        r"\[bot\]",
+        "-x",
+        "nothing",
+    ]);
+
+    // The tip was dropped too, so the branch is on its parent.
+    assert_eq!(repo.log("out"), ["second", "first"]);
+    assert_eq!(repo.read("out", "f"), "3");
+}
+
+/// Creates a branch merged into the main line twice. Returns the tip.
+fn merges(repo: &TestRepo) -> String {
+    let a = repo.commit(&[("f", "1")], &[], "first");
+    let b = repo.commit(&[("f", "1"), ("g", "1")], &[a], "side");
+    let c = repo.commit(&[("f", "2")], &[a], "second");
+    let d = repo.commit(&[("f", "2"), ("g", "1")], &[c, b], "merge side");
+    let e = repo.commit(&[("f", "2"), ("g", "2")], &[b], "side again");
+    let f = repo.commit(&[("f", "2"), ("g", "2")], &[d, e], "merge again");
+    repo.commit(&[("f", "3"), ("g", "2")], &[f], "third")
+        .to_string()
+}
+
+#[test]
+fn test_no_merges() {
+    let repo = TestRepo::new("drop-no-merges");
+    let tip = merges(&repo);
+
+    repo.subset(&[&tip, "-b", "out", "--no-merges", "-x", "nothing"]);
+
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(repo.read("out", "g"), "2");
+}
+
+#[test]
+fn test_merges_only() {
+    let repo = TestRepo::new("drop-merges-only");
+    let tip = merges(&repo);
+
+    // The merges become degenerate once everything else is left out, so they
+    // are collapsed into regular commits.
+    repo.subset(&[&tip, "-b", "out", "--merges-only", "-x", "nothing"]);
+
+    assert_eq!(repo.log("out"), ["merge again", "merge side"]);
+    assert_eq!(repo.read("out", "f"), "2");
+}
+
+#[test]
+fn test_drop_merges() {
+    let repo = TestRepo::new("drop-merges");
+    let tip = merges(&repo);
+
+    // Dropped merges are left out like with `--no-merges`.
+    repo.subset(&[
+        &tip,
+        "-b",
+        "out",
+        "--drop-message-regex",
+        "^merge",
+        "-x",
+        "nothing",
+    ]);
+
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(repo.read("out", "g"), "2");
+}
+
+#[test]
+fn test_drop_merges_by() {
+    let repo = TestRepo::new("drop-merges-by");
+    let a = repo.commit(&[("f", "1")], &[], "first");
+    let b = repo.commit(&[("f", "1"), ("g", "1")], &[a], "side");
+    let c = repo.commit(&[("f", "2")], &[a], "second");
+    let d = repo.commit_as(
+        "Contractor",
+        &[("f", "2"), ("g", "1")],
+        &[c, b],
+        "merge side",
+    );
+    let e = repo.commit(&[("f", "3"), ("g", "1")], &[d], "third");
+    let e = e.to_string();
+
+    repo.subset(&[
+        &e,
+        "-b",
+        "out",
+        "--drop-commits-by",
+        r"contractor@example\.com",
+        "-x",
+        "nothing",
+    ]);
+
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(repo.read("out", "g"), "1");
+    assert_eq!(repo.parents("out~").len(), 1);
+}
@@ -0,0 +1,94 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for exporting a stream for `git fast-import`.
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::TestRepo;
+
+/// Returns the number of objects in a repository.
+fn count_objects(repo: &git2::Repository) -> usize {
+    let mut count = 0;
+    repo.odb()
+        .unwrap()
+        .foreach(|_| {
+            count += 1;
+            true
+        })
+        .unwrap();
+    count
+}
+
+#[test]
+fn test_export_stream() {
+    let repo = TestRepo::new("export");
+    let a =
+        repo.commit(&[("a/x", "1"), ("a/s p", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "2")], &[a], "second");
+    let c =
+        repo.commit(&[("a/x", "2"), ("a/s p", "1"), ("b", "1")], &[a], "third");
+    let d = repo.commit(&[("a/x", "2"), ("b", "2")], &[b, c], "merge");
+
+    let d = d.to_string();
+    let path = repo.path.join("export.stream");
+    let objects = count_objects(&repo.repo);
+
+    let stream = path.to_str().unwrap();
+    repo.subset(&[&d, "-b", "out", "-p", "a", "--export-stream", stream]);
+
+    // Nothing is written to the repository.
+    assert_eq!(count_objects(&repo.repo), objects);
+    assert!(repo.repo.find_reference("refs/heads/out").is_err());
+
+    // Standard output gets the same stream.
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--nomap", "--repo", repo.path.to_str().unwrap()])
+        .args([&d, "-b", "out", "-p", "a", "--export-stream", "-"])
+        .output()
+        .unwrap();
+    let stream = fs::read(&path).unwrap();
+    assert_eq!(output.stdout, stream);
+
+    // Importing it gives the same commits as creating the branch.
+    let imported = repo.path.join("out-imported");
+    git2::Repository::init_bare(&imported).unwrap();
+
+    let mut child = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .env("GIT_DIR", &imported)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&stream).unwrap();
+    assert!(child.wait().unwrap().success());
+
+    repo.subset(&[&d, "-b", "out", "-p", "a"]);
+
+    let imported = git2::Repository::open_bare(&imported).unwrap();
+    assert_eq!(
+        imported.refname_to_id("refs/heads/out").unwrap(),
+        repo.find("out").id()
+    );
+}
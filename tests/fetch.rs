@@ -0,0 +1,49 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for fetching before creating the subset.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_fetch() {
+    let upstream = TestRepo::new("fetch-upstream");
+    let a = upstream.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let commit = upstream.repo.find_commit(a).unwrap();
+    upstream.repo.branch("main", &commit, true).unwrap();
+
+    let repo = TestRepo::new("fetch");
+    let url = upstream.path.to_str().unwrap();
+    repo.repo.remote("origin", url).unwrap();
+
+    let args = ["--fetch", "origin", "origin/main", "-b", "out", "-p", "a"];
+    repo.subset(&args);
+    assert_eq!(repo.log("out"), ["first"]);
+
+    // New upstream commits are picked up by the next run.
+    let b = upstream.commit(&[("a", "2"), ("b", "1")], &[a], "second");
+    let commit = upstream.repo.find_commit(b).unwrap();
+    upstream.repo.branch("main", &commit, true).unwrap();
+
+    repo.subset(&[&args[..], &["-f"]].concat());
+    assert_eq!(repo.log("out"), ["second", "first"]);
+}
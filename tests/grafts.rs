@@ -0,0 +1,142 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for honoring replace references and grafts.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::TestRepo;
+
+/// Runs `git-subset` on a repository without `--nomap`.
+fn subset_with_map(repo: &TestRepo, args: &[&str]) {
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "git-subset {:?} failed", args);
+}
+
+#[test]
+fn test_replace_objects() {
+    let repo = TestRepo::new("replace-objects");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2"), ("b", "1")], &[a], "second");
+    let c = repo.commit(&[("a", "3"), ("b", "1")], &[b], "third");
+
+    // The second commit is replaced by a root commit.
+    let replacement = repo.commit(&[("a", "2"), ("b", "2")], &[], "replaced");
+    repo.repo
+        .reference(&format!("refs/replace/{}", b), replacement, false, "test")
+        .unwrap();
+
+    let c = c.to_string();
+    repo.subset(&[&c, "-b", "replaced", "-p", "a"]);
+    repo.subset(&[&c, "-b", "raw", "-p", "a", "--no-replace-objects"]);
+
+    assert_eq!(repo.log("replaced"), ["third", "replaced"]);
+    assert_eq!(repo.read("replaced~", "a"), "2");
+    assert_eq!(repo.log("raw"), ["third", "second", "first"]);
+}
+
+#[test]
+fn test_replace_objects_map() {
+    let repo = TestRepo::new("replace-objects-map");
+    let a = repo.commit(&[("a", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2")], &[a], "second");
+    let c = repo.commit(&[("a", "3")], &[b], "third");
+
+    let replacement = repo.commit(&[("a", "2")], &[], "replaced");
+    repo.repo
+        .reference(&format!("refs/replace/{}", b), replacement, false, "test")
+        .unwrap();
+
+    // Results for one topology are not reused for the other.
+    let c = c.to_string();
+    subset_with_map(&repo, &[&c, "-b", "replaced", "-p", "a"]);
+    subset_with_map(
+        &repo,
+        &[&c, "-b", "raw", "-p", "a", "--no-replace-objects"],
+    );
+
+    assert_eq!(repo.log("replaced"), ["third", "replaced"]);
+    assert_eq!(repo.log("raw"), ["third", "second", "first"]);
+}
+
+#[test]
+fn test_replace_refs_ignored() {
+    let repo = TestRepo::new("replace-refs-ignored");
+    let a = repo.commit(&[("a", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2")], &[a], "second");
+
+    let b = b.to_string();
+    subset_with_map(
+        &repo,
+        &[
+            &b,
+            "-b",
+            "out",
+            "--to-subdirectory-filter",
+            "sub/",
+            "--replace-refs",
+            "update-or-add",
+        ],
+    );
+    let out = repo.find("out").id();
+
+    // The replace references point at rewritten commits, which would be
+    // moved into the subdirectory again if they were honored.
+    let c = repo.commit(&[("a", "3")], &[b.parse().unwrap()], "third");
+    subset_with_map(
+        &repo,
+        &[
+            &c.to_string(),
+            "-b",
+            "out",
+            "--to-subdirectory-filter",
+            "sub/",
+            "--force",
+        ],
+    );
+
+    assert_eq!(repo.find("out~").id(), out);
+    assert_eq!(repo.read("out", "sub/a"), "3");
+}
+
+#[test]
+fn test_grafts() {
+    let repo = TestRepo::new("grafts");
+    let a = repo.commit(&[("a", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2")], &[a], "second");
+    let c = repo.commit(&[("a", "3")], &[b], "third");
+    let d = repo.commit(&[("a", "4")], &[c], "fourth");
+
+    // The third commit skips over the second one.
+    fs::create_dir_all(repo.path.join("info")).unwrap();
+    fs::write(repo.path.join("info/grafts"), format!("{} {}\n", c, a)).unwrap();
+
+    repo.subset(&[&d.to_string(), "-b", "out", "-p", "a"]);
+
+    assert_eq!(repo.log("out"), ["fourth", "third", "first"]);
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for filtering a stream from `git fast-export`.
+
+mod common;
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use common::TestRepo;
+
+/// Returns a stream of the `main` branch in a repository from `git
+/// fast-export`.
+fn fast_export(repo: &TestRepo) -> Vec<u8> {
+    let output = Command::new("git")
+        .args(["fast-export", "--signed-tags=verbatim", "refs/heads/main"])
+        .env("GIT_DIR", &repo.path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn test_import_stream() {
+    let repo = TestRepo::new("import");
+    let a =
+        repo.commit(&[("a/x", "1"), ("a/s p", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "2")], &[a], "second");
+    let c =
+        repo.commit(&[("a/x", "2"), ("a/s p", "1"), ("b", "1")], &[a], "third");
+    let d = repo.commit(&[("a/x", "2"), ("b", "2")], &[b, c], "merge");
+    repo.repo
+        .reference("refs/heads/main", d, false, "")
+        .unwrap();
+
+    let stream = fast_export(&repo);
+    let path = repo.path.join("import.stream");
+    std::fs::write(&path, &stream).unwrap();
+
+    repo.subset(&["main", "-b", "out", "-p", "a"]);
+
+    // Filtering the stream in another repository gives the same commits.
+    let other = TestRepo::new("import-other");
+    let stream_path = path.to_str().unwrap();
+    other.subset(&["--import-stream", stream_path, "-b", "out", "-p", "a"]);
+    assert_eq!(other.find("out").id(), repo.find("out").id());
+    assert!(other.repo.find_reference("refs/heads/main").is_err());
+
+    // Imported references can be named as revisions.
+    let named = TestRepo::new("import-named");
+    let path = path.to_str().unwrap();
+    named.subset(&["main", "--import-stream", path, "-b", "out", "-p", "a"]);
+    assert_eq!(named.find("out").id(), repo.find("out").id());
+
+    // Streams can be filtered from standard input to standard output.
+    let piped = TestRepo::new("import-piped");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--nomap", "--repo", piped.path.to_str().unwrap()])
+        .args(["--import-stream", "-", "--export-stream", "-"])
+        .args(["-b", "out", "-p", "a"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(&stream).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let imported = TestRepo::new("import-imported");
+    let mut child = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .env("GIT_DIR", &imported.path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&output.stdout)
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+    assert_eq!(imported.find("out").id(), repo.find("out").id());
+}
+
+#[test]
+fn test_import_stream_empty() {
+    let repo = TestRepo::new("import-empty");
+    let path = repo.path.join("empty.stream");
+    std::fs::write(&path, "done\n").unwrap();
+
+    let err = repo.subset_err(&[
+        "--import-stream",
+        path.to_str().unwrap(),
+        "-b",
+        "out",
+        "-p",
+        "a",
+    ]);
+    assert!(err.contains("doesn't update any references"), "{}", err);
+}
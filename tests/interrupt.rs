@@ -0,0 +1,107 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for stopping cleanly when interrupted.
+
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::TestRepo;
+
+#[test]
+fn test_interrupt() {
+    let repo = TestRepo::new("interrupt");
+    let a = repo.commit(&[("a/x", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "3")], &[b], "third");
+    let c = c.to_string();
+
+    // Interrupts the run while the second commit is being rewritten, for as
+    // long as the file is there.
+    let interrupt = repo.path.join("interrupt");
+    fs::write(&interrupt, "").unwrap();
+    let callback = format!(
+        "case \"$(cat)\" in *second*) test -e '{}' && kill -INT $PPID && \
+         sleep 1;; esac; true",
+        interrupt.display()
+    );
+
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_git-subset"))
+            .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+            .args([&c, "-f", "-b", "out", "-p", "a"])
+            .args(["--commit-callback", &callback])
+            .output()
+            .unwrap()
+    };
+
+    let output = run();
+    assert_eq!(output.status.code(), Some(130));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Run the same command again"), "{}", stderr);
+    assert!(repo.repo.find_reference("refs/heads/out").is_err());
+
+    // The commits up to and including the one being rewritten were saved.
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["map", "--repo", repo.path.to_str().unwrap(), "export"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    let commits = json.as_object().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert!(commits.contains_key(&b.to_string()));
+
+    fs::remove_file(&interrupt).unwrap();
+    assert!(run().status.success());
+    assert_eq!(repo.log("out"), ["third", "second", "first"]);
+    assert_eq!(
+        repo.find("out").parent_id(0).unwrap().to_string(),
+        commits[&b.to_string()].as_str().unwrap()
+    );
+}
+
+#[test]
+fn test_interrupt_export() {
+    let repo = TestRepo::new("interrupt-export");
+    let a = repo.commit(&[("a/x", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "3")], &[b], "third");
+    let c = c.to_string();
+
+    let callback = "case \"$(cat)\" in *second*) kill -INT $PPID && \
+                    sleep 1;; esac; true";
+    let stream = repo.path.join("stream");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args([&c, "-b", "out", "-p", "a", "--commit-callback", callback])
+        .args(["--export-stream", stream.to_str().unwrap()])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(130));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Run the same command again"), "{}", stderr);
+}
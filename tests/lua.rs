@@ -0,0 +1,90 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for Lua scripts.
+
+#![cfg(feature = "lua")]
+
+mod common;
+
+use common::TestRepo;
+
+const SCRIPT: &str = r#"
+function on_commit(commit)
+  if commit.message:find("^WIP") then
+    return false
+  end
+
+  commit.message = commit.message:upper()
+  commit.author.name = "Bot"
+end
+
+function on_blob(path, contents)
+  if path:find("%.key$") then
+    return false
+  end
+
+  if path == "config" then
+    return contents:gsub("hunter2", "***")
+  end
+end
+"#;
+
+#[test]
+fn test_lua() {
+    let repo = TestRepo::new("lua");
+    let a = repo.commit(
+        &[("config", "password=hunter2"), ("id.key", "1")],
+        &[],
+        "first",
+    );
+    let b = repo.commit(
+        &[("config", "password=hunter2"), ("id.key", "1"), ("a", "1")],
+        &[a],
+        "WIP: second",
+    );
+    let c = repo.commit(
+        &[("config", "password=hunter2"), ("id.key", "2"), ("a", "2")],
+        &[b],
+        "third",
+    );
+
+    let script = repo.path.join("script.lua");
+    std::fs::write(&script, SCRIPT).unwrap();
+
+    repo.subset(&[
+        &c.to_string(),
+        "-b",
+        "out",
+        "--lua",
+        script.to_str().unwrap(),
+    ]);
+
+    assert_eq!(repo.log("out"), ["THIRD", "FIRST"]);
+    assert_eq!(repo.find("out").author().name(), Some("Bot"));
+    assert_eq!(repo.read("out", "config"), "password=***");
+    assert_eq!(repo.read("out", "a"), "2");
+    assert!(repo
+        .find("out")
+        .tree()
+        .unwrap()
+        .get_name("id.key")
+        .is_none());
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for the object map kept between runs.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+use common::TestRepo;
+
+/// Runs `git-subset` on a repository without `--nomap`.
+fn subset_with_map(repo: &TestRepo, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Returns the path of the only map file in a repository.
+fn map_file(repo: &TestRepo) -> PathBuf {
+    let mut files: Vec<_> = fs::read_dir(repo.path.join("subset"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    files.pop().unwrap()
+}
+
+#[test]
+fn test_map_migration() {
+    let repo = TestRepo::new("map-migration");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let a = a.to_string();
+
+    let args = [a.as_str(), "-f", "-b", "out", "-p", "a"];
+    assert!(subset_with_map(&repo, &args).status.success());
+
+    let path = map_file(&repo);
+    let written = fs::read(&path).unwrap();
+
+    // Maps are compressed, with a header that says which version wrote them.
+    let binary = zstd::decode_all(&written[..]).unwrap();
+    assert!(binary.starts_with(b"GSUBMAP\0"));
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    assert!(binary.windows(version.len()).any(|w| w == version));
+
+    // Maps in the old text format are read, and saved in the new one.
+    let out = repo.find("out").id();
+    fs::write(&path, format!("{} {}\n", a, out)).unwrap();
+    assert!(subset_with_map(&repo, &args).status.success());
+    assert_eq!(repo.find("out").id(), out);
+    let migrated = zstd::decode_all(&fs::read(&path).unwrap()[..]).unwrap();
+    assert!(migrated.starts_with(b"GSUBMAP\0"));
+
+    // Maps from newer versions are refused instead of being misread.
+    let mut newer = binary;
+    newer[8..12].copy_from_slice(&99u32.to_le_bytes());
+    fs::write(&path, &newer).unwrap();
+
+    let output = subset_with_map(&repo, &args);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("version 99"), "{}", stdout);
+
+    // They can still be ignored.
+    let output = subset_with_map(&repo, &[&args[..], &["--nomap"]].concat());
+    assert!(output.status.success());
+}
+
+/// Runs `git-subset map` on a repository.
+fn map_command(repo: &TestRepo, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["map", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_map_json() {
+    let repo = TestRepo::new("map-json");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "2")], &[a], "second");
+    let b = b.to_string();
+
+    let args = [b.as_str(), "-f", "-b", "out", "-p", "a"];
+    assert!(subset_with_map(&repo, &args).status.success());
+    let out = repo.find("out").id().to_string();
+
+    // The empty commit stands for the one it was pruned into.
+    let output = map_command(&repo, &["export"]);
+    assert!(output.status.success());
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    let commits = json.as_object().unwrap();
+    assert_eq!(commits.len(), 2);
+    assert_eq!(commits[&a.to_string()], out.as_str());
+    assert_eq!(commits[&b], out.as_str());
+
+    // An imported map is used just like one that was written by a run.
+    let path = repo.path.join("map.json");
+    fs::write(&path, &output.stdout).unwrap();
+    let name = map_file(&repo);
+    let name = name.file_name().unwrap().to_str().unwrap().to_string();
+    fs::remove_file(repo.path.join("subset").join(&name)).unwrap();
+
+    let output =
+        map_command(&repo, &["--map", &name, "import", path.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    let output = map_command(&repo, &["export"]);
+    let imported: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(imported, json);
+
+    let output = map_command(&repo, &["--map", "0", "export"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.trim(), "{\n}");
+}
+
+#[test]
+fn test_map_commit_map() {
+    let repo = TestRepo::new("map-commit-map");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let a = a.to_string();
+
+    let args = [a.as_str(), "-f", "-b", "out", "-p", "a"];
+    assert!(subset_with_map(&repo, &args).status.success());
+    let out = repo.find("out").id();
+
+    let output = map_command(&repo, &["export", "--format", "commit-map"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, format!("{:<40} new\n{} {}\n", "old", a, out));
+
+    // Commits left out by `git filter-repo` are all zeros.
+    let path = repo.path.join("commit-map");
+    let zero = "0".repeat(40);
+    fs::write(&path, format!("{:<40} new\n{} {}\n", "old", a, zero)).unwrap();
+
+    let import = ["import", "--format", "commit-map", path.to_str().unwrap()];
+    assert!(map_command(&repo, &import).status.success());
+
+    let output = map_command(&repo, &["export", "--format", "json"]);
+    let json: serde_json::Value =
+        serde_json::from_slice(&output.stdout).unwrap();
+    assert!(json[&a].is_null());
+}
+
+#[test]
+fn test_map_unchanged() {
+    let repo = TestRepo::new("map-unchanged");
+    let a = repo.commit(&[("a/x", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "1")], &[a], "second");
+    let c = repo.commit(&[("a/x", "2"), ("b", "1")], &[b], "third");
+    let c = c.to_string();
+
+    // The first commit is kept as it is, and the second is pruned onto it.
+    let args = [c.as_str(), "-f", "-b", "out", "-p", "a"];
+    assert!(subset_with_map(&repo, &args).status.success());
+    let out = repo.find("out").id().to_string();
+
+    let output = map_command(&repo, &["export", "--format", "commit-map"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut expected = vec![
+        format!("{} {}", a, a),
+        format!("{} {}", b, a),
+        format!("{} {}", c, out),
+    ];
+    expected.sort();
+    expected.insert(0, format!("{:<40} new", "old"));
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn test_map_verify() {
+    let repo = TestRepo::new("map-verify");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let a = a.to_string();
+
+    let args = [a.as_str(), "-f", "-b", "out", "-p", "a"];
+    assert!(subset_with_map(&repo, &args).status.success());
+
+    let output = map_command(&repo, &["verify"]);
+    assert!(output.status.success());
+
+    // Pretend that the rewritten commit was garbage collected.
+    let missing = "1".repeat(40);
+    let path = repo.path.join("map.json");
+    fs::write(&path, format!("{{\"{}\": \"{}\"}}", a, missing)).unwrap();
+    assert!(map_command(&repo, &["import", path.to_str().unwrap()])
+        .status
+        .success());
+
+    let output = map_command(&repo, &["verify"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("{} maps to {}", a, missing)),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("1 problem"), "{}", stdout);
+}
+
+#[test]
+fn test_map_prune() {
+    let repo = TestRepo::new("map-prune");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let a = a.to_string();
+
+    let filter = repo.path.join("subset.filter");
+    fs::write(&filter, "a\n").unwrap();
+
+    let filter_file = filter.to_str().unwrap();
+    let args = [a.as_str(), "-f", "-b", "out", "--filter-file", filter_file];
+    assert!(subset_with_map(&repo, &args).status.success());
+
+    let output = map_command(&repo, &["list"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("0 days ago"), "{}", stdout);
+    assert!(stdout.contains("subset.filter"), "{}", stdout);
+
+    let output = map_command(&repo, &["prune"]);
+    assert!(!output.status.success());
+
+    // Maps that are still used, or that would be deleted in a dry run, are
+    // kept.
+    let output = map_command(&repo, &["prune", "--older-than", "1"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = map_command(&repo, &["prune", "--older-than", "0", "-n"]);
+    assert!(!output.stdout.is_empty());
+    assert_eq!(fs::read_dir(repo.path.join("subset")).unwrap().count(), 2);
+
+    let output = map_command(&repo, &["prune", "--missing-filters"]);
+    assert!(output.stdout.is_empty());
+
+    fs::remove_file(&filter).unwrap();
+    let output = map_command(&repo, &["prune", "--missing-filters"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read_dir(repo.path.join("subset")).unwrap().count(), 0);
+}
+
+#[test]
+fn test_map_tips() {
+    let repo = TestRepo::new("map-tips");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2"), ("b", "1")], &[a], "second");
+    let b = b.to_string();
+
+    let args = [b.as_str(), "-f", "-b", "out", "-p", "a"];
+    let output = subset_with_map(&repo, &args);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(2/2)"), "{}", stdout);
+
+    // Only the new commit is walked the next time.
+    let c = repo.commit(&[("a/x", "3"), ("b", "1")], &[b.parse().unwrap()], "");
+    let c = c.to_string();
+
+    let args = [c.as_str(), "-f", "-b", "out", "-p", "a"];
+    let output = subset_with_map(&repo, &args);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("(1/1)"), "{}", stdout);
+    assert_eq!(repo.log("out").len(), 3);
+    assert_eq!(repo.read("out", "a/x"), "3");
+
+    // Nothing at all is walked when nothing is new.
+    let output = subset_with_map(&repo, &args);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("Rewriting"), "{}", stdout);
+    assert_eq!(repo.log("out").len(), 3);
+}
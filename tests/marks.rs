@@ -0,0 +1,156 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for reading and writing marks files.
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use common::TestRepo;
+
+/// Runs Git on a repository with its input from a file, if any.
+fn git(repo: &Path, args: &[&str], input: Option<&Path>) -> Vec<u8> {
+    let stdin = match input {
+        Some(path) => Stdio::from(fs::File::open(path).unwrap()),
+        None => Stdio::null(),
+    };
+
+    let output = Command::new("git")
+        .args(args)
+        .env("GIT_DIR", repo)
+        .stdin(stdin)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    output.stdout
+}
+
+#[test]
+fn test_export_marks() {
+    let repo = TestRepo::new("marks-export");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2"), ("b", "1")], &[a], "second");
+
+    let (a, b) = (a.to_string(), b.to_string());
+    let path = |name: &str| repo.path.join(name).to_str().unwrap().to_string();
+    let (s1, s2, m1, m2) = (path("s1"), path("s2"), path("m1"), path("m2"));
+
+    let export = |rev: &str, stream: &str, marks: &[&str]| {
+        let mut args = vec![rev, "-b", "out", "-p", "a"];
+        args.extend(["--export-stream", stream]);
+        args.extend(marks);
+        repo.subset(&args);
+    };
+
+    export(&a, &s1, &["--export-marks", &m1]);
+    export(&b, &s2, &["--import-marks", &m1, "--export-marks", &m2]);
+
+    // The second stream only has what is new.
+    let second = fs::read_to_string(&s2).unwrap();
+    assert!(!second.contains("first"));
+    assert!(second.contains("second"));
+
+    // `git fast-import` can import it on top of the first with the marks.
+    let other = TestRepo::new("marks-export-other");
+    git(&other.path, &["fast-import", "--quiet"], Some(s1.as_ref()));
+    git(
+        &other.path,
+        &["fast-import", "--quiet", &format!("--import-marks={}", m1)],
+        Some(s2.as_ref()),
+    );
+
+    repo.subset(&[&b, "-b", "out", "-p", "a"]);
+    let out = repo.find("out").id();
+    assert_eq!(other.find("out").id(), out);
+
+    let marks = fs::read_to_string(&m2).unwrap();
+    assert!(marks.starts_with(":1 "));
+    assert!(marks.contains(&format!(" {}\n", out)));
+}
+
+#[test]
+fn test_import_marks() {
+    let repo = TestRepo::new("marks-import");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    repo.repo
+        .reference("refs/heads/main", a, false, "")
+        .unwrap();
+
+    let path = |name: &str| repo.path.join(name).to_str().unwrap().to_string();
+    let (s1, s2, m1, m2) = (path("s1"), path("s2"), path("m1"), path("m2"));
+
+    // `git fast-export` keeps its own marks to only export what is new.
+    let fast_export = |stream: &str| {
+        let export_marks = format!("--export-marks={}", path("git-marks"));
+        let import_marks = format!("--import-marks={}", path("git-marks"));
+        let mut args = vec!["fast-export", "refs/heads/main", &export_marks];
+        if Path::new(&path("git-marks")).exists() {
+            args.push(&import_marks);
+        }
+        fs::write(stream, git(&repo.path, &args, None)).unwrap();
+    };
+
+    let other = TestRepo::new("marks-import-other");
+    let import = |branch: &str, stream: &str, marks: &[&str]| {
+        let mut args = vec!["main", "-b", branch, "-p", "a"];
+        args.extend(["--import-stream", stream]);
+        args.extend(marks);
+        other.subset(&args);
+    };
+
+    fast_export(&s1);
+    import("out1", &s1, &["--export-marks", &m1]);
+
+    let b = repo.commit(&[("a/x", "2"), ("b", "1")], &[a], "second");
+    repo.repo.reference("refs/heads/main", b, true, "").unwrap();
+
+    fast_export(&s2);
+    assert!(!fs::read_to_string(&s2).unwrap().contains("first"));
+
+    // The second stream refers to the first commit by its mark.
+    let err = other.subset_err(&[
+        "main",
+        "-b",
+        "out2",
+        "-p",
+        "a",
+        "--import-stream",
+        &s2,
+    ]);
+    assert!(err.contains("mark"), "{}", err);
+
+    import("out2", &s2, &["--import-marks", &m1, "--export-marks", &m2]);
+
+    repo.subset(&["main", "-b", "out", "-p", "a"]);
+    assert_eq!(other.find("out2").id(), repo.find("out").id());
+    assert!(fs::read_to_string(&m2).unwrap().contains(&b.to_string()));
+}
+
+#[test]
+fn test_marks_without_stream() {
+    let repo = TestRepo::new("marks-without-stream");
+    let a = repo.commit(&[("a/x", "1")], &[], "first").to_string();
+
+    let err = repo.subset_err(&[&a, "-b", "out", "--export-marks", "marks"]);
+    assert!(err.contains("--export-stream"), "{}", err);
+}
@@ -0,0 +1,100 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for keeping a filtered mirror up to date.
+
+mod common;
+
+use std::process::Command;
+
+use common::TestRepo;
+
+/// Runs one cycle of `git-subset mirror` for a path and returns what it
+/// printed. Unlike other tests, this keeps the object map since mirroring
+/// relies on it.
+fn mirror(repo: &TestRepo, path: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["mirror", "origin/main", "--repo"])
+        .arg(&repo.path)
+        .args(["--fetch", "origin", "--push", "public:main"])
+        .args(["-b", "out", "-p", path])
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "git-subset mirror failed:\n{}",
+        stdout
+    );
+    stdout
+}
+
+#[test]
+fn test_mirror() {
+    let upstream = TestRepo::new("mirror-upstream");
+    let public = TestRepo::new("mirror-public");
+    let repo = TestRepo::new("mirror");
+
+    repo.repo
+        .remote("origin", upstream.path.to_str().unwrap())
+        .unwrap();
+    repo.repo
+        .remote("public", public.path.to_str().unwrap())
+        .unwrap();
+
+    let advance = |files: &[(&str, &str)], message: &str| {
+        let parents: Vec<_> = upstream
+            .repo
+            .refname_to_id("refs/heads/main")
+            .into_iter()
+            .collect();
+        let id = upstream.commit(files, &parents, message);
+        let commit = upstream.repo.find_commit(id).unwrap();
+        upstream.repo.branch("main", &commit, true).unwrap();
+    };
+
+    advance(&[("a", "1"), ("b", "1")], "first");
+    mirror(&repo, "a");
+    assert_eq!(public.log("main"), ["first"]);
+
+    // Nothing is rewritten until upstream changes.
+    let output = mirror(&repo, "a");
+    assert!(!output.contains("Rewriting"), "{}", output);
+    assert_eq!(public.log("main"), ["first"]);
+
+    // Commits that don't touch the subset are left out.
+    advance(&[("a", "1"), ("b", "2")], "second");
+    advance(&[("a", "2"), ("b", "2")], "third");
+    let output = mirror(&repo, "a");
+    assert!(output.contains("(2/2)"), "{}", output);
+    assert_eq!(public.log("main"), ["third", "first"]);
+
+    advance(&[("a", "2"), ("b", "3")], "fourth");
+    mirror(&repo, "a");
+    assert_eq!(public.log("main"), ["third", "first"]);
+
+    // Another filter has a map of its own, so all of the history is walked
+    // for it instead of just what is new.
+    advance(&[("a", "2"), ("b", "4")], "fifth");
+    let output = mirror(&repo, "b");
+    assert!(output.contains("(5/5)"), "{}", output);
+    assert_eq!(public.log("main"), ["fifth", "fourth", "second", "first"]);
+}
@@ -0,0 +1,98 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for rewriting notes and keeping the commits they are on.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns the note on a commit, if there is one.
+fn note(repo: &TestRepo, notes: &str, rev: &str) -> Option<String> {
+    let commit = repo.find(rev).id();
+    repo.repo
+        .find_note(Some(notes), commit)
+        .ok()
+        .and_then(|note| note.message().map(String::from))
+}
+
+#[test]
+fn test_notes() {
+    let repo = TestRepo::new("notes");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "second");
+    let c = repo.commit(&[("a", "2"), ("b", "2")], &[b], "third");
+    let d = repo.commit(&[("a", "2"), ("b", "3")], &[c], "fourth");
+
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+
+    for (commit, message) in [(a, "Left out"), (b, "Empty"), (c, "LGTM")] {
+        repo.repo
+            .note(&signature, &signature, None, commit, message, false)
+            .unwrap();
+    }
+
+    let range = format!("{}..{}", a, d);
+    repo.subset(&[&range, "-b", "out", "-p", "a", "--notes"]);
+
+    // Commits with notes are kept even if they are empty.
+    let notes = "refs/subset/out/notes/commits";
+    assert_eq!(repo.log("out"), ["third", "second"]);
+    assert_eq!(note(&repo, notes, "out").as_deref(), Some("LGTM"));
+    assert_eq!(note(&repo, notes, "out~").as_deref(), Some("Empty"));
+    assert_eq!(repo.repo.notes(Some(notes)).unwrap().count(), 2);
+
+    // The original notes are left as they are.
+    assert_eq!(
+        note(&repo, "refs/notes/commits", &a.to_string()).as_deref(),
+        Some("Left out")
+    );
+}
+
+#[test]
+fn test_keep_commit() {
+    let repo = TestRepo::new("keep-commit");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "second");
+    let c = repo.commit(&[("a", "1"), ("b", "3")], &[b], "third");
+    let d = repo.commit(&[("a", "2"), ("b", "3")], &[c], "fourth");
+
+    let list = repo.path.join("keep-commits");
+    std::fs::write(&list, format!("# Referenced elsewhere\n{} third\n", c))
+        .unwrap();
+
+    let d = d.to_string();
+    let b = b.to_string();
+    repo.subset(&[&d, "-b", "out", "-p", "a", "--keep-commit", &b]);
+    repo.subset(&[
+        &d,
+        "-b",
+        "both",
+        "-p",
+        "a",
+        "--keep-commit",
+        &b,
+        "--keep-commits-from",
+        list.to_str().unwrap(),
+    ]);
+
+    assert_eq!(repo.log("out"), ["fourth", "second", "first"]);
+    assert_eq!(repo.log("both"), ["fourth", "third", "second", "first"]);
+}
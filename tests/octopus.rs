@@ -0,0 +1,146 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for rewriting octopus merges, which have more than two parents.
+
+mod common;
+
+use common::TestRepo;
+
+/// Creates a base commit with the directories `a`, `b`, and `c`, a branch off
+/// of it changing each of them, and an octopus merge of the three branches.
+/// Returns the merge.
+fn octopus(repo: &TestRepo) -> git2::Oid {
+    let base =
+        repo.commit(&[("a/f", "1"), ("b/f", "1"), ("c/f", "1")], &[], "base");
+    let a =
+        repo.commit(&[("a/f", "2"), ("b/f", "1"), ("c/f", "1")], &[base], "a");
+    let b =
+        repo.commit(&[("a/f", "1"), ("b/f", "2"), ("c/f", "1")], &[base], "b");
+    let c =
+        repo.commit(&[("a/f", "1"), ("b/f", "1"), ("c/f", "2")], &[base], "c");
+
+    repo.commit(
+        &[("a/f", "2"), ("b/f", "2"), ("c/f", "2")],
+        &[a, b, c],
+        "merge",
+    )
+}
+
+#[test]
+fn test_octopus_kept() {
+    let repo = TestRepo::new("octopus-kept");
+    let merge = octopus(&repo).to_string();
+
+    repo.subset(&["-b", "out", &merge, "-p", "a", "b", "c"]);
+
+    // Nothing changed, so neither did the commits.
+    assert_eq!(repo.find("out").id().to_string(), merge);
+    assert_eq!(repo.parents("out"), ["a", "b", "c"]);
+}
+
+#[test]
+fn test_octopus_partially_degenerate() {
+    let repo = TestRepo::new("octopus-partially-degenerate");
+    let merge = octopus(&repo).to_string();
+
+    // `c` is empty and pruned, leaving the base as a parent. Since it is an
+    // ancestor of the other parents, it is dropped.
+    repo.subset(&["-b", "out", &merge, "-p", "a", "b"]);
+
+    assert_eq!(repo.find("out").message(), Some("merge"));
+    assert_eq!(repo.parents("out"), ["a", "b"]);
+
+    repo.subset(&[
+        "-b",
+        "never",
+        "--prune-degenerate",
+        "never",
+        &merge,
+        "-p",
+        "a",
+        "b",
+    ]);
+    assert_eq!(repo.parents("never"), ["a", "b", "base"]);
+}
+
+#[test]
+fn test_octopus_becomes_regular_commit() {
+    let repo = TestRepo::new("octopus-regular-commit");
+    let merge = octopus(&repo).to_string();
+
+    // Only `a` is left, so the merge becomes a regular commit on top of it.
+    // Since it doesn't change anything, it is pruned.
+    repo.subset(&["-b", "out", &merge, "-p", "a"]);
+
+    assert_eq!(repo.find("out").message(), Some("a"));
+    assert_eq!(repo.parents("out"), ["base"]);
+}
+
+#[test]
+fn test_octopus_no_prune_merges() {
+    let repo = TestRepo::new("octopus-no-prune-merges");
+    let merge = octopus(&repo).to_string();
+
+    // Both `b` and `c` are pruned, so they become the same parent. The merge
+    // is kept, but only has that parent once.
+    repo.subset(&["-b", "out", "--no-prune-merges", &merge, "-p", "a"]);
+
+    assert_eq!(repo.find("out").message(), Some("merge"));
+    assert_eq!(repo.parents("out"), ["a", "base"]);
+}
+
+#[test]
+fn test_octopus_already_degenerate() {
+    let repo = TestRepo::new("octopus-already-degenerate");
+    let base =
+        repo.commit(&[("a/f", "1"), ("b/f", "1"), ("c/f", "1")], &[], "base");
+    let a =
+        repo.commit(&[("a/f", "2"), ("b/f", "1"), ("c/f", "1")], &[base], "a");
+    let b =
+        repo.commit(&[("a/f", "1"), ("b/f", "2"), ("c/f", "1")], &[base], "b");
+    let c =
+        repo.commit(&[("a/f", "1"), ("b/f", "1"), ("c/f", "2")], &[base], "c");
+
+    // The base is already redundant, since it is an ancestor of `a`.
+    let merge = repo
+        .commit(
+            &[("a/f", "2"), ("b/f", "2"), ("c/f", "2")],
+            &[a, base, b, c],
+            "merge",
+        )
+        .to_string();
+
+    // Only the parent that became redundant due to filtering is dropped.
+    repo.subset(&["-b", "auto", &merge, "-p", "a", "b"]);
+    assert_eq!(repo.parents("auto"), ["a", "base", "b"]);
+
+    repo.subset(&[
+        "-b",
+        "always",
+        "--prune-degenerate",
+        "always",
+        &merge,
+        "-p",
+        "a",
+        "b",
+    ]);
+    assert_eq!(repo.parents("always"), ["a", "b"]);
+}
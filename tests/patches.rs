@@ -0,0 +1,110 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for exporting history as a series of patches.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::TestRepo;
+
+#[test]
+fn test_export_patches() {
+    let repo = TestRepo::new("patches");
+    let a = repo.commit(&[("a/x", "1\n"), ("b", "1")], &[], "Add x");
+    let b = repo.commit_as(
+        "Zoë",
+        &[("a/x", "2\n"), ("a/y", "1\n"), ("b", "1")],
+        &[a],
+        "Change x\n\nAnd add y.\n",
+    );
+    let c =
+        repo.commit(&[("a/x", "2\n"), ("a/y", "1\n"), ("b", "2")], &[b], "b");
+    let d = repo.commit(&[("a/y", "1\n"), ("b", "2")], &[c], "Remove x");
+
+    let d = d.to_string();
+    let dir = repo.path.join("out-patches");
+    let dir_arg = dir.to_str().unwrap();
+
+    repo.subset(&[&d, "-b", "out", "-p", "a", "--export-patches", dir_arg]);
+
+    // Nothing is written to the repository.
+    assert!(repo.repo.find_reference("refs/heads/out").is_err());
+
+    let mut names: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "0001-Add-x.patch",
+            "0002-Change-x.patch",
+            "0003-Remove-x.patch"
+        ]
+    );
+
+    let patch = fs::read_to_string(dir.join(&names[1])).unwrap();
+    assert!(patch.contains("From: =?UTF-8?q?Zo=C3=AB?= <zoë@example.com>\n"));
+    assert!(
+        patch.contains("Subject: [PATCH 2/3] Change x\n\nAnd add y.\n---\n")
+    );
+
+    // Applying the patches gives the same tree as creating the branch.
+    let applied = repo.path.join("out-applied");
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&applied)
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    fs::create_dir(&applied).unwrap();
+    git(&["init", "--quiet"]);
+    let paths: Vec<_> = names
+        .iter()
+        .map(|name| dir.join(name).to_str().unwrap().to_string())
+        .collect();
+    let mut args = vec!["am", "--quiet"];
+    args.extend(paths.iter().map(|path| path.as_str()));
+    git(&args);
+
+    repo.subset(&[&d, "-b", "out", "-p", "a"]);
+    let out = repo.find("out");
+
+    let applied = git2::Repository::open(&applied).unwrap();
+    let head = applied.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.tree_id(), out.tree_id());
+
+    let second = head.parent(0).unwrap();
+    assert_eq!(second.author().name(), Some("Zoë"));
+    assert_eq!(
+        second.author().when(),
+        out.parent(0).unwrap().author().when()
+    );
+    assert_eq!(second.message(), Some("Change x\n\nAnd add y.\n"));
+}
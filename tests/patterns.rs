@@ -0,0 +1,68 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for pruning `.gitignore` and `.gitattributes` files.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_prune_patterns() {
+    let repo = TestRepo::new("prune-patterns");
+    let a = repo.commit(
+        &[
+            (".gitignore", "*.o\n/internal/build/\n/src/gen/\n"),
+            (".gitattributes", "/internal/*.bin binary\n"),
+            ("internal/a", "1"),
+            ("src/b", "1"),
+            ("src/sub/.gitignore", "/old/\n/new\n"),
+            ("src/sub/old/c", "1"),
+            ("src/sub/d", "1"),
+        ],
+        &[],
+        "first",
+    );
+
+    let a = a.to_string();
+    repo.subset(&[&a, "-b", "kept", "-x", "internal", "-x", "src/sub/old"]);
+    repo.subset(&[
+        &a,
+        "-b",
+        "pruned",
+        "-x",
+        "internal",
+        "-x",
+        "src/sub/old",
+        "--prune-patterns",
+    ]);
+
+    assert_eq!(
+        repo.read("kept", ".gitignore"),
+        "*.o\n/internal/build/\n/src/gen/\n"
+    );
+
+    // Paths that were never there (such as build output) are left alone.
+    assert_eq!(repo.read("pruned", ".gitignore"), "*.o\n/src/gen/\n");
+    assert_eq!(repo.read("pruned", "src/sub/.gitignore"), "/new\n");
+
+    let tree = repo.find("pruned").tree().unwrap();
+    assert!(tree.get_name(".gitattributes").is_none());
+}
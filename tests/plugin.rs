@@ -0,0 +1,91 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for WebAssembly plugins.
+
+#![cfg(feature = "wasm")]
+
+mod common;
+
+use common::TestRepo;
+
+/// A plugin that drops files starting with `s`, moves files starting with `o`
+/// to `new.txt`, and capitalizes the first letter of commit messages.
+const PLUGIN: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (global $next (mut i32) (i32.const 1024))
+  (data (i32.const 16) "new.txt")
+
+  (func (export "alloc") (param $len i32) (result i32)
+    (local $ptr i32)
+    (local.set $ptr (global.get $next))
+    (global.set $next (i32.add (global.get $next) (local.get $len)))
+    (local.get $ptr))
+
+  (func (export "filter_path") (param $ptr i32) (param $len i32) (result i64)
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 115))
+      (then (return (i64.const -1))))
+    (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 111))
+      (then (return (i64.or (i64.shl (i64.const 16) (i64.const 32))
+                            (i64.const 7)))))
+    (i64.const 0))
+
+  (func (export "rewrite_message") (param $ptr i32) (param $len i32)
+    (result i64)
+    (i32.store8 (local.get $ptr)
+      (i32.sub (i32.load8_u (local.get $ptr)) (i32.const 32)))
+    (i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+            (i64.extend_i32_u (local.get $len))))
+)
+"#;
+
+#[test]
+fn test_plugin() {
+    let repo = TestRepo::new("plugin");
+    let a = repo.commit(
+        &[("a/f", "1"), ("old.txt", "1"), ("secret.txt", "1")],
+        &[],
+        "first",
+    );
+    let b = repo.commit(
+        &[("a/f", "2"), ("old.txt", "2"), ("secret.txt", "2")],
+        &[a],
+        "second",
+    );
+
+    let plugin = repo.path.join("plugin.wasm");
+    std::fs::write(&plugin, wat::parse_str(PLUGIN).unwrap()).unwrap();
+
+    repo.subset(&[
+        &b.to_string(),
+        "-b",
+        "out",
+        "--plugin",
+        plugin.to_str().unwrap(),
+    ]);
+
+    assert_eq!(repo.log("out"), ["Second", "First"]);
+    assert_eq!(repo.read("out", "a/f"), "2");
+    assert_eq!(repo.read("out", "new.txt"), "2");
+
+    let tree = repo.find("out").tree().unwrap();
+    assert_eq!(tree.len(), 2);
+}
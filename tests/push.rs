@@ -0,0 +1,63 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for pushing the subset to a remote.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_push() {
+    let repo = TestRepo::new("push");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2"), ("b", "1")], &[a], "second");
+
+    let path = repo.path.join("out-remote");
+    let remote = git2::Repository::init_bare(&path).unwrap();
+    repo.repo.remote("origin", path.to_str().unwrap()).unwrap();
+
+    let b = b.to_string();
+    repo.subset(&[&b, "-b", "out", "-p", "a", "--push", "origin"]);
+    repo.subset(&[&b, "-b", "main", "-p", "a", "--push", "origin:subset"]);
+
+    let find = |name: &str| remote.refname_to_id(name).unwrap();
+    let out = repo.find("out").id();
+    assert_eq!(find("refs/heads/out"), out);
+    assert_eq!(find("refs/heads/subset"), out);
+
+    // Unrelated history is rejected unless forced.
+    let args = [&b, "-b", "other", "-p", "b", "--push", "origin:subset"];
+    let output = repo.subset_err(&args);
+    assert!(output.contains("Failed to push to 'origin'"), "{}", output);
+    assert_eq!(find("refs/heads/subset"), out);
+
+    repo.subset(&[
+        &b,
+        "-b",
+        "forced",
+        "-p",
+        "b",
+        "-f",
+        "--push",
+        "origin:subset",
+    ]);
+    assert_eq!(find("refs/heads/subset"), repo.find("forced").id());
+}
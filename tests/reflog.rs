@@ -0,0 +1,58 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for the reflog messages of created references.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns the message of the newest reflog entry of a reference.
+fn reflog_message(repo: &TestRepo, name: &str) -> String {
+    let reflog = repo.repo.reflog(name).unwrap();
+    reflog.get(0).unwrap().message().unwrap().to_string()
+}
+
+#[test]
+fn test_reflog_message() {
+    let repo = TestRepo::new("reflog-message");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+
+    // Bare repositories don't keep reflogs by default.
+    repo.repo
+        .config()
+        .unwrap()
+        .set_bool("core.logAllRefUpdates", true)
+        .unwrap();
+
+    let a = a.to_string();
+    repo.subset(&[&a, "-b", "out", "-p", "a"]);
+    repo.subset(&[&a, "-b", "custom", "-p", "a", "--reflog-message", "Hi"]);
+
+    let message = reflog_message(&repo, "refs/heads/out");
+    let prefix = format!("git-subset: filtered from {} with filter ", a);
+    assert!(message.starts_with(&prefix), "{}", message);
+
+    // The filter is named by the hash that names its object map.
+    let map = &message[prefix.len()..];
+    assert!(repo.path.join("subset").join(map).exists());
+
+    assert_eq!(reflog_message(&repo, "refs/heads/custom"), "Hi");
+}
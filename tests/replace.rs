@@ -0,0 +1,79 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for replace references.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns what a replace reference points to, if it exists.
+fn replacement(repo: &TestRepo, oid: git2::Oid) -> Option<git2::Oid> {
+    repo.repo
+        .find_reference(&format!("refs/replace/{}", oid))
+        .ok()
+        .and_then(|reference| reference.target())
+}
+
+#[test]
+fn test_replace_refs() {
+    let repo = TestRepo::new("replace-refs");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "second");
+    let c = repo.commit(&[("a", "2"), ("b", "2")], &[b], "third");
+
+    // An existing replace reference for a commit that is left out. What it is
+    // replaced by changes nothing in `a` either.
+    let replaced_by = repo.commit(&[("a", "1"), ("b", "3")], &[a], "second");
+    repo.repo
+        .reference(&format!("refs/replace/{}", b), replaced_by, false, "test")
+        .unwrap();
+
+    let c = c.to_string();
+    repo.subset(&[
+        &c,
+        "-b",
+        "out",
+        "-p",
+        "a",
+        "--replace-refs",
+        "update-or-add",
+    ]);
+
+    let out = repo.find("out").id();
+    let first = repo.find("out~").id();
+
+    assert_eq!(replacement(&repo, a), Some(first));
+    assert_eq!(replacement(&repo, c.parse().unwrap()), Some(out));
+    assert_eq!(replacement(&repo, b), None);
+
+    repo.subset(&[
+        &c,
+        "-b",
+        "other",
+        "-p",
+        "b",
+        "--replace-refs",
+        "delete-no-add",
+    ]);
+
+    assert_eq!(replacement(&repo, a), None);
+    assert_eq!(replacement(&repo, c.parse().unwrap()), None);
+}
@@ -0,0 +1,111 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for serving subsets over HTTP.
+
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+use common::TestRepo;
+
+/// Kills the server when the test is done, even if it fails.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_serve() {
+    let repo = TestRepo::new("serve");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "second");
+    let commit = repo.repo.find_commit(b).unwrap();
+    repo.repo.branch("main", &commit, true).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["serve", "main", "--listen", "127.0.0.1:0", "--repo"])
+        .arg(&repo.path)
+        .args(["--nomap", "-b", "out", "-p", "a"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let _server = Server(child);
+
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+
+    // Keep reading so that the server doesn't fail to print.
+    thread::spawn(move || stdout.lines().count());
+
+    let url = line.trim().rsplit(' ').next().unwrap().to_string();
+    assert!(url.ends_with("/out"), "{}", line);
+
+    let path = repo.path.join("out-clone");
+    let clone = git2::Repository::clone(&url, &path).unwrap();
+    let log = |rev: &str| {
+        let mut walk = clone.revwalk().unwrap();
+        walk.push(clone.revparse_single(rev).unwrap().id()).unwrap();
+        walk.map(|id| {
+            let commit = clone.find_commit(id.unwrap()).unwrap();
+            commit.summary().unwrap().to_string()
+        })
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(log("HEAD"), ["first"]);
+    assert!(clone.find_branch("out", git2::BranchType::Local).is_ok());
+
+    // Commits made in the clone are sent as haves, which the server doesn't
+    // know about.
+    let head = clone.head().unwrap().peel_to_commit().unwrap();
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    clone
+        .commit(
+            Some("refs/heads/local"),
+            &signature,
+            &signature,
+            "local",
+            &head.tree().unwrap(),
+            &[&head],
+        )
+        .unwrap();
+
+    // New commits are picked up when fetching.
+    let c = repo.commit(&[("a", "2"), ("b", "2")], &[b], "third");
+    let commit = repo.repo.find_commit(c).unwrap();
+    repo.repo.branch("main", &commit, true).unwrap();
+
+    let mut remote = clone.find_remote("origin").unwrap();
+    remote.fetch(&["out"], None, None).unwrap();
+    assert_eq!(log("origin/out"), ["third", "first"]);
+
+    // Nothing but the subsets is served.
+    let other = url.replace("/out", "/main");
+    let path = repo.path.join("out-main");
+    assert!(git2::Repository::clone(&other, &path).is_err());
+}
@@ -0,0 +1,89 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for keeping the object map in an SQLite database.
+
+mod common;
+
+use std::process::Command;
+
+use common::TestRepo;
+
+/// Runs `git-subset` on a repository without `--nomap`.
+fn subset_with_map(repo: &TestRepo, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_map() {
+    let repo = TestRepo::new("sqlite");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2"), ("b", "1")], &[a], "second");
+
+    // Start with a map file, which the database is seeded from.
+    let (a, b) = (a.to_string(), b.to_string());
+    let output = subset_with_map(&repo, &[&a, "-b", "out", "-p", "a"]);
+    assert!(output.status.success());
+
+    for rev in [&a, &b] {
+        let output = subset_with_map(
+            &repo,
+            &[rev, "-f", "-b", "out", "-p", "a", "--map-backend", "sqlite"],
+        );
+        assert!(output.status.success());
+    }
+
+    let databases: Vec<_> = std::fs::read_dir(repo.path.join("subset"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("sqlite".as_ref()))
+        .collect();
+    assert_eq!(databases.len(), 1);
+
+    let out = repo.find("out").id();
+    repo.subset(&[&b, "-f", "-b", "out", "-p", "a"]);
+    assert_eq!(repo.find("out").id(), out);
+    assert_eq!(repo.log("out"), ["second", "first"]);
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[test]
+fn test_sqlite_map_without_feature() {
+    let repo = TestRepo::new("sqlite-without-feature");
+    let a = repo.commit(&[("a/x", "1")], &[], "first").to_string();
+
+    let output = subset_with_map(
+        &repo,
+        &[&a, "-b", "out", "-p", "a", "--map-backend", "sqlite"],
+    );
+    assert!(!output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("without the `sqlite` feature"),
+        "{}",
+        stdout
+    );
+}
@@ -0,0 +1,113 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for keeping the object map on a state branch.
+
+mod common;
+
+use std::process::{Command, Output};
+
+use git_subset::map::OidMap;
+
+use common::TestRepo;
+
+const STATE: &str = "refs/subset/state/main";
+
+/// Runs `git-subset` on a repository without `--nomap`.
+fn subset_with_map(repo: &TestRepo, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-subset"))
+        .args(["--quiet", "--repo", repo.path.to_str().unwrap()])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_state_branch() {
+    let repo = TestRepo::new("state");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "2"), ("b", "1")], &[a], "second");
+    let c = repo.commit(&[("a/x", "3"), ("b", "1")], &[b], "third");
+
+    let run = |rev: git2::Oid| {
+        let rev = rev.to_string();
+        let args =
+            [&rev, "-f", "-b", "out", "-p", "a", "--state-branch", STATE];
+        let output = subset_with_map(&repo, &args);
+        assert!(output.status.success(), "{:?}", output);
+    };
+
+    run(a);
+    let out = repo.find("out").id();
+
+    // The map is committed instead of being written under `.git/subset/`.
+    assert!(!repo.path.join("subset").exists());
+    let first = repo.find(STATE);
+    let tree = first.tree().unwrap();
+    assert_eq!(tree.len(), 1);
+    let name = tree.get(0).unwrap().name().unwrap().to_string();
+
+    // Running again on the same commits changes nothing.
+    run(a);
+    assert_eq!(repo.find(STATE).id(), first.id());
+
+    run(b);
+    let second = repo.find(STATE);
+    assert_eq!(second.parent_ids().collect::<Vec<_>>(), [first.id()]);
+
+    // The map on the branch is what is used, so pretending that the last
+    // commit was left out puts the branch back where it was.
+    let mut map = OidMap::new();
+    map.insert(c, Some(a));
+    let blob = repo
+        .repo
+        .blob(&map.encode(&repo.repo, &name).unwrap())
+        .unwrap();
+    let mut builder = repo.repo.treebuilder(None).unwrap();
+    builder.insert(&name, blob, 0o100644).unwrap();
+    let tree = repo.repo.find_tree(builder.write().unwrap()).unwrap();
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    repo.repo
+        .commit(
+            Some(STATE),
+            &signature,
+            &signature,
+            "fake",
+            &tree,
+            &[&second],
+        )
+        .unwrap();
+
+    run(c);
+    assert_eq!(repo.find("out").id(), out);
+}
+
+#[test]
+fn test_state_branch_invalid() {
+    let repo = TestRepo::new("state-invalid");
+    let a = repo.commit(&[("a/x", "1")], &[], "first").to_string();
+
+    let args = [&a[..], "-b", "out", "-p", "a", "--state-branch", "state"];
+    let output = subset_with_map(&repo, &args);
+    assert!(!output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("refs/subset/state/main"), "{}", stdout);
+}
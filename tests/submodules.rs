@@ -0,0 +1,186 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for filtering histories with submodules.
+
+mod common;
+
+use std::path::Path;
+
+use common::TestRepo;
+
+const GITMODULES: &str = "\
+[submodule \"a\"]
+	path = lib/a
+	url = https://example.com/a.git
+[submodule \"b\"]
+	path = lib/b
+	url = https://example.com/b.git
+";
+
+#[test]
+fn test_prune_gitmodules() {
+    let repo = TestRepo::new("prune-gitmodules");
+    let sub = repo.commit(&[("x", "1")], &[], "submodule");
+    let a = repo.commit_with_gitlinks(
+        "Test",
+        &[(".gitmodules", GITMODULES), ("src/main.c", "int main;\n")],
+        &[("lib/a", sub), ("lib/b", sub)],
+        &[],
+        "first",
+    );
+
+    repo.subset(&[&a.to_string(), "-b", "out", "-x", "lib/b"]);
+
+    assert_eq!(
+        repo.read("out", ".gitmodules"),
+        "[submodule \"a\"]\n\
+         \tpath = lib/a\n\
+         \turl = https://example.com/a.git\n"
+    );
+
+    repo.subset(&[&a.to_string(), "-b", "out2", "-x", "lib"]);
+
+    let tree = repo.find("out2").tree().unwrap();
+    assert!(tree.get_path(Path::new(".gitmodules")).is_err());
+    assert_eq!(repo.read("out2", "src/main.c"), "int main;\n");
+}
+
+#[test]
+fn test_move_gitmodules() {
+    let repo = TestRepo::new("move-gitmodules");
+    let sub = repo.commit(&[("x", "1")], &[], "submodule");
+    let a = repo.commit_with_gitlinks(
+        "Test",
+        &[(".gitmodules", GITMODULES), ("src/main.c", "int main;\n")],
+        &[("lib/a", sub), ("lib/b", sub)],
+        &[],
+        "first",
+    );
+
+    repo.subset(&[
+        &a.to_string(),
+        "-b",
+        "out",
+        "-x",
+        "lib/b",
+        "--path-rename",
+        "lib/:vendor/",
+    ]);
+
+    // The submodule is listed where it was moved to.
+    assert_eq!(
+        repo.read("out", ".gitmodules"),
+        "[submodule \"a\"]\n\
+         \tpath = vendor/a\n\
+         \turl = https://example.com/a.git\n"
+    );
+
+    let tree = repo.find("out").tree().unwrap();
+    let entry = tree.get_path(Path::new("vendor/a")).unwrap();
+    assert_eq!(entry.filemode(), i32::from(git2::FileMode::Commit));
+    assert!(tree.get_path(Path::new("vendor/b")).is_err());
+
+    // `.gitmodules` stays at the root when everything is moved.
+    repo.subset(&[
+        &a.to_string(),
+        "-b",
+        "out2",
+        "--to-subdirectory-filter",
+        "x/",
+    ]);
+
+    assert_eq!(
+        repo.read("out2", ".gitmodules"),
+        "[submodule \"a\"]\n\
+         \tpath = x/lib/a\n\
+         \turl = https://example.com/a.git\n\
+         [submodule \"b\"]\n\
+         \tpath = x/lib/b\n\
+         \turl = https://example.com/b.git\n"
+    );
+
+    let tree = repo.find("out2").tree().unwrap();
+    assert!(tree.get_path(Path::new("x/.gitmodules")).is_err());
+    assert!(tree.get_path(Path::new("x/lib/a")).is_ok());
+
+    // Submodules outside of the new root are left out, and so is the file if
+    // none are left.
+    repo.subset(&[&a.to_string(), "-b", "out3", "--strip-components", "1"]);
+
+    assert_eq!(
+        repo.read("out3", ".gitmodules"),
+        GITMODULES.replace("lib/", "")
+    );
+
+    repo.subset(&[
+        &a.to_string(),
+        "-b",
+        "out4",
+        "--subdirectory-filter",
+        "src",
+    ]);
+
+    let tree = repo.find("out4").tree().unwrap();
+    assert!(tree.get_path(Path::new(".gitmodules")).is_err());
+    assert!(tree.get_path(Path::new("main.c")).is_ok());
+}
+
+#[test]
+fn test_submodules() {
+    let repo = TestRepo::new("submodules");
+    let sub = repo.commit(&[("x", "1")], &[], "submodule");
+    let a = repo.commit_with_gitlinks(
+        "Test",
+        &[("src/main.c", "int main;\n")],
+        &[("lib/a", sub), ("src/b", sub)],
+        &[],
+        "first",
+    );
+    let a = a.to_string();
+
+    repo.subset(&[&a, "-b", "keep", "-p", "src", "--submodules", "keep"]);
+    let tree = repo.find("keep").tree().unwrap();
+    assert!(tree.get_path(Path::new("src/b")).is_ok());
+    assert!(tree.get_path(Path::new("lib/a")).is_err());
+
+    repo.subset(&[&a, "-b", "drop", "--submodules", "drop"]);
+    let tree = repo.find("drop").tree().unwrap();
+    assert!(tree.get_path(Path::new("lib/a")).is_err());
+    assert!(tree.get_path(Path::new("src/b")).is_err());
+    assert_eq!(repo.read("drop", "src/main.c"), "int main;\n");
+
+    let output = repo.subset_err(&[&a, "-b", "error", "--submodules", "error"]);
+    assert!(output.contains("found lib/a, src/b"), "{}", output);
+
+    // Submodules that are left out anyway are fine.
+    repo.subset(&[
+        &a,
+        "-b",
+        "error",
+        "--submodules",
+        "error",
+        "-x",
+        "lib",
+        "-x",
+        "src/b",
+    ]);
+    assert_eq!(repo.read("error", "src/main.c"), "int main;\n");
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for rewriting tags.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn test_tagged_commits_kept() {
+    let repo = TestRepo::new("tags-kept");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "release");
+    let c = repo.commit(&[("a", "2"), ("b", "2")], &[b], "second");
+
+    let release = repo.repo.find_object(b, None).unwrap();
+    repo.repo.tag_lightweight("v1", &release, false).unwrap();
+    repo.repo
+        .reference("refs/heads/main", c, false, "test")
+        .unwrap();
+
+    repo.subset(&["main", "-b", "out", "--all", "-p", "a"]);
+
+    // The tagged commit is empty, but is kept for the tag.
+    assert_eq!(repo.log("out"), ["second", "release", "first"]);
+    assert_eq!(repo.log("refs/subset/out/tags/v1"), ["release", "first"]);
+}
+
+#[test]
+fn test_tagged_commits_kept_without_refs() {
+    let repo = TestRepo::new("tags-kept-without-refs");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "1"), ("b", "2")], &[a], "release");
+    let c = repo.commit(&[("a", "2"), ("b", "2")], &[b], "second");
+
+    let release = repo.repo.find_object(b, None).unwrap();
+    let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+    repo.repo
+        .tag("v1", &release, &signature, "Release 1", false)
+        .unwrap();
+
+    // Annotated tags keep their commits even when they aren't rewritten.
+    repo.subset(&[&c.to_string(), "-b", "out", "-p", "a"]);
+
+    assert_eq!(repo.log("out"), ["second", "release", "first"]);
+}
+
+#[test]
+fn test_tags_of_dropped_commits() {
+    let repo = TestRepo::new("tags-dropped");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a", "2"), ("b", "1")], &[a], "fixup! first");
+    let c = repo.commit(&[("a", "3"), ("b", "1")], &[b], "second");
+
+    let fixup = repo.repo.find_object(b, None).unwrap();
+    repo.repo.tag_lightweight("v1", &fixup, false).unwrap();
+    repo.repo
+        .reference("refs/heads/main", c, false, "test")
+        .unwrap();
+
+    repo.subset(&[
+        "main",
+        "-b",
+        "out",
+        "--all",
+        "-p",
+        "a",
+        "--drop-message-regex",
+        "^fixup!",
+    ]);
+
+    assert_eq!(repo.log("out"), ["second", "first"]);
+    assert!(repo.repo.find_reference("refs/subset/out/tags/v1").is_err());
+    assert_eq!(repo.log("refs/subset/out/heads/main"), ["second", "first"]);
+}
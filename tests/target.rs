@@ -0,0 +1,83 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for writing the subset into a separate repository.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns the number of objects in a repository's own object database.
+fn count_objects(repo: &git2::Repository) -> usize {
+    let mut count = 0;
+    repo.odb()
+        .unwrap()
+        .foreach(|_| {
+            count += 1;
+            true
+        })
+        .unwrap();
+    count
+}
+
+#[test]
+fn test_target() {
+    let repo = TestRepo::new("target");
+    let a = repo.commit(&[("a/x", "1"), ("b", "1")], &[], "first");
+    let b = repo.commit(&[("a/x", "1"), ("b", "2")], &[a], "second");
+    let c = repo.commit(&[("a/x", "2"), ("b", "2")], &[b], "third");
+
+    let path = repo.path.join("out-target");
+    let objects = count_objects(&repo.repo);
+
+    let c = c.to_string();
+    let target = path.to_str().unwrap();
+    repo.subset(&[&c, "-b", "out", "-p", "a", "--target", target]);
+
+    // Nothing is written to the source repository.
+    assert_eq!(count_objects(&repo.repo), objects);
+    assert!(repo
+        .repo
+        .find_branch("out", git2::BranchType::Local)
+        .is_err());
+
+    // The target has everything it needs without the source as an alternate.
+    let out = git2::Repository::open_bare(&path).unwrap();
+    let tip = out
+        .revparse_single("out")
+        .unwrap()
+        .peel_to_commit()
+        .unwrap();
+    assert_eq!(tip.summary(), Some("third"));
+    assert_eq!(tip.parent(0).unwrap().summary(), Some("first"));
+    assert_eq!(tip.parent_count(), 1);
+
+    let tree = tip.tree().unwrap();
+    assert!(tree.get_name("b").is_none());
+    let blob = tree.get_path("a/x".as_ref()).unwrap().id();
+    assert_eq!(out.find_blob(blob).unwrap().content(), b"2");
+
+    // An existing target repository is written into as well.
+    repo.subset(&[&c, "-b", "again", "-p", "a", "--target", target]);
+    assert_eq!(
+        out.revparse_single("again").unwrap().id(),
+        out.revparse_single("out").unwrap().id()
+    );
+}
@@ -0,0 +1,97 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for setting the upstream of the new branch.
+
+mod common;
+
+use common::TestRepo;
+
+/// Returns the upstream settings of a branch.
+fn upstream(repo: &TestRepo, branch: &str) -> (String, String) {
+    let config = repo.repo.config().unwrap().snapshot().unwrap();
+    (
+        config
+            .get_string(&format!("branch.{}.remote", branch))
+            .unwrap(),
+        config
+            .get_string(&format!("branch.{}.merge", branch))
+            .unwrap(),
+    )
+}
+
+#[test]
+fn test_track() {
+    let repo = TestRepo::new("track");
+    let a = repo.commit(&[("a", "1"), ("b", "1")], &[], "first");
+    repo.repo
+        .reference("refs/heads/main", a, false, "test")
+        .unwrap();
+
+    repo.repo
+        .remote("origin", "https://example.com/repo.git")
+        .unwrap();
+    repo.repo
+        .remote("origin/mirror", "https://example.com/mirror.git")
+        .unwrap();
+
+    let mut config = repo.repo.config().unwrap();
+    config.set_str("branch.main.remote", "origin").unwrap();
+    config
+        .set_str("branch.main.merge", "refs/heads/main")
+        .unwrap();
+
+    repo.subset(&["main", "-b", "out", "-p", "a", "--track", "origin/subset"]);
+    repo.subset(&[
+        "main",
+        "-b",
+        "mirrored",
+        "-p",
+        "a",
+        "--track",
+        "origin/mirror/subset",
+    ]);
+    repo.subset(&["main", "-b", "copied", "-p", "a", "--copy-upstream"]);
+
+    assert_eq!(
+        upstream(&repo, "out"),
+        ("origin".to_string(), "refs/heads/subset".to_string())
+    );
+    assert_eq!(
+        upstream(&repo, "mirrored"),
+        ("origin/mirror".to_string(), "refs/heads/subset".to_string())
+    );
+    assert_eq!(
+        upstream(&repo, "copied"),
+        ("origin".to_string(), "refs/heads/main".to_string())
+    );
+
+    let output = repo.subset_err(&[
+        "main",
+        "-b",
+        "unknown",
+        "-p",
+        "a",
+        "--track",
+        "upstream/subset",
+    ]);
+    assert!(output.contains("isn't of the form <remote>/<branch>"));
+    assert!(repo.repo.find_reference("refs/heads/unknown").is_err());
+}
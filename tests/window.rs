@@ -0,0 +1,80 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for only rewriting the commits made within a date window.
+
+mod common;
+
+use common::TestRepo;
+
+/// Creates four commits, one minute apart, starting at `@1500000000`. Returns
+/// the last one.
+fn history(repo: &TestRepo) -> String {
+    let a = repo.commit(&[("f", "1")], &[], "first");
+    let b = repo.commit(&[("f", "2")], &[a], "second");
+    let c = repo.commit(&[("f", "3")], &[b], "third");
+    repo.commit(&[("f", "4")], &[c], "fourth").to_string()
+}
+
+#[test]
+fn test_since() {
+    let repo = TestRepo::new("window-since");
+    let tip = history(&repo);
+
+    repo.subset(&[&tip, "-b", "out", "--since", "@1500000060", "-p", "f"]);
+    assert_eq!(repo.log("out"), ["fourth", "third", "second"]);
+
+    // The boundary can be squashed instead.
+    repo.subset(&[
+        &tip,
+        "-b",
+        "squashed",
+        "--since",
+        "@1500000120",
+        "--squash-before",
+        "@1500000120",
+        "-p",
+        "f",
+    ]);
+    assert_eq!(repo.log("squashed").len(), 3);
+    assert_eq!(repo.read("squashed~2", "f"), "2");
+}
+
+#[test]
+fn test_until() {
+    let repo = TestRepo::new("window-until");
+    let tip = history(&repo);
+
+    repo.subset(&[&tip, "-b", "out", "--until", "@1500000060", "-p", "f"]);
+    assert_eq!(repo.log("out"), ["second", "first"]);
+
+    repo.subset(&[
+        &tip,
+        "-b",
+        "window",
+        "--since",
+        "@1500000060",
+        "--until",
+        "@1500000120",
+        "-p",
+        "f",
+    ]);
+    assert_eq!(repo.log("window"), ["third", "second"]);
+}
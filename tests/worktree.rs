@@ -0,0 +1,66 @@
+// Copyright (c) 2017 Jason White
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests for checking out the new branch in a worktree.
+
+mod common;
+
+use std::fs;
+
+use common::TestRepo;
+
+#[test]
+fn test_worktree() {
+    let repo = TestRepo::new("worktree");
+    let a = repo.commit(&[("a", "1"), ("b/c", "2")], &[], "first");
+
+    // The worktree is put inside the repository so that it is removed along
+    // with it.
+    let path = repo.path.join("out-worktree");
+    let a = a.to_string();
+    repo.subset(&[
+        &a,
+        "-b",
+        "out",
+        "-p",
+        "a",
+        "--worktree",
+        path.to_str().unwrap(),
+    ]);
+
+    assert_eq!(fs::read_to_string(path.join("a")).unwrap(), "1");
+    assert!(!path.join("b").exists());
+
+    let worktree = git2::Repository::open(&path).unwrap();
+    assert_eq!(worktree.head().unwrap().name(), Some("refs/heads/out"));
+
+    // Nothing is rewritten if the worktree can't be created.
+    let output = repo.subset_err(&[
+        &a,
+        "-b",
+        "again",
+        "-p",
+        "a",
+        "--worktree",
+        path.to_str().unwrap(),
+    ]);
+    assert!(output.contains("already exists"));
+    assert!(repo.repo.find_reference("refs/heads/again").is_err());
+}